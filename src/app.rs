@@ -4,6 +4,10 @@
 // to those terms.
 
 use clap::{crate_version, value_parser, Arg, ArgAction, ColorChoice, Command};
+use hyperex::utils::RegionRegistry;
+
+use std::ops::RangeInclusive;
+use std::path::Path;
 
 pub fn build_app() -> Command {
     let clap_color_setting = if std::env::var_os("NO_COLOR").is_none() {
@@ -15,7 +19,7 @@ pub fn build_app() -> Command {
     Command::new("hyperex")
         .version(crate_version!())
         .override_usage(
-            "hyperex [options] [<FILE>]"
+            "hyperex [options] [<FILE>...]"
         )
         .color(clap_color_setting)
         .after_help(
@@ -26,60 +30,500 @@ pub fn build_app() -> Command {
         .about("Hypervariable region primer-based extractor")
         .arg(
             Arg::new("FILE")
-                .help("input fasta file or stdin")
-                .long_help("input fasta file. With no FILE, or when FILE is -, read standard input. Input data can be gzip'd, xz'd or bzip'd")
+                .help("input fasta file(s) or stdin")
+                .long_help("input fasta file. With no FILE, or when FILE is -, read standard input. Input data can be gzip'd, xz'd or bzip'd. \
+                    When built with the `remote` feature, FILE can also be an http://, https:// or ftp:// URL which is streamed. \
+                    Several files are processed concurrently (see --threads) and their results merged")
+                .num_args(1..)
                 .index(1),
         )
         .arg(
-            Arg::new("forward_primer")
-                .short('f')
-                .long("forward-primer")
-                .help("forward primer sequence")
-                .long_help("Specifies forward primer sequence which can contains IUPAC ambiguities")
-                .conflicts_with("region")
-                .requires("reverse_primer")
+            Arg::new("accession")
+                .help("NCBI accession to fetch and process")
+                .long_help(
+                    "Fetches sequences from NCBI by accession instead of reading\n\
+                    FILE. Can be repeated, or be a file with one accession per line.\n\
+                    Requires the `remote` feature"
+                )
+                .long("accession")
+                .conflicts_with("FILE")
                 .num_args(1..)
                 .number_of_values(1)
+                .action(ArgAction::Append)
                 .value_name("STR")
         )
         .arg(
-            Arg::new("reverse_primer")
-                .short('r')
-                .long("reverse-primer")
-                .help("reverse primer sequence")
-                .long_help("Specifies reverse primer sequence which can contains IUPAC ambiguities")
-                .conflicts_with("region")
-                .num_args(1..)
-                .number_of_values(1)
+            Arg::new("sra")
+                .help("SRA run whose reads are streamed and processed")
+                .long_help(
+                    "Streams the FASTQ reads of a SRA, ENA or DDBJ run, e.g.\n\
+                    SRR1234567, from ENA instead of reading FILE, to screen public\n\
+                    datasets without downloading them first. The mates of paired\n\
+                    runs are merged as with --mates. Requires the `remote` feature"
+                )
+                .long("sra")
+                .value_name("RUN")
+                .conflicts_with_all(["FILE", "accession", "mates", "interleaved"])
+        )
+        .arg(
+            Arg::new("cache_dir")
+                .help("directory caching fetched sequences")
+                .long("cache-dir")
+                .value_name("PATH")
+                .default_value("hyperex_cache")
+        )
+        .args(primer_args())
+        .arg(
+            Arg::new("all_matches")
+                .help("extract every amplicon of each primer pair")
+                .long_help(
+                    "Extracts every amplicon of each primer pair, e.g. from the\n\
+                    several rRNA operons of an assembly, instead of only the best\n\
+                    one. Each forward primer hit is paired with the first reverse\n\
+                    primer hit downstream of it"
+                )
+                .long("all-matches")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("max_amplicons")
+                .help("maximum number of amplicons extracted per sequence")
+                .long_help(
+                    "Caps the number of amplicons extracted from a sequence with\n\
+                    --all-matches, protecting from output explosions on repetitive\n\
+                    assemblies. Suppressed amplicons are counted in the summary"
+                )
+                .long("max-amplicons-per-seq")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .requires("all_matches")
+        )
+        .arg(
+            Arg::new("min_primer_identity")
+                .help("smallest identity of primer hits")
+                .long_help(
+                    "Keeps primer hits at least this identical to their primer,\n\
+                    the identity being 1 - edit distance / primer length, e.g.\n\
+                    0.9 allows 1 mismatch to a 17 bases primer and 2 to a 21\n\
+                    bases one. Replaces --mismatch, except for the pairs of a\n\
+                    primers file with their own mismatches"
+                )
+                .long("min-primer-identity")
+                .value_name("F")
+                .value_parser(identity_value)
+                .conflicts_with("mismatch")
+        )
+        .arg(
+            Arg::new("max_amplicon_length")
+                .help("longest amplicon a primer pair may amplify")
+                .long_help(
+                    "Only pairs primer hits making an amplicon, primers included,\n\
+                    of at most N bases. Keeps hits of different operons from\n\
+                    being paired on multi-operon genomes"
+                )
+                .long("max-amplicon-length")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("max_pair_distance")
+                .help("most bases between paired primer hits")
+                .long_help(
+                    "Only pairs a forward primer hit with a reverse primer hit\n\
+                    starting at most N bases downstream of its end, instead of\n\
+                    the next reverse hit wherever it is on the sequence. Keeps\n\
+                    hits of distant operons from being paired on long contigs"
+                )
+                .long("max-pair-distance")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("anchor")
+                .help("primers a region has to be bound by")
+                .long_help(
+                    "Extracts regions bound by both primers (both) or, when a\n\
+                    truncated sequence lacks the site of the other primer, from\n\
+                    the forward primer hit to the sequence end (forward) or from\n\
+                    the sequence start to the reverse primer hit (reverse).\n\
+                    Anchored regions are tagged with anchored=forward|reverse"
+                )
+                .long("anchor")
                 .value_name("STR")
+                .value_parser(["forward", "reverse", "both"])
+                .default_value("both")
+                .conflicts_with("both_strands")
         )
         .arg(
-            Arg::new("region")
-                .long("region")
-                .help("hypervariable region name")
+            Arg::new("dedup_overlaps")
+                .help("handling of overlapping regions of a sequence")
                 .long_help(
-                    "Specifies 16S rRNA region name wanted. Supported values are\n\
-                    v1v2, v1v3, v1v9, v3v4, v3v5, v4, v4v5, v5v7, v6v9, v7v9"
+                    "Keeps all the regions extracted from a sequence (keep-all) or,\n\
+                    with keep-longest, only the longest of overlapping regions,\n\
+                    e.g. v3v4 rather than the nested v4, for databases rejecting\n\
+                    nested duplicates. Left out regions are counted in the summary"
                 )
-                .value_parser(clap::builder::PossibleValuesParser::new(["v1v2", "v1v3", "v1v9", "v3v4", "v3v5", "v4", "v4v5", "v5v7", "v6v9", "v7v9"]))
-                .hide_possible_values(true)
-                .num_args(1..)
-                .number_of_values(1)
+                .long("dedup-overlaps")
                 .value_name("STR")
+                .value_parser(["keep-all", "keep-longest"])
+                .default_value("keep-all")
         )
         .arg(
-            Arg::new("mismatch")
-                .help("number of allowed mismatch")
+            Arg::new("keep_primer_dimers")
+                .help("extract primer-dimer-like amplicons")
                 .long_help(
-                    "Specifies the number of allowed mismatch. This cannot\n\
-                    be greater than the length of the lengthest primer"
+                    "Extracts the amplicons whose primer hits overlap, or which are\n\
+                    shorter than the two primers combined, flagged with\n\
+                    primer_dimer=true in the FASTA headers. They are otherwise left\n\
+                    out as primer-dimer artifacts and listed in\n\
+                    <prefix>.skipped.tsv. Both are counted in the summary"
                 )
-                .long("mismatch")
-                .short('m')
+                .long("keep-primer-dimers")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("rescue")
+                .help("search missing primers again with N more mismatches")
+                .long_help(
+                    "When a single primer of a pair is found, searches the other\n\
+                    one again with N (1 or 2) more mismatches, only where the\n\
+                    typical amplicons of the pair put it: within 20% of the\n\
+                    length of the E. coli amplicon or, for other primers, within\n\
+                    --max-amplicon-length. Rescued regions are flagged with\n\
+                    rescued=true and low confidence. Not available with\n\
+                    --both-strands"
+                )
+                .long("rescue")
+                .value_name("N")
+                .value_parser(value_parser!(u8).range(1..=2))
+                .conflicts_with("both_strands")
+        )
+        .arg(
+            Arg::new("anchored")
+                .help("only keep primer hits within K bases of record ends")
+                .long_help(
+                    "Only searches primers within K bases of the record ends, as\n\
+                    in reads of trimmed amplicons, ignoring internal hits. Removes\n\
+                    false internal matches and speeds up the search"
+                )
+                .long("anchored")
+                .value_name("K")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("chunk_size")
+                .help("search long records in windows of N bases")
+                .long_help(
+                    "Searches records longer than N bases in overlapping windows of\n\
+                    N bases, stitching their hits. Bounds the memory used by primer\n\
+                    matching, several times the record size otherwise, on\n\
+                    chromosome-scale records"
+                )
+                .long("chunk-size")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("max_record_len")
+                .help("skip records longer than N bases")
+                .long_help(
+                    "Skips, with a warning, records longer than N bases before\n\
+                    searching them. Skipped records are counted in the summary"
+                )
+                .long("max-record-len")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("sanitize")
+                .help("handling of - and * padding characters in records")
+                .long_help(
+                    "Handles the - and * characters of records, e.g. alignment\n\
+                    gaps or translation stops, before searching them: strip\n\
+                    removes them, shifting the positions of the following bases,\n\
+                    to-N replaces them by N and error skips the records holding\n\
+                    them as of an unknown alphabet"
+                )
+                .long("sanitize")
+                .value_name("STR")
+                .value_parser(["strip", "to-N", "error"])
+                .default_value("error")
+        )
+        .arg(
+            Arg::new("alphabet_sample")
+                .help("bases sampled to detect the alphabet of records")
+                .long_help(
+                    "Detects the alphabet, DNA or RNA, of each record from its\n\
+                    first N bases instead of all of them, which is faster on long\n\
+                    genomes. 0 checks every base"
+                )
+                .long("alphabet-sample")
                 .value_name("N")
-                .value_parser(value_parser!(u8))
-                .hide_possible_values(true)
-                .default_value("0")
+                .value_parser(value_parser!(usize))
+                .default_value("10000")
+        )
+        .arg(
+            Arg::new("mixed_alphabet")
+                .help("handling of records neither DNA nor RNA")
+                .long_help(
+                    "Handles the records whose sampled bases are neither all DNA\n\
+                    nor all RNA, e.g. mixing T and U or holding unknown symbols:\n\
+                    skip lists them in <prefix>.skipped.tsv as of an unknown\n\
+                    alphabet, dna searches them as DNA and error stops the run"
+                )
+                .long("mixed-alphabet")
+                .value_name("STR")
+                .value_parser(["skip", "dna", "error"])
+                .default_value("skip")
+        )
+        .arg(
+            Arg::new("id_parser")
+                .help("id of the records written in the outputs")
+                .long_help(
+                    "Parses the record ids written in the outputs from the record\n\
+                    headers: first-word keeps the first word of the header, ncbi\n\
+                    the accession of NCBI headers, e.g. NR_000001.1 of\n\
+                    gi|42|ref|NR_000001.1|, and regex:<re> the first capture group,\n\
+                    or whole match, of <re> on the header. Records whose header\n\
+                    does not match keep their first word"
+                )
+                .long("id-parser")
+                .value_name("STR")
+                .default_value("first-word")
+        )
+        .arg(
+            Arg::new("header_filter")
+                .help("only process records whose header matches STR")
+                .long_help(
+                    "Only processes the records whose header contains STR, e.g.\n\
+                    a taxon name, or matches the regular expression <re> with\n\
+                    regex:<re>. The records passing the filter and those left out\n\
+                    are counted in the summary"
+                )
+                .long("header-filter")
+                .value_name("STR")
+        )
+        .arg(
+            Arg::new("subsample")
+                .help("only process a random fraction F of the records")
+                .long_help(
+                    "Only processes each record with probability F, between 0 and\n\
+                    1. Whether a record is kept only depends on its id and on the\n\
+                    seed of the run, so that runs given the same --seed keep the\n\
+                    same records, whatever the number of threads. The records\n\
+                    left out are counted in the summary"
+                )
+                .long("subsample")
+                .value_name("F")
+                .value_parser(identity_value)
+        )
+        .arg(
+            Arg::new("seed")
+                .help("seed of the random draws of the run")
+                .long_help(
+                    "Seeds the random draws of the run, e.g. of --subsample, so\n\
+                    that it can be repeated exactly. A seed is drawn when none is\n\
+                    given, and the seed of the run is logged and recorded in\n\
+                    <prefix>.run.json"
+                )
+                .long("seed")
+                .value_name("N")
+                .value_parser(value_parser!(u64))
+        )
+        .arg(
+            Arg::new("record_timeout")
+                .help("give up records searched for more than SECS seconds")
+                .long_help(
+                    "Skips, with a warning, records whose primer search takes more\n\
+                    than SECS seconds, so that pathological records cannot stall\n\
+                    the run. The budget is checked during the scans of the record,\n\
+                    every megabase or --chunk-size bases, and while pairing the\n\
+                    primer hits. Skipped records are counted in the summary"
+                )
+                .long("record-timeout")
+                .value_name("SECS")
+                .value_parser(value_parser!(u64))
+        )
+        .arg(
+            Arg::new("both_strands")
+                .help("search primers on both strands")
+                .long_help(
+                    "Also searches the minus strand of the sequences, e.g. for rRNA\n\
+                    genes on both strands of a genome. Primer hits are only paired\n\
+                    when they lie on the same strand and face each other, other\n\
+                    pairings are rejected and reported. Regions of the minus strand\n\
+                    are written reverse complemented, in the 5'-3' orientation of\n\
+                    their gene, unless --keep-strand is given"
+                )
+                .long("both-strands")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("reference")
+                .help("transfer the primer sites of a reference to the sequences")
+                .long_help(
+                    "Locates the primers on the first sequence of FILE, e.g. the\n\
+                    E. coli 16S gene, and extracts the regions of each sequence by\n\
+                    aligning it to the reference and transferring the primer sites,\n\
+                    instead of searching the primers in the sequence. Sequences\n\
+                    mutated at the primer sites keep their regions, at the cost of\n\
+                    an alignment per sequence. Every primer pair must amplify the\n\
+                    reference"
+                )
+                .long("reference")
+                .value_name("FILE")
+                .conflicts_with_all(["all_matches", "watch"])
+        )
+        .arg(
+            Arg::new("keep_strand")
+                .help("write minus strand regions as read on the sequence")
+                .long_help(
+                    "Writes the regions of the minus strand as read on the\n\
+                    sequence instead of reverse complementing them into the\n\
+                    orientation of their gene"
+                )
+                .long("keep-strand")
+                .action(ArgAction::SetTrue)
+                .requires("both_strands")
+        )
+        .arg(
+            Arg::new("degenerate_score")
+                .help("score primer hits by their share of the primer mix")
+                .long_help(
+                    "Adds forward_score and reverse_score to the FASTA headers: the\n\
+                    fraction of an equimolar degenerate primer mix matching each\n\
+                    hit, each degenerate position resolved to one of its k variants\n\
+                    weighing 1/k. Hits of primers without degenerate bases score 1.\n\
+                    Not computed with --homopolymer-tolerant"
+                )
+                .long("degenerate-score")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("auto_gene")
+                .help("classify each sequence as 16S, 18S or ITS")
+                .long_help(
+                    "Guesses the marker gene of each sequence from k-mers of the\n\
+                    conserved sites of the built-in primers and searches it with the\n\
+                    built-in regions of this gene, all of them when unclassified.\n\
+                    Regions are tagged with their gene and the classification is\n\
+                    logged"
+                )
+                .long("auto-gene")
+                .conflicts_with_all(["region", "forward_primer", "gene"])
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("cache_hits")
+                .help("reuse primer hits of identical sequences")
+                .long_help(
+                    "Searches primers once per distinct sequence, identical\n\
+                    sequences reusing the hits of the first one. Speeds up read\n\
+                    datasets with many duplicates at the cost of keeping every\n\
+                    distinct sequence in memory"
+                )
+                .long("cache-hits")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("homopolymer_tolerant")
+                .help("ignore homopolymer length errors in primer hits")
+                .long_help(
+                    "Collapses homopolymer runs in primers and sequences before\n\
+                    matching so that run-length errors, the main error mode of\n\
+                    nanopore and IonTorrent reads, do not use up the allowed\n\
+                    mismatches. Coordinates are reported on the original sequences"
+                )
+                .long("homopolymer-tolerant")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("trim_primers")
+                .help("leave primer-binding sites out of extracted regions")
+                .long("trim-primers")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("primer_offsets")
+                .help("report where primer sites lie in extracted regions")
+                .long_help(
+                    "Adds to the FASTA headers where the primer sites start in the\n\
+                    written regions: forward_offset=0 and reverse_offset=LENGTH-SITE\n\
+                    by default, a negative forward offset and a reverse offset of\n\
+                    the region length with --trim-primers, so that downstream\n\
+                    primer removal can be checked. Primers missing from anchored\n\
+                    regions have no offset"
+                )
+                .long("primer-offsets")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("interleaved")
+                .help("merge read pairs of interleaved inputs")
+                .long_help(
+                    "Reads the input as interleaved pairs, R1 and R2 alternating,\n\
+                    and merges the mates of each pair on their overlap before\n\
+                    searching the primers. Pairs whose mates do not overlap are\n\
+                    skipped"
+                )
+                .long("interleaved")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("mates")
+                .help("reverse reads merged with the reads of FILE")
+                .long_help(
+                    "Reads the R2 mates of the reads of FILE, in the same order,\n\
+                    and merges each pair on its overlap before searching the\n\
+                    primers, so that regions longer than a read, e.g. V3V4, are\n\
+                    extracted from short-read pairs. Pairs whose mates do not\n\
+                    overlap are skipped"
+                )
+                .long("mates")
+                .value_name("FILE")
+                .conflicts_with_all(["interleaved", "accession", "watch"])
+        )
+        .arg(
+            Arg::new("min_overlap")
+                .help("shortest overlap of merged mates")
+                .long("min-overlap")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("taxonomy")
+                .help("file mapping sequence ids to lineages")
+                .long_help(
+                    "Reads the lineage of each sequence from a two-column TSV file\n\
+                    (id and `;` separated lineage), as distributed with QIIME and\n\
+                    GTDB references. Lineages are added to the FASTA output and the\n\
+                    extraction success rate of each taxon is written to\n\
+                    <prefix>.taxa.tsv, and with the mean distance of each primer\n\
+                    by rank, in long format, to <prefix>.ranks.tsv"
+                )
+                .long("taxonomy")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("taxonomy_from_headers")
+                .help("read lineages from SILVA/GTDB style FASTA headers")
+                .long("taxonomy-from-headers")
+                .conflicts_with("taxonomy")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("silva_alignment")
+                .help("add E. coli positions and SILVA alignment columns of regions")
+                .long_help(
+                    "Reads the E. coli 16S rRNA gene (J01859) as aligned by SILVA,\n\
+                    e.g. exported from ARB, from the first sequence of FILE and adds\n\
+                    the boundaries of the regions of the built-in primers, as E. coli\n\
+                    positions and SILVA alignment columns, to the FASTA headers as\n\
+                    ecoli=START-END silva=START-END, primers excluded with\n\
+                    --trim-primers"
+                )
+                .long("silva-alignment")
+                .value_name("FILE")
         )
         .arg(
             Arg::new("prefix")
@@ -90,12 +534,341 @@ pub fn build_app() -> Command {
                 .value_name("PATH")
                 .default_value("hyperex_out"),
         )
+        .arg(
+            Arg::new("no_fasta")
+                .help("do not write the FASTA output")
+                .long("no-fasta")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("index_output")
+                .help("write the faidx index of the FASTA output")
+                .long_help(
+                    "Writes PREFIX.fa.fai, the samtools faidx index of the FASTA\n\
+                    output, for random access to the extracted regions"
+                )
+                .long("index-output")
+                .conflicts_with_all(["no_fasta", "watch"])
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("no_gff")
+                .help("do not write the GFF3 output")
+                .long("no-gff")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("gff_source")
+                .help("source column of the GFF3 output")
+                .long("gff-source")
+                .value_name("STR")
+                .default_value("hyperex")
+        )
+        .arg(
+            Arg::new("gff_type")
+                .help("feature type column of the GFF3 output")
+                .long_help(
+                    "Specifies the feature type of the GFF3 records, e.g. a\n\
+                    Sequence Ontology term such as rRNA_hypervariable_region\n\
+                    expected by annotation mergers"
+                )
+                .long("gff-type")
+                .value_name("STR")
+                .default_value("region")
+        )
+        .arg(
+            Arg::new("coords")
+                .help("coordinate convention of the TSV and JSON outputs")
+                .long_help(
+                    "Writes hit and region positions of the TSV and JSON outputs\n\
+                    with 0-based starts and exclusive ends (0based, as in BED) or\n\
+                    1-based starts and inclusive ends (1based, as in GFF3). The\n\
+                    GFF3 output is always 1-based"
+                )
+                .long("coords")
+                .value_name("STR")
+                .value_parser(["0based", "1based"])
+                .default_value("0based")
+        )
+        .arg(
+            Arg::new("flush_every")
+                .help("flush outputs every N records")
+                .long_help(
+                    "Flushes the FASTA, GFF3 and TSV outputs every N input records\n\
+                    so that an interrupted run keeps the regions of the records\n\
+                    processed so far"
+                )
+                .long("flush-every")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+        )
+        .arg(
+            Arg::new("fsync")
+                .help("sync outputs to disk at each flush")
+                .long_help(
+                    "Syncs the outputs to disk at each flush and at the end of the\n\
+                    run, for network filesystems losing buffered data when jobs\n\
+                    are killed"
+                )
+                .long("fsync")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("out_bam")
+                .help("write extracted regions as BAM")
+                .long_help(
+                    "Writes extracted regions as BAM alignments against the input\n\
+                    sequences, sorted by coordinate, for display in genome browsers.\n\
+                    Primers are stored in the XF/XR tags and their edit distances in\n\
+                    the YF/YR tags"
+                )
+                .long("out-bam")
+                .value_name("PATH")
+        )
+        .arg(
+            Arg::new("out_tbl")
+                .help("write extracted regions as a feature table")
+                .long_help(
+                    "Writes extracted regions as misc_feature entries of a feature\n\
+                    table, in the EMBL format when PATH ends with .embl and in the\n\
+                    NCBI five-column .tbl format used for GenBank submissions\n\
+                    otherwise"
+                )
+                .long("out-tbl")
+                .value_name("PATH")
+        )
+        .arg(
+            Arg::new("out_track")
+                .help("write a bedGraph track of windowed GC content and entropy")
+                .long_help(
+                    "Writes the GC content and the Shannon entropy of the bases in\n\
+                    windows sliding along each input sequence as two bedGraph\n\
+                    tracks, to check in a genome browser, along with the GFF3 or\n\
+                    BAM output, that extracted regions lie in variable parts of the\n\
+                    sequences"
+                )
+                .long("out-track")
+                .value_name("PATH")
+        )
+        .arg(
+            Arg::new("track_window")
+                .help("width of the windows of --out-track")
+                .long_help(
+                    "Width in bp of the windows of --out-track, which slide by a\n\
+                    fifth of their width"
+                )
+                .long("track-window")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value("50")
+                .requires("out_track")
+        )
+        .arg(
+            Arg::new("out_sqlite")
+                .help("add the run to a SQLite results database")
+                .long_help(
+                    "Adds the run, its sequences, primer hits and extracted regions\n\
+                    to the runs, sequences, primer_hits and extractions tables of\n\
+                    the SQLite database FILE, created if missing, so that the\n\
+                    results of many runs can be queried with SQL. Only the\n\
+                    records written by the run are added, e.g. not those of\n\
+                    the outputs it is appended to. Requires the `sqlite`\n\
+                    feature"
+                )
+                .long("out-sqlite")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("out_parquet")
+                .help("write extracted regions as a Parquet table")
+                .long_help(
+                    "Writes the extracted regions as a Parquet table, one row per\n\
+                    region with the fields of PREFIX.regions.jsonl, to be loaded\n\
+                    with pandas, polars or Arrow. The table only holds the regions\n\
+                    of the run, even with --append. Requires the `parquet` feature"
+                )
+                .long("out-parquet")
+                .value_name("FILE")
+        )
+        .arg(
+            Arg::new("report_md")
+                .help("write a Markdown report of the run")
+                .long_help(
+                    "Writes a concise Markdown report of the run, with its\n\
+                    parameters, the count and lengths of each region and its\n\
+                    warnings, to paste into lab notebooks or issues"
+                )
+                .long("report-md")
+                .value_name("PATH")
+        )
+        .arg(
+            Arg::new("bundle")
+                .help("package the outputs into a .tar.gz archive")
+                .long_help(
+                    "Packages the FASTA, GFF3, TSV and JSON outputs of the run,\n\
+                    and the --report-md report, into a gzip'd tar archive with a\n\
+                    manifest.json giving the run metadata and the SHA-256 of each\n\
+                    file, and a SHA256SUMS file to check them with sha256sum -c"
+                )
+                .long("bundle")
+                .value_name("PATH")
+        )
+        .arg(
+            Arg::new("plot")
+                .help("draw primer hits and regions along each sequence")
+                .long_help(
+                    "Writes a diagram showing where each primer hit and extracted\n\
+                    region lies along every sequence, as SVG when PATH ends with\n\
+                    .svg and as text otherwise"
+                )
+                .long("plot")
+                .value_name("PATH")
+        )
+        .arg(
+            Arg::new("prefilter")
+                .help("only search near primer seed hits")
+                .long_help(
+                    "Indexes exact primer seeds and only runs the full matcher on\n\
+                    the parts of the sequences containing a seed hit. No hit is\n\
+                    lost, and large assemblies are processed much faster. The\n\
+                    seeds of all the primers are looked up in a single pass\n\
+                    over each sequence, the full matcher then running once per\n\
+                    distinct primer near its own seed hits"
+                )
+                .long("prefilter")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("threads")
+                .help("number of files processed concurrently")
+                .long_help(
+                    "Number of input files processed concurrently. The blocks\n\
+                    of a single BGZF input, e.g. a .fa.bgz file written by\n\
+                    bgzip, are decompressed on this number of threads instead,\n\
+                    their records being searched on one: split large inputs\n\
+                    into several files to search them in parallel"
+                )
+                .short('t')
+                .long("threads")
+                .value_name("N")
+                .value_parser(value_parser!(usize))
+                .default_value("1")
+        )
+        .arg(
+            Arg::new("watch")
+                .help("process new files appearing in DIR")
+                .long_help(
+                    "Watches DIR for new FASTA/FASTQ files, e.g. from a basecaller,\n\
+                    and processes each file once it is complete. Regions are\n\
+                    appended to PREFIX_<sample>.fa and PREFIX_<sample>.gff, where\n\
+                    the sample is the subdirectory of DIR holding the file, and\n\
+                    counts are kept in PREFIX_summary.tsv. Runs until interrupted"
+                )
+                .long("watch")
+                .value_name("DIR")
+                .conflicts_with_all(["FILE", "accession", "sra", "out_bam", "out_tbl", "out_track", "out_sqlite", "out_parquet", "bundle", "plot"])
+        )
+        .arg(
+            Arg::new("watch_interval")
+                .help("seconds between two scans of the watched directory")
+                .long("watch-interval")
+                .value_name("N")
+                .value_parser(value_parser!(u64))
+                .default_value("5")
+                .requires("watch")
+        )
+        .arg(
+            Arg::new("metrics")
+                .help("dump watch counters for Prometheus to PATH")
+                .long_help(
+                    "Rewrites PATH after each scan of the watched directory with\n\
+                    the files processed and failed, the records processed and\n\
+                    skipped and the regions extracted by sample, in the Prometheus\n\
+                    text format, e.g. for the node exporter textfile collector"
+                )
+                .long("metrics")
+                .value_name("PATH")
+                .requires("watch")
+        )
         .arg(
             Arg::new("force")
                 .help("overwrite output")
                 .long("force")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("append")
+                .help("append to existing outputs")
+                .long_help(
+                    "Appends the regions to the outputs of previous runs with the\n\
+                    same prefix, e.g. to accumulate batches, once their headers\n\
+                    and PREFIX.run.json schema and coordinates are checked to be\n\
+                    compatible. The summary, statistics and run report cover all\n\
+                    the runs appended to"
+                )
+                .long("append")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("force"),
+        )
+        .arg(
+            Arg::new("sparklines")
+                .help("show region length distributions in the summary")
+                .long_help(
+                    "Logs the length distribution of each region as a sparkline,\n\
+                    making bimodal or aberrant amplicon sizes easy to spot. The\n\
+                    distributions are always written to PREFIX.lengths.tsv"
+                )
+                .long("sparklines")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("progress")
+                .help("log the progress of the run")
+                .long_help(
+                    "Logs every few seconds the records processed, with the\n\
+                    percentage done and the time left for local inputs, whose\n\
+                    records are counted beforehand (see hyperex count)"
+                )
+                .long("progress")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats_every")
+                .help("rewrite <prefix>.stats.json every N records or SECS seconds")
+                .long_help(
+                    "Rewrites <prefix>.stats.json with the records processed, the\n\
+                    regions extracted and their rates so far every N records, or\n\
+                    every SECS seconds with an s suffix, e.g. 30s, to monitor long\n\
+                    runs. The file is marked done once the run is over"
+                )
+                .long("stats-every")
+                .value_name("N|SECSs")
+        )
+        .arg(
+            Arg::new("porcelain")
+                .help("print a parsable outcome line on stdout")
+                .long_help(
+                    "Prints, once the run succeeded, a single line such as\n\
+                    OK sequences=1200 extracted=1130 regions=v3v4:980,v4:150 warnings=70\n\
+                    on the standard output for shell scripts, logs going to the\n\
+                    standard error. Failed runs print nothing and exit with a\n\
+                    non-zero status"
+                )
+                .long("porcelain")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("primer_db_version")
+                .help("print the version of the built-in primer database")
+                .long_help(
+                    "Prints the version of the built-in primer database and exits.\n\
+                    The version is also recorded in the GFF3 header and in\n\
+                    PREFIX.run.json so that results can be traced to the primers used"
+                )
+                .long("primer-db-version")
+                .exclusive(true)
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("quiet")
                 .long_help("decreases program verbosity")
@@ -103,6 +876,498 @@ pub fn build_app() -> Command {
                 .long("quiet")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("tui")
+                .help("show a live dashboard of the run")
+                .long_help(
+                    "Shows a dashboard of the run redrawn in place on the terminal,\n\
+                    with the records and regions per second, the regions extracted\n\
+                    so far and the recent warnings, instead of the logs, for long\n\
+                    runs. Logs are printed as usual once the run is over, and\n\
+                    throughout when the standard error is not a terminal"
+                )
+                .long("tui")
+                .action(ArgAction::SetTrue),
+        )
+        .subcommand(
+            Command::new("view")
+                .about("show primer-binding sites on sequences")
+                .long_about(
+                    "Prints each sequence region bound by the primers, with matching\n\
+                    bases highlighted and mismatches marked. When a primer is not\n\
+                    found, its closest site is shown to explain the miss"
+                )
+                .override_usage("hyperex view [options] <FILE>")
+                .arg(
+                    Arg::new("FILE")
+                        .help("input fasta or fastq file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("id")
+                        .help("only show the sequence with this identifier")
+                        .long("id")
+                        .num_args(1..)
+                        .number_of_values(1)
+                        .action(ArgAction::Append)
+                        .value_name("STR")
+                )
+                .arg(
+                    Arg::new("context")
+                        .help("number of flanking bases shown around a site")
+                        .long("context")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10")
+                )
+                .args(primer_args())
+        )
+        .subcommand(
+            Command::new("count")
+                .about("count records and bases of input files")
+                .long_about(
+                    "Prints the number of records and bases of each file, plain or\n\
+                    compressed, without parsing the records"
+                )
+                .override_usage("hyperex count <FILE>...")
+                .arg(
+                    Arg::new("FILE")
+                        .help("input fasta or fastq file(s)")
+                        .required(true)
+                        .num_args(1..)
+                        .index(1),
+                )
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("compare the regions of two runs")
+                .long_about(
+                    "Lists the regions gained, lost and moved to other coordinates\n\
+                    from the OLD to the NEW run, e.g. to tune mismatch settings.\n\
+                    Runs are read from their PREFIX.run.json reports or PREFIX.gff\n\
+                    outputs"
+                )
+                .override_usage("hyperex diff <OLD> <NEW>")
+                .arg(
+                    Arg::new("OLD")
+                        .help("run.json report or GFF3 output of the first run")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("NEW")
+                        .help("run.json report or GFF3 output of the second run")
+                        .required(true)
+                        .index(2),
+                )
+        )
+        .subcommand(
+            Command::new("extract-by-gff")
+                .about("extract the regions of a GFF3 file")
+                .long_about(
+                    "Writes the regions annotated in GFF, e.g. a PREFIX.gff output\n\
+                    with edited coordinates or the annotations of another tool,\n\
+                    from the sequences of FILE as FASTA to the standard output,\n\
+                    without searching primers. Minus strand regions are reverse\n\
+                    complemented"
+                )
+                .override_usage("hyperex extract-by-gff <GFF> <FILE>")
+                .arg(
+                    Arg::new("GFF")
+                        .help("GFF3 file of the regions")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("FILE")
+                        .help("fasta or fastq file of the sequences")
+                        .required(true)
+                        .index(2),
+                )
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("check primers for dimers and hairpins")
+                .long_about(
+                    "Reports the GC content and melting temperature of each primer\n\
+                    along with self-dimer, cross-dimer and hairpin checks of each\n\
+                    primer pair"
+                )
+                .override_usage("hyperex validate [options]")
+                .args(primer_args())
+        )
+        .subcommand(
+            Command::new("primer-dist")
+                .about("compare primers and flag near-duplicates")
+                .long_about(
+                    "Prints the pairwise edit distances of all the primers, ambiguity\n\
+                    codes matching any of the bases they stand for, and lists the\n\
+                    primers at most N edits apart, on the same strand or once\n\
+                    reverse complemented. Built-in primers are compared unless\n\
+                    primers or regions are given"
+                )
+                .override_usage("hyperex primer-dist [options]")
+                .arg(
+                    Arg::new("max_dist")
+                        .help("largest edit distance of near-duplicates")
+                        .long("max-dist")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("2")
+                )
+                .args(primer_args())
+        )
+        .subcommand(
+            Command::new("import-primers")
+                .about("convert primer lists of other tools to a TOML primers file")
+                .long_about(
+                    "Converts the primers of a QIIME mapping file, a FASTA file of\n\
+                    cutadapt adapters or a tab-separated list of [name] forward\n\
+                    and reverse primers to a TOML primers file, printed to the standard\n\
+                    output, to be given to --region. Pairs listed several times are\n\
+                    kept once"
+                )
+                .override_usage("hyperex import-primers [options] <FILE>")
+                .arg(
+                    Arg::new("import_format")
+                        .help("format of the primer list [default: detected]")
+                        .long_help(
+                            "Format of the primer list, detected from its first line\n\
+                            when not given: a QIIME mapping file starts with a\n\
+                            #SampleID header, a cutadapt FASTA file with a >"
+                        )
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["qiime", "cutadapt", "tsv"])
+                )
+                .arg(
+                    Arg::new("import_file")
+                        .help("primer list to convert")
+                        .value_name("FILE")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("coverage")
+                .about("evaluate primer coverage of a reference database")
+                .long_about(
+                    "Reports the fraction of references amplified by each primer\n\
+                    pair with 0 up to N mismatches per primer and, for each number\n\
+                    of mismatches, the taxa with the most missed references.\n\
+                    Lineages are read from SILVA/GTDB style headers unless\n\
+                    --taxonomy is given"
+                )
+                .override_usage("hyperex coverage [options] <FILE>")
+                .arg(
+                    Arg::new("FILE")
+                        .help("reference fasta or fastq file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("taxonomy")
+                        .help("file mapping sequence ids to lineages")
+                        .long("taxonomy")
+                        .value_name("FILE")
+                )
+                .arg(
+                    Arg::new("rank")
+                        .help("number of lineage ranks grouping references")
+                        .long("rank")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("2")
+                )
+                .arg(
+                    Arg::new("top")
+                        .help("number of most missed taxa listed")
+                        .long("top")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("5")
+                )
+                .args(primer_args())
+                .mut_arg("mismatch", |arg| arg.default_value("2"))
+        )
+        .subcommand(
+            Command::new("sweep")
+                .about("compare primer hits over a range of mismatches")
+                .long_about(
+                    "Searches the primers in the records with each number of\n\
+                    mismatches of a range, the records being read once, and\n\
+                    reports the primer hits and amplified records at each number\n\
+                    of mismatches with the records gained over the previous one,\n\
+                    to pick the fewest mismatches recovering the data"
+                )
+                .override_usage("hyperex sweep [options] <FILE>")
+                .arg(
+                    Arg::new("FILE")
+                        .help("input fasta or fastq file")
+                        .required(true)
+                        .index(1),
+                )
+                .args(primer_args())
+                .mut_arg("mismatch", |arg| {
+                    arg.help("range of allowed mismatches")
+                        .long_help(
+                            "Specifies the numbers of allowed mismatches as an\n\
+                            inclusive range, e.g. 0..4 for 0 up to 4 mismatches"
+                        )
+                        .value_name("N..M")
+                        .value_parser(mismatch_range)
+                        .default_value("0..4")
+                })
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("measure extraction throughput on synthetic data")
+                .long_about(
+                    "Generates random records holding the sites of the primers,\n\
+                    extracts their regions with THREADS threads in a temporary\n\
+                    directory and reports the records and megabases processed per\n\
+                    second, to estimate the runtime of a dataset on this machine.\n\
+                    The built-in regions are used unless primers or regions are\n\
+                    given"
+                )
+                .override_usage("hyperex bench [options]")
+                .arg(
+                    Arg::new("records")
+                        .help("number of synthetic records")
+                        .long("records")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10000")
+                )
+                .arg(
+                    Arg::new("length")
+                        .help("length of the synthetic records")
+                        .long("length")
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1600")
+                )
+                .arg(
+                    Arg::new("seed")
+                        .help("seed of the random records")
+                        .long("seed")
+                        .value_name("N")
+                        .value_parser(value_parser!(u64))
+                        .default_value("1")
+                )
+                .arg(
+                    Arg::new("threads")
+                        .help("number of threads")
+                        .long("threads")
+                        .short('t')
+                        .value_name("N")
+                        .value_parser(value_parser!(usize))
+                        .default_value("1")
+                )
+                .args(primer_args())
+        )
+        .subcommand(
+            Command::new("probe")
+                .about("find the binding sites of single oligos")
+                .long_about(
+                    "Lists every binding site of each probe, e.g. a FISH or qPCR\n\
+                    probe, on both strands of the sequences with its distance, and\n\
+                    the number of sequences bound by each probe to check its\n\
+                    specificity. With a panel of named probes, reports instead\n\
+                    a matrix of the distance of each probe on each sequence"
+                )
+                .override_usage("hyperex probe [options] (--probe <STR> | --panel <FILE>) <FILE>")
+                .arg(
+                    Arg::new("FILE")
+                        .help("input fasta or fastq file")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("probe")
+                        .help("probe sequence")
+                        .long_help(
+                            "Specifies a probe sequence which can contain IUPAC\n\
+                            ambiguities. Can be repeated"
+                        )
+                        .long("probe")
+                        .short('p')
+                        .required_unless_present("panel")
+                        .conflicts_with("panel")
+                        .num_args(1..)
+                        .number_of_values(1)
+                        .action(ArgAction::Append)
+                        .value_name("STR")
+                )
+                .arg(
+                    Arg::new("panel")
+                        .help("panel of named probes")
+                        .long_help(
+                            "Specifies a TSV file of probe names and sequences, one\n\
+                            probe per line, and reports a presence/absence matrix\n\
+                            giving for each sequence the distance of the best site\n\
+                            of each probe, or - when it does not bind"
+                        )
+                        .long("panel")
+                        .value_name("FILE")
+                )
+                .args(matching_args())
+        )
+}
+
+// Primer selection arguments, shared by extraction and the view subcommand
+fn primer_args() -> Vec<Arg> {
+    vec![
+        Arg::new("forward_primer")
+            .short('f')
+            .long("forward-primer")
+            .help("forward primer sequence")
+            .long_help("Specifies forward primer sequence which can contains IUPAC ambiguities")
+            .conflicts_with("region")
+            .requires("reverse_primer")
+            .num_args(1..)
+            .number_of_values(1)
+            .action(ArgAction::Append)
+            .value_name("STR"),
+        Arg::new("reverse_primer")
+            .short('r')
+            .long("reverse-primer")
+            .help("reverse primer sequence")
+            .long_help("Specifies reverse primer sequence which can contains IUPAC ambiguities")
+            .conflicts_with("region")
+            .num_args(1..)
+            .number_of_values(1)
+            .action(ArgAction::Append)
+            .value_name("STR"),
+        Arg::new("cross")
+            .long("cross")
+            .help("pair every forward primer with every reverse primer")
+            .long_help(
+                "Tests every combination of the forward and reverse primers\n\
+                given with -f and -r, e.g. to screen primer panels, instead of\n\
+                pairing them in the order they are given"
+            )
+            .requires("forward_primer")
+            .action(ArgAction::SetTrue),
+        Arg::new("region")
+            .long("region")
+            .help("hypervariable region name")
+            .long_help(format!(
+                "Specifies region name wanted or a CSV file of forward and\n\
+                reverse primers, one pair per line, or a TOML file of [[pair]]\n\
+                tables. Supported values are\n\
+                {}",
+                RegionRegistry::builtin_names().join(", ")
+            ))
+            .value_parser(region_value)
+            .num_args(1..)
+            .number_of_values(1)
+            .action(ArgAction::Append)
+            .value_name("STR"),
+        Arg::new("gene")
+            .long("gene")
+            .help("marker gene whose regions are extracted")
+            .long_help(
+                "Extracts all the built-in regions of a marker gene: 16S, 18S or\n\
+                ITS. Can be repeated to screen several genes in one pass, the\n\
+                regions being tagged with their gene"
+            )
+            .conflicts_with_all(["region", "forward_primer"])
+            .value_parser(["16S", "18S", "ITS"])
+            .num_args(1..)
+            .number_of_values(1)
+            .action(ArgAction::Append)
+            .value_name("GENE"),
+    ]
+    .into_iter()
+    .chain(matching_args())
+    .collect()
+}
+
+// Primer matching arguments, shared by primer and probe searches
+fn matching_args() -> Vec<Arg> {
+    vec![
+        Arg::new("mismatch")
+            .help("number of allowed mismatch")
+            .long_help(
+                "Specifies the number of allowed mismatch. This must be\n\
+                smaller than the length of each primer, which would\n\
+                otherwise match anywhere"
+            )
+            .long("mismatch")
+            .short('m')
+            .value_name("N")
+            .value_parser(value_parser!(u8))
+            .hide_possible_values(true)
+            .default_value("0"),
+        Arg::new("ambig_map")
+            .help("file of custom symbol equivalences")
+            .long_help(
+                "Reads symbol equivalences replacing the IUPAC ambiguity codes\n\
+                for the listed symbols. Each line is a primer symbol followed by\n\
+                the sequence symbols it matches (e.g. `R AG`). A symbol alone only\n\
+                matches itself (e.g. `N`) and a symbol followed by `*` is matched\n\
+                by any primer base when found in sequences (e.g. `- *`)"
+            )
+            .long("ambig-map")
+            .value_name("FILE"),
+        Arg::new("no_indels")
+            .help("only allow substitutions in primer hits")
+            .long_help(
+                "Restricts primer matching to substitutions, the allowed\n\
+                mismatches cannot be insertions or deletions"
+            )
+            .long("no-indels")
+            .action(ArgAction::SetTrue),
+        Arg::new("bisulfite")
+            .help("match primers on bisulfite-converted sequences")
+            .long_help(
+                "Matches primers on bisulfite-converted sequences, where the\n\
+                unmethylated C read as T: the C of the primers, and of their\n\
+                reverse complements searched for the regions of the minus\n\
+                strand, also match T. Sequences are taken to be reads of the\n\
+                converted strands, as from directional libraries"
+            )
+            .long("bisulfite")
+            .action(ArgAction::SetTrue),
+        Arg::new("no_ambig")
+            .help("match ambiguity codes literally")
+            .long_help(
+                "Disables IUPAC ambiguity expansion so that every primer symbol,\n\
+                ambiguity codes included, only matches itself"
+            )
+            .long("no-ambig")
+            .conflicts_with("ambig_map")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+// Built-in region name or file of primer pairs
+fn region_value(value: &str) -> Result<String, String> {
+    if Path::new(value).is_file() || RegionRegistry::default().contains(value) {
+        Ok(value.to_string())
+    } else {
+        Err(RegionRegistry::unknown(value))
+    }
+}
+
+// Inclusive range of numbers of mismatches, e.g. 0..4
+fn mismatch_range(value: &str) -> Result<RangeInclusive<u8>, String> {
+    let bounds = value
+        .split_once("..")
+        .map(|(start, end)| (start.parse::<u8>(), end.parse::<u8>()));
+    match bounds {
+        Some((Ok(start), Ok(end))) if start <= end => Ok(start..=end),
+        _ => Err("expected a range of mismatches, e.g. 0..4".to_string()),
+    }
+}
+
+// Fraction between 0 and 1
+fn identity_value(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(identity) if (0.0..=1.0).contains(&identity) => Ok(identity),
+        _ => Err("expected a number between 0 and 1".to_string()),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,628 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::sink::{OutputSink, SinkRegion};
+use crate::utils::{RegionMatch, Strand};
+
+use bio::io::fasta;
+
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+// Maximum uncompressed size of a BGZF block as used by htslib
+const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+// Empty BGZF block marking the end of a BAM file
+const BGZF_EOF: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00,
+    0x42, 0x43, 0x02, 0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+// 4-bit nucleotide encoding order used by BAM
+const SEQ_NT16: &[u8; 16] = b"=ACMGRSVTWYHKDBN";
+// Maximum length of a read name allowed by the BAM specification
+const MAX_NAME_LEN: usize = 254;
+
+struct BamRecord {
+    pos: usize,
+    data: Vec<u8>,
+}
+
+/// Writes extracted regions as BAM alignments against their source sequence.
+///
+/// The BAM header needs every reference before the first alignment, so
+/// only the references are kept in memory. The records of a reference are
+/// sorted by position and spilled to a temporary file when the next
+/// reference is added, then written after the header when `finish` is
+/// called. References being added in increasing order, the records end up
+/// sorted by coordinate. Names repeated by several references, e.g. the
+/// records of an input given twice or of batch inputs sharing ids, are
+/// made unique with a `.2`, `.3`, ... suffix.
+pub struct BamWriter {
+    path: String,
+    refs: Vec<(String, usize)>,
+    // Records of the last reference, not spilled yet
+    pending: Vec<BamRecord>,
+    // Encoded records of the previous references
+    spill: Option<BufWriter<File>>,
+    // Reference of the record being written as a sink, once added
+    current: Option<usize>,
+}
+
+impl BamWriter {
+    pub fn new(path: &str) -> Self {
+        BamWriter {
+            path: path.to_string(),
+            refs: Vec::new(),
+            pending: Vec::new(),
+            spill: None,
+            current: None,
+        }
+    }
+
+    /// Register a source sequence and return its reference index
+    pub fn add_reference(
+        &mut self,
+        name: &str,
+        len: usize,
+    ) -> io::Result<usize> {
+        self.flush_pending()?;
+        self.refs.push((name.to_string(), len));
+        Ok(self.refs.len() - 1)
+    }
+
+    /// Add an extracted region aligned to the reference `ref_id`.
+    ///
+    /// The extraction is an exact slice of the reference so the CIGAR is a
+    /// single match operation; the primers and their edit distances are
+    /// kept in the XF/XR and YF/YR tags.
+    pub fn add_match(
+        &mut self,
+        ref_id: usize,
+        record_id: &str,
+        region_match: &RegionMatch,
+        seq: &[u8],
+    ) {
+        let suffix = if region_match.region.is_empty() {
+            format!(":{}-{}", region_match.start, region_match.end)
+        } else {
+            format!(":{}", region_match.region)
+        };
+        let name = read_name(record_id, &suffix);
+        // Regions of the minus strand are reverse complemented
+        let flag = match region_match.strand {
+            Strand::Plus => 0,
+            Strand::Minus => 0x10,
+        };
+
+        let mut tags = Vec::new();
+        if !region_match.region.is_empty() {
+            push_string_tag(&mut tags, b"XG", &region_match.region);
+        }
+        push_string_tag(&mut tags, b"XF", &region_match.forward_primer);
+        push_string_tag(&mut tags, b"XR", &region_match.reverse_primer);
+        push_int_tag(&mut tags, b"YF", region_match.forward_dist as i32);
+        push_int_tag(&mut tags, b"YR", region_match.reverse_dist as i32);
+
+        self.pending.push(BamRecord {
+            pos: region_match.start,
+            data: encode_record(
+                ref_id,
+                region_match.start,
+                flag,
+                &name,
+                &seq[region_match.start..region_match.end],
+                &tags,
+            ),
+        });
+    }
+
+    // Sort the records of the last reference and append them to the spill
+    fn flush_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let spill = match self.spill.as_mut() {
+            Some(spill) => spill,
+            None => self.spill.insert(BufWriter::new(tempfile::tempfile()?)),
+        };
+        self.pending.sort_by_key(|r| r.pos);
+        for record in self.pending.drain(..) {
+            spill.write_all(&record.data)?;
+        }
+
+        Ok(())
+    }
+
+    // Spilled records, read from their start
+    fn into_spilled(mut self) -> io::Result<Option<BufReader<File>>> {
+        self.flush_pending()?;
+        match self.spill {
+            Some(spill) => {
+                let mut file =
+                    spill.into_inner().map_err(|e| e.into_error())?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Some(BufReader::new(file)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Move the references and records of `other` into this writer, its
+    /// references coming after the ones of this writer
+    pub fn merge(&mut self, other: BamWriter) -> io::Result<()> {
+        self.flush_pending()?;
+        let offset = self.refs.len();
+        self.refs.extend(other.refs.iter().cloned());
+        let mut spilled = match other.into_spilled()? {
+            Some(spilled) => spilled,
+            None => return Ok(()),
+        };
+        let spill = match self.spill.as_mut() {
+            Some(spill) => spill,
+            None => self.spill.insert(BufWriter::new(tempfile::tempfile()?)),
+        };
+        while let Some(mut data) = read_record(&mut spilled)? {
+            // refID follows the block size in the encoded record
+            let ref_id =
+                i32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+            data[4..8].copy_from_slice(&(ref_id + offset as i32).to_le_bytes());
+            spill.write_all(&data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write header and coordinate-sorted records to disk
+    pub fn finish(self) -> anyhow::Result<()> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(b"BAM\x01");
+
+        let refs = unique_names(&self.refs);
+        let mut text = String::from("@HD\tVN:1.6\tSO:coordinate\n");
+        for (name, len) in refs.iter() {
+            text.push_str(&format!("@SQ\tSN:{}\tLN:{}\n", name, len));
+        }
+        text.push_str(&format!(
+            "@PG\tID:hyperex\tPN:hyperex\tVN:{}\n",
+            env!("CARGO_PKG_VERSION")
+        ));
+        raw.extend_from_slice(&(text.len() as i32).to_le_bytes());
+        raw.extend_from_slice(text.as_bytes());
+
+        raw.extend_from_slice(&(refs.len() as i32).to_le_bytes());
+        for (name, len) in refs.iter() {
+            raw.extend_from_slice(&(name.len() as i32 + 1).to_le_bytes());
+            raw.extend_from_slice(name.as_bytes());
+            raw.push(0);
+            raw.extend_from_slice(&(*len as i32).to_le_bytes());
+        }
+
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        let full = raw.len() - raw.len() % BGZF_BLOCK_SIZE;
+        for chunk in raw[..full].chunks(BGZF_BLOCK_SIZE) {
+            write_bgzf_block(&mut writer, chunk)?;
+        }
+        raw.drain(..full);
+        if let Some(mut spilled) = self.into_spilled()? {
+            // Fill the last block of the header and the next ones with the
+            // spilled records
+            loop {
+                let start = raw.len();
+                raw.resize(BGZF_BLOCK_SIZE, 0);
+                let n = read_full(&mut spilled, &mut raw[start..])?;
+                raw.truncate(start + n);
+                if raw.len() < BGZF_BLOCK_SIZE {
+                    break;
+                }
+                write_bgzf_block(&mut writer, &raw)?;
+                raw.clear();
+            }
+        }
+        if !raw.is_empty() {
+            write_bgzf_block(&mut writer, &raw)?;
+        }
+        writer.write_all(&BGZF_EOF)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+// References with the names already taken suffixed by their occurrence
+fn unique_names(refs: &[(String, usize)]) -> Vec<(String, usize)> {
+    let mut taken = HashSet::new();
+    refs.iter()
+        .map(|(name, len)| {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while !taken.insert(unique.clone()) {
+                n += 1;
+                unique = format!("{}.{}", name, n);
+            }
+            (unique, *len)
+        })
+        .collect()
+}
+
+// Name of the read extracted from a record, the record id being truncated
+// so that the name fits in the length allowed by BAM
+fn read_name(record_id: &str, suffix: &str) -> String {
+    let mut len = MAX_NAME_LEN
+        .saturating_sub(suffix.len())
+        .min(record_id.len());
+    while !record_id.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut name = format!("{}{}", &record_id[..len], suffix);
+    let mut len = name.len().min(MAX_NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    name.truncate(len);
+    name
+}
+
+// Next encoded record, none at the end of the records
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut data = vec![0u8; 4];
+    match read_full(reader, &mut data)? {
+        0 => return Ok(None),
+        4 => (),
+        _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+    let size = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    data.resize(4 + size as usize, 0);
+    reader.read_exact(&mut data[4..])?;
+
+    Ok(Some(data))
+}
+
+// Fill buf as much as possible, returning the number of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+fn push_string_tag(tags: &mut Vec<u8>, tag: &[u8; 2], value: &str) {
+    tags.extend_from_slice(tag);
+    tags.push(b'Z');
+    tags.extend_from_slice(value.as_bytes());
+    tags.push(0);
+}
+
+fn push_int_tag(tags: &mut Vec<u8>, tag: &[u8; 2], value: i32) {
+    tags.extend_from_slice(tag);
+    tags.push(b'i');
+    tags.extend_from_slice(&value.to_le_bytes());
+}
+
+fn encode_record(
+    ref_id: usize,
+    pos: usize,
+    flag: u16,
+    name: &str,
+    seq: &[u8],
+    tags: &[u8],
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(ref_id as i32).to_le_bytes());
+    body.extend_from_slice(&(pos as i32).to_le_bytes());
+    body.push(name.len() as u8 + 1);
+    // Mapping quality is not available
+    body.push(255);
+    body.extend_from_slice(&reg2bin(pos, pos + seq.len()).to_le_bytes());
+    // One CIGAR operation
+    body.extend_from_slice(&1u16.to_le_bytes());
+    // Flag: mapped, on the strand of the region
+    body.extend_from_slice(&flag.to_le_bytes());
+    body.extend_from_slice(&(seq.len() as i32).to_le_bytes());
+    // No mate
+    body.extend_from_slice(&(-1i32).to_le_bytes());
+    body.extend_from_slice(&(-1i32).to_le_bytes());
+    body.extend_from_slice(&0i32.to_le_bytes());
+    body.extend_from_slice(name.as_bytes());
+    body.push(0);
+    // CIGAR <len>M
+    body.extend_from_slice(&((seq.len() as u32) << 4).to_le_bytes());
+    body.extend_from_slice(&encode_seq(seq));
+    // Base qualities are not available
+    body.extend_from_slice(&vec![0xff; seq.len()]);
+    body.extend_from_slice(tags);
+
+    let mut data = Vec::with_capacity(body.len() + 4);
+    data.extend_from_slice(&(body.len() as i32).to_le_bytes());
+    data.extend_from_slice(&body);
+    data
+}
+
+fn encode_seq(seq: &[u8]) -> Vec<u8> {
+    let code = |base: u8| -> u8 {
+        // BAM has no U, RNA is stored as its DNA equivalent
+        let base = match base.to_ascii_uppercase() {
+            b'U' => b'T',
+            b => b,
+        };
+        SEQ_NT16.iter().position(|&x| x == base).unwrap_or(15) as u8
+    };
+
+    seq.chunks(2)
+        .map(|pair| {
+            let high = code(pair[0]) << 4;
+            match pair.get(1) {
+                Some(&low) => high | code(low),
+                None => high,
+            }
+        })
+        .collect()
+}
+
+// Compute the UCSC bin of a 0-based, end-exclusive interval. Offsets are
+// the first bin of each level ((1 << 3k) - 1) / 7
+fn reg2bin(beg: usize, end: usize) -> u16 {
+    let end = end.saturating_sub(1);
+    if beg >> 14 == end >> 14 {
+        return (4681 + (beg >> 14)) as u16;
+    }
+    if beg >> 17 == end >> 17 {
+        return (585 + (beg >> 17)) as u16;
+    }
+    if beg >> 20 == end >> 20 {
+        return (73 + (beg >> 20)) as u16;
+    }
+    if beg >> 23 == end >> 23 {
+        return (9 + (beg >> 23)) as u16;
+    }
+    if beg >> 26 == end >> 26 {
+        return (1 + (beg >> 26)) as u16;
+    }
+    0
+}
+
+fn write_bgzf_block<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    // Total block size minus one: 18 bytes header, 8 bytes footer
+    let bsize = (compressed.len() + 25) as u16;
+    out.write_all(&[
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00,
+        b'B', b'C', 0x02, 0x00,
+    ])?;
+    out.write_all(&bsize.to_le_bytes())?;
+    out.write_all(&compressed)?;
+    out.write_all(&crc.sum().to_le_bytes())?;
+    out.write_all(&(data.len() as u32).to_le_bytes())?;
+
+    Ok(())
+}
+
+impl OutputSink for BamWriter {
+    fn start_record(&mut self, _record: &fasta::Record) -> anyhow::Result<()> {
+        self.current = None;
+        Ok(())
+    }
+
+    // The record is added as a reference on its first region
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let (record, seq) = (found.record, found.record.seq());
+        let ref_id = match self.current {
+            Some(ref_id) => ref_id,
+            None => {
+                let ref_id = self.add_reference(record.id(), seq.len())?;
+                *self.current.insert(ref_id)
+            }
+        };
+        self.add_match(ref_id, record.id(), found.region, seq);
+        Ok(())
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Confidence;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_reg2bin() {
+        assert_eq!(reg2bin(0, 100), 4681);
+        assert_eq!(reg2bin(16380, 16390), 585);
+        assert_eq!(reg2bin(0, 1 << 29), 0);
+    }
+
+    #[test]
+    fn test_encode_seq() {
+        assert_eq!(encode_seq(b"ACGT"), vec![0x12, 0x48]);
+        assert_eq!(encode_seq(b"acgun"), vec![0x12, 0x48, 0xf0]);
+    }
+
+    // Encoded records of a BAM file
+    fn read_records(path: &str) -> Vec<Vec<u8>> {
+        let mut content = Vec::new();
+        MultiGzDecoder::new(File::open(path).unwrap())
+            .read_to_end(&mut content)
+            .unwrap();
+        let int = |at: usize| {
+            let bytes = &content[at..at + 4];
+            i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                as usize
+        };
+        let mut at = 8 + int(4);
+        let n_ref = int(at);
+        at += 4;
+        for _ in 0..n_ref {
+            at += 8 + int(at);
+        }
+        let mut reader = &content[at..];
+        let mut records = Vec::new();
+        while let Some(data) = read_record(&mut reader).unwrap() {
+            records.push(data);
+        }
+        records
+    }
+
+    fn region_match(region: &str, start: usize, end: usize) -> RegionMatch {
+        RegionMatch {
+            region: region.to_string(),
+            forward_primer: "AC".to_string(),
+            reverse_primer: "GT".to_string(),
+            start,
+            end,
+            forward_dist: 0,
+            reverse_dist: 0,
+            strand: Strand::Plus,
+            confidence: Confidence::High,
+        }
+    }
+
+    #[test]
+    fn test_bam_merge() {
+        let tmpfile = NamedTempFile::new().expect("Cannot create temp file");
+        let path = tmpfile.path().to_str().unwrap();
+
+        let mut first = BamWriter::new(path);
+        let ref_id = first.add_reference("seq1", 8).unwrap();
+        first.add_match(ref_id, "seq1", &region_match("v4", 4, 8), b"ACGTACGT");
+        first.add_match(ref_id, "seq1", &region_match("v3", 0, 4), b"ACGTACGT");
+        let mut second = BamWriter::new("");
+        let ref_id = second.add_reference("seq2", 4).unwrap();
+        second.add_match(ref_id, "seq2", &region_match("v4", 0, 4), b"ACGT");
+
+        first.merge(second).unwrap();
+        assert_eq!(first.refs.len(), 2);
+        first.finish().expect("Cannot write BAM file");
+
+        // Sorted by reference then position, refID following the size
+        let records = read_records(path);
+        let keys = records
+            .iter()
+            .map(|data| (&data[4..8], &data[8..12]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                (&0i32.to_le_bytes()[..], &0i32.to_le_bytes()[..]),
+                (&0i32.to_le_bytes()[..], &4i32.to_le_bytes()[..]),
+                (&1i32.to_le_bytes()[..], &0i32.to_le_bytes()[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bam_long_name() {
+        let tmpfile = NamedTempFile::new().expect("Cannot create temp file");
+        let path = tmpfile.path().to_str().unwrap();
+        let record_id = "é".repeat(150);
+
+        let mut writer = BamWriter::new(path);
+        let ref_id = writer.add_reference(&record_id, 4).unwrap();
+        writer.add_match(
+            ref_id,
+            &record_id,
+            &region_match("v4", 0, 4),
+            b"ACGT",
+        );
+        writer.finish().expect("Cannot write BAM file");
+
+        // l_read_name, NUL included, follows refID and pos
+        let records = read_records(path);
+        let len = records[0][12] as usize;
+        assert!(len <= MAX_NAME_LEN + 1);
+        let name = std::str::from_utf8(&records[0][36..36 + len - 1]).unwrap();
+        assert!(name.ends_with("é:v4"));
+        assert_eq!(read_name("seq1", ":v4"), "seq1:v4");
+    }
+
+    #[test]
+    fn test_bam_repeated_reference() {
+        let tmpfile = NamedTempFile::new().expect("Cannot create temp file");
+        let path = tmpfile.path().to_str().unwrap();
+
+        let mut first = BamWriter::new(path);
+        first.add_reference("seq1", 8).unwrap();
+        first.add_reference("seq1", 8).unwrap();
+        let mut second = BamWriter::new("");
+        second.add_reference("seq1.2", 4).unwrap();
+        let ref_id = second.add_reference("seq1", 4).unwrap();
+        let mut minus = region_match("v4", 0, 4);
+        minus.strand = Strand::Minus;
+        second.add_match(ref_id, "seq1", &minus, b"ACGT");
+        first.merge(second).unwrap();
+        first.finish().expect("Cannot write BAM file");
+
+        let mut content = Vec::new();
+        MultiGzDecoder::new(File::open(path).unwrap())
+            .read_to_end(&mut content)
+            .unwrap();
+        let header = String::from_utf8_lossy(&content);
+        let names = header
+            .lines()
+            .filter_map(|line| line.strip_prefix("@SQ\tSN:"))
+            .map(|line| line.split('\t').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["seq1", "seq1.2", "seq1.2.2", "seq1.3"]);
+        // Flag, after bin_mq_nl and n_cigar_op, marks the reverse strand
+        let records = read_records(path);
+        assert_eq!(records[0][4..8], 3i32.to_le_bytes());
+        assert_eq!(records[0][18..20], 0x10u16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_bam_writer() {
+        let tmpfile = NamedTempFile::new().expect("Cannot create temp file");
+        let path = tmpfile.path().to_str().unwrap();
+
+        let mut writer = BamWriter::new(path);
+        let ref_id = writer.add_reference("seq1", 8).unwrap();
+        writer.add_match(
+            ref_id,
+            "seq1",
+            &RegionMatch {
+                region: "v4".to_string(),
+                forward_primer: "AC".to_string(),
+                reverse_primer: "GT".to_string(),
+                start: 2,
+                end: 6,
+                forward_dist: 0,
+                reverse_dist: 1,
+                strand: Strand::Plus,
+                confidence: Confidence::High,
+            },
+            b"TTACGTTT",
+        );
+        writer.finish().expect("Cannot write BAM file");
+
+        let mut content = Vec::new();
+        MultiGzDecoder::new(File::open(path).unwrap())
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(&content[..4], b"BAM\x01");
+        assert!(content
+            .windows(b"SN:seq1\tLN:8".len())
+            .any(|w| w == b"SN:seq1\tLN:8"));
+        assert!(content.windows(b"seq1:v4".len()).any(|w| w == b"seq1:v4"));
+    }
+}
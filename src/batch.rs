@@ -0,0 +1,310 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::ids::GffIds;
+use crate::sink::{SharedSinks, Sinks};
+use crate::utils::{
+    self, Config, ExtraOutputs, OnMatch, RegionMatch, RunSummary,
+};
+
+use anyhow::Context;
+use bio::io::fasta;
+use log::info;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type FileResult = (RunSummary, ExtraOutputs);
+
+/// Process several input files concurrently on up to `threads` threads.
+///
+/// Each file is extracted with its own writers into temporary
+/// `<prefix>.part<N>` outputs which are then concatenated, in input order,
+/// into the final `<prefix>.fa` and `<prefix>.gff`. The returned summary
+/// merges the counts of all files. Calls to `on_match` and to the sinks of
+/// `extras` are serialized but those for regions of different files are
+/// interleaved.
+pub fn run_batch(
+    files: &[String],
+    threads: usize,
+    primers: &[Vec<String>],
+    prefix: &str,
+    config: &Config,
+    extras: &mut ExtraOutputs,
+    on_match: Option<&mut OnMatch>,
+) -> anyhow::Result<RunSummary> {
+    let part_prefixes = (0..files.len())
+        .map(|i| format!("{}.part{}", prefix, i))
+        .collect::<Vec<_>>();
+
+    let on_match = Mutex::new(on_match);
+    // Parts are always new, only the merged outputs are appended to, the
+    // records already in them being skipped
+    let seen_ids = match &config.seen_ids {
+        None if config.append => Some(Arc::new(utils::read_seen_ids(prefix)?)),
+        seen_ids => seen_ids.clone(),
+    };
+    // Inputs rather than their blocks are spread over the threads
+    let part_config = Config {
+        append: false,
+        seen_ids,
+        decompress_threads: 1,
+        ..config.clone()
+    };
+    // Sinks of the caller are shared by the files, the other outputs being
+    // collected per file
+    let sinks = Arc::new(Mutex::new(mem::take(&mut extras.sinks)));
+    let results = process_files(
+        files,
+        threads,
+        primers,
+        &part_prefixes,
+        &part_config,
+        (&Mutex::new(extras.empty_like()), &sinks, &on_match),
+    );
+    let merged = results.and_then(|results| {
+        append_outputs(&part_prefixes, prefix, config.append)?;
+        Ok(results)
+    });
+    remove_parts(&part_prefixes);
+    extras.sinks = mem::take(&mut *sinks.lock().unwrap());
+
+    let mut summary = RunSummary::default();
+    for (file, (file_summary, file_extras)) in files.iter().zip(merged?) {
+        file_summary.log(file);
+        summary.merge(&file_summary);
+        extras.merge(file_extras)?;
+    }
+
+    Ok(summary)
+}
+
+fn process_files(
+    files: &[String],
+    threads: usize,
+    primers: &[Vec<String>],
+    part_prefixes: &[String],
+    config: &Config,
+    (extras, sinks, on_match): (
+        &Mutex<ExtraOutputs>,
+        &Arc<Mutex<Sinks>>,
+        &Mutex<Option<&mut OnMatch>>,
+    ),
+) -> anyhow::Result<Vec<FileResult>> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<FileResult>>> =
+        Mutex::new((0..files.len()).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        let workers = (0..threads.clamp(1, files.len()))
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<()> {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        if i >= files.len() {
+                            return Ok(());
+                        }
+                        info!("Processing {}", files[i]);
+                        let mut file_extras =
+                            extras.lock().unwrap().empty_like();
+                        file_extras
+                            .sinks
+                            .push(Box::new(SharedSinks(Arc::clone(sinks))));
+                        // Forward matches to the shared callback
+                        let mut forward =
+                            |m: &RegionMatch, record: &fasta::Record| {
+                                let mut on_match = on_match.lock().unwrap();
+                                if let Some(on_match) = on_match.as_mut() {
+                                    on_match(m, record);
+                                }
+                            };
+                        let summary = utils::get_hypervar_regions(
+                            &files[i],
+                            primers,
+                            &part_prefixes[i],
+                            config,
+                            &mut file_extras,
+                            Some(&mut forward),
+                        )
+                        .with_context(|| {
+                            format!("Cannot process {}", files[i])
+                        })?;
+                        results.lock().unwrap()[i] =
+                            Some((summary, file_extras));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("worker panicked"))
+    })?;
+
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every file is processed"))
+        .collect())
+}
+
+// Part outputs with the header line of each, if any
+const OUTPUTS: [(&str, Option<&str>); 6] = [
+    ("fa", None),
+    ("gff", Some(utils::GFF_HEADER)),
+    ("tsv", Some(utils::RECORDS_TSV_HEADER)),
+    ("hits.tsv", Some(utils::HITS_TSV_HEADER)),
+    ("operons.tsv", Some(utils::OPERONS_TSV_HEADER)),
+    ("skipped.tsv", Some(utils::SKIPPED_TSV_HEADER)),
+];
+
+/// Concatenate the outputs written under each part prefix into
+/// `<prefix>.fa`, `<prefix>.gff`, `<prefix>.tsv`, `<prefix>.hits.tsv`,
+/// `<prefix>.operons.tsv` and `<prefix>.skipped.tsv`, appended to existing
+/// files with the same headers with `append`. GFF3 records whose id is
+/// already used are renamed. Disabled outputs, without parts, are not
+/// created.
+pub fn append_outputs(
+    part_prefixes: &[String],
+    prefix: &str,
+    append: bool,
+) -> anyhow::Result<()> {
+    for (ext, header) in OUTPUTS.iter() {
+        let parts = part_prefixes
+            .iter()
+            .map(|part| format!("{}.{}", part, ext))
+            .filter(|path| Path::new(path).exists())
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            continue;
+        }
+        let path = format!("{}.{}", prefix, ext);
+        let mut gff_ids = match *ext {
+            "gff" if append => Some(GffIds::read(&path)?),
+            "gff" => Some(GffIds::default()),
+            _ => None,
+        };
+        let mut writer =
+            io::BufWriter::new(utils::open_output(&path, *header, append)?);
+
+        match header {
+            None => {
+                for part in parts {
+                    io::copy(&mut File::open(part)?, &mut writer)?;
+                }
+            }
+            Some(header) => {
+                for part in parts {
+                    let reader = BufReader::new(File::open(part)?);
+                    for line in reader.lines().skip(header.lines().count()) {
+                        let line = line?;
+                        match gff_ids.as_mut() {
+                            Some(ids) => writeln!(
+                                writer,
+                                "{}",
+                                ids.reserve_record(&line)
+                            )?,
+                            None => writeln!(writer, "{}", line)?,
+                        }
+                    }
+                }
+            }
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Remove the outputs written under the part prefixes
+pub fn remove_parts(part_prefixes: &[String]) {
+    for part in part_prefixes {
+        for (ext, _) in OUTPUTS.iter() {
+            let path = format!("{}.{}", part, ext);
+            if Path::new(&path).exists() {
+                // Leftover parts are harmless, do not mask the real outcome
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_run_batch() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let files =
+            vec!["tests/test.fa".to_string(), "tests/test.fa.gz".to_string()];
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+
+        let summary = run_batch(
+            &files,
+            2,
+            &primers,
+            prefix,
+            &Config::default(),
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.sequences, 2);
+        assert_eq!(summary.regions["v3v4"], 2);
+
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert_eq!(gff.matches("##gff-version").count(), 1);
+        assert_eq!(gff.lines().count(), 4);
+        // The same record in both files keeps unique ids
+        let lines = gff.lines().collect::<Vec<_>>();
+        assert!(lines[2]
+            .contains("\tID=Allorhizobium_borbori__DN316__EF125187.v3v4;"));
+        assert!(lines[3]
+            .contains("\tID=Allorhizobium_borbori__DN316__EF125187.v3v4.2;"));
+        let tsv = fs::read_to_string(format!("{}.tsv", prefix)).unwrap();
+        assert_eq!(tsv.lines().count(), 3);
+        assert!(!Path::new(&format!("{}.part0.fa", prefix)).exists());
+    }
+
+    #[test]
+    fn test_run_batch_disabled_outputs() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let files =
+            vec!["tests/test.fa".to_string(), "tests/test.fa.gz".to_string()];
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+        let config = Config {
+            no_fasta: true,
+            no_gff: true,
+            ..Default::default()
+        };
+
+        run_batch(
+            &files,
+            2,
+            &primers,
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert!(!Path::new(&format!("{}.fa", prefix)).exists());
+        assert!(!Path::new(&format!("{}.gff", prefix)).exists());
+        assert!(Path::new(&format!("{}.tsv", prefix)).exists());
+    }
+}
@@ -0,0 +1,221 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::matcher::AmbigMap;
+use crate::rng::Rng;
+use crate::utils;
+use crate::Extractor;
+
+use anyhow::{anyhow, Context};
+use bio::io::fasta;
+
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Bases drawn for random positions and degenerate primer bases
+const BASES: &[u8] = b"ACGT";
+
+/// Synthetic dataset of a benchmark
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Workload {
+    pub records: usize,
+    // Length of every record
+    pub length: usize,
+    pub seed: u64,
+}
+
+/// Throughput of a benchmark run
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub records: usize,
+    pub bases: usize,
+    pub threads: usize,
+    pub extracted: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn records_per_sec(&self) -> f64 {
+        self.records as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn mb_per_sec(&self) -> f64 {
+        self.bases as f64 / 1e6 / self.elapsed.as_secs_f64()
+    }
+}
+
+// Variant of a primer with each degenerate base resolved to one of the
+// bases it stands for
+fn primer_variant(primer: &str, ambigs: &AmbigMap, rng: &mut Rng) -> Vec<u8> {
+    primer
+        .bytes()
+        .map(|symbol| {
+            let matched = ambigs.matched_by(symbol);
+            let variants = BASES
+                .iter()
+                .filter(|&&b| matched[b as usize])
+                .collect::<Vec<_>>();
+            match variants.len() {
+                0 => symbol,
+                n => *variants[rng.below(n)],
+            }
+        })
+        .collect()
+}
+
+/// Random records of the workload, each holding the sites of a primer pair,
+/// taken in turn, so that every record yields a region. Sites are centred
+/// and as far apart as in E. coli when known and fitting in the records, a
+/// quarter and three quarters into the records otherwise.
+pub fn synthetic_records(
+    primers: &[Vec<String>],
+    workload: &Workload,
+) -> anyhow::Result<Vec<fasta::Record>> {
+    let longest = primers
+        .iter()
+        .map(|pair| pair[0].len() + pair[1].len())
+        .max()
+        .ok_or_else(|| anyhow!("No primers to benchmark"))?;
+    if workload.length < 2 * longest {
+        return Err(anyhow!(
+            "Records of {} bp are too short for primers of {} bp",
+            workload.length,
+            longest
+        ));
+    }
+
+    let ambigs = AmbigMap::default();
+    let mut rng = Rng::new(workload.seed);
+    let records = (0..workload.records)
+        .map(|i| {
+            let pair = &primers[i % primers.len()];
+            let mut seq = (0..workload.length)
+                .map(|_| BASES[rng.below(BASES.len())])
+                .collect::<Vec<_>>();
+            let forward = primer_variant(&pair[0], &ambigs, &mut rng);
+            let reverse = utils::to_reverse_complement(
+                &String::from_utf8_lossy(&primer_variant(
+                    &pair[1], &ambigs, &mut rng,
+                )),
+                "dna",
+            );
+            let (start, end) = match utils::ecoli_amplicon_length(pair) {
+                Some(amplicon)
+                    if amplicon <= workload.length
+                        && amplicon >= forward.len() + reverse.len() =>
+                {
+                    let start = (workload.length - amplicon) / 2;
+                    (start, start + amplicon)
+                }
+                _ => (workload.length / 4, workload.length * 3 / 4),
+            };
+            seq[start..start + forward.len()].copy_from_slice(&forward);
+            seq[end - reverse.len()..end].copy_from_slice(reverse.as_bytes());
+            fasta::Record::with_attrs(
+                &format!("synthetic_{}", i + 1),
+                None,
+                &seq,
+            )
+        })
+        .collect();
+
+    Ok(records)
+}
+
+/// Time the extraction of `records` with `extractor`, split in one input
+/// file per thread, in the directory `dir`. Writing the inputs is left out
+/// of the timing.
+pub fn run(
+    extractor: &Extractor,
+    records: &[fasta::Record],
+    threads: usize,
+    dir: &Path,
+) -> anyhow::Result<BenchResult> {
+    let threads = threads.max(1);
+    let per_file = records.len().div_ceil(threads).max(1);
+    let mut files = Vec::new();
+    for (i, chunk) in records.chunks(per_file).enumerate() {
+        let path = dir.join(format!("bench_{}.fa", i + 1));
+        let mut writer = fasta::Writer::to_file(&path)
+            .with_context(|| format!("Cannot write {}", path.display()))?;
+        for record in chunk {
+            writer.write_record(record)?;
+        }
+        writer.flush()?;
+        files.push(path.to_string_lossy().into_owned());
+    }
+    let prefix = dir.join("bench").to_string_lossy().into_owned();
+
+    let start = Instant::now();
+    let summary =
+        extractor.run(&files, &prefix, &mut utils::ExtraOutputs::default())?;
+    let elapsed = start.elapsed();
+
+    Ok(BenchResult {
+        records: records.len(),
+        bases: records.iter().map(|record| record.seq().len()).sum(),
+        threads,
+        extracted: summary.extracted,
+        elapsed,
+    })
+}
+
+/// Throughput of a benchmark, to estimate the runtime of a dataset
+pub fn report(result: &BenchResult) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "records\t{}", result.records);
+    let _ = writeln!(out, "bases\t{}", result.bases);
+    let _ = writeln!(out, "threads\t{}", result.threads);
+    let _ = writeln!(out, "regions\t{}", result.extracted);
+    let _ = writeln!(out, "seconds\t{:.3}", result.elapsed.as_secs_f64());
+    let _ = writeln!(out, "records/sec\t{:.0}", result.records_per_sec());
+    let _ = writeln!(out, "MB/sec\t{:.2}", result.mb_per_sec());
+
+    out
+}
+
+/// Remove the inputs and outputs of a benchmark run in `dir`
+pub fn clean(dir: &Path) {
+    let _ = fs::remove_dir_all(dir);
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        let primers = vec![vec![
+            "GTGYCAGCMGCCGCGGTAA".to_string(),
+            "GGACTACNVGGGTWTCTAAT".to_string(),
+        ]];
+        let workload = Workload {
+            records: 6,
+            length: 400,
+            seed: 1,
+        };
+        let records = synthetic_records(&primers, &workload).unwrap();
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0].seq().len(), 400);
+        assert_eq!(synthetic_records(&primers, &workload).unwrap(), records);
+        let short = Workload {
+            length: 60,
+            ..workload
+        };
+        assert!(synthetic_records(&primers, &short).is_err());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let extractor =
+            Extractor::builder().primer_pairs(&primers).build().unwrap();
+        let result = run(&extractor, &records, 2, dir.path()).unwrap();
+        assert_eq!(result.extracted, 6);
+        assert_eq!(result.bases, 2400);
+        assert!(report(&result).starts_with("records\t6\nbases\t2400\n"));
+    }
+}
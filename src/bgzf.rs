@@ -0,0 +1,323 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// Blocks queued for decompression per thread, read ahead of the records
+const BLOCKS_PER_THREAD: usize = 4;
+// Gzip magic, deflate method and FEXTRA flag of BGZF blocks
+const MAGIC: [u8; 3] = [0x1f, 0x8b, 0x08];
+const FEXTRA: u8 = 0x04;
+
+// Deflated data of a block, with the CRC32 and size of the inflated data
+struct Block {
+    cdata: Vec<u8>,
+    crc: u32,
+    size: usize,
+}
+
+/// Is `path` a BGZF file, as written by bgzip, whose blocks can be
+/// decompressed independently?
+pub fn is_bgzf(path: &str) -> io::Result<bool> {
+    let mut header = [0u8; 16];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+        Err(e) => return Err(e),
+    }
+
+    Ok(header[..3] == MAGIC
+        && header[3] & FEXTRA != 0
+        && &header[12..14] == b"BC")
+}
+
+// Threads inflating the blocks sent to them, each block being sent back
+// with its number in the file
+struct Pool {
+    jobs: Option<Sender<(usize, Block)>>,
+    results: Receiver<(usize, io::Result<Vec<u8>>)>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Pool {
+    fn new(threads: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<(usize, Block)>();
+        let (done, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                let done = done.clone();
+                thread::spawn(move || loop {
+                    let job = queue.lock().expect("worker panicked").recv();
+                    let (number, block) = match job {
+                        Ok(job) => job,
+                        // The reader is dropped
+                        Err(_) => break,
+                    };
+                    if done.send((number, inflate(&block))).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Pool {
+            jobs: Some(jobs),
+            results,
+            workers,
+        }
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        // Workers stop once the queue is closed and empty
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Reader of a BGZF file decompressing its blocks on `threads` threads
+/// started with the reader. Blocks are read ahead and queued for the
+/// threads, the decompressed data being returned in order as the records
+/// are read. Only decompression is parallel: records span blocks and the
+/// outputs follow the input order, so parsing and primer search read the
+/// data as it comes on one thread, inputs being searched concurrently by
+/// [`batch`](crate::batch) instead.
+pub struct BgzfReader<R> {
+    inner: R,
+    pool: Pool,
+    // Number of the next block to return and of blocks sent to the pool
+    next: usize,
+    sent: usize,
+    // Blocks decompressed before some of the previous ones
+    waiting: BTreeMap<usize, io::Result<Vec<u8>>>,
+    // Decompressed blocks not read yet, the first one being read
+    blocks: VecDeque<Vec<u8>>,
+    // Position in the first block
+    pos: usize,
+    eof: bool,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub fn new(inner: R, threads: usize) -> Self {
+        let threads = threads.max(1);
+        BgzfReader {
+            inner,
+            pool: Pool::new(threads),
+            next: 0,
+            sent: 0,
+            waiting: BTreeMap::new(),
+            blocks: VecDeque::new(),
+            pos: 0,
+            eof: false,
+        }
+    }
+
+    // Next compressed block, none at the end of the file
+    fn read_block(&mut self) -> io::Result<Option<Block>> {
+        let mut header = [0u8; 12];
+        match self.inner.read(&mut header[..1])? {
+            0 => return Ok(None),
+            _ => self.inner.read_exact(&mut header[1..])?,
+        }
+        if header[..3] != MAGIC || header[3] & FEXTRA == 0 {
+            return Err(invalid("not a BGZF block"));
+        }
+        let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let mut extra = vec![0u8; xlen];
+        self.inner.read_exact(&mut extra)?;
+
+        // Size of the block minus one, from the BC subfield
+        let mut bsize = None;
+        let mut i = 0;
+        while i + 4 <= extra.len() {
+            let len = u16::from_le_bytes([extra[i + 2], extra[i + 3]]);
+            if &extra[i..i + 2] == b"BC" && len == 2 && i + 6 <= extra.len() {
+                let size = [extra[i + 4], extra[i + 5]];
+                bsize = Some(u16::from_le_bytes(size) as usize);
+            }
+            i += 4 + len as usize;
+        }
+        let bsize = bsize.ok_or_else(|| invalid("missing block size"))?;
+        let cdata_len = (bsize + 1)
+            .checked_sub(header.len() + xlen + 8)
+            .ok_or_else(|| invalid("invalid block size"))?;
+
+        let mut cdata = vec![0u8; cdata_len];
+        self.inner.read_exact(&mut cdata)?;
+        let mut trailer = [0u8; 8];
+        self.inner.read_exact(&mut trailer)?;
+
+        Ok(Some(Block {
+            cdata,
+            crc: u32::from_le_bytes([
+                trailer[0], trailer[1], trailer[2], trailer[3],
+            ]),
+            size: u32::from_le_bytes([
+                trailer[4], trailer[5], trailer[6], trailer[7],
+            ]) as usize,
+        }))
+    }
+
+    // Queue blocks for the threads until enough are in flight, then wait
+    // for the next block in the file to be decompressed
+    fn fill_blocks(&mut self) -> io::Result<()> {
+        let capacity = self.pool.workers.len() * BLOCKS_PER_THREAD;
+        while !self.eof && self.sent - self.next < capacity {
+            match self.read_block()? {
+                Some(block) => {
+                    let jobs = self.pool.jobs.as_ref().expect("pool stopped");
+                    jobs.send((self.sent, block))
+                        .map_err(|_| invalid("decompression stopped"))?;
+                    self.sent += 1;
+                }
+                None => self.eof = true,
+            }
+        }
+        if self.next == self.sent {
+            return Ok(());
+        }
+
+        while !self.waiting.contains_key(&self.next) {
+            let (number, data) = self
+                .pool
+                .results
+                .recv()
+                .map_err(|_| invalid("decompression stopped"))?;
+            self.waiting.insert(number, data);
+        }
+        let data = self
+            .waiting
+            .remove(&self.next)
+            .expect("block decompressed")?;
+        self.next += 1;
+        // Empty blocks, e.g. the end-of-file marker, are left out
+        if !data.is_empty() {
+            self.blocks.push_back(data);
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.blocks.is_empty() {
+            if self.eof && self.next == self.sent {
+                return Ok(0);
+            }
+            self.fill_blocks()?;
+        }
+
+        let block = &self.blocks[0];
+        let n = buf.len().min(block.len() - self.pos);
+        buf[..n].copy_from_slice(&block[self.pos..self.pos + n]);
+        self.pos += n;
+        if self.pos == block.len() {
+            self.blocks.pop_front();
+            self.pos = 0;
+        }
+
+        Ok(n)
+    }
+}
+
+// Inflate a block, checking its size and CRC32
+fn inflate(block: &Block) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(block.size);
+    DeflateDecoder::new(&block.cdata[..]).read_to_end(&mut data)?;
+    let mut crc = Crc::new();
+    crc.update(&data);
+    if data.len() != block.size || crc.sum() != block.crc {
+        return Err(invalid("corrupt block"));
+    }
+
+    Ok(data)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid BGZF file: {}", message),
+    )
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+
+    // BGZF block of `data`
+    fn bgzf_block(data: &[u8]) -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(data).unwrap();
+        let cdata = encoder.finish().unwrap();
+        let mut crc = Crc::new();
+        crc.update(data);
+
+        let mut block = vec![0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0];
+        block.extend_from_slice(b"BC\x02\x00");
+        block.extend_from_slice(&((cdata.len() + 25) as u16).to_le_bytes());
+        block.extend_from_slice(&cdata);
+        block.extend_from_slice(&crc.sum().to_le_bytes());
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn test_bgzf_reader() {
+        let records = (0..40)
+            .map(|i| format!(">r{}\nACGTACGTAC{}\n", i, "G".repeat(i)))
+            .collect::<Vec<_>>();
+        let mut content = records
+            .chunks(3)
+            .flat_map(|chunk| bgzf_block(chunk.concat().as_bytes()))
+            .collect::<Vec<_>>();
+        content.extend(bgzf_block(b""));
+
+        let mut data = String::new();
+        BgzfReader::new(&content[..], 3)
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, records.concat());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("in.fa.bgz");
+        fs::write(&path, &content).unwrap();
+        assert!(is_bgzf(path.to_str().unwrap()).unwrap());
+        assert!(!is_bgzf("tests/test.fa.gz").unwrap());
+
+        // Readers dropped before the end stop their threads
+        let mut reader = BgzfReader::new(&content[..], 4);
+        let mut record = [0u8; 8];
+        reader.read_exact(&mut record).unwrap();
+        assert_eq!(&record, b">r0\nACGT");
+        drop(reader);
+
+        // Corrupt blocks are reported
+        let last = content.len() - 40;
+        content[last] ^= 0xff;
+        assert!(BgzfReader::new(&content[..], 2)
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+}
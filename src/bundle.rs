@@ -0,0 +1,378 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils;
+
+use anyhow::{anyhow, Context};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// Size of the blocks of tar archives
+const BLOCK: usize = 512;
+
+/// File of a bundle, with its size and SHA-256 checksum
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BundledFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Manifest of a bundle, `manifest.json` in the archive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    // Version of hyperex and of its built-in primers
+    pub version: String,
+    pub primer_db_version: String,
+    // Command line of the run
+    pub command: String,
+    pub files: Vec<BundledFile>,
+}
+
+/// Package the output `files` of a run into the gzip'd tar archive `path`,
+/// under a directory named after the archive, with a `manifest.json` giving
+/// the run metadata and the checksum of every file and a `SHA256SUMS` file
+/// to check them with `sha256sum -c`
+pub fn write_bundle(
+    path: &str,
+    files: &[String],
+    command: &str,
+) -> anyhow::Result<Manifest> {
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("hyperex");
+    let dir = name
+        .trim_end_matches(".tar.gz")
+        .trim_end_matches(".tgz")
+        .to_string();
+
+    let mut manifest = Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        primer_db_version: utils::PRIMER_DB_VERSION.to_string(),
+        command: command.to_string(),
+        files: Vec::new(),
+    };
+    for file in files {
+        let name = Path::new(file)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid file name {}", file))?;
+        let mut reader = File::open(file)
+            .with_context(|| format!("Cannot read {}", file))?;
+        let mut hasher = Sha256::default();
+        let size = hasher.update_from(&mut reader)?;
+        manifest.files.push(BundledFile {
+            path: name.to_string(),
+            size,
+            sha256: hasher.hex_digest(),
+        });
+    }
+
+    let mut sums = String::new();
+    for file in manifest.files.iter() {
+        let _ = writeln!(sums, "{}  {}", file.sha256, file.path);
+    }
+    let mut json = serde_json::to_vec_pretty(&manifest)?;
+    json.push(b'\n');
+
+    let mut tar = GzEncoder::new(
+        io::BufWriter::new(File::create(path)?),
+        Compression::default(),
+    );
+    let mtime = now();
+    for (name, data) in [
+        ("manifest.json", &json[..]),
+        ("SHA256SUMS", sums.as_bytes()),
+    ] {
+        let entry = Entry {
+            name: format!("{}/{}", dir, name),
+            size: data.len() as u64,
+            mtime,
+        };
+        entry.append(&mut tar, &mut &data[..])?;
+    }
+    for (file, bundled) in files.iter().zip(manifest.files.iter()) {
+        let mut reader = File::open(file)
+            .with_context(|| format!("Cannot read {}", file))?;
+        let entry = Entry {
+            name: format!("{}/{}", dir, bundled.path),
+            size: bundled.size,
+            mtime: reader
+                .metadata()?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map_or(mtime, |d| d.as_secs()),
+        };
+        entry
+            .append(&mut tar, &mut reader)
+            .with_context(|| format!("Cannot bundle {}", file))?;
+    }
+    // An archive ends with two empty blocks
+    tar.write_all(&[0u8; 2 * BLOCK])?;
+    tar.finish()?.flush()?;
+
+    Ok(manifest)
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Regular file of a tar archive
+struct Entry {
+    name: String,
+    size: u64,
+    mtime: u64,
+}
+
+impl Entry {
+    // Write the ustar header of the file and its data, padded to whole
+    // blocks
+    fn append<W: Write, R: Read>(
+        &self,
+        writer: &mut W,
+        reader: &mut R,
+    ) -> anyhow::Result<()> {
+        if self.name.len() >= 100 {
+            return Err(anyhow!("File name {} is too long", self.name));
+        }
+        let mut header = [0u8; BLOCK];
+        let mut field = |offset: usize, value: &[u8]| {
+            header[offset..offset + value.len()].copy_from_slice(value)
+        };
+        field(0, self.name.as_bytes());
+        field(100, b"0000644\0");
+        field(108, b"0000000\0");
+        field(116, b"0000000\0");
+        field(124, format!("{:011o}\0", self.size).as_bytes());
+        field(136, format!("{:011o}\0", self.mtime).as_bytes());
+        // The checksum is computed with its own field set to spaces
+        field(148, b"        ");
+        field(156, b"0");
+        field(257, b"ustar\0");
+        field(263, b"00");
+        let checksum = header.iter().map(|&b| b as u32).sum::<u32>();
+        header[148..156]
+            .copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        writer.write_all(&header)?;
+        let copied = io::copy(&mut reader.take(self.size), writer)?;
+        if copied != self.size {
+            return Err(anyhow!("{} changed while being bundled", self.name));
+        }
+        let padding = (BLOCK - (self.size % BLOCK as u64) as usize) % BLOCK;
+        writer.write_all(&vec![0u8; padding])?;
+
+        Ok(())
+    }
+}
+
+// Round constants of SHA-256
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+    0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+    0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+    0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+    0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+    0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+    0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+    0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+    0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// SHA-256 of a stream, there being no hashing crate among the dependencies
+struct Sha256 {
+    state: [u32; 8],
+    block: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f,
+                0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            block: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.block.len() == 64 {
+                let block = std::mem::take(&mut self.block);
+                self.compress(&block);
+                self.block = block;
+                self.block.clear();
+            }
+        }
+    }
+
+    // Hash everything read from `reader`, returning its size
+    fn update_from<R: Read>(&mut self, reader: &mut R) -> io::Result<u64> {
+        let mut buf = vec![0u8; 1 << 16];
+        let start = self.len;
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(self.len - start),
+                n => self.update(&buf[..n]),
+            }
+        }
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7)
+                ^ w[i - 15].rotate_right(18)
+                ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17)
+                ^ w[i - 2].rotate_right(19)
+                ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut h = self.state;
+        for i in 0..64 {
+            let s1 = h[4].rotate_right(6)
+                ^ h[4].rotate_right(11)
+                ^ h[4].rotate_right(25);
+            let ch = (h[4] & h[5]) ^ (!h[4] & h[6]);
+            let t1 = h[7]
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = h[0].rotate_right(2)
+                ^ h[0].rotate_right(13)
+                ^ h[0].rotate_right(22);
+            let maj = (h[0] & h[1]) ^ (h[0] & h[2]) ^ (h[1] & h[2]);
+            let t2 = s0.wrapping_add(maj);
+            h = [
+                t1.wrapping_add(t2),
+                h[0],
+                h[1],
+                h[2],
+                h[3].wrapping_add(t1),
+                h[4],
+                h[5],
+                h[6],
+            ];
+        }
+        for (state, h) in self.state.iter_mut().zip(h.iter()) {
+            *state = state.wrapping_add(*h);
+        }
+    }
+
+    fn hex_digest(mut self) -> String {
+        let bits = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize((119 - self.block.len()) % 64 + 1, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        self.update(&padding);
+
+        self.state
+            .iter()
+            .map(|word| format!("{:08x}", word))
+            .collect()
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::fs;
+
+    fn sha256(data: &[u8]) -> String {
+        let mut hasher = Sha256::default();
+        hasher.update(data);
+        hasher.hex_digest()
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let fasta = dir.path().join("out.fa");
+        fs::write(&fasta, ">a\nACGT\n").unwrap();
+        let path = dir.path().join("results.tar.gz");
+        let manifest = write_bundle(
+            path.to_str().unwrap(),
+            &[fasta.to_str().unwrap().to_string()],
+            "hyperex -p out in.fa",
+        )
+        .unwrap();
+        assert_eq!(manifest.files[0].path, "out.fa");
+        assert_eq!(manifest.files[0].size, 8);
+        assert_eq!(manifest.files[0].sha256, sha256(b">a\nACGT\n"));
+
+        let mut tar = Vec::new();
+        GzDecoder::new(File::open(&path).unwrap())
+            .read_to_end(&mut tar)
+            .unwrap();
+        // Manifest, checksums and FASTA entries, then the end of archive
+        assert_eq!(tar.len(), 8 * BLOCK);
+        assert!(tar.starts_with(b"results/manifest.json\0"));
+        assert_eq!(&tar[257..263], b"ustar\0");
+        assert!(tar[4 * BLOCK..].starts_with(b"results/out.fa\0"));
+        assert!(tar[5 * BLOCK..].starts_with(b">a\nACGT\n"));
+        let checksum = tar[..BLOCK]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    b as u32
+                }
+            })
+            .sum::<u32>();
+        let field = std::str::from_utf8(&tar[148..154]).unwrap();
+        assert_eq!(u32::from_str_radix(field, 8).unwrap(), checksum);
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::matcher::AmbigMap;
+use crate::prefilter::{self, MAX_SEED_VARIANTS};
+use crate::utils::{self, GENES};
+
+use std::collections::{HashMap, HashSet};
+
+// Length of the k-mers seeding the classification
+const K: usize = 10;
+// Fraction of the primer sites of a gene a sequence has to share a k-mer
+// with to be classified as this gene
+const MIN_SITE_FRACTION: f64 = 0.5;
+
+/// Marker gene classifier seeded with k-mers of the conserved sites the
+/// built-in primers of each gene bind, on both strands.
+///
+/// A sequence is classified as the gene with the largest fraction of its
+/// primer sites sharing at least one k-mer with the sequence, provided it
+/// reaches half of them. Ties go to the first gene of [`GENES`].
+pub struct GeneClassifier {
+    // k-mer -> (gene index, site index)
+    kmers: HashMap<Vec<u8>, Vec<(usize, usize)>>,
+    // Number of primer sites of each gene
+    sites: Vec<usize>,
+}
+
+impl Default for GeneClassifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GeneClassifier {
+    pub fn new() -> Self {
+        let ambigs = AmbigMap::default();
+        let ambigs = ambigs.ambigs();
+        let mut kmers: HashMap<Vec<u8>, Vec<(usize, usize)>> = HashMap::new();
+        let mut sites = Vec::new();
+
+        for (gene_idx, gene) in GENES.iter().enumerate() {
+            let mut primers = utils::gene_regions(gene)
+                .into_iter()
+                .flat_map(|region| {
+                    utils::region_to_primer(region).unwrap_or_default()
+                })
+                .collect::<Vec<_>>();
+            primers.sort();
+            primers.dedup();
+            sites.push(primers.len());
+
+            for (site_idx, primer) in primers.iter().enumerate() {
+                let reverse = utils::to_reverse_complement(primer, "dna");
+                for site in [primer.as_bytes(), reverse.as_bytes()] {
+                    for window in site.windows(K) {
+                        let variants = prefilter::expand(window, &ambigs);
+                        if variants.len() > MAX_SEED_VARIANTS {
+                            continue;
+                        }
+                        for kmer in variants {
+                            kmers
+                                .entry(kmer)
+                                .or_default()
+                                .push((gene_idx, site_idx));
+                        }
+                    }
+                }
+            }
+        }
+
+        GeneClassifier { kmers, sites }
+    }
+
+    /// Most likely marker gene of a DNA or RNA sequence, if any
+    pub fn classify(&self, seq: &[u8]) -> Option<&'static str> {
+        let seq = seq
+            .iter()
+            .map(|base| match base.to_ascii_uppercase() {
+                b'U' => b'T',
+                base => base,
+            })
+            .collect::<Vec<_>>();
+        let found = seq
+            .windows(K)
+            .filter_map(|kmer| self.kmers.get(kmer))
+            .flatten()
+            .collect::<HashSet<_>>();
+
+        let mut best: Option<(usize, f64)> = None;
+        for (gene_idx, &sites) in self.sites.iter().enumerate() {
+            let hit = found.iter().filter(|(gene, _)| *gene == gene_idx);
+            let fraction = hit.count() as f64 / sites.max(1) as f64;
+            if fraction >= MIN_SITE_FRACTION
+                && best.is_none_or(|(_, best)| fraction > best)
+            {
+                best = Some((gene_idx, fraction));
+            }
+        }
+
+        best.map(|(gene_idx, _)| GENES[gene_idx])
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let classifier = GeneClassifier::new();
+        // Both 18S primer sites of the v4 and v9 regions, one strand each
+        let seq = [
+            "CCAGCACCCGCGGTAATTCC",
+            "AAAAAAAAAA",
+            "TCAATCAAGAACGAAAGT",
+            "AAAAAAAAAA",
+            "GTACACACCGCCCGTC",
+            "AAAAAAAAAA",
+            "GTAGGTGAACCTGCAGAAGGATCA",
+        ]
+        .concat();
+        assert_eq!(classifier.classify(seq.as_bytes()), Some("18S"));
+        // The ITS primer sites, as RNA
+        let seq = [
+            "CTTGGTCATTTAGAGGAAGTAA",
+            "GCAUCGAUGAAGAACGCAGC",
+            "GTGAATCATCGAATCTTTG",
+            "GCATATCAATAAGCGGAGGA",
+        ]
+        .concat()
+        .replace('T', "U");
+        assert_eq!(classifier.classify(seq.as_bytes()), Some("ITS"));
+        assert_eq!(classifier.classify(&[b'A'; 200]), None);
+        assert_eq!(classifier.classify(b"ACGT"), None);
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Coordinate convention of an output.
+///
+/// Hits and regions are located internally with 0-based starts and
+/// exclusive ends, as in BED files and Rust slices: the first 10 bases of
+/// a sequence span 0-10. The 1-based convention of GFF and of most genome
+/// browsers counts from 1 and includes the end, the same bases spanning
+/// 1-10. Ends are therefore the same in both conventions, and starts differ
+/// by one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Coords {
+    #[default]
+    #[serde(rename = "0based")]
+    ZeroBased,
+    #[serde(rename = "1based")]
+    OneBased,
+}
+
+impl Coords {
+    /// Start and end, in this convention, of an interval located with a
+    /// 0-based start and an exclusive end
+    pub fn interval(self, start: usize, end: usize) -> (usize, usize) {
+        match self {
+            Coords::ZeroBased => (start, end),
+            Coords::OneBased => (start + 1, end),
+        }
+    }
+
+    /// 0-based start and exclusive end of an interval located in this
+    /// convention
+    pub fn to_zero_based(self, start: usize, end: usize) -> (usize, usize) {
+        match self {
+            Coords::ZeroBased => (start, end),
+            Coords::OneBased => (start.saturating_sub(1), end),
+        }
+    }
+}
+
+impl FromStr for Coords {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "0based" => Ok(Coords::ZeroBased),
+            "1based" => Ok(Coords::OneBased),
+            _ => Err(anyhow!(
+                "Unknown coordinate convention {}, expected 0based or 1based",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Coords {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Coords::ZeroBased => write!(f, "0based"),
+            Coords::OneBased => write!(f, "1based"),
+        }
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions() {
+        // The first 10 bases of a sequence
+        assert_eq!(Coords::ZeroBased.interval(0, 10), (0, 10));
+        assert_eq!(Coords::OneBased.interval(0, 10), (1, 10));
+        // A single base
+        assert_eq!(Coords::OneBased.interval(4, 5), (5, 5));
+        for coords in [Coords::ZeroBased, Coords::OneBased] {
+            let (start, end) = coords.interval(267, 707);
+            assert_eq!(coords.to_zero_based(start, end), (267, 707));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("0based".parse::<Coords>().unwrap(), Coords::ZeroBased);
+        assert_eq!("1based".parse::<Coords>().unwrap(), Coords::OneBased);
+        assert!("1-based".parse::<Coords>().is_err());
+        assert_eq!(Coords::OneBased.to_string(), "1based");
+        assert_eq!(Coords::default(), Coords::ZeroBased);
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils;
+
+use log::info;
+
+use std::io::{self, BufRead};
+use std::time::{Duration, Instant};
+
+// Shortest time between two progress logs
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Records and bases of a sequence file
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub records: usize,
+    pub bases: usize,
+}
+
+/// Count the records and bases of a possibly compressed FASTA or FASTQ
+/// file, line by line without parsing the records. FASTQ records are
+/// expected on four lines.
+pub fn count(filename: &str) -> anyhow::Result<Counts> {
+    let (reader, _compression) = utils::read_file(filename)?;
+    let mut reader = io::BufReader::new(reader);
+    let fastq = reader.fill_buf()?.first() == Some(&b'@');
+    let mut counts = Counts::default();
+    let mut line = Vec::new();
+    let mut line_number = 0;
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let bases = line.trim_ascii_end().len();
+        if fastq {
+            match line_number % 4 {
+                0 => counts.records += 1,
+                1 => counts.bases += bases,
+                _ => {}
+            }
+        } else if line.first() == Some(&b'>') {
+            counts.records += 1;
+        } else {
+            counts.bases += bases;
+        }
+        line_number += 1;
+    }
+
+    Ok(counts)
+}
+
+/// Time left to process `total` records when `done` of them took
+/// `elapsed`, if any was done
+pub fn eta(done: usize, total: usize, elapsed: Duration) -> Option<Duration> {
+    if done == 0 {
+        return None;
+    }
+    let left = total.saturating_sub(done) as f64;
+    Some(elapsed.mul_f64(left / done as f64))
+}
+
+/// Duration as hours, minutes and seconds, e.g. 1h02m03s
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+/// Periodic log of the records processed by a run, with the percentage
+/// done and the time left when the number of records is known
+pub struct Progress {
+    label: String,
+    total: Option<usize>,
+    start: Instant,
+    last: Instant,
+}
+
+impl Progress {
+    pub fn new(label: &str, total: Option<usize>) -> Self {
+        let now = Instant::now();
+        Progress {
+            label: label.to_string(),
+            total,
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Log the progress once `done` records are processed, at most every
+    /// few seconds
+    pub fn update(&mut self, done: usize) {
+        if self.last.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        self.last = Instant::now();
+        info!("{}", self.message(done));
+    }
+
+    fn message(&self, done: usize) -> String {
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{}: {} of {} records ({:.1}%), {} left",
+                self.label,
+                done,
+                total,
+                done as f64 * 100.0 / total as f64,
+                eta(done, total, self.start.elapsed())
+                    .map_or("?".to_string(), format_duration)
+            ),
+            _ => format!("{}: {} records", self.label, done),
+        }
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_count() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let fasta = dir.path().join("in.fa");
+        fs::write(&fasta, ">a desc\nACGT\nAC\n>b\r\nACG\r\n").unwrap();
+        assert_eq!(
+            count(fasta.to_str().unwrap()).unwrap(),
+            Counts {
+                records: 2,
+                bases: 9
+            }
+        );
+        // Quality lines may start with @ or >
+        let fastq = dir.path().join("in.fq");
+        fs::write(&fastq, "@r1\nACGT\n+\n>III\n@r2\nAC\n+\n@I\n").unwrap();
+        assert_eq!(
+            count(fastq.to_str().unwrap()).unwrap(),
+            Counts {
+                records: 2,
+                bases: 6
+            }
+        );
+        assert!(count("tests/missing.fa").is_err());
+    }
+
+    #[test]
+    fn test_eta() {
+        let minute = Duration::from_secs(60);
+        assert_eq!(eta(0, 10, minute), None);
+        assert_eq!(eta(5, 10, minute), Some(minute));
+        assert_eq!(eta(10, 10, minute), Some(Duration::ZERO));
+        assert_eq!(format_duration(Duration::from_secs(59)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h02m03s");
+        let progress = Progress::new("in.fa", Some(4));
+        assert!(progress
+            .message(1)
+            .starts_with("in.fa: 1 of 4 records (25.0%), "));
+        assert_eq!(Progress::new("-", None).message(3), "-: 3 records");
+    }
+}
@@ -0,0 +1,222 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils::{self, Alphabet, Config};
+
+use anyhow::Context;
+
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+
+// Taxon of the references without a known lineage
+const UNCLASSIFIED: &str = "unclassified";
+
+/// In silico coverage of a reference database by a primer pair
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairCoverage {
+    pub primers: Vec<String>,
+    // Taxon of each reference with the fewest mismatches, allowed to each
+    // primer, amplifying it
+    pub references: Vec<(String, Option<u8>)>,
+}
+
+impl PairCoverage {
+    /// References amplified with at most `mismatch` mismatches per primer
+    pub fn matched(&self, mismatch: u8) -> usize {
+        self.references
+            .iter()
+            .filter(|(_, found)| found.is_some_and(|m| m <= mismatch))
+            .count()
+    }
+
+    /// Taxa with the most references missed at `mismatch` mismatches, as
+    /// (taxon, missed, references), at most `top` of them
+    pub fn most_missed(
+        &self,
+        mismatch: u8,
+        top: usize,
+    ) -> Vec<(&str, usize, usize)> {
+        let mut taxa: HashMap<&str, (usize, usize)> = HashMap::new();
+        for (taxon, found) in self.references.iter() {
+            let counts = taxa.entry(taxon.as_str()).or_insert((0, 0));
+            if !found.is_some_and(|m| m <= mismatch) {
+                counts.0 += 1;
+            }
+            counts.1 += 1;
+        }
+        let mut missed = taxa
+            .into_iter()
+            .filter(|(_, (missed, _))| *missed > 0)
+            .map(|(taxon, (missed, total))| (taxon, missed, total))
+            .collect::<Vec<_>>();
+        missed.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        missed.truncate(top);
+
+        missed
+    }
+}
+
+/// Evaluate each primer pair on the references of `file`, allowing up to
+/// `config.mismatch` mismatches per primer. References are grouped by their
+/// lineage, from `config.taxonomy`, cut to its first `depth` ranks.
+pub fn coverage(
+    file: &str,
+    primers: &[Vec<String>],
+    config: &Config,
+    depth: usize,
+) -> anyhow::Result<Vec<PairCoverage>> {
+    let mut coverages = primers
+        .iter()
+        .map(|pair| PairCoverage {
+            primers: pair.to_vec(),
+            references: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    let mut records = utils::read_records(file)
+        .with_context(|| format!("Cannot read references {}", file))?;
+
+    while let Some(Ok(record)) = records.next() {
+        let seq = record.seq();
+        let alphabet = match utils::sequence_type(std::str::from_utf8(seq)?) {
+            Some(Alphabet::Rna) => "rna",
+            _ => "dna",
+        };
+        let taxon = match config.taxonomy.lineage(&record) {
+            Some(lineage) => lineage
+                .split(';')
+                .take(depth.max(1))
+                .collect::<Vec<_>>()
+                .join(";"),
+            None => UNCLASSIFIED.to_string(),
+        };
+        for coverage in coverages.iter_mut() {
+            let found = utils::amplicon_mismatches(
+                &coverage.primers,
+                seq,
+                alphabet,
+                config,
+            );
+            coverage.references.push((taxon.clone(), found));
+        }
+    }
+
+    Ok(coverages)
+}
+
+/// Human readable report of the coverage at each number of mismatches up to
+/// `max_mismatch`, listing the `top` most missed taxa
+pub fn report(
+    coverages: &[PairCoverage],
+    max_mismatch: u8,
+    top: usize,
+) -> String {
+    let mut out = String::new();
+
+    for coverage in coverages {
+        let region = utils::primers_to_region(coverage.primers.to_vec());
+        let _ = writeln!(
+            out,
+            "{} {} {}",
+            if region.is_empty() { "pair" } else { &region },
+            coverage.primers[0],
+            coverage.primers[1]
+        );
+        let total = coverage.references.len();
+        for mismatch in 0..=max_mismatch {
+            let matched = coverage.matched(mismatch);
+            let unit = if mismatch == 1 {
+                "mismatch"
+            } else {
+                "mismatches"
+            };
+            let _ = writeln!(
+                out,
+                "  {} {:<10} {}/{} references ({:.1}%)",
+                mismatch,
+                unit,
+                matched,
+                total,
+                matched as f64 * 100.0 / total.max(1) as f64
+            );
+            let missed = coverage
+                .most_missed(mismatch, top)
+                .iter()
+                .map(|(taxon, missed, total)| {
+                    format!("{} {}/{}", taxon, missed, total)
+                })
+                .collect::<Vec<_>>();
+            if !missed.is_empty() {
+                let _ = writeln!(out, "    most missed: {}", missed.join(", "));
+            }
+        }
+    }
+
+    out
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taxonomy::Taxonomy;
+    use std::fs;
+
+    #[test]
+    fn test_most_missed() {
+        let coverage = PairCoverage {
+            primers: vec!["AC".to_string(), "GT".to_string()],
+            references: vec![
+                ("Bacillota".to_string(), Some(0)),
+                ("Bacillota".to_string(), Some(2)),
+                ("Chloroflexota".to_string(), None),
+                ("Chloroflexota".to_string(), Some(1)),
+            ],
+        };
+        assert_eq!(coverage.matched(0), 1);
+        assert_eq!(coverage.matched(2), 3);
+        assert_eq!(
+            coverage.most_missed(0, 5),
+            vec![("Chloroflexota", 2, 2), ("Bacillota", 1, 2)]
+        );
+        assert_eq!(coverage.most_missed(1, 1), vec![("Bacillota", 1, 2)]);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("refs.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(
+            &input,
+            format!(
+                ">s1 Bacteria;Pseudomonadota;Alphaproteobacteria\n{}\n\
+                >s2 Bacteria;Bacillota\nACGTACGTACGTACGT\n",
+                seq
+            ),
+        )
+        .unwrap();
+        let config = Config {
+            mismatch: 2,
+            taxonomy: Taxonomy::Headers,
+            ..Default::default()
+        };
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+
+        let coverages =
+            coverage(input.to_str().unwrap(), &primers, &config, 2).unwrap();
+        assert_eq!(
+            coverages[0].references,
+            vec![
+                ("Bacteria;Pseudomonadota".to_string(), Some(0)),
+                ("Bacteria;Bacillota".to_string(), None)
+            ]
+        );
+        let text = report(&coverages, 2, 5);
+        assert!(text.starts_with("v3v4 CCTACGGGNGGCWGCAG"));
+        assert!(text.contains("  0 mismatches 1/2 references (50.0%)"));
+        assert!(text.contains("most missed: Bacteria;Bacillota 1/1"));
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use hyperex::count;
+use hyperex::sink::{OutputSink, SinkRecord};
+
+use log::{Level, Log, Metadata, Record};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Time between two redraws of the dashboard
+const REFRESH: Duration = Duration::from_millis(250);
+// Warnings kept on the dashboard, the most recent ones
+const RECENT_WARNINGS: usize = 5;
+
+/// Live terminal dashboard of a run with --tui, redrawn in place on the
+/// standard error with the records and regions per second, the regions
+/// extracted so far and the recent warnings. Logs are shown on the
+/// dashboard while it runs and printed as usual once it is finished.
+pub struct Dashboard {
+    state: Arc<Mutex<State>>,
+    drawer: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct State {
+    start: Instant,
+    records: usize,
+    extracted: usize,
+    regions: BTreeMap<String, usize>,
+    // Last informative message, e.g. the progress of an input
+    status: String,
+    warnings: VecDeque<String>,
+    // Lines of the last frame, overwritten by the next one
+    drawn: usize,
+    done: bool,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            start: Instant::now(),
+            records: 0,
+            extracted: 0,
+            regions: BTreeMap::new(),
+            status: String::new(),
+            warnings: VecDeque::new(),
+            drawn: 0,
+            done: false,
+        }
+    }
+}
+
+impl Dashboard {
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State::new()));
+        let shared = Arc::clone(&state);
+        let drawer = thread::spawn(move || loop {
+            thread::sleep(REFRESH);
+            let mut state = shared.lock().unwrap();
+            if state.done {
+                break;
+            }
+            draw(&mut state);
+        });
+
+        Dashboard {
+            state,
+            drawer: Some(drawer),
+        }
+    }
+
+    /// Terminal logger showing messages on the dashboard
+    pub fn logger(&self) -> Box<dyn Log> {
+        Box::new(DashboardLog(Arc::clone(&self.state)))
+    }
+
+    /// Sink counting the records and regions of the run
+    pub fn sink(&self) -> Box<dyn OutputSink + Send> {
+        Box::new(DashboardSink(Arc::clone(&self.state)))
+    }
+
+    /// Draw the last frame and leave the terminal to the logs
+    pub fn finish(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if state.done {
+            return;
+        }
+        state.done = true;
+        draw(&mut state);
+        drop(state);
+        if let Some(drawer) = self.drawer.take() {
+            let _ = drawer.join();
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+// Overwrite the previous frame with the current one
+fn draw(state: &mut State) {
+    let width = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80);
+    let lines = render(state, state.start.elapsed(), width);
+    let mut frame = String::new();
+    if state.drawn > 0 {
+        // Back to the first line of the previous frame, cleared to the end
+        frame.push_str(&format!("\x1b[{}A\r\x1b[J", state.drawn));
+    }
+    for line in lines.iter() {
+        frame.push_str(line);
+        frame.push('\n');
+    }
+    let mut stderr = io::stderr().lock();
+    let _ = stderr.write_all(frame.as_bytes());
+    let _ = stderr.flush();
+    state.drawn = lines.len();
+}
+
+// Lines of a frame, cut to the width of the terminal
+fn render(state: &State, elapsed: Duration, width: usize) -> Vec<String> {
+    let secs = elapsed.as_secs_f64().max(1e-3);
+    let mut lines = vec![
+        format!("hyperex  {}", count::format_duration(elapsed)),
+        format!(
+            "records  {:>10}  {:>8.1}/s",
+            state.records,
+            state.records as f64 / secs
+        ),
+        format!(
+            "regions  {:>10}  {:>8.1}/s",
+            state.extracted,
+            state.extracted as f64 / secs
+        ),
+        String::new(),
+    ];
+    for (region, count) in state.regions.iter() {
+        lines.push(format!("  {:<12} {:>10}", region, count));
+    }
+    if !state.regions.is_empty() {
+        lines.push(String::new());
+    }
+    lines.push(state.status.clone());
+    if !state.warnings.is_empty() {
+        lines.push("recent warnings".to_string());
+        lines.extend(state.warnings.iter().map(|w| format!("  {}", w)));
+    }
+
+    lines
+        .into_iter()
+        .map(|line| {
+            let mut cut = line.chars().take(width).collect::<String>();
+            // Colors of the log levels are reset if cut short
+            if cut.len() < line.len() && cut.contains('\x1b') {
+                cut.push_str("\x1b[0m");
+            }
+            cut
+        })
+        .collect()
+}
+
+// Logs shown on the dashboard, and printed once it is finished
+struct DashboardLog(Arc<Mutex<State>>);
+
+impl Log for DashboardLog {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let message = record.args().to_string();
+        let mut state = self.0.lock().unwrap();
+        if state.done {
+            let _ = writeln!(io::stderr(), "{}", message);
+        } else if record.level() <= Level::Warn {
+            if state.warnings.len() == RECENT_WARNINGS {
+                state.warnings.pop_front();
+            }
+            state.warnings.push_back(message);
+        } else {
+            state.status = message;
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+struct DashboardSink(Arc<Mutex<State>>);
+
+impl OutputSink for DashboardSink {
+    fn write_record(&mut self, record: &SinkRecord) -> anyhow::Result<()> {
+        let mut state = self.0.lock().unwrap();
+        state.records += 1;
+        state.extracted += record.regions.len();
+        for region in record.regions {
+            *state.regions.entry(region.clone()).or_default() += 1;
+        }
+        Ok(())
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyperex::utils::Completeness;
+
+    #[test]
+    fn test_render() {
+        let state = Arc::new(Mutex::new(State::new()));
+        let mut sink = DashboardSink(Arc::clone(&state));
+        let regions = ["v4".to_string()];
+        for regions in [&regions[..], &[]] {
+            sink.write_record(&SinkRecord {
+                id: "a",
+                length: 300,
+                completeness: Completeness::Fragment,
+                regions,
+            })
+            .unwrap();
+        }
+        let logger = DashboardLog(Arc::clone(&state));
+        for i in 0..7 {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .args(format_args!("warning {}", i))
+                    .build(),
+            );
+        }
+
+        let state = state.lock().unwrap();
+        let lines = render(&state, Duration::from_secs(2), 80);
+        assert_eq!(lines[0], "hyperex  2s");
+        assert_eq!(lines[1], "records           2       1.0/s");
+        assert_eq!(lines[2], "regions           1       0.5/s");
+        assert_eq!(lines[4], "  v4                    1");
+        assert_eq!(lines[lines.len() - 5], "  warning 2");
+        assert_eq!(lines.last().unwrap(), "  warning 6");
+        assert!(render(&state, Duration::from_secs(2), 5)
+            .iter()
+            .all(|line| line.chars().count() <= 5));
+    }
+}
@@ -0,0 +1,326 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::coords::Coords;
+use crate::report::{self, ExtractedRegion, RunReport};
+use crate::utils::Strand;
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+
+// Attribute of the GFF3 records of older hyperex outputs holding the region
+// name, newer ones having a Name attribute
+const GFF_NOTE: &str = "Note Hypervariable region ";
+
+/// Region extracted by a run, with a 0-based start and an exclusive end
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located {
+    pub id: String,
+    pub region: String,
+    pub start: usize,
+    pub end: usize,
+    pub strand: Strand,
+}
+
+// Regions listed by the reports of schema version 1
+#[derive(Deserialize)]
+struct Listed {
+    regions: Vec<ExtractedRegion>,
+}
+
+/// Regions of a run, read from the `<prefix>.regions.jsonl` output next to
+/// its `<prefix>.run.json` report, or from a `<prefix>.gff` output
+pub fn read_regions(path: &str) -> anyhow::Result<Vec<Located>> {
+    if path.ends_with(".gff") || path.ends_with(".gff3") {
+        return read_gff(path);
+    }
+
+    let report = RunReport::read(path)?;
+    let coords = report.coords;
+    let regions = if report.schema_version < 2 {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path))?;
+        serde_json::from_str::<Listed>(&content)
+            .with_context(|| format!("Cannot parse report {}", path))?
+            .regions
+    } else {
+        let prefix = path.strip_suffix(".run.json").ok_or_else(|| {
+            anyhow!("{} is not a <prefix>.run.json report", path)
+        })?;
+        report::read_regions(&format!("{}.regions.jsonl", prefix))?
+    };
+    Ok(regions
+        .into_iter()
+        .map(|extracted| {
+            let region = extracted.region;
+            let (start, end) = coords.to_zero_based(region.start, region.end);
+            Located {
+                id: extracted.id,
+                region: region.name(),
+                start,
+                end,
+                strand: region.strand,
+            }
+        })
+        .collect())
+}
+
+/// Regions of a GFF3 file, written by hyperex or by another tool
+pub fn read_gff(path: &str) -> anyhow::Result<Vec<Located>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read {}", path))?;
+    let mut regions = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let invalid = || anyhow!("Invalid GFF3 record at line {}", i + 1);
+        if fields.len() < 9 {
+            return Err(invalid());
+        }
+        let start = fields[3].parse::<usize>().map_err(|_| invalid())?;
+        let end = fields[4].parse::<usize>().map_err(|_| invalid())?;
+        let (start, end) = Coords::OneBased.to_zero_based(start, end);
+        regions.push(Located {
+            id: fields[0].to_string(),
+            region: gff_name(fields[8]).to_string(),
+            start,
+            end,
+            strand: if fields[6] == "-" {
+                Strand::Minus
+            } else {
+                Strand::Plus
+            },
+        });
+    }
+
+    Ok(regions)
+}
+
+// Region name of a GFF3 record, from the note of older hyperex outputs or
+// the Name or ID attribute of newer ones and other files
+fn gff_name(attributes: &str) -> &str {
+    if let Some(name) = attributes.strip_prefix(GFF_NOTE) {
+        return name;
+    }
+    ["Name=", "ID="]
+        .iter()
+        .find_map(|key| {
+            attributes
+                .split(';')
+                .find_map(|attribute| attribute.trim().strip_prefix(key))
+        })
+        .unwrap_or(attributes)
+}
+
+/// Difference of a region between two runs
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Gained(Located),
+    Lost(Located),
+    // Same region of the same sequence at other coordinates, old and new
+    Moved(Located, Located),
+}
+
+// Copies of a region on a sequence in the old and new runs
+type Copies<'a> = (Vec<&'a Located>, Vec<&'a Located>);
+
+/// Changes from the `old` to the `new` regions, and the number of regions
+/// found at the same place by both runs. Copies of a region on a sequence
+/// are paired in order of their coordinates.
+pub fn diff(old: &[Located], new: &[Located]) -> (Vec<Change>, usize) {
+    // Regions of the old and new runs by sequence and region name
+    let mut by_region: BTreeMap<(&str, &str), Copies> = BTreeMap::new();
+    for located in old {
+        let key = (located.id.as_str(), located.region.as_str());
+        by_region.entry(key).or_default().0.push(located);
+    }
+    for located in new {
+        let key = (located.id.as_str(), located.region.as_str());
+        by_region.entry(key).or_default().1.push(located);
+    }
+
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for (_, (mut old, mut new)) in by_region {
+        old.sort_by_key(|l| (l.start, l.end));
+        new.sort_by_key(|l| (l.start, l.end));
+        for i in 0..old.len().max(new.len()) {
+            match (old.get(i), new.get(i)) {
+                (Some(&o), Some(&n)) if o == n => unchanged += 1,
+                (Some(&o), Some(&n)) => {
+                    changes.push(Change::Moved(o.clone(), n.clone()))
+                }
+                (Some(&o), None) => changes.push(Change::Lost(o.clone())),
+                (None, Some(&n)) => changes.push(Change::Gained(n.clone())),
+                (None, None) => {}
+            }
+        }
+    }
+
+    (changes, unchanged)
+}
+
+// Coordinates of a region as in the FASTA headers
+fn location(located: &Located) -> String {
+    format!("{}-{}{}", located.start, located.end, located.strand)
+}
+
+/// Tab-separated list of the changes, with the old and new coordinates of
+/// the regions, followed by a count of each kind of change
+pub fn report(changes: &[Change], unchanged: usize) -> String {
+    let mut out = String::new();
+    let mut counts = [0; 3];
+
+    let _ = writeln!(out, "change\tid\tregion\told\tnew");
+    for change in changes {
+        let (kind, located, old, new) = match change {
+            Change::Gained(n) => {
+                counts[0] += 1;
+                ("gained", n, ".".to_string(), location(n))
+            }
+            Change::Lost(o) => {
+                counts[1] += 1;
+                ("lost", o, location(o), ".".to_string())
+            }
+            Change::Moved(o, n) => {
+                counts[2] += 1;
+                ("moved", o, location(o), location(n))
+            }
+        };
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            kind, located.id, located.region, old, new
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# {} unchanged, {} gained, {} lost, {} moved",
+        unchanged, counts[0], counts[1], counts[2]
+    );
+
+    out
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn located(id: &str, region: &str, start: usize, end: usize) -> Located {
+        Located {
+            id: id.to_string(),
+            region: region.to_string(),
+            start,
+            end,
+            strand: Strand::Plus,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![
+            located("a", "v4", 10, 300),
+            located("a", "v3v4", 5, 450),
+            located("b", "v4", 10, 300),
+            located("c", "v4", 0, 290),
+        ];
+        let new = vec![
+            located("a", "v4", 10, 300),
+            located("a", "v3v4", 5, 452),
+            located("c", "v4", 0, 290),
+            located("c", "v4", 1000, 1290),
+        ];
+        let (changes, unchanged) = diff(&old, &new);
+        assert_eq!(unchanged, 2);
+        assert_eq!(
+            changes,
+            vec![
+                Change::Moved(old[1].clone(), new[1].clone()),
+                Change::Lost(old[2].clone()),
+                Change::Gained(new[3].clone()),
+            ]
+        );
+        let report = report(&changes, unchanged);
+        assert!(report.contains("moved\ta\tv3v4\t5-450+\t5-452+\n"));
+        assert!(report.contains("lost\tb\tv4\t10-300+\t.\n"));
+        assert!(report.ends_with("# 2 unchanged, 1 gained, 1 lost, 1 moved\n"));
+    }
+
+    #[test]
+    fn test_read_report() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.run.json");
+        let path = path.to_str().unwrap();
+        let region = r#"{"id": "a", "region": "v4", "forward_primer": "ACGT",
+            "reverse_primer": "TTGG", "start": 11, "end": 300,
+            "forward_dist": 0, "reverse_dist": 0, "strand": "+"}"#;
+        RunReport::new(Default::default())
+            .with_coords(Coords::OneBased)
+            .write(path)
+            .unwrap();
+        fs::write(
+            dir.path().join("out.regions.jsonl"),
+            format!("{}\n", region.replace('\n', "")),
+        )
+        .unwrap();
+        assert_eq!(
+            read_regions(path).unwrap(),
+            vec![located("a", "v4", 10, 300)]
+        );
+
+        // Older reports list their regions
+        fs::write(
+            path,
+            format!(
+                r#"{{"schema_version": 1, "version": "0.2.0",
+                "summary": {{"sequences": 1, "extracted": 1}},
+                "regions": [{}]}}"#,
+                region
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            read_regions(path).unwrap(),
+            vec![located("a", "v4", 11, 300)]
+        );
+    }
+
+    #[test]
+    fn test_read_gff() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.gff");
+        fs::write(
+            &path,
+            "##gff-version 3\n\
+            a\thyperex\tregion\t268\t707\t.\t-\t.\tNote Hypervariable region v3v4\n",
+        )
+        .unwrap();
+        let regions = read_regions(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            regions,
+            vec![Located {
+                strand: Strand::Minus,
+                ..located("a", "v3v4", 267, 707)
+            }]
+        );
+        fs::write(
+            &path,
+            "a\tbarrnap\trRNA\t1\t1500\t.\t+\t.\tID=r1;Name=16S_rRNA\n",
+        )
+        .unwrap();
+        let regions = read_regions(path.to_str().unwrap()).unwrap();
+        assert_eq!(regions, vec![located("a", "16S_rRNA", 0, 1500)]);
+        fs::write(&path, "a\thyperex\tregion\tx\n").unwrap();
+        assert!(read_regions(path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,637 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::batch;
+use crate::coords::Coords;
+use crate::matcher::AmbigMap;
+use crate::reference::Reference;
+use crate::silva::SilvaColumns;
+use crate::stats::LiveStats;
+use crate::taxonomy::Taxonomy;
+use crate::utils::{
+    self, Anchor, Config, ExtraOutputs, HeaderFilter, IdParser, MixedAlphabet,
+    OnMatch, Overlaps, RegionMatch, RegionRegistry, RunSummary, Sanitize,
+};
+
+use anyhow::{anyhow, Context};
+use bio::io::fasta::Record;
+
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Reusable hypervariable region extractor, configured once with
+/// [`Extractor::builder`] and run on any number of inputs.
+///
+/// ```no_run
+/// use hyperex::Extractor;
+///
+/// let extractor = Extractor::builder()
+///     .mismatch(2)
+///     .regions(["v3v4"])
+///     .trim_primers(true)
+///     .threads(8)
+///     .build()?;
+/// let summary = extractor.extract("reads.fa", "v3v4")?;
+/// println!("{} regions extracted", summary.extracted);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    primers: Vec<Vec<String>>,
+    config: Config,
+    threads: usize,
+}
+
+impl Extractor {
+    pub fn builder() -> ExtractorBuilder {
+        ExtractorBuilder::default()
+    }
+
+    /// Primer pairs, as forward and reverse primers
+    pub fn primers(&self) -> &[Vec<String>] {
+        &self.primers
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Extract the regions of one input into the `<prefix>.*` outputs
+    pub fn extract(
+        &self,
+        file: &str,
+        prefix: &str,
+    ) -> anyhow::Result<RunSummary> {
+        self.run(&[file.to_string()], prefix, &mut ExtraOutputs::default())
+    }
+
+    /// Extract the regions of several inputs, processed concurrently and
+    /// merged into the `<prefix>.*` outputs. Optional outputs are filled
+    /// in `extras` and left for the caller to finish.
+    pub fn run(
+        &self,
+        files: &[String],
+        prefix: &str,
+        extras: &mut ExtraOutputs,
+    ) -> anyhow::Result<RunSummary> {
+        self.dispatch(files, prefix, extras, None)
+    }
+
+    /// Same as [`Extractor::run`], also calling `on_match` with every
+    /// extracted region and the record it comes from, e.g. to store regions
+    /// in a database. Calls for regions of different inputs are interleaved.
+    pub fn run_with_callback<F>(
+        &self,
+        files: &[String],
+        prefix: &str,
+        extras: &mut ExtraOutputs,
+        mut on_match: F,
+    ) -> anyhow::Result<RunSummary>
+    where
+        F: FnMut(&RegionMatch, &Record) + Send,
+    {
+        self.dispatch(files, prefix, extras, Some(&mut on_match))
+    }
+
+    fn dispatch(
+        &self,
+        files: &[String],
+        prefix: &str,
+        extras: &mut ExtraOutputs,
+        on_match: Option<&mut OnMatch>,
+    ) -> anyhow::Result<RunSummary> {
+        match files {
+            [file] => utils::get_hypervar_regions(
+                file,
+                &self.primers,
+                prefix,
+                &self.config,
+                extras,
+                on_match,
+            ),
+            _ => batch::run_batch(
+                files,
+                self.threads,
+                &self.primers,
+                prefix,
+                &self.config,
+                extras,
+                on_match,
+            ),
+        }
+    }
+}
+
+/// Builder of an [`Extractor`]
+#[derive(Debug, Clone)]
+pub struct ExtractorBuilder {
+    primers: Vec<Vec<String>>,
+    regions: Vec<String>,
+    config: Config,
+    threads: usize,
+    // Reference sequence the primer sites are located on
+    reference: Option<String>,
+}
+
+impl Default for ExtractorBuilder {
+    fn default() -> Self {
+        ExtractorBuilder {
+            primers: Vec::new(),
+            regions: Vec::new(),
+            config: Config::default(),
+            threads: 1,
+            reference: None,
+        }
+    }
+}
+
+impl ExtractorBuilder {
+    /// Start from existing settings, e.g. parsed from the command line
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn mismatch(mut self, mismatch: u8) -> Self {
+        self.config.mismatch = mismatch;
+        self
+    }
+
+    /// Add regions by name, e.g. `v3v4`, or CSV files of primer pairs
+    pub fn regions<I, S>(mut self, regions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.regions
+            .extend(regions.into_iter().map(|r| r.as_ref().to_string()));
+        self
+    }
+
+    /// Add a primer pair, the reverse primer being given as ordered
+    pub fn primer_pair(mut self, forward: &str, reverse: &str) -> Self {
+        self.primers
+            .push(vec![forward.to_string(), reverse.to_string()]);
+        self
+    }
+
+    /// Add several primer pairs
+    pub fn primer_pairs(mut self, primers: &[Vec<String>]) -> Self {
+        self.primers.extend(primers.iter().cloned());
+        self
+    }
+
+    pub fn trim_primers(mut self, trim_primers: bool) -> Self {
+        self.config.trim_primers = trim_primers;
+        self
+    }
+
+    /// Add to the FASTA headers where each primer site starts in the
+    /// region, 0 and the region length minus the site for the forward and
+    /// reverse primers when untrimmed, before the region when trimmed
+    pub fn primer_offsets(mut self, primer_offsets: bool) -> Self {
+        self.config.primer_offsets = primer_offsets;
+        self
+    }
+
+    /// Number of inputs processed concurrently, or of threads decompressing
+    /// the blocks of a single BGZF input
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn ambigs(mut self, ambigs: AmbigMap) -> Self {
+        self.config.ambigs = ambigs;
+        self
+    }
+
+    pub fn no_indels(mut self, no_indels: bool) -> Self {
+        self.config.no_indels = no_indels;
+        self
+    }
+
+    /// Match primers on bisulfite-converted sequences, their C also
+    /// matching the T of unmethylated C
+    pub fn bisulfite(mut self, bisulfite: bool) -> Self {
+        self.config.bisulfite = bisulfite;
+        self
+    }
+
+    pub fn all_matches(mut self, all_matches: bool) -> Self {
+        self.config.all_matches = all_matches;
+        self
+    }
+
+    /// Primers a region has to be bound by, regions anchored on a single
+    /// primer extending to the sequence end. Ignored, with a warning, when
+    /// searching both strands.
+    pub fn anchor(mut self, anchor: Anchor) -> Self {
+        self.config.anchor = anchor;
+        self
+    }
+
+    /// Handling of the `-` and `*` padding characters of the records,
+    /// applied before any search
+    pub fn sanitize(mut self, sanitize: Sanitize) -> Self {
+        self.config.sanitize = sanitize;
+        self
+    }
+
+    /// Only process the records whose header passes `filter`, e.g. contains
+    /// a taxon name
+    pub fn header_filter(mut self, filter: HeaderFilter) -> Self {
+        self.config.header_filter = Some(filter);
+        self
+    }
+
+    /// Only process each record with probability `fraction`, decided from
+    /// the seed of the run and the id of the record, so that the same
+    /// records are kept whatever the order they are processed in
+    pub fn subsample(mut self, fraction: f64) -> Self {
+        self.config.subsample = Some(fraction);
+        self
+    }
+
+    /// Seed of the random draws, e.g. of [`ExtractorBuilder::subsample`],
+    /// 0 by default
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    /// Bases from the start of each record telling its alphabet, all of
+    /// them when 0, the default
+    pub fn alphabet_sample(mut self, bases: usize) -> Self {
+        self.config.alphabet_sample = bases;
+        self
+    }
+
+    /// Handling of the records neither DNA nor RNA, skipped by default
+    pub fn mixed_alphabet(mut self, mixed_alphabet: MixedAlphabet) -> Self {
+        self.config.mixed_alphabet = mixed_alphabet;
+        self
+    }
+
+    /// Parsing of the record ids written in the outputs from the record
+    /// headers, e.g. the accession of NCBI headers
+    pub fn id_parser(mut self, id_parser: IdParser) -> Self {
+        self.config.id_parser = id_parser;
+        self
+    }
+
+    /// Extract the primer-dimer-like amplicons, whose primer hits overlap
+    /// or which are shorter than the two primers combined, rather than
+    /// leaving them out
+    pub fn keep_primer_dimers(mut self, keep: bool) -> Self {
+        self.config.keep_primer_dimers = keep;
+        self
+    }
+
+    /// Mismatches added to a primer missing while its mate is found, to
+    /// search it again where the typical amplicons of the pair put it.
+    /// Ignored, with a warning, when searching both strands.
+    pub fn rescue(mut self, extra: Option<u8>) -> Self {
+        self.config.rescue = extra;
+        self
+    }
+
+    /// Handling of the overlapping regions extracted from a sequence
+    pub fn overlaps(mut self, overlaps: Overlaps) -> Self {
+        self.config.overlaps = overlaps;
+        self
+    }
+
+    pub fn max_amplicons(mut self, max_amplicons: Option<usize>) -> Self {
+        self.config.max_amplicons = max_amplicons;
+        self
+    }
+
+    /// Smallest identity, 1 - distance / primer length, of the primer hits,
+    /// replacing the mismatch count for pairs without their own
+    pub fn min_primer_identity(mut self, identity: Option<f64>) -> Self {
+        self.config.min_primer_identity = identity;
+        self
+    }
+
+    /// Longest amplicon, primers included, a primer pair may amplify
+    pub fn max_amplicon_length(mut self, max_length: Option<usize>) -> Self {
+        self.config.max_amplicon_length = max_length;
+        self
+    }
+
+    /// Most bases between a forward hit and the reverse hit it is paired
+    /// with, the reverse hit being searched within this window only
+    pub fn max_pair_distance(mut self, max_distance: Option<usize>) -> Self {
+        self.config.max_pair_distance = max_distance;
+        self
+    }
+
+    /// Only keep the primer hits within `anchored` bases of the record ends
+    pub fn anchored(mut self, anchored: Option<usize>) -> Self {
+        self.config.anchored = anchored;
+        self
+    }
+
+    /// Search records in overlapping windows of `chunk_size` bases
+    pub fn chunk_size(mut self, chunk_size: Option<usize>) -> Self {
+        self.config.chunk_size = chunk_size;
+        self
+    }
+
+    /// Log the progress of each input periodically
+    pub fn progress(mut self, progress: bool) -> Self {
+        self.config.progress = progress;
+        self
+    }
+
+    /// Skip records longer than `max_record_len` bases
+    pub fn max_record_len(mut self, max_record_len: Option<usize>) -> Self {
+        self.config.max_record_len = max_record_len;
+        self
+    }
+
+    /// Skip records whose primer search outlasts `timeout`, checked
+    /// during the scans of each record and while pairing the primer hits
+    pub fn record_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.record_timeout = timeout;
+        self
+    }
+
+    /// Flush the outputs every `records` records
+    pub fn flush_every(mut self, records: Option<usize>) -> Self {
+        self.config.flush_every = records;
+        self
+    }
+
+    /// Sync the outputs to disk at each flush and at the end of a file
+    pub fn fsync(mut self, fsync: bool) -> Self {
+        self.config.fsync = fsync;
+        self
+    }
+
+    /// Append to the existing `<prefix>.*` outputs instead of overwriting
+    /// them, failing on outputs whose header differs
+    pub fn append(mut self, append: bool) -> Self {
+        self.config.append = append;
+        self
+    }
+
+    /// Locate the primer sites on the first sequence of the FASTA file
+    /// `path` and extract regions by transferring them to the sequences
+    /// through their alignment to it, instead of searching the primers in
+    /// each sequence. Sequences mutated at the primer sites keep their
+    /// regions, at the cost of an alignment per sequence.
+    pub fn reference(mut self, path: &str) -> Self {
+        self.reference = Some(path.to_string());
+        self
+    }
+
+    /// Stop the run before the next record once `flag` is set, e.g. by a
+    /// signal handler, leaving finalized outputs of the records handled so
+    /// far. The checkpoints of the run tell where each input stopped.
+    pub fn interrupt(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.config.interrupt = Some(flag);
+        self
+    }
+
+    /// Count the records and regions of the run in `stats`, rewritten
+    /// every few records or seconds to monitor the run
+    pub fn live_stats(mut self, stats: Arc<LiveStats>) -> Self {
+        self.config.live_stats = Some(stats);
+        self
+    }
+
+    /// Reuse the primer hits of identical sequences, keeping every distinct
+    /// sequence of a file in memory
+    pub fn cache_hits(mut self, cache_hits: bool) -> Self {
+        self.config.cache_hits = cache_hits;
+        self
+    }
+
+    /// Merge the read pairs of interleaved inputs, R1 and R2 alternating,
+    /// before searching them
+    pub fn interleaved(mut self, interleaved: bool) -> Self {
+        self.config.interleaved = interleaved;
+        self
+    }
+
+    /// Merge the reads of the input with their mates, the reverse reads in
+    /// `mates`, before searching them
+    pub fn mates(mut self, mates: Option<&str>) -> Self {
+        self.config.mates = mates.map(|mates| mates.to_string());
+        self
+    }
+
+    /// Shortest overlap of the mates of a pair to merge them
+    pub fn min_overlap(mut self, min_overlap: Option<usize>) -> Self {
+        self.config.min_overlap = min_overlap;
+        self
+    }
+
+    /// Coordinate convention of the hits TSV output
+    pub fn coords(mut self, coords: Coords) -> Self {
+        self.config.coords = coords;
+        self
+    }
+
+    /// Search primers on both strands of the sequences
+    pub fn both_strands(mut self, both_strands: bool) -> Self {
+        self.config.both_strands = both_strands;
+        self
+    }
+
+    /// Add the fraction of the degenerate primer mix matching each primer
+    /// hit to the FASTA headers, as `forward_score` and `reverse_score`
+    pub fn degenerate_score(mut self, degenerate_score: bool) -> Self {
+        self.config.degenerate_score = degenerate_score;
+        self
+    }
+
+    /// Write regions of the minus strand as read on the sequence instead of
+    /// reverse complementing them into the orientation of their gene
+    pub fn keep_strand(mut self, keep_strand: bool) -> Self {
+        self.config.keep_strand = keep_strand;
+        self
+    }
+
+    /// Search each sequence with the built-in regions of its marker gene,
+    /// classified from its k-mers. The primers should include the regions
+    /// of every gene, see [`crate::utils::gene_regions`].
+    pub fn auto_gene(mut self, auto_gene: bool) -> Self {
+        self.config.auto_gene = auto_gene;
+        self.config.tag_genes |= auto_gene;
+        self
+    }
+
+    pub fn homopolymer_tolerant(mut self, tolerant: bool) -> Self {
+        self.config.homopolymer_tolerant = tolerant;
+        self
+    }
+
+    /// Source of the lineages of the input sequences
+    pub fn taxonomy(mut self, taxonomy: Taxonomy) -> Self {
+        self.config.taxonomy = taxonomy;
+        self
+    }
+
+    /// Add the E. coli positions and SILVA alignment columns bounding the
+    /// regions of the built-in primers to the FASTA headers
+    pub fn silva_columns(mut self, columns: SilvaColumns) -> Self {
+        self.config.silva = Some(Arc::new(columns));
+        self
+    }
+
+    pub fn prefilter(mut self, prefilter: bool) -> Self {
+        self.config.prefilter = prefilter;
+        self
+    }
+
+    /// Check the settings and build the extractor
+    pub fn build(self) -> anyhow::Result<Extractor> {
+        let mut primers = self.primers;
+        let mut config = self.config;
+        let mut registry = RegionRegistry::default();
+        for region in self.regions.iter() {
+            if !registry.contains(region) && Path::new(region).is_file() {
+                registry.load(region)?;
+            }
+            primers.extend(registry.primers(region)?);
+        }
+        config.pair_options.extend(registry.pair_options().clone());
+        if primers.is_empty() {
+            return Err(anyhow!("No region or primer pair to extract"));
+        }
+        if primers.iter().flatten().any(|primer| primer.is_empty()) {
+            return Err(anyhow!("Primers cannot be empty"));
+        }
+        utils::check_primer_pairs(&primers)?;
+        if config
+            .subsample
+            .is_some_and(|fraction| !(0.0..=1.0).contains(&fraction))
+        {
+            return Err(anyhow!(
+                "Subsampling fraction must be between 0 and 1"
+            ));
+        }
+        utils::check_mismatches(&primers, &config)?;
+        if let Some(path) = self.reference.as_deref() {
+            let reference = Reference::locate(path, &primers, &config)
+                .with_context(|| {
+                    format!("Cannot locate primers on {}", path)
+                })?;
+            config.reference = Some(Arc::new(reference));
+        }
+        config.decompress_threads = self.threads.max(1);
+
+        Ok(Extractor {
+            primers,
+            config,
+            threads: self.threads.max(1),
+        })
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_build() {
+        let extractor = Extractor::builder()
+            .mismatch(2)
+            .regions(["v3v4"])
+            .primer_pair("GTGYCAGCMGCCGCGGTAA", "GGACTACNVGGGTWTCTAAT")
+            .threads(0)
+            .build()
+            .unwrap();
+        assert_eq!(extractor.primers().len(), 2);
+        assert_eq!(extractor.config().mismatch, 2);
+        assert_eq!(extractor.threads, 1);
+
+        assert!(Extractor::builder().build().is_err());
+        assert!(Extractor::builder().regions(["v10"]).build().is_err());
+        assert!(Extractor::builder()
+            .mismatch(30)
+            .regions(["v4"])
+            .build()
+            .is_err());
+        // The forward primer of v4 is 19 bases long
+        assert!(Extractor::builder()
+            .mismatch(19)
+            .regions(["v4"])
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_extract_trimmed() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let extractor = Extractor::builder()
+            .regions(["v3v4"])
+            .trim_primers(true)
+            .build()
+            .unwrap();
+
+        let summary = extractor.extract("tests/test.fa", prefix).unwrap();
+        assert_eq!(summary.extracted, 1);
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.contains("\t285\t686\t"));
+    }
+
+    #[test]
+    fn test_extract_one_based() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let extractor = Extractor::builder()
+            .regions(["v3v4"])
+            .coords(Coords::OneBased)
+            .build()
+            .unwrap();
+
+        extractor.extract("tests/test.fa", prefix).unwrap();
+        let hits = fs::read_to_string(format!("{}.hits.tsv", prefix)).unwrap();
+        assert!(hits.contains("\tforward\t268\t284\t"));
+        // GFF3 coordinates do not depend on the convention
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.contains("\t268\t707\t"));
+    }
+
+    #[test]
+    fn test_run_with_callback() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let extractor = Extractor::builder()
+            .regions(["v3v4", "v4"])
+            .threads(2)
+            .build()
+            .unwrap();
+        let files =
+            vec!["tests/test.fa".to_string(), "tests/test.fa.gz".to_string()];
+
+        let mut seen = Vec::new();
+        extractor
+            .run_with_callback(
+                &files,
+                prefix,
+                &mut ExtraOutputs::default(),
+                |region_match, record| {
+                    seen.push((record.id().to_string(), region_match.name()))
+                },
+            )
+            .unwrap();
+        seen.sort();
+        assert_eq!(seen.len(), 4);
+        assert_eq!(seen[0].1, "v3v4");
+    }
+}
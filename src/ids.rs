@@ -0,0 +1,148 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use anyhow::Context;
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+// Characters with a meaning in the attributes column of GFF3 records
+const RESERVED: &[char] = &[';', '=', '&', ',', '%', '\t', '\n', '\r'];
+
+/// Allocator of the `ID` attributes of the GFF3 records, unique across the
+/// regions and amplicons of a sequence and the runs appended to an output.
+///
+/// The first region of a sequence is `<sequence>.<region>`, e.g. `a.v3v4`,
+/// and its other copies `<sequence>.<region>.2`, `<sequence>.<region>.3`,
+/// so that ids only depend on the order of the regions.
+#[derive(Debug, Clone, Default)]
+pub struct GffIds {
+    used: HashSet<String>,
+}
+
+impl GffIds {
+    /// Allocator taking the ids of an existing GFF3 file as used, a missing
+    /// file having none
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let mut ids = GffIds::default();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ids),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Cannot read {}", path))
+            }
+        };
+        for line in io::BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("Cannot read {}", path))?;
+            if let Some(id) = record_id(&line) {
+                ids.used.insert(id.to_string());
+            }
+        }
+
+        Ok(ids)
+    }
+
+    /// Id of the next region of a sequence
+    pub fn allocate(&mut self, seq_id: &str, region: &str) -> String {
+        let region = if region.is_empty() { "region" } else { region };
+        self.reserve(&format!("{}.{}", escape(seq_id), escape(region)))
+    }
+
+    /// Reserve `id`, or its first free numbered copy when already used
+    pub fn reserve(&mut self, id: &str) -> String {
+        let mut reserved = id.to_string();
+        let mut copy = 1;
+        while self.used.contains(&reserved) {
+            copy += 1;
+            reserved = format!("{}.{}", id, copy);
+        }
+        self.used.insert(reserved.clone());
+
+        reserved
+    }
+
+    /// GFF3 record with its id reserved, renamed when already used, e.g.
+    /// when merging outputs. Other lines are kept.
+    pub fn reserve_record(&mut self, line: &str) -> String {
+        let id = match record_id(line) {
+            Some(id) => id,
+            None => return line.to_string(),
+        };
+        let reserved = self.reserve(id);
+        if reserved == id {
+            return line.to_string();
+        }
+        let (columns, attributes) = line.rsplit_once('\t').unwrap_or_default();
+        let attributes = attributes
+            .split(';')
+            .map(|attribute| match attribute.strip_prefix("ID=") {
+                Some(_) => format!("ID={}", reserved),
+                None => attribute.to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        format!("{}\t{}", columns, attributes.join(";"))
+    }
+}
+
+// Id attribute of a GFF3 record
+fn record_id(line: &str) -> Option<&str> {
+    if line.starts_with('#') {
+        return None;
+    }
+    line.split('\t')
+        .nth(8)?
+        .split(';')
+        .find_map(|attribute| attribute.strip_prefix("ID="))
+}
+
+/// Value percent-encoded for the attributes column of GFF3 records
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if RESERVED.contains(&c) {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+
+    escaped
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_gff_ids() {
+        let mut ids = GffIds::default();
+        assert_eq!(ids.allocate("a", "v3v4"), "a.v3v4");
+        assert_eq!(ids.allocate("a", "v3v4"), "a.v3v4.2");
+        assert_eq!(ids.allocate("a", "v4"), "a.v4");
+        assert_eq!(ids.allocate("b;c", ""), "b%3Bc.region");
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.gff");
+        let path = path.to_str().unwrap();
+        assert!(GffIds::read(path).unwrap().used.is_empty());
+        fs::write(
+            path,
+            "##gff-version 3\n\
+            a\thyperex\tregion\t1\t9\t.\t.\t.\tID=a.v4;Name=v4\n",
+        )
+        .unwrap();
+        let mut ids = GffIds::read(path).unwrap();
+        assert_eq!(ids.allocate("a", "v4"), "a.v4.2");
+        assert_eq!(
+            ids.reserve_record("a\th\tr\t1\t9\t.\t.\t.\tID=a.v4;Name=v4"),
+            "a\th\tr\t1\t9\t.\t.\t.\tID=a.v4.3;Name=v4"
+        );
+        assert_eq!(ids.reserve_record("# comment"), "# comment");
+    }
+}
@@ -0,0 +1,403 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils::{self, PairOptions};
+
+use anyhow::{anyhow, Context};
+use log::info;
+
+use std::fs;
+use std::str::FromStr;
+
+// Symbols of primers, bases and IUPAC ambiguity codes
+const PRIMER_SYMBOLS: &[u8] = b"ACGTUMRWSYKVHDBNI";
+// Suffixes telling the forward adapter of a pair in a FASTA file
+const FORWARD_SUFFIXES: [&str; 6] =
+    ["_forward", "-forward", "_fwd", "-fwd", "_f", "-f"];
+
+/// Formats of the primer lists of other tools
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrimerFormat {
+    // QIIME mapping file, with LinkerPrimerSequence and ReversePrimer
+    // columns
+    Qiime,
+    // FASTA file of cutadapt adapters, either linked as FWD...REVRC or
+    // given as consecutive forward and reverse records
+    Cutadapt,
+    // Tab-separated forward and reverse primers, optionally after a name
+    Tsv,
+}
+
+impl FromStr for PrimerFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "qiime" => Ok(PrimerFormat::Qiime),
+            "cutadapt" => Ok(PrimerFormat::Cutadapt),
+            "tsv" => Ok(PrimerFormat::Tsv),
+            _ => Err(anyhow!(
+                "Unknown primer format {}, expected qiime, cutadapt or tsv",
+                s
+            )),
+        }
+    }
+}
+
+impl PrimerFormat {
+    /// Format of a primer list, from its first line
+    pub fn detect(content: &str) -> Self {
+        let first = content.lines().find(|line| !line.trim().is_empty());
+        match first.map(str::trim_start) {
+            Some(line) if line.starts_with("#SampleID") => PrimerFormat::Qiime,
+            Some(line) if line.starts_with('>') => PrimerFormat::Cutadapt,
+            _ => PrimerFormat::Tsv,
+        }
+    }
+}
+
+/// Primer pair read from the primer list of another tool
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedPair {
+    pub forward: String,
+    pub reverse: String,
+    pub label: Option<String>,
+}
+
+/// Read the primer pairs of `content`, a primer list in `format`. Pairs
+/// listed several times, e.g. by every sample of a QIIME mapping file, are
+/// kept once.
+pub fn parse(
+    content: &str,
+    format: PrimerFormat,
+) -> anyhow::Result<Vec<ImportedPair>> {
+    let pairs = match format {
+        PrimerFormat::Qiime => parse_qiime(content)?,
+        PrimerFormat::Cutadapt => parse_cutadapt(content)?,
+        PrimerFormat::Tsv => parse_tsv(content)?,
+    };
+    let mut unique: Vec<ImportedPair> = Vec::new();
+    for pair in pairs {
+        if !unique
+            .iter()
+            .any(|u| u.forward == pair.forward && u.reverse == pair.reverse)
+        {
+            unique.push(pair);
+        }
+    }
+    if unique.is_empty() {
+        return Err(anyhow!("No primer pair found"));
+    }
+
+    Ok(unique)
+}
+
+/// Primer pairs as a TOML primers file, to be given to --region
+pub fn to_primers_file(pairs: &[ImportedPair]) -> anyhow::Result<String> {
+    let pairs = pairs
+        .iter()
+        .map(|pair| {
+            (
+                vec![pair.forward.clone(), pair.reverse.clone()],
+                PairOptions {
+                    label: pair.label.clone(),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    utils::primers_to_toml(&pairs)
+}
+
+/// Convert the primer list `path` to a primers file, its format being
+/// detected when not given
+pub fn import(
+    path: &str,
+    format: Option<PrimerFormat>,
+) -> anyhow::Result<String> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Cannot read {}", path))?;
+    let format = format.unwrap_or_else(|| PrimerFormat::detect(&content));
+    let pairs = parse(&content, format)
+        .with_context(|| format!("Cannot import primers of {}", path))?;
+
+    to_primers_file(&pairs)
+}
+
+// Primer in upper case, refusing symbols other than bases and ambiguity
+// codes
+fn primer(symbols: &str, line: usize) -> anyhow::Result<String> {
+    if !is_primer(symbols) {
+        return Err(anyhow!("line {}: invalid primer {:?}", line, symbols));
+    }
+    let primer = symbols.trim().to_ascii_uppercase();
+    // Inosine pairs with any base but is not an IUPAC code of the matcher
+    if primer.contains('I') {
+        info!("line {}: inosine of primer {} read as N", line, primer);
+        return Ok(primer.replace('I', "N"));
+    }
+    Ok(primer)
+}
+
+fn is_primer(symbols: &str) -> bool {
+    let primer = symbols.trim();
+    !primer.is_empty()
+        && primer
+            .bytes()
+            .all(|b| PRIMER_SYMBOLS.contains(&b.to_ascii_uppercase()))
+}
+
+fn parse_qiime(content: &str) -> anyhow::Result<Vec<ImportedPair>> {
+    let mut lines = content.lines().enumerate();
+    let header = lines
+        .by_ref()
+        .find(|(_, line)| line.starts_with("#SampleID"))
+        .map(|(_, line)| line.split('\t').collect::<Vec<_>>())
+        .ok_or_else(|| anyhow!("No #SampleID header line"))?;
+    let column = |name: &str| {
+        header.iter().position(|c| c.trim() == name).ok_or_else(|| {
+            anyhow!("No {} column in the QIIME mapping file", name)
+        })
+    };
+    let (forward, reverse) =
+        (column("LinkerPrimerSequence")?, column("ReversePrimer")?);
+
+    let mut pairs = Vec::new();
+    for (i, line) in lines {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let cell = |n: usize| {
+            fields.get(n).ok_or_else(|| {
+                anyhow!("line {}: expected {} columns", i + 1, header.len())
+            })
+        };
+        // Several primers of a sample are separated by commas
+        let (forwards, reverses) = (cell(forward)?, cell(reverse)?);
+        for forward in forwards.split(',') {
+            for reverse in reverses.split(',') {
+                pairs.push(ImportedPair {
+                    forward: primer(forward, i + 1)?,
+                    reverse: primer(reverse, i + 1)?,
+                    label: None,
+                });
+            }
+        }
+    }
+
+    Ok(pairs)
+}
+
+fn parse_cutadapt(content: &str) -> anyhow::Result<Vec<ImportedPair>> {
+    // Records as (name, line of the header, sequence)
+    let mut records: Vec<(String, usize, String)> = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('>') {
+            let name = name.split_whitespace().next().unwrap_or_default();
+            records.push((name.to_string(), i + 1, String::new()));
+        } else if !line.is_empty() {
+            match records.last_mut() {
+                Some((_, _, seq)) => seq.push_str(line),
+                None => {
+                    return Err(anyhow!("line {}: expected a header", i + 1))
+                }
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let mut unpaired: Option<(String, usize, String)> = None;
+    for (name, line, seq) in records {
+        if let Some((forward, reverse)) = seq.split_once("...") {
+            // The reverse primer is read through, reverse complemented
+            let reverse = primer(strip(reverse), line)?;
+            pairs.push(ImportedPair {
+                forward: primer(strip(forward), line)?,
+                reverse: utils::to_reverse_complement(&reverse, "dna"),
+                label: Some(name).filter(|name| !name.is_empty()),
+            });
+            continue;
+        }
+        match unpaired.take() {
+            None => unpaired = Some((name, line, seq)),
+            Some((forward_name, forward_line, forward)) => {
+                let lower = forward_name.to_ascii_lowercase();
+                let label = FORWARD_SUFFIXES
+                    .iter()
+                    .find(|suffix| lower.ends_with(*suffix))
+                    .map_or(forward_name.as_str(), |suffix| {
+                        &forward_name[..forward_name.len() - suffix.len()]
+                    });
+                pairs.push(ImportedPair {
+                    forward: primer(strip(&forward), forward_line)?,
+                    reverse: primer(strip(&seq), line)?,
+                    label: Some(label.to_string())
+                        .filter(|label| !label.is_empty()),
+                });
+            }
+        }
+    }
+    if let Some((name, line, _)) = unpaired {
+        return Err(anyhow!(
+            "line {}: adapter {} has no reverse primer after it",
+            line,
+            name
+        ));
+    }
+
+    Ok(pairs)
+}
+
+// Adapter without the anchors and other cutadapt markers
+fn strip(seq: &str) -> &str {
+    seq.trim_matches(|c| c == '^' || c == '$' || c == 'X')
+}
+
+fn parse_tsv(content: &str) -> anyhow::Result<Vec<ImportedPair>> {
+    let mut pairs = Vec::new();
+    let mut first = true;
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').map(str::trim).collect::<Vec<_>>();
+        let (label, forward, reverse) = match fields[..] {
+            [forward, reverse] => (None, forward, reverse),
+            [label, forward, reverse, ..] => (Some(label), forward, reverse),
+            _ => {
+                return Err(anyhow!(
+                    "line {}: expected [name<TAB>]forward<TAB>reverse",
+                    i + 1
+                ))
+            }
+        };
+        // The first line may be a header naming the columns
+        let header = first && !is_primer(forward);
+        first = false;
+        if header {
+            continue;
+        }
+        pairs.push(ImportedPair {
+            forward: primer(forward, i + 1)?,
+            reverse: primer(reverse, i + 1)?,
+            label: label.filter(|l| !l.is_empty()).map(str::to_string),
+        });
+    }
+
+    Ok(pairs)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(forward: &str, reverse: &str, label: Option<&str>) -> ImportedPair {
+        ImportedPair {
+            forward: forward.to_string(),
+            reverse: reverse.to_string(),
+            label: label.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let qiime = "#SampleID\tBarcodeSequence\tLinkerPrimerSequence\tReversePrimer\tDescription\n\
+            #a comment\n\
+            s1\tACGT\tGTGYCAGCMGCCGCGGTAA\tGGACTACNVGGGTWTCTAAT\tsoil\n\
+            s2\tTGCA\tGTGYCAGCMGCCGCGGTAA\tGGACTACNVGGGTWTCTAAT\tsoil\n";
+        assert_eq!(PrimerFormat::detect(qiime), PrimerFormat::Qiime);
+        assert_eq!(
+            parse(qiime, PrimerFormat::Qiime).unwrap(),
+            vec![pair("GTGYCAGCMGCCGCGGTAA", "GGACTACNVGGGTWTCTAAT", None)]
+        );
+        assert!(parse(
+            "#SampleID\tLinkerPrimerSequence\n",
+            PrimerFormat::Qiime
+        )
+        .is_err());
+
+        let cutadapt = ">V4\n^GTGYCAGCMGCCGCGGTAA...ATTAGAWACCCBNGTAGTCC$\n\
+            >V3V4_fwd\nCCTACGGGNGGCWGCAG\n>V3V4_rev\nGACTACHVGGGTATCTAATCC\n";
+        assert_eq!(PrimerFormat::detect(cutadapt), PrimerFormat::Cutadapt);
+        assert_eq!(
+            parse(cutadapt, PrimerFormat::Cutadapt).unwrap(),
+            vec![
+                pair("GTGYCAGCMGCCGCGGTAA", "GGACTACNVGGGTWTCTAAT", Some("V4")),
+                pair(
+                    "CCTACGGGNGGCWGCAG",
+                    "GACTACHVGGGTATCTAATCC",
+                    Some("V3V4")
+                ),
+            ]
+        );
+        assert!(parse(">a\nACGTACGT\n", PrimerFormat::Cutadapt).is_err());
+
+        let tsv = "name\tforward\treverse\nv4\tgtgycagcmgccgcggtaa\tGGACTACNVGGGTWTCTAAT\n";
+        assert_eq!(PrimerFormat::detect(tsv), PrimerFormat::Tsv);
+        let pairs = parse(tsv, PrimerFormat::Tsv).unwrap();
+        assert_eq!(
+            pairs,
+            vec![pair(
+                "GTGYCAGCMGCCGCGGTAA",
+                "GGACTACNVGGGTWTCTAAT",
+                Some("v4")
+            )]
+        );
+        assert!(parse("ACGT\tACGZ\n", PrimerFormat::Tsv).is_err());
+        // Only the first line may be a header
+        assert!(parse(
+            "name\tforward\treverse\nv4\tGTGYCAGCMG!\tGGACTAC\nv3\tACGT\tACGT\n",
+            PrimerFormat::Tsv
+        )
+        .is_err());
+        // Inosine is read as any base
+        assert_eq!(
+            parse("ACGITT\tTTIGCA\n", PrimerFormat::Tsv).unwrap(),
+            vec![pair("ACGNTT", "TTNGCA", None)]
+        );
+        assert!(parse("name\tforward\treverse\n", PrimerFormat::Tsv).is_err());
+
+        assert_eq!(
+            to_primers_file(&[
+                pairs[0].clone(),
+                pair("ACGTACGT", "TTGGCCAA", None)
+            ])
+            .unwrap(),
+            "[[pair]]\n\
+            forward = \"GTGYCAGCMGCCGCGGTAA\"\n\
+            reverse = \"GGACTACNVGGGTWTCTAAT\"\n\
+            label = \"v4\"\n\n\
+            [[pair]]\n\
+            forward = \"ACGTACGT\"\n\
+            reverse = \"TTGGCCAA\"\n"
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("primers.tsv");
+        fs::write(
+            &path,
+            "V4, V4-V5\tGTGYCAGCMGCCGCGGTAA\tGGACTACNVGGGTWTCTAAT\n",
+        )
+        .unwrap();
+        let imported = import(path.to_str().unwrap(), None).unwrap();
+        let primers = dir.path().join("primers.toml");
+        fs::write(&primers, imported).unwrap();
+
+        // Imported primers are read back by --region, labels with commas
+        // included
+        let pairs =
+            utils::read_primers_file(primers.to_str().unwrap()).unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].1.label.as_deref(), Some("V4, V4-V5"));
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+//! Hypervariable region primer-based extraction.
+//!
+//! The [`Extractor`] is the entry point of the library, see
+//! [`Extractor::builder`].
+//!
+//! # Logging
+//!
+//! The library logs progress and warnings through the [`log`] facade and
+//! never installs a logger itself: messages go to the logger of the host
+//! application, e.g. `env_logger`, or to `tracing` subscribers through
+//! `tracing-log`, and are dropped when there is none.
+//!
+//! ```
+//! struct Warnings;
+//!
+//! impl log::Log for Warnings {
+//!     fn enabled(&self, metadata: &log::Metadata) -> bool {
+//!         metadata.level() <= log::Level::Warn
+//!     }
+//!
+//!     fn log(&self, record: &log::Record) {
+//!         if self.enabled(record.metadata()) {
+//!             eprintln!("hyperex: {}", record.args());
+//!         }
+//!     }
+//!
+//!     fn flush(&self) {}
+//! }
+//!
+//! log::set_boxed_logger(Box::new(Warnings))?;
+//! log::set_max_level(log::LevelFilter::Warn);
+//! # Ok::<(), log::SetLoggerError>(())
+//! ```
+
+pub mod bam;
+pub mod batch;
+pub mod bench;
+pub mod bgzf;
+pub mod bundle;
+pub mod classify;
+pub mod coords;
+pub mod count;
+pub mod coverage;
+pub mod diff;
+pub mod extractor;
+pub mod ids;
+pub mod import;
+pub mod matcher;
+pub mod ncbi;
+pub mod operon;
+pub mod pairs;
+pub mod parquet;
+pub mod plot;
+pub mod prefilter;
+pub mod primerdist;
+pub mod probe;
+pub mod reference;
+pub mod remote;
+pub mod report;
+pub mod rng;
+pub mod search;
+pub mod silva;
+pub mod sink;
+pub mod slice;
+pub mod sqlite;
+pub mod sra;
+pub mod stats;
+pub mod sweep;
+pub mod taxonomy;
+pub mod tbl;
+pub mod thermo;
+pub mod track;
+pub mod utils;
+pub mod view;
+pub mod watch;
+
+pub use bio::io::fasta::Record;
+pub use extractor::{Extractor, ExtractorBuilder};
+pub use utils::{Config, RegionMatch, RunSummary};
@@ -0,0 +1,86 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use fern::colors::ColoredLevelConfig;
+
+use std::io;
+use std::path::Path;
+
+/// Log to the terminal and to `log_path`. The library only logs through
+/// the `log` facade, the logger being installed here for the command-line
+/// interface alone. Terminal logs go to the standard error with
+/// `to_stderr`, or to `terminal`, e.g. the dashboard of --tui, when given.
+pub fn setup_logging(
+    quiet: bool,
+    to_stderr: bool,
+    terminal: Option<Box<dyn log::Log>>,
+    log_path: &Path,
+) -> anyhow::Result<(), fern::InitError> {
+    let colors = ColoredLevelConfig::default();
+    let mut base_config = fern::Dispatch::new();
+
+    base_config = match quiet {
+        // if user required quietness let only output warning messages
+        // or messages more severe than warnings
+        true => base_config.level(log::LevelFilter::Warn),
+        // if quietness is not specified which implies verbosity is allowed
+        // output
+        false => base_config.level(log::LevelFilter::Debug),
+    };
+
+    // Separate file config so we can include year, month and day in file logs
+    let file_config = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .chain(fern::log_file(log_path)?);
+
+    let stdout_config =
+        fern::Dispatch::new().format(move |out, message, record| {
+            out.finish(format_args!(
+                "[{}][{}] {}",
+                chrono::Local::now().format("%H:%M:%S"),
+                colors.color(record.level()),
+                message
+            ))
+        });
+    // Standard output is left to machine-readable results when asked
+    let stdout_config = if let Some(terminal) = terminal {
+        stdout_config.chain(terminal)
+    } else if to_stderr {
+        stdout_config.chain(io::stderr())
+    } else {
+        stdout_config.chain(io::stdout())
+    };
+
+    base_config
+        .chain(file_config)
+        .chain(stdout_config)
+        .apply()?;
+
+    Ok(())
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_logging() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let log_path = dir.path().join("hyperex.log");
+        assert!(setup_logging(false, false, None, &log_path).is_ok());
+        assert!(log_path.exists());
+        // A single logger per process
+        assert!(setup_logging(false, false, None, &log_path).is_err());
+    }
+}
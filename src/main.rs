@@ -4,149 +4,439 @@
 // to those terms.
 
 mod app;
-mod utils;
+mod dashboard;
+mod logging;
 
+use dashboard::Dashboard;
+use hyperex::bam::BamWriter;
+use hyperex::coords::Coords;
+use hyperex::matcher::AmbigMap;
+use hyperex::parquet::ParquetSink;
+use hyperex::plot::RegionPlot;
+use hyperex::report::{JsonSink, RegionsSink, RunReport};
+use hyperex::silva::SilvaColumns;
+use hyperex::sink::Sinks;
+use hyperex::sqlite::SqliteSink;
+use hyperex::stats::LiveStats;
+use hyperex::taxonomy::Taxonomy;
+use hyperex::tbl::FeatureTable;
+use hyperex::track::VariabilityTrack;
+use hyperex::{
+    bench, bundle, count, coverage, diff, import, ncbi, primerdist, probe,
+    remote, rng, slice, sra, sweep, thermo, utils, view, watch, Extractor,
+};
+
+use anyhow::{anyhow, Context};
 use bio::io::fasta;
-use clap::crate_version;
+use clap::{crate_version, ArgMatches};
 use log::{error, info, warn};
 
 use std::env;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::ops::RangeInclusive;
 use std::path::Path;
 use std::process;
-use std::time::Instant;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 fn main() -> anyhow::Result<()> {
     // Starting up the Walltime chrono
     let startime = Instant::now();
-    let stderr = std::io::stderr();
-    let mut ehandle = stderr.lock();
+    // Locked by each write only, as logs and the dashboard of --tui are
+    // written to it from other threads
+    let mut ehandle = std::io::stderr();
 
     // Get command-line arguments (see app.rs)
     let matches = app::build_app().get_matches_from(env::args_os());
 
     // is --quiet option specified by the user?
     let quiet = matches.get_flag("quiet");
-    utils::setup_logging(quiet)?; // Settting up logging
+    // Machine-readable outcome printed on stdout with --porcelain
+    let porcelain = matches.get_flag("porcelain");
+    // Live dashboard of extraction runs, plain logs when not on a terminal
+    let tui = matches.get_flag("tui") && matches.subcommand().is_none();
+    let mut dashboard =
+        (tui && io::stderr().is_terminal()).then(Dashboard::start);
+    let terminal = dashboard.as_ref().map(|dashboard| dashboard.logger());
+    let log_path = Path::new("hyperex.log");
+    logging::setup_logging(quiet, porcelain, terminal, log_path)?; // Settting up logging
+    if tui && dashboard.is_none() {
+        info!("Standard error is not a terminal, --tui falls back to logs");
+    }
 
-    // Reading input data
-    // This can be a piped data or a filename
-    // So we match the value to '-' or some other value and read it
-    let infile = match matches.get_one::<String>("FILE") {
-        // Read from file if passed arg is not '-', otherwise read from stdin
-        Some(value) => {
-            if value == "-" {
-                let mut writer = fasta::Writer::to_file("infile.fa")?;
-                let mut records = fasta::Reader::new(io::stdin()).records();
-                while let Some(Ok(record)) = records.next() {
-                    writer.write_record(&record)?;
-                }
-                "infile.fa"
-            } else {
-                value
-            }
-        }
-        // Read from STDIN
-        None => {
-            let mut writer = fasta::Writer::to_file("infile.fa")?;
-            let mut records = fasta::Reader::new(io::stdin()).records();
-            while let Some(Ok(record)) = records.next() {
-                writer.write_record(&record)?;
-            }
-            "infile.fa"
+    if matches.get_flag("primer_db_version") {
+        writeln!(std::io::stdout(), "{}", utils::PRIMER_DB_VERSION)?;
+        return Ok(());
+    }
+
+    if let Some(("view", view_matches)) = matches.subcommand() {
+        let file = view_matches.get_one::<String>("FILE").unwrap();
+        let ids = view_matches
+            .get_many::<String>("id")
+            .map(|values| values.map(|v| v.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let primers = select_primers(view_matches)?;
+        let config = utils::Config {
+            mismatch: *view_matches.get_one("mismatch").unwrap(),
+            ambigs: ambig_map(view_matches)?,
+            no_indels: view_matches.get_flag("no_indels"),
+            bisulfite: view_matches.get_flag("bisulfite"),
+            ..Default::default()
+        };
+        let context: usize = *view_matches.get_one("context").unwrap();
+        let stdout = std::io::stdout();
+        let color = stdout.is_terminal() && env::var_os("NO_COLOR").is_none();
+        let mut handle = stdout.lock();
+        return view::view(
+            file,
+            &ids,
+            &primers,
+            &config,
+            context,
+            color,
+            &mut handle,
+        );
+    }
+
+    if let Some(("count", count_matches)) = matches.subcommand() {
+        let stdout = std::io::stdout();
+        let mut handle = stdout.lock();
+        writeln!(handle, "file\trecords\tbases")?;
+        for file in count_matches.get_many::<String>("FILE").unwrap() {
+            let counts = count::count(file)
+                .with_context(|| format!("Cannot count records of {}", file))?;
+            writeln!(handle, "{}\t{}\t{}", file, counts.records, counts.bases)?;
         }
-    };
+        return Ok(());
+    }
+
+    if let Some(("diff", diff_matches)) = matches.subcommand() {
+        let old = diff_matches.get_one::<String>("OLD").unwrap();
+        let new = diff_matches.get_one::<String>("NEW").unwrap();
+        let (changes, unchanged) = diff::diff(
+            &diff::read_regions(old)
+                .with_context(|| format!("Cannot read regions of {}", old))?,
+            &diff::read_regions(new)
+                .with_context(|| format!("Cannot read regions of {}", new))?,
+        );
+        write!(std::io::stdout(), "{}", diff::report(&changes, unchanged))?;
+        return Ok(());
+    }
+
+    if let Some(("extract-by-gff", slice_matches)) = matches.subcommand() {
+        let gff = slice_matches.get_one::<String>("GFF").unwrap();
+        let file = slice_matches.get_one::<String>("FILE").unwrap();
+        let intervals = diff::read_gff(gff)
+            .with_context(|| format!("Cannot read regions of {}", gff))?;
+        let stdout = std::io::stdout();
+        let counts = slice::extract(
+            file,
+            &intervals,
+            &mut fasta::Writer::new(stdout.lock()),
+        )?;
+        info!(
+            "{} regions extracted, {} out of their sequence and {} on missing sequences",
+            counts.extracted, counts.out_of_bounds, counts.missing
+        );
+        return Ok(());
+    }
+
+    if let Some(("bench", bench_matches)) = matches.subcommand() {
+        let primers = select_primers(bench_matches)?;
+        let threads: usize = *bench_matches.get_one("threads").unwrap();
+        let workload = bench::Workload {
+            records: *bench_matches.get_one("records").unwrap(),
+            length: *bench_matches.get_one("length").unwrap(),
+            seed: *bench_matches.get_one("seed").unwrap(),
+        };
+        let records = bench::synthetic_records(&primers, &workload)?;
+        let extractor = Extractor::builder()
+            .primer_pairs(&primers)
+            .threads(threads)
+            .build()?;
+        let dir =
+            env::temp_dir().join(format!("hyperex_bench_{}", process::id()));
+        fs::create_dir_all(&dir)?;
+        let result = bench::run(&extractor, &records, threads, &dir);
+        bench::clean(&dir);
+        write!(std::io::stdout(), "{}", bench::report(&result?))?;
+        return Ok(());
+    }
 
-    // Check that the supplied file exists
-    if infile != "infile.fa" {
-        match Path::new(infile).exists() {
-            true => (),
-            false => {
-                writeln!(ehandle, "error: No such file or directory. Is the path correct? Do you have permission to read the file?")?;
-                process::exit(1);
+    if let Some(("probe", probe_matches)) = matches.subcommand() {
+        let file = probe_matches.get_one::<String>("FILE").unwrap();
+        let panel = match probe_matches.get_one::<String>("panel") {
+            Some(panel) => Some(probe::read_panel(panel)?),
+            None => None,
+        };
+        let probes = match &panel {
+            Some(panel) => panel.iter().map(|(_, p)| p.clone()).collect(),
+            None => probe_matches
+                .get_many::<String>("probe")
+                .unwrap()
+                .map(|probe| probe.to_ascii_uppercase())
+                .collect::<Vec<_>>(),
+        };
+        let config = utils::Config {
+            mismatch: *probe_matches.get_one("mismatch").unwrap(),
+            ambigs: ambig_map(probe_matches)?,
+            no_indels: probe_matches.get_flag("no_indels"),
+            bisulfite: probe_matches.get_flag("bisulfite"),
+            ..Default::default()
+        };
+        let (sites, ids) = probe::probe_sites(file, &probes, &config)?;
+        let report = match &panel {
+            Some(panel) => probe::matrix(&sites, panel, &ids),
+            None => probe::report(&sites, &probes, ids.len()),
+        };
+        write!(std::io::stdout(), "{}", report)?;
+        return Ok(());
+    }
+
+    if let Some(("validate", validate_matches)) = matches.subcommand() {
+        let primers = select_primers(validate_matches)?;
+        let stdout = std::io::stdout();
+        let mut handle = stdout.lock();
+        for pair in primers.iter() {
+            let region = utils::primers_to_region(pair.to_vec());
+            writeln!(
+                handle,
+                "{} {} {}",
+                if region.is_empty() { "pair" } else { &region },
+                pair[0],
+                pair[1]
+            )?;
+            if let Err(e) = utils::check_primer_pairs(&[pair.to_vec()]) {
+                writeln!(handle, "  error: {}", e)?;
             }
+            let (report, _) = thermo::report(&pair[0], &pair[1]);
+            write!(handle, "{}", report)?;
         }
+        return Ok(());
     }
 
-    // Read prefix for output files
-    let prefix = matches.get_one::<String>("prefix").unwrap();
-    let force = matches.get_flag("force");
-    if !force {
-        if Path::new(format!("{}.fa", prefix).as_str()).exists()
-            || Path::new(format!("{}.gff", prefix).as_str()).exists()
-        {
-            writeln!(std::io::stderr(), "error: file already exists. Please change it using --prefix option or use --force to overwrite it")?;
-            process::exit(1);
-        }
-    } else if force {
-        fs::remove_file(format!("{}.fa", prefix).as_str())?;
-        fs::remove_file(format!("{}.gff", prefix).as_str())?;
+    if let Some(("primer-dist", dist_matches)) = matches.subcommand() {
+        let primers = select_primers(dist_matches)?;
+        let max_dist: usize = *dist_matches.get_one("max_dist").unwrap();
+        write!(
+            std::io::stdout(),
+            "{}",
+            primerdist::report(
+                &primerdist::primer_labels(&primers),
+                &ambig_map(dist_matches)?,
+                max_dist
+            )
+        )?;
+        return Ok(());
     }
 
-    // Get primers from command-line as a list of primer can be specified
-    let mut primers: Vec<Vec<String>> = Vec::new();
-    let all = [
-        "v1v2", "v1v3", "v1v9", "v3v4", "v3v5", "v4", "v4v5", "v5v7", "v6v9",
-        "v7v9",
-    ];
+    if let Some(("import-primers", import_matches)) = matches.subcommand() {
+        let file: &String = import_matches.get_one("import_file").unwrap();
+        let format = import_matches
+            .get_one::<String>("import_format")
+            .map(|format| format.parse())
+            .transpose()?;
+        write!(std::io::stdout(), "{}", import::import(file, format)?)?;
+        return Ok(());
+    }
 
-    // Case the user go for -f and -r options
-    if matches.get_flag("forward_primer") && primers.is_empty() {
-        // Read supplied forward and reverse primers
-        let first: Vec<&str> = matches
-            .get_many::<String>("forward_primer")
-            .unwrap_or_default()
-            .map(|v| v.as_str())
-            .collect::<Vec<_>>();
-        let second: Vec<&str> = matches
-            .get_many::<String>("reverse_primer")
-            .unwrap_or_default()
-            .map(|v| v.as_str())
-            .collect::<Vec<_>>();
+    if let Some(("coverage", coverage_matches)) = matches.subcommand() {
+        let file = coverage_matches.get_one::<String>("FILE").unwrap();
+        let primers = select_primers(coverage_matches)?;
+        let mismatch: u8 = *coverage_matches.get_one("mismatch").unwrap();
+        let config = utils::Config {
+            mismatch,
+            ambigs: ambig_map(coverage_matches)?,
+            no_indels: coverage_matches.get_flag("no_indels"),
+            bisulfite: coverage_matches.get_flag("bisulfite"),
+            taxonomy: match coverage_matches.get_one::<String>("taxonomy") {
+                Some(path) => Taxonomy::from_file(path)?,
+                None => Taxonomy::Headers,
+            },
+            ..Default::default()
+        };
+        let rank: usize = *coverage_matches.get_one("rank").unwrap();
+        let top: usize = *coverage_matches.get_one("top").unwrap();
+        let coverages = coverage::coverage(file, &primers, &config, rank)?;
+        write!(
+            std::io::stdout(),
+            "{}",
+            coverage::report(&coverages, mismatch, top)
+        )?;
+        return Ok(());
+    }
+
+    if let Some(("sweep", sweep_matches)) = matches.subcommand() {
+        let file = sweep_matches.get_one::<String>("FILE").unwrap();
+        let primers = select_primers(sweep_matches)?;
+        let mismatches = sweep_matches
+            .get_one::<RangeInclusive<u8>>("mismatch")
+            .unwrap()
+            .clone();
+        let shortest = primers.iter().flatten().map(|p| p.len()).min();
+        if shortest.is_some_and(|l| *mismatches.end() as usize >= l) {
+            return Err(anyhow!(
+                "Supplied mismatch is not smaller than the length of a primer"
+            ));
+        }
+        let config = utils::Config {
+            ambigs: ambig_map(sweep_matches)?,
+            no_indels: sweep_matches.get_flag("no_indels"),
+            bisulfite: sweep_matches.get_flag("bisulfite"),
+            ..Default::default()
+        };
+        let sweeps = sweep::sweep(file, &primers, mismatches, &config)?;
+        write!(std::io::stdout(), "{}", sweep::report(&sweeps))?;
+        return Ok(());
+    }
 
-        // Primers should be in pairs!
-        if (first.len() % 2) == 0 && first.len() != second.len() {
-            writeln!(ehandle,
-                "Supplied forward and reverse primers are not multiple of 2. Please check specified primers"
+    // Reading input data
+    // This can be a piped data or a filename
+    // So we match the value to '-' or some other value and read it
+    // Sequences can also be fetched from NCBI by accession, streamed from
+    // the reads of a SRA run, or come from a watched directory
+    let watch_dir = matches.get_one::<String>("watch");
+    let accessions = matches
+        .get_many::<String>("accession")
+        .map(|values| values.map(|v| v.as_str()).collect::<Vec<_>>());
+    let mut sra_mates = None;
+    let infiles: Vec<String> = if let Some(values) = accessions {
+        let accessions = ncbi::parse_accessions(&values)?;
+        let cache_dir = matches.get_one::<String>("cache_dir").unwrap();
+        ncbi::fetch_to_file(&accessions, cache_dir, "infile.fa")?;
+        vec!["infile.fa".to_string()]
+    } else if let Some(run) = matches.get_one::<String>("sra") {
+        let files = sra::run_files(run)?;
+        info!("Streaming the reads of {} from {}", run, files.reads);
+        sra_mates = files.mates;
+        vec![files.reads]
+    } else if watch_dir.is_some() {
+        Vec::new()
+    } else {
+        let values = matches
+            .get_many::<String>("FILE")
+            .map(|values| values.map(|v| v.to_string()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        // Read from STDIN with no file or when the only file is '-'
+        if values.is_empty() || values == ["-"] {
+            utils::stdin_to_file("infile.fa")?;
+            vec!["infile.fa".to_string()]
+        } else if values.iter().any(|v| v == "-") {
+            writeln!(
+                ehandle,
+                "error: standard input cannot be combined with input files"
             )?;
             process::exit(1);
+        } else {
+            values
         }
+    };
 
-        // Combine both Vec<String> into Vec<Vec<String>>
-        primers = utils::combine_vec(first, second);
+    // Check that the supplied files exist, remote files are checked when
+    // they are opened
+    for infile in infiles.iter() {
+        if infile != "infile.fa"
+            && !remote::is_url(infile)
+            && !Path::new(infile).exists()
+        {
+            writeln!(ehandle, "error: {}: No such file or directory. Is the path correct? Do you have permission to read the file?", infile)?;
+            process::exit(1);
+        }
+    }
 
-    // Case user goes for --region option
-    } else if matches.get_flag("region") {
-        // Get supplied region names which can be multiple
-        let regions: Vec<&str> = matches
-            .get_many::<String>("region")
-            .unwrap_or_default()
-            .map(|v| v.as_str())
-            .collect::<Vec<_>>();
+    // Mates are paired with the reads of a single input
+    if matches.contains_id("mates") && infiles.len() != 1 {
+        writeln!(ehandle, "error: --mates requires a single input file")?;
+        process::exit(1);
+    }
 
-        // Check if its a file that have been supplied or region name
-        if Path::new(&regions[0]).is_file() {
-            // We will consider in this case that the region name is a file
-            primers = utils::file_to_vec(regions[0]).unwrap();
-        // Check that the region name is supported
-        } else if regions.iter().all(|x| all.contains(x)) {
-            primers = regions
-                .iter()
-                .map(|x| utils::region_to_primer(x).unwrap())
-                .collect::<Vec<_>>();
-        } else {
-            writeln!(ehandle, "Supplied region is not a correct file name nor a supported region name")?;
+    // Read prefix for output files
+    let prefix = matches.get_one::<String>("prefix").unwrap();
+    let force = matches.get_flag("force");
+    let append = matches.get_flag("append");
+    let no_fasta = matches.get_flag("no_fasta");
+    let no_gff = matches.get_flag("no_gff");
+    let index_output = matches.get_flag("index_output");
+    let all_matches = matches.get_flag("all_matches");
+    let taxonomy = taxonomy(&matches)?;
+    let taxa_output = !matches!(taxonomy, Taxonomy::None);
+    let outputs = [
+        "fa",
+        "fa.fai",
+        "gff",
+        "tsv",
+        "hits.tsv",
+        "operons.tsv",
+        "skipped.tsv",
+        "lengths.tsv",
+        "mismatches.tsv",
+        "taxa.tsv",
+        "ranks.tsv",
+        "run.json",
+        "regions.jsonl",
+        "stats.json",
+    ]
+    .iter()
+    .filter(|&&ext| !(ext == "fa" && no_fasta || ext == "gff" && no_gff))
+    .filter(|&&ext| ext != "fa.fai" || index_output)
+    .filter(|&&ext| ext != "operons.tsv" || all_matches)
+    .filter(|&&ext| ext != "stats.json" || matches.contains_id("stats_every"))
+    .filter(|&&ext| !(ext == "taxa.tsv" || ext == "ranks.tsv") || taxa_output)
+    .map(|ext| format!("{}.{}", prefix, ext))
+    .collect::<Vec<_>>();
+    // Outputs appended to are checked when opened
+    if !force && !append {
+        if outputs.iter().any(|path| Path::new(path).exists()) {
+            writeln!(std::io::stderr(), "error: file already exists. Please change it using --prefix option or use --force to overwrite it")?;
             process::exit(1);
         }
-    } else {
-        // Case when no region or primer is supplied, all the built-in regions are
-        // extracted
-        primers = all
+    } else if force {
+        for path in outputs.iter().filter(|path| Path::new(path).exists()) {
+            fs::remove_file(path)?;
+        }
+    }
+    // Outputs at given paths are never appended to, while the SQLite
+    // database collects the runs added to it
+    let given = [
+        "out_bam",
+        "out_tbl",
+        "out_track",
+        "out_parquet",
+        "report_md",
+        "bundle",
+        "plot",
+    ]
+    .iter()
+    .filter_map(|id| matches.get_one::<String>(id))
+    .filter(|path| Path::new(path).exists())
+    .collect::<Vec<_>>();
+    if let Some(path) = given.first().filter(|_| !force) {
+        writeln!(
+            std::io::stderr(),
+            "error: {} already exists. Please change it or use --force to overwrite it",
+            path
+        )?;
+        process::exit(1);
+    }
+    for path in given {
+        fs::remove_file(path)?;
+    }
+
+    // Sequences classified by marker gene are searched with the built-in
+    // regions of their gene
+    let (primers, pair_options) = if matches.get_flag("auto_gene") {
+        let primers = utils::GENES
             .iter()
+            .flat_map(|gene| utils::gene_regions(gene))
             .map(|x| utils::region_to_primer(x).unwrap())
             .collect::<Vec<_>>();
-    }
+        (primers, utils::PairOptionsMap::new())
+    } else {
+        select_primers_with_options(&matches)?
+    };
 
     let mismatch: u8 = *matches.get_one("mismatch").unwrap();
 
@@ -164,32 +454,338 @@ fn main() -> anyhow::Result<()> {
     }
 
     if force {
-        warn!("Overwriting {}.fa and {}.gff files", prefix, prefix);
+        warn!("Overwriting {} files", outputs.join(", "));
     }
 
-    // Check that required number of mismatch is not greater than
-    // the length of the longest primer
-    let cp_primers = primers.clone();
-    let longest_primer_length =
-        cp_primers.into_iter().flatten().map(|x| x.len()).max();
+    if primers.is_empty() {
+        error!("No primer sequence detected");
+        error!("Aborting...");
+        process::exit(1);
+    }
 
-    match longest_primer_length {
-        Some(l) => {
-            if mismatch as usize > l {
-                error!("Supplied mismatch is greater that length of primer");
-                error!("Aborting...");
-                process::exit(1);
-            }
+    if let Err(e) = utils::check_primer_pairs(&primers) {
+        error!("{}", e);
+        error!("Aborting...");
+        process::exit(1);
+    }
+
+    let coords: Coords =
+        matches.get_one::<String>("coords").unwrap().parse()?;
+    // Report of the runs appended to, read before appending anything
+    let run_json = format!("{}.run.json", prefix);
+    let previous = if append && Path::new(&run_json).exists() {
+        Some(RunReport::read(&run_json)?)
+    } else {
+        None
+    };
+    if let Some(previous) = previous.as_ref() {
+        // Regions listed in reports of schema version 1 would be lost
+        if previous.schema_version < 2 {
+            error!(
+                "{} lists its regions, which are now written to {}.regions.jsonl, start new outputs",
+                run_json, prefix
+            );
+            process::exit(1);
         }
-        None => {
-            error!("No primer sequence detected");
-            error!("Aborting...");
+        if previous.coords != coords {
+            error!(
+                "{} uses {} coordinates, append to it with --coords {}",
+                run_json, previous.coords, previous.coords
+            );
             process::exit(1);
         }
     }
+    let live_stats = match matches.get_one::<String>("stats_every") {
+        Some(every) => Some(Arc::new(LiveStats::new(
+            &format!("{}.stats.json", prefix),
+            every.parse()?,
+        ))),
+        None => None,
+    };
+    // Seed of the random draws, drawn when the run makes some without one
+    let seed = matches.get_one::<u64>("seed").copied().or_else(|| {
+        matches
+            .get_one::<f64>("subsample")
+            .map(|_| rng::random_seed())
+    });
+    if let Some(seed) = seed {
+        info!("Random seed: {}", seed);
+    }
+    let config = utils::Config {
+        mismatch,
+        prefilter: matches.get_flag("prefilter"),
+        ambigs: ambig_map(&matches)?,
+        no_indels: matches.get_flag("no_indels"),
+        bisulfite: matches.get_flag("bisulfite"),
+        all_matches,
+        max_amplicons: matches.get_one::<usize>("max_amplicons").copied(),
+        min_primer_identity: matches
+            .get_one::<f64>("min_primer_identity")
+            .copied(),
+        max_amplicon_length: matches
+            .get_one::<usize>("max_amplicon_length")
+            .copied(),
+        max_pair_distance: matches
+            .get_one::<usize>("max_pair_distance")
+            .copied(),
+        chunk_size: matches.get_one::<usize>("chunk_size").copied(),
+        anchored: matches.get_one::<usize>("anchored").copied(),
+        max_record_len: matches.get_one::<usize>("max_record_len").copied(),
+        progress: matches.get_flag("progress"),
+        keep_primer_dimers: matches.get_flag("keep_primer_dimers"),
+        rescue: matches.get_one::<u8>("rescue").copied(),
+        overlaps: matches
+            .get_one::<String>("dedup_overlaps")
+            .unwrap()
+            .parse()?,
+        anchor: matches.get_one::<String>("anchor").unwrap().parse()?,
+        sanitize: matches.get_one::<String>("sanitize").unwrap().parse()?,
+        alphabet_sample: *matches.get_one("alphabet_sample").unwrap(),
+        mixed_alphabet: matches
+            .get_one::<String>("mixed_alphabet")
+            .unwrap()
+            .parse()?,
+        id_parser: matches.get_one::<String>("id_parser").unwrap().parse()?,
+        header_filter: matches
+            .get_one::<String>("header_filter")
+            .map(|filter| filter.parse())
+            .transpose()?,
+        subsample: matches.get_one::<f64>("subsample").copied(),
+        seed,
+        record_timeout: matches
+            .get_one::<u64>("record_timeout")
+            .map(|secs| Duration::from_secs(*secs)),
+        cache_hits: matches.get_flag("cache_hits"),
+        both_strands: matches.get_flag("both_strands"),
+        keep_strand: matches.get_flag("keep_strand"),
+        tag_genes: matches.contains_id("gene") || matches.get_flag("auto_gene"),
+        auto_gene: matches.get_flag("auto_gene"),
+        gff: utils::GffLabels::new(
+            matches.get_one::<String>("gff_source").unwrap(),
+            matches.get_one::<String>("gff_type").unwrap(),
+        )?,
+        coords,
+        homopolymer_tolerant: matches.get_flag("homopolymer_tolerant"),
+        degenerate_score: matches.get_flag("degenerate_score"),
+        trim_primers: matches.get_flag("trim_primers"),
+        primer_offsets: matches.get_flag("primer_offsets"),
+        interleaved: matches.get_flag("interleaved"),
+        mates: matches.get_one::<String>("mates").cloned().or(sra_mates),
+        min_overlap: matches.get_one::<usize>("min_overlap").copied(),
+        no_fasta,
+        no_gff,
+        flush_every: matches.get_one::<usize>("flush_every").copied(),
+        fsync: matches.get_flag("fsync"),
+        append,
+        taxonomy,
+        pair_options,
+        // Given to the extractor along with the signal handlers
+        interrupt: None,
+        // Read from the outputs appended to when extracting
+        seen_ids: None,
+        live_stats: live_stats.clone(),
+        decompress_threads: 1,
+        // Located on the primers by the extractor
+        reference: None,
+        silva: match matches.get_one::<String>("silva_alignment") {
+            Some(path) => Some(Arc::new(SilvaColumns::from_file(path)?)),
+            None => None,
+        },
+    };
+    // Mismatches are checked against each primer once the settings of the
+    // pairs are known
+    if let Err(e) = utils::check_mismatches(&primers, &config) {
+        error!("{}", e);
+        error!("Aborting...");
+        process::exit(1);
+    }
+    if config.prefilter && config.ambigs.has_wildcards() {
+        warn!(
+            "Prefiltering is disabled by the sequence wildcards of --ambig-map"
+        );
+    }
+    if config.prefilter && config.homopolymer_tolerant {
+        warn!("Prefiltering is disabled by --homopolymer-tolerant");
+    }
+
+    // A first interrupt stops the run after the record being processed,
+    // or the watch after the file being processed, its outputs being
+    // finalized, and a second one exits at once
+    let interrupt = Arc::new(AtomicBool::new(false));
+    for &signal in signal_hook::consts::TERM_SIGNALS {
+        signal_hook::flag::register_conditional_shutdown(
+            signal,
+            130,
+            Arc::clone(&interrupt),
+        )?;
+        signal_hook::flag::register(signal, Arc::clone(&interrupt))?;
+    }
+
+    if let Some(dir) = watch_dir {
+        if !Path::new(dir).is_dir() {
+            error!("{} is not a directory", dir);
+            process::exit(1);
+        }
+        let interval: u64 = *matches.get_one("watch_interval").unwrap();
+        let mut watcher = watch::Watcher::new(dir, &primers, prefix, &config)
+            .with_interrupt(Arc::clone(&interrupt));
+        if let Some(path) = matches.get_one::<String>("metrics") {
+            watcher = watcher.with_metrics(path);
+        }
+        watcher.run(Duration::from_secs(interval))?;
+        // A watch only ends when interrupted
+        process::exit(130);
+    }
+
+    // Parameters listed in the Markdown report, before the settings are
+    // handed to the extractor
+    let parameters = matches.get_one::<String>("report_md").map(|_| {
+        let mut parameters = vec![
+            (
+                "Command".to_string(),
+                format!("`{}`", env::args().collect::<Vec<_>>().join(" ")),
+            ),
+            (
+                "Date".to_string(),
+                chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+            ),
+            ("Inputs".to_string(), infiles.join(", ")),
+            ("Mismatches".to_string(), mismatch.to_string()),
+        ];
+        if let Some(seed) = seed {
+            parameters.push(("Seed".to_string(), seed.to_string()));
+        }
+        for pair in primers.iter() {
+            let region = config.region_name(pair);
+            parameters.push((
+                format!("Primers {}", region).trim_end().to_string(),
+                format!("`{}`, `{}`", pair[0], pair[1]),
+            ));
+        }
+        parameters
+    });
+
+    let mut extras = utils::ExtraOutputs {
+        bam: matches
+            .get_one::<String>("out_bam")
+            .map(|p| BamWriter::new(p)),
+        plot: matches
+            .get_one::<String>("plot")
+            .map(|p| RegionPlot::new(p)),
+        tbl: matches
+            .get_one::<String>("out_tbl")
+            .map(|p| FeatureTable::new(p)),
+        track: matches.get_one::<String>("out_track").map(|p| {
+            let window = *matches.get_one("track_window").unwrap();
+            VariabilityTrack::new(p, window)
+        }),
+        sinks: Vec::new(),
+    };
+    if let Some(dashboard) = dashboard.as_ref() {
+        extras.sinks.push(dashboard.sink());
+    }
+    let command = env::args().collect::<Vec<_>>().join(" ");
+    // Records of the run are added to the database as they are written,
+    // the run being committed with its report
+    if let Some(path) = matches.get_one::<String>("out_sqlite") {
+        let sink = SqliteSink::create(path, &command, prefix, coords)
+            .with_context(|| "Cannot write results database")?;
+        extras.sinks.push(Box::new(sink));
+    }
+    // Regions are written as they are found, the Parquet table being
+    // closed with the report
+    let regions_jsonl = format!("{}.regions.jsonl", prefix);
+    extras.sinks.push(Box::new(RegionsSink::create(
+        &regions_jsonl,
+        coords,
+        append,
+    )?));
+    if let Some(path) = matches.get_one::<String>("out_parquet") {
+        let sink = ParquetSink::create(path, coords)
+            .with_context(|| "Cannot write Parquet table")?;
+        extras.sinks.push(Box::new(sink));
+    }
+    let threads: usize = *matches.get_one("threads").unwrap();
 
-    utils::get_hypervar_regions(infile, primers, prefix, mismatch)?;
+    let mut builder = Extractor::builder()
+        .primer_pairs(&primers)
+        .config(config)
+        .threads(threads)
+        .interrupt(Arc::clone(&interrupt));
+    if let Some(path) = matches.get_one::<String>("reference") {
+        builder = builder.reference(path);
+    }
+    let extractor = builder.build()?;
+    let mut summary = extractor.run(&infiles, prefix, &mut extras)?;
+    let run_sinks = mem::take(&mut extras.sinks);
+    extras.finish()?;
+    if let Some(dashboard) = dashboard.as_mut() {
+        dashboard.finish();
+    }
+    if let Some(stats) = live_stats {
+        stats.finish()?;
+    }
+    if index_output {
+        utils::index_fasta(&format!("{}.fa", prefix))
+            .with_context(|| "Cannot index FASTA output")?;
+    }
     info!("Done getting hypervariable regions");
+    summary.log("Total");
+    if matches.get_flag("sparklines") {
+        summary.log_sparklines("Total");
+    }
+    // Statistics and report cover the runs appended to
+    if let Some(previous) = previous {
+        let mut total = previous.summary;
+        total.merge(&summary);
+        summary = total;
+    }
+    summary
+        .write_lengths(&format!("{}.lengths.tsv", prefix))
+        .with_context(|| "Cannot write region lengths")?;
+    summary.log_three_prime_mismatches("Total");
+    summary
+        .write_mismatches(&format!("{}.mismatches.tsv", prefix))
+        .with_context(|| "Cannot write primer mismatches")?;
+    if taxa_output {
+        summary
+            .write_taxa(&format!("{}.taxa.tsv", prefix))
+            .with_context(|| "Cannot write taxon statistics")?;
+        summary
+            .write_ranks(&format!("{}.ranks.tsv", prefix))
+            .with_context(|| "Cannot write rank statistics")?;
+    }
+    let outcome = porcelain.then(|| summary.porcelain());
+    let interrupted = !summary.checkpoints.is_empty();
+    let mut report = RunReport::new(summary).with_coords(coords);
+    if let Some(seed) = seed {
+        report = report.with_seed(seed);
+    }
+    // Outputs of the whole run, written from its report
+    let mut sinks: Sinks =
+        vec![Box::new(JsonSink::new(&format!("{}.run.json", prefix)))];
+    sinks.extend(run_sinks);
+    for sink in sinks.iter_mut() {
+        sink.finish(&report)?;
+    }
+    if let (Some(path), Some(parameters)) =
+        (matches.get_one::<String>("report_md"), parameters)
+    {
+        report
+            .write_markdown(path, &parameters)
+            .with_context(|| "Cannot write Markdown report")?;
+    }
+    if let Some(path) = matches.get_one::<String>("bundle") {
+        let files = outputs
+            .iter()
+            .cloned()
+            .chain(matches.get_one::<String>("report_md").cloned())
+            .filter(|file| Path::new(file).exists())
+            .collect::<Vec<_>>();
+        bundle::write_bundle(path, &files, &command)
+            .with_context(|| format!("Cannot write bundle {}", path))?;
+        info!("Outputs bundled into {}", path);
+    }
 
     // FINISHING ------------------------------------------------------------
     // Cleaning around
@@ -212,6 +808,112 @@ fn main() -> anyhow::Result<()> {
         hours, minutes, seconds, milliseconds
     );
     info!("Enjoy. Share. Come back again!");
+    if let Some(outcome) = outcome {
+        writeln!(std::io::stdout(), "{}", outcome)?;
+    }
+    // Interrupted runs exit as if killed by the signal, for job schedulers
+    if interrupted {
+        process::exit(130);
+    }
 
     Ok(())
 }
+
+// Get primers from command-line as a list of primer can be specified
+fn select_primers(matches: &ArgMatches) -> anyhow::Result<Vec<Vec<String>>> {
+    Ok(select_primers_with_options(matches)?.0)
+}
+
+// Primers from command-line with the settings of the pairs of primers files
+// having their own
+fn select_primers_with_options(
+    matches: &ArgMatches,
+) -> anyhow::Result<(Vec<Vec<String>>, utils::PairOptionsMap)> {
+    let mut primers: Vec<Vec<String>> = Vec::new();
+    let mut pair_options = utils::PairOptionsMap::new();
+    let all = utils::gene_regions("16S");
+
+    // Case the user go for -f and -r options
+    if matches.contains_id("forward_primer") && primers.is_empty() {
+        // Read supplied forward and reverse primers
+        let first: Vec<&str> = matches
+            .get_many::<String>("forward_primer")
+            .unwrap_or_default()
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>();
+        let second: Vec<&str> = matches
+            .get_many::<String>("reverse_primer")
+            .unwrap_or_default()
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>();
+
+        // Combine both Vec<String> into Vec<Vec<String>>, primers being
+        // in pairs unless every combination is tested
+        primers = if matches.get_flag("cross") {
+            utils::cross_vec(first, second)
+        } else {
+            utils::combine_vec(first, second)?
+        };
+
+    // Case user goes for --region option
+    } else if matches.contains_id("region") {
+        // Get supplied region names which can be multiple
+        let regions: Vec<&str> = matches
+            .get_many::<String>("region")
+            .unwrap_or_default()
+            .map(|v| v.as_str())
+            .collect::<Vec<_>>();
+
+        // Region names are built-in regions or files of primer pairs
+        let mut registry = utils::RegionRegistry::default();
+        for region in regions {
+            if !registry.contains(region) {
+                registry.load(region)?;
+            }
+            primers.extend(registry.primers(region)?);
+        }
+        pair_options = registry.pair_options().clone();
+    // Case user goes for --gene option, all the built-in regions of each
+    // gene are extracted
+    } else if matches.contains_id("gene") {
+        primers = matches
+            .get_many::<String>("gene")
+            .unwrap_or_default()
+            .flat_map(|gene| utils::gene_regions(gene))
+            .map(|x| utils::region_to_primer(x).unwrap())
+            .collect::<Vec<_>>();
+    } else {
+        // Case when no region or primer is supplied, all the built-in regions are
+        // extracted
+        primers = all
+            .iter()
+            .map(|x| utils::region_to_primer(x).unwrap())
+            .collect::<Vec<_>>();
+    }
+
+    Ok((primers, pair_options))
+}
+
+// Source of the sequence lineages, if any
+fn taxonomy(matches: &ArgMatches) -> anyhow::Result<Taxonomy> {
+    if matches.get_flag("taxonomy_from_headers") {
+        return Ok(Taxonomy::Headers);
+    }
+    match matches.get_one::<String>("taxonomy") {
+        Some(path) => Taxonomy::from_file(path),
+        None => Ok(Taxonomy::None),
+    }
+}
+
+// Symbol equivalences, the IUPAC ambiguity codes unless --ambig-map or
+// --no-ambig is given
+fn ambig_map(matches: &ArgMatches) -> anyhow::Result<AmbigMap> {
+    if matches.get_flag("no_ambig") {
+        return Ok(AmbigMap::literal());
+    }
+    match matches.get_one::<String>("ambig_map") {
+        Some(path) => AmbigMap::from_file(path)
+            .with_context(|| format!("Cannot read ambiguity map {}", path)),
+        None => Ok(AmbigMap::default()),
+    }
+}
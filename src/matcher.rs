@@ -0,0 +1,638 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use anyhow::anyhow;
+use bio::alignment::AlignmentOperation;
+use bio::pattern_matching::myers::MyersBuilder;
+
+use std::fs;
+use std::time::Instant;
+
+// IUPAC ambiguity codes and the bases they match
+static IUPAC_AMBIGS: [(u8, &[u8]); 11] = [
+    (b'M', b"AC"),
+    (b'R', b"AG"),
+    (b'W', b"AT"),
+    (b'S', b"CG"),
+    (b'Y', b"CT"),
+    (b'K', b"GT"),
+    (b'V', b"ACGMRS"),
+    (b'H', b"ACTMWY"),
+    (b'D', b"AGTRWK"),
+    (b'B', b"CGTSYK"),
+    (b'N', b"ACGTMRWSYKVHDB"),
+];
+
+/// Hit of a pattern as (start, exclusive end, distance)
+pub type Hit = (usize, usize, u8);
+
+/// Symbol equivalences used when matching primers, the IUPAC ambiguity
+/// codes by default
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmbigMap {
+    // Primer symbol and the sequence symbols it matches
+    ambigs: Vec<(u8, Vec<u8>)>,
+    // Sequence symbols matched by any primer symbol
+    wildcards: Vec<u8>,
+}
+
+impl Default for AmbigMap {
+    fn default() -> Self {
+        AmbigMap::new(&IUPAC_AMBIGS)
+    }
+}
+
+impl AmbigMap {
+    /// Equivalences of a custom alphabet, each pattern symbol with the
+    /// text symbols it matches besides itself, e.g. `(b'X', b"ACDE..")`
+    /// for any residue of a protein motif
+    pub fn new(equivalences: &[(u8, &[u8])]) -> Self {
+        AmbigMap {
+            ambigs: equivalences
+                .iter()
+                .map(|&(base, equivalents)| (base, equivalents.to_vec()))
+                .collect(),
+            wildcards: Vec::new(),
+        }
+    }
+
+    /// Every symbol only matches itself
+    pub fn literal() -> Self {
+        AmbigMap::new(&[])
+    }
+
+    /// Read equivalences overriding the IUPAC defaults from a file. Each
+    /// line is a symbol followed by the sequence symbols it matches in
+    /// primers. A symbol alone only matches itself and a symbol followed
+    /// by `*` is matched by any primer symbol when found in sequences.
+    pub fn from_file(filename: &str) -> anyhow::Result<Self> {
+        let mut map = AmbigMap::default();
+        let content = fs::read_to_string(filename)?;
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let symbol = match fields[0].as_bytes() {
+                [symbol] => symbol.to_ascii_uppercase(),
+                _ => {
+                    return Err(anyhow!(
+                        "line {}: {} is not a single symbol",
+                        i + 1,
+                        fields[0]
+                    ))
+                }
+            };
+
+            map.ambigs.retain(|(base, _)| *base != symbol);
+            match fields.get(1) {
+                _ if fields.len() > 2 => {
+                    return Err(anyhow!(
+                        "line {}: expected a symbol and its equivalents",
+                        i + 1
+                    ))
+                }
+                Some(&"*") => map.wildcards.push(symbol),
+                Some(equivalents) => map.ambigs.push((
+                    symbol,
+                    equivalents.to_ascii_uppercase().into_bytes(),
+                )),
+                None => {}
+            }
+        }
+
+        Ok(map)
+    }
+
+    /// Equivalences of texts where `from` may read as `to`, e.g. after a
+    /// chemical conversion: every symbol matching `from` also matches
+    /// `to`
+    pub fn converted(&self, from: u8, to: u8) -> Self {
+        let mut map = self.clone();
+        for (_, equivalents) in map.ambigs.iter_mut() {
+            if equivalents.contains(&from) && !equivalents.contains(&to) {
+                equivalents.push(to);
+            }
+        }
+        match map.ambigs.iter_mut().find(|(base, _)| *base == from) {
+            Some((_, equivalents)) if !equivalents.contains(&to) => {
+                equivalents.push(to)
+            }
+            Some(_) => {}
+            None => map.ambigs.push((from, vec![to])),
+        }
+        map
+    }
+
+    /// Equivalences of bisulfite-converted DNA, where the unmethylated C
+    /// read as T on the converted strand
+    pub fn bisulfite(&self) -> Self {
+        self.converted(b'C', b'T')
+    }
+
+    /// Myers builder matching primers with these equivalences
+    pub fn builder(&self) -> MyersBuilder {
+        let mut builder = MyersBuilder::new();
+        for (base, equivalents) in self.ambigs.iter() {
+            builder.ambig(*base, equivalents);
+        }
+        for &wildcard in self.wildcards.iter() {
+            builder.text_wildcard(wildcard);
+        }
+        builder
+    }
+
+    pub fn ambigs(&self) -> Vec<(u8, &[u8])> {
+        self.ambigs
+            .iter()
+            .map(|(base, equivalents)| (*base, equivalents.as_slice()))
+            .collect()
+    }
+
+    /// Sequence symbols matched by a primer symbol, as a lookup table
+    pub fn matched_by(&self, symbol: u8) -> [bool; 256] {
+        let mut table = [false; 256];
+        table[symbol as usize] = true;
+        if let Some((_, equivalents)) =
+            self.ambigs.iter().find(|(base, _)| *base == symbol)
+        {
+            for &b in equivalents.iter() {
+                table[b as usize] = true;
+            }
+        }
+        for &wildcard in self.wildcards.iter() {
+            table[wildcard as usize] = true;
+        }
+        table
+    }
+
+    /// Are sequence symbols matched by any primer symbol? Such matches
+    /// cannot be found by exact seed lookups.
+    pub fn has_wildcards(&self) -> bool {
+        !self.wildcards.is_empty()
+    }
+}
+
+/// Approximate search of patterns in texts over any alphabet, the symbols
+/// of the texts each pattern symbol matches being given by an
+/// [`AmbigMap`]. Hits are found with up to a number of edits, or of
+/// substitutions only when indels are not allowed. Searches stop at the
+/// deadline of the matcher, if any, their hits being then partial.
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    ambigs: AmbigMap,
+    // Myers builder with the equivalences of the alphabet
+    builder: MyersBuilder,
+    no_indels: bool,
+    // Instant past which no window of the text is searched
+    deadline: Option<Instant>,
+}
+
+impl Matcher {
+    pub fn new(ambigs: &AmbigMap, no_indels: bool) -> Self {
+        Matcher {
+            ambigs: ambigs.clone(),
+            builder: ambigs.builder(),
+            no_indels,
+            deadline: None,
+        }
+    }
+
+    /// Same matcher, giving up searches past `deadline`. The deadline is
+    /// checked before each window of the text.
+    pub fn until(&self, deadline: Instant) -> Self {
+        Matcher {
+            deadline: Some(deadline),
+            ..self.clone()
+        }
+    }
+
+    pub fn ambigs(&self) -> &AmbigMap {
+        &self.ambigs
+    }
+
+    /// Best hit of `pattern` in `text` with at most `mismatch` edits, the
+    /// first one winning on ties. When windows are given only those parts
+    /// of the text are searched.
+    pub fn best_hit(
+        &self,
+        pattern: &[u8],
+        text: &[u8],
+        mismatch: u8,
+        windows: Option<&[(usize, usize)]>,
+    ) -> Option<Hit> {
+        if self.no_indels {
+            best_substitution_hit(
+                pattern,
+                text,
+                mismatch,
+                &self.ambigs,
+                windows,
+                self.deadline,
+            )
+        } else {
+            best_hit(
+                &self.builder,
+                pattern,
+                text,
+                mismatch,
+                windows,
+                self.deadline,
+            )
+        }
+    }
+
+    /// Every non-overlapping hit of `pattern` in `text` with at most
+    /// `mismatch` edits, sorted by start position
+    pub fn all_hits(
+        &self,
+        pattern: &[u8],
+        text: &[u8],
+        mismatch: u8,
+        windows: Option<&[(usize, usize)]>,
+    ) -> Vec<Hit> {
+        if self.no_indels {
+            all_substitution_hits(
+                pattern,
+                text,
+                mismatch,
+                &self.ambigs,
+                windows,
+                self.deadline,
+            )
+        } else {
+            all_hits(
+                &self.builder,
+                pattern,
+                text,
+                mismatch,
+                windows,
+                self.deadline,
+            )
+        }
+    }
+
+    /// Operations (=, X, I, D) of the alignment of `pattern` on a hit, I
+    /// being pattern symbols missing from the text and D text symbols
+    /// missing from the pattern, if the alignment can be traced back
+    pub fn alignment(
+        &self,
+        pattern: &[u8],
+        text: &[u8],
+        hit: Hit,
+    ) -> Option<Vec<u8>> {
+        let (start, end, dist) = hit;
+        let site = &text[start..end];
+        let ops = if self.no_indels {
+            pattern
+                .iter()
+                .zip(site.iter())
+                .map(|(&symbol, &base)| {
+                    if self.ambigs.matched_by(symbol)[base as usize] {
+                        b'='
+                    } else {
+                        b'X'
+                    }
+                })
+                .collect::<Vec<_>>()
+        } else {
+            // Trace back the alignment ending at the last base of the site
+            let mut myers = self.builder.build_64(pattern);
+            let mut matches = myers.find_all_lazy(site, dist);
+            matches.by_ref().for_each(drop);
+            let mut path = Vec::new();
+            let traced = !site.is_empty()
+                && matches.path_at(site.len() - 1, &mut path).is_some();
+            if !traced {
+                return None;
+            }
+            path.iter()
+                .map(|op| match op {
+                    AlignmentOperation::Match => b'=',
+                    AlignmentOperation::Subst => b'X',
+                    AlignmentOperation::Ins => b'I',
+                    AlignmentOperation::Del => b'D',
+                    _ => b'?',
+                })
+                .collect::<Vec<_>>()
+        };
+
+        Some(ops)
+    }
+}
+
+// Has a deadline passed?
+pub(crate) fn expired(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+// Find the best hit of pattern in text as (start, exclusive end, distance).
+// When windows are given only those parts of the text are searched, up to
+// the deadline.
+pub(crate) fn best_hit(
+    builder: &MyersBuilder,
+    pattern: &[u8],
+    text: &[u8],
+    mismatch: u8,
+    windows: Option<&[(usize, usize)]>,
+    deadline: Option<Instant>,
+) -> Option<Hit> {
+    let mut myers = builder.build_64(pattern);
+    let whole = [(0, text.len())];
+    let mut best: Option<Hit> = None;
+
+    for &(offset, end) in windows.unwrap_or(&whole) {
+        if expired(deadline) {
+            break;
+        }
+        let mut matches = myers.find_all_lazy(&text[offset..end], mismatch);
+
+        // Get the best hit, first one wins on ties
+        let (best_end, dist) =
+            match matches.by_ref().min_by_key(|&(_, dist)| dist) {
+                Some(hit) => hit,
+                None => continue,
+            };
+        if best.is_some_and(|(_, _, best_dist)| best_dist <= dist) {
+            continue;
+        }
+        // Get match start position
+        if let Some((start, _)) = matches.hit_at(best_end) {
+            best = Some((offset + start, offset + best_end + 1, dist));
+        }
+    }
+
+    best
+}
+
+// Number of substitutions of pattern against site, None above mismatch
+fn substitutions(
+    accepted: &[[bool; 256]],
+    site: &[u8],
+    mismatch: u8,
+) -> Option<u8> {
+    let mut dist = 0;
+    for (accepted, &base) in accepted.iter().zip(site.iter()) {
+        if !accepted[base as usize] {
+            dist += 1;
+            if dist > mismatch {
+                return None;
+            }
+        }
+    }
+    Some(dist)
+}
+
+/// Find the best substitution-only hit of pattern in text, with the same
+/// contract as the Myers based search
+pub fn best_substitution_hit(
+    pattern: &[u8],
+    text: &[u8],
+    mismatch: u8,
+    ambigs: &AmbigMap,
+    windows: Option<&[(usize, usize)]>,
+    deadline: Option<Instant>,
+) -> Option<Hit> {
+    let accepted = pattern
+        .iter()
+        .map(|&symbol| ambigs.matched_by(symbol))
+        .collect::<Vec<_>>();
+    let whole = [(0, text.len())];
+    let mut best: Option<Hit> = None;
+
+    for &(offset, end) in windows.unwrap_or(&whole) {
+        if expired(deadline) {
+            break;
+        }
+        if end - offset < pattern.len() {
+            continue;
+        }
+        for start in offset..=end - pattern.len() {
+            let site = &text[start..start + pattern.len()];
+            let dist = match substitutions(&accepted, site, mismatch) {
+                Some(dist) => dist,
+                None => continue,
+            };
+            // First one wins on ties
+            if best.is_none_or(|(_, _, best_dist)| dist < best_dist) {
+                best = Some((start, start + pattern.len(), dist));
+                if dist == 0 {
+                    return best;
+                }
+            }
+        }
+    }
+
+    best
+}
+
+// Keep the best of overlapping hits, hits are sorted by start position
+pub(crate) fn collapse_hits(hits: Vec<Hit>) -> Vec<Hit> {
+    let mut kept: Vec<Hit> = Vec::new();
+
+    for hit in hits {
+        match kept.last_mut() {
+            Some(last) if hit.0 < last.1 => {
+                if hit.2 < last.2 {
+                    *last = hit;
+                }
+            }
+            _ => kept.push(hit),
+        }
+    }
+
+    kept
+}
+
+// Find every non-overlapping hit of pattern in text, up to the deadline
+pub(crate) fn all_hits(
+    builder: &MyersBuilder,
+    pattern: &[u8],
+    text: &[u8],
+    mismatch: u8,
+    windows: Option<&[(usize, usize)]>,
+    deadline: Option<Instant>,
+) -> Vec<Hit> {
+    let mut myers = builder.build_64(pattern);
+    let whole = [(0, text.len())];
+    let mut hits = Vec::new();
+
+    for &(offset, end) in windows.unwrap_or(&whole) {
+        if expired(deadline) {
+            break;
+        }
+        let mut matches = myers.find_all_lazy(&text[offset..end], mismatch);
+        let ends = matches.by_ref().collect::<Vec<_>>();
+        for (hit_end, dist) in ends {
+            if let Some((start, _)) = matches.hit_at(hit_end) {
+                hits.push((offset + start, offset + hit_end + 1, dist));
+            }
+        }
+    }
+    hits.sort_unstable();
+
+    collapse_hits(hits)
+}
+
+// Find every non-overlapping substitution-only hit of pattern in text, up
+// to the deadline
+fn all_substitution_hits(
+    pattern: &[u8],
+    text: &[u8],
+    mismatch: u8,
+    ambigs: &AmbigMap,
+    windows: Option<&[(usize, usize)]>,
+    deadline: Option<Instant>,
+) -> Vec<Hit> {
+    let accepted = pattern
+        .iter()
+        .map(|&symbol| ambigs.matched_by(symbol))
+        .collect::<Vec<_>>();
+    let whole = [(0, text.len())];
+    let mut hits = Vec::new();
+
+    for &(offset, end) in windows.unwrap_or(&whole) {
+        if expired(deadline) {
+            break;
+        }
+        if end - offset < pattern.len() {
+            continue;
+        }
+        for start in offset..=end - pattern.len() {
+            let site = &text[start..start + pattern.len()];
+            if let Some(dist) = substitutions(&accepted, site, mismatch) {
+                hits.push((start, start + pattern.len(), dist));
+            }
+        }
+    }
+
+    collapse_hits(hits)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_ambig_map_from_file() {
+        let mut tmpfile =
+            NamedTempFile::new().expect("Cannot create temp file");
+        writeln!(tmpfile, "# no N expansion\nN\n- *\nr agt")
+            .expect("Cannot write to tmp file");
+        let map =
+            AmbigMap::from_file(tmpfile.path().to_str().unwrap()).unwrap();
+        assert!(map.has_wildcards());
+        assert!(map.ambigs().contains(&(b'R', &b"AGT"[..])));
+        assert!(!map.ambigs().iter().any(|(base, _)| *base == b'N'));
+
+        let builder = map.builder();
+        assert_eq!(builder.build_64(b"ACNT").distance(b"ACGT"), 1);
+        assert_eq!(builder.build_64(b"ACGT").distance(b"AC-T"), 0);
+        assert_eq!(builder.build_64(b"ARGT").distance(b"ATGT"), 0);
+    }
+
+    #[test]
+    fn test_ambig_map_literal() {
+        let builder = AmbigMap::literal().builder();
+        assert_eq!(builder.build_64(b"ACNT").distance(b"ACGT"), 1);
+        assert_eq!(builder.build_64(b"ACNT").distance(b"ACNT"), 0);
+    }
+
+    #[test]
+    fn test_best_substitution_hit() {
+        let ambigs = AmbigMap::default();
+        let text = b"TTTTACGAAGGTTTTACGAACGT";
+        // A deletion is only found by the Myers search
+        assert_eq!(
+            best_hit(
+                &ambigs.builder(),
+                b"ACGTACGT",
+                b"TTACGACGTT",
+                1,
+                None,
+                None
+            ),
+            Some((2, 9, 1))
+        );
+        assert_eq!(
+            best_substitution_hit(
+                b"ACGTACGT",
+                b"TTACGACGTT",
+                1,
+                &ambigs,
+                None,
+                None
+            ),
+            None
+        );
+        assert_eq!(
+            best_substitution_hit(b"ACGARCGT", text, 1, &ambigs, None, None),
+            Some((15, 23, 0))
+        );
+        assert_eq!(
+            best_substitution_hit(
+                b"ACGARCGT",
+                text,
+                1,
+                &ambigs,
+                Some(&[(0, 13)]),
+                None
+            ),
+            Some((4, 12, 1))
+        );
+    }
+
+    #[test]
+    fn test_collapse_hits() {
+        assert_eq!(
+            collapse_hits(vec![(0, 8, 1), (1, 9, 0), (2, 10, 1), (20, 28, 0)]),
+            vec![(1, 9, 0), (20, 28, 0)]
+        );
+    }
+
+    #[test]
+    fn test_matcher() {
+        // Converted C of the primer, and of its Y, read as T
+        let text = b"GGGGTTTTAGTTAGTTGGGG";
+        let iupac = AmbigMap::default();
+        let bisulfite = iupac.bisulfite();
+        assert!(bisulfite.matched_by(b'Y')[b'T' as usize]);
+        assert!(!bisulfite.matched_by(b'T')[b'C' as usize]);
+        for no_indels in [false, true] {
+            let matcher = Matcher::new(&iupac, no_indels);
+            assert_eq!(matcher.best_hit(b"CCAGCYAG", text, 0, None), None);
+            assert_eq!(
+                matcher.best_hit(b"TTAGTTAG", text, 0, None),
+                Some((6, 14, 0))
+            );
+            let matcher = Matcher::new(&bisulfite, no_indels);
+            assert_eq!(
+                matcher.all_hits(b"CCAGCYAG", text, 0, None),
+                vec![(6, 14, 0)]
+            );
+            assert_eq!(
+                matcher.alignment(b"CCAGCYAG", text, (6, 14, 0)),
+                Some(b"========".to_vec())
+            );
+            // Nothing is searched past the deadline
+            let matcher = matcher.until(Instant::now());
+            assert_eq!(matcher.best_hit(b"CCAGCYAG", text, 0, None), None);
+            assert!(matcher.all_hits(b"CCAGCYAG", text, 0, None).is_empty());
+        }
+
+        // Protein motif, X standing for any residue
+        let protein = AmbigMap::new(&[(b'X', b"ACDEFGHIKLMNPQRSTVWY")]);
+        let matcher = Matcher::new(&protein, true);
+        assert_eq!(
+            matcher.all_hits(b"GXSXG", b"MKGHSAGAGESLG", 0, None),
+            vec![(2, 7, 0), (8, 13, 0)]
+        );
+    }
+}
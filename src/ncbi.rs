@@ -0,0 +1,163 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::remote;
+
+use anyhow::{anyhow, Context};
+use log::info;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const EFETCH_URL: &str =
+    "https://eutils.ncbi.nlm.nih.gov/entrez/eutils/efetch.fcgi";
+
+/// Collect accessions from the command line. A value naming an existing
+/// file is read as a list of accessions, one per line.
+pub fn parse_accessions(values: &[&str]) -> anyhow::Result<Vec<String>> {
+    let mut accessions = Vec::new();
+
+    for value in values {
+        if Path::new(value).is_file() {
+            let content = fs::read_to_string(value)?;
+            accessions.extend(
+                content
+                    .lines()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(|l| l.to_string()),
+            );
+        } else {
+            accessions.push(value.to_string());
+        }
+    }
+
+    if let Some(bad) = accessions.iter().find(|a| !is_valid_accession(a)) {
+        return Err(anyhow!("{} is not a valid NCBI accession", bad));
+    }
+
+    Ok(accessions)
+}
+
+fn is_valid_accession(accession: &str) -> bool {
+    !accession.is_empty()
+        && accession
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+}
+
+fn efetch_url(accession: &str) -> String {
+    format!(
+        "{}?db=nuccore&id={}&rettype=fasta&retmode=text",
+        EFETCH_URL, accession
+    )
+}
+
+/// Fetch the accessions FASTA from NCBI, reusing files in `cache_dir`, and
+/// concatenate them into `outfile`
+pub fn fetch_to_file(
+    accessions: &[String],
+    cache_dir: &str,
+    outfile: &str,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(cache_dir)
+        .with_context(|| format!("Cannot create cache in {}", cache_dir))?;
+
+    let mut cached_files = Vec::new();
+    for accession in accessions {
+        let cached = Path::new(cache_dir).join(format!("{}.fa", accession));
+        if cached.exists() {
+            info!("Using cached sequence of {}", accession);
+        } else {
+            info!("Fetching {} from NCBI", accession);
+            fetch(accession, &cached)?;
+        }
+        cached_files.push(cached);
+    }
+
+    // Only create the output once everything is available
+    let mut writer = io::BufWriter::new(File::create(outfile)?);
+    for cached in cached_files {
+        io::copy(&mut File::open(&cached)?, &mut writer)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Stream the FASTA of an accession to `cached`. The sequence is written
+// to a temporary file of the cache, renamed once complete, so that an
+// interrupted download does not leave a truncated entry.
+fn fetch(accession: &str, cached: &Path) -> anyhow::Result<()> {
+    let url = efetch_url(accession);
+    let mut reader = io::BufReader::new(remote::open(&url)?);
+    if !reader.fill_buf()?.starts_with(b">") {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        return Err(anyhow!(
+            "NCBI returned no sequence for {}: {}",
+            accession,
+            line.trim_end()
+        ));
+    }
+
+    let dir = cached.parent().unwrap_or(Path::new("."));
+    let mut partial = tempfile::NamedTempFile::new_in(dir)?;
+    io::copy(&mut reader, &mut partial)
+        .with_context(|| format!("Cannot fetch {}", url))?;
+    partial
+        .persist(cached)
+        .with_context(|| format!("Cannot write {}", cached.display()))?;
+
+    Ok(())
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[test]
+    fn test_parse_accessions() {
+        let mut tmpfile =
+            NamedTempFile::new().expect("Cannot create temp file");
+        writeln!(tmpfile, "NR_024570.1\n\n# comment\nNC_000913.3")
+            .expect("Cannot write to tmp file");
+        let path = tmpfile.path().to_str().unwrap();
+
+        assert_eq!(
+            parse_accessions(&["CP000001", path]).unwrap(),
+            vec!["CP000001", "NR_024570.1", "NC_000913.3"]
+        );
+        assert!(parse_accessions(&["NC_000913.3&id=1"]).is_err());
+    }
+
+    #[test]
+    fn test_efetch_url() {
+        assert_eq!(
+            efetch_url("NC_000913.3"),
+            "https://eutils.ncbi.nlm.nih.gov/entrez/eutils/efetch.fcgi?db=nuccore&id=NC_000913.3&rettype=fasta&retmode=text"
+        );
+    }
+
+    #[test]
+    fn test_fetch_to_file_cached() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let cache = dir.path().join("cache");
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(cache.join("X1.fa"), ">X1\nACGT\n").unwrap();
+        let outfile = dir.path().join("out.fa");
+
+        fetch_to_file(
+            &["X1".to_string()],
+            cache.to_str().unwrap(),
+            outfile.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(outfile).unwrap(), ">X1\nACGT\n");
+    }
+}
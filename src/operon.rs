@@ -0,0 +1,120 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use bio::alignment::pairwise::Aligner;
+use bio::alignment::AlignmentOperation;
+
+use std::collections::HashSet;
+
+// Largest gap between two amplicons of the same operon. Regions of one 16S
+// gene overlap or lie a few hundred bases apart, while operons are
+// separated by the 23S and 5S genes at least.
+const OPERON_GAP: usize = 1000;
+
+/// Number, from 1, the operon of each amplicon of a sequence given as
+/// (start, exclusive end, primer pair index). Amplicons are grouped along
+/// the sequence, a new operon starting after a gap of more than
+/// `OPERON_GAP` bases or at a second amplicon of the same primer pair.
+pub fn label_operons(amplicons: &[(usize, usize, usize)]) -> Vec<usize> {
+    let mut order = (0..amplicons.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| amplicons[i]);
+    let mut labels = vec![0; amplicons.len()];
+    let mut operon = 0;
+    let mut operon_end = 0;
+    let mut operon_pairs = HashSet::new();
+
+    for i in order {
+        let (start, end, pair) = amplicons[i];
+        if operon == 0
+            || start > operon_end + OPERON_GAP
+            || operon_pairs.contains(&pair)
+        {
+            operon += 1;
+            operon_end = end;
+            operon_pairs.clear();
+        }
+        operon_end = operon_end.max(end);
+        operon_pairs.insert(pair);
+        labels[i] = operon;
+    }
+
+    labels
+}
+
+/// Identity of two sequences as the fraction of matching columns of their
+/// global alignment
+pub fn identity(first: &[u8], second: &[u8]) -> f64 {
+    if first.is_empty() && second.is_empty() {
+        return 1.0;
+    }
+    // Soft-masked bases are compared as any other
+    let first = first.to_ascii_uppercase();
+    let second = second.to_ascii_uppercase();
+    let score = |a: u8, b: u8| if a == b { 1i32 } else { -1i32 };
+    let mut aligner =
+        Aligner::with_capacity(first.len(), second.len(), -5, -1, &score);
+    let alignment = aligner.global(&first, &second);
+    let matches = alignment
+        .operations
+        .iter()
+        .filter(|op| **op == AlignmentOperation::Match)
+        .count();
+
+    matches as f64 / alignment.operations.len() as f64
+}
+
+/// Identity of every pair of copies, given as (operon, sequence), as
+/// (first operon, second operon, identity)
+pub fn pairwise_identities(
+    copies: &[(usize, &[u8])],
+) -> Vec<(usize, usize, f64)> {
+    let mut identities = Vec::new();
+
+    for (i, (first_operon, first)) in copies.iter().enumerate() {
+        for (second_operon, second) in copies.iter().skip(i + 1) {
+            identities.push((
+                *first_operon,
+                *second_operon,
+                identity(first, second),
+            ));
+        }
+    }
+
+    identities
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_operons() {
+        // Two regions of three operons, given out of order
+        let amplicons = [
+            (100, 500, 0),
+            (5000, 5400, 0),
+            (400, 800, 1),
+            (5300, 5700, 1),
+            (12000, 12400, 0),
+        ];
+        assert_eq!(label_operons(&amplicons), vec![1, 2, 1, 2, 3]);
+        // Copies of a region close to each other
+        assert_eq!(label_operons(&[(0, 400, 0), (900, 1300, 0)]), vec![1, 2]);
+        assert!(label_operons(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(identity(b"ACGTACGTAC", b"ACGTACGTAC"), 1.0);
+        assert_eq!(identity(b"ACGTACGTAC", b"acgtTcgtac"), 0.9);
+        // One deletion over ten columns
+        assert_eq!(identity(b"ACGTACGTAC", b"ACGTCGTAC"), 0.9);
+        assert_eq!(
+            pairwise_identities(&[(1, b"ACGT"), (2, b"ACGT"), (3, b"ACGA")]),
+            vec![(1, 2, 1.0), (1, 3, 0.75), (2, 3, 0.75)]
+        );
+    }
+}
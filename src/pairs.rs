@@ -0,0 +1,212 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils::{self, Records};
+
+use anyhow::anyhow;
+use bio::io::fasta;
+use log::warn;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Shortest overlap of the mates of a pair by default
+pub const MIN_OVERLAP: usize = 10;
+// Mismatches allowed in the overlap, per overlapping base
+const MAX_MISMATCH_RATE: f64 = 0.1;
+
+/// Read pairs seen by a merger and how many of them were merged
+#[derive(Debug, Default)]
+pub struct MergeCounts {
+    pub pairs: Cell<usize>,
+    pub merged: Cell<usize>,
+}
+
+/// Read pairs of an interleaved input, R1 and R2 alternating, merged into
+/// the sequences they were read from. Pairs whose mates do not overlap are
+/// skipped with a warning.
+pub fn merge_interleaved(
+    mut records: Records,
+    min_overlap: usize,
+    counts: Rc<MergeCounts>,
+) -> Records {
+    let next_pair = move || {
+        let first = match records.next()? {
+            Ok(record) => record,
+            Err(e) => return Some(Err(e)),
+        };
+        match records.next() {
+            Some(Ok(second)) => Some(Ok((first, second))),
+            Some(Err(e)) => Some(Err(e)),
+            None => Some(Err(anyhow!(
+                "Record {} has no mate in the interleaved input",
+                first.id()
+            ))),
+        }
+    };
+
+    merge_records(next_pair, min_overlap, counts)
+}
+
+/// Read pairs of two inputs, R1 and R2 in the same order, merged as with
+/// [`merge_interleaved`]
+pub fn merge_mates(
+    mut first: Records,
+    mut second: Records,
+    min_overlap: usize,
+    counts: Rc<MergeCounts>,
+) -> Records {
+    let next_pair = move || match (first.next(), second.next()) {
+        (None, None) => None,
+        (Some(Ok(first)), Some(Ok(second))) => Some(Ok((first, second))),
+        (Some(Err(e)), _) | (_, Some(Err(e))) => Some(Err(e)),
+        (Some(Ok(record)), None) | (None, Some(Ok(record))) => Some(Err(
+            anyhow!("Record {} has no mate in the other input", record.id()),
+        )),
+    };
+
+    merge_records(next_pair, min_overlap, counts)
+}
+
+type Pair = anyhow::Result<(fasta::Record, fasta::Record)>;
+
+fn merge_records(
+    mut next_pair: impl FnMut() -> Option<Pair> + 'static,
+    min_overlap: usize,
+    counts: Rc<MergeCounts>,
+) -> Records {
+    Box::new(std::iter::from_fn(move || loop {
+        let (first, second) = match next_pair()? {
+            Ok(pair) => pair,
+            Err(e) => return Some(Err(e)),
+        };
+        let id = pair_id(first.id());
+        if id != pair_id(second.id()) {
+            return Some(Err(anyhow!(
+                "Records {} and {} are not mates, are the inputs paired?",
+                first.id(),
+                second.id()
+            )));
+        }
+        counts.pairs.set(counts.pairs.get() + 1);
+        match merge_pair(first.seq(), second.seq(), min_overlap) {
+            Some(seq) => {
+                counts.merged.set(counts.merged.get() + 1);
+                return Some(Ok(fasta::Record::with_attrs(
+                    id,
+                    first.desc(),
+                    &seq,
+                )));
+            }
+            None => warn!("Mates of {} do not overlap, pair skipped", id),
+        }
+    }))
+}
+
+// Read id without its /1 or /2 mate suffix
+fn pair_id(id: &str) -> &str {
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
+/// Merge R1 with the reverse complement of R2 on their longest overlap of
+/// at least `min_overlap` bases, R1 bases being kept where they differ
+pub fn merge_pair(
+    first: &[u8],
+    second: &[u8],
+    min_overlap: usize,
+) -> Option<Vec<u8>> {
+    let second = String::from_utf8_lossy(second).to_ascii_uppercase();
+    let second = utils::to_reverse_complement(&second, "dna").into_bytes();
+
+    (min_overlap.max(1)..=first.len().min(second.len()))
+        .rev()
+        .find(|&overlap| {
+            let mismatches = first[first.len() - overlap..]
+                .iter()
+                .zip(&second[..overlap])
+                .filter(|(a, b)| !a.eq_ignore_ascii_case(b))
+                .count();
+            mismatches as f64 <= overlap as f64 * MAX_MISMATCH_RATE
+        })
+        .map(|overlap| [first, &second[overlap..]].concat())
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, seq: &[u8]) -> anyhow::Result<fasta::Record> {
+        Ok(fasta::Record::with_attrs(id, None, seq))
+    }
+
+    #[test]
+    fn test_merge_pair() {
+        // Amplicon ACGTTGCAAGCTTAGGCATC read from both ends
+        let first = b"ACGTTGCAAGCTTAG";
+        let second = b"GATGCCTAAGCTTGC";
+        assert_eq!(
+            merge_pair(first, second, MIN_OVERLAP),
+            Some(b"ACGTTGCAAGCTTAGGCATC".to_vec())
+        );
+        // One mismatch in the overlap
+        assert_eq!(
+            merge_pair(first, b"GATGCCTAAGCATGC", MIN_OVERLAP),
+            Some(b"ACGTTGCAAGCTTAGGCATC".to_vec())
+        );
+        assert_eq!(merge_pair(first, second, 11), None);
+        assert_eq!(merge_pair(first, b"AAAAAAAAAAAAAAA", MIN_OVERLAP), None);
+    }
+
+    #[test]
+    fn test_merge_interleaved() {
+        let records: Records = Box::new(
+            vec![
+                record("a/1", b"ACGTTGCAAGCTTAG"),
+                record("a/2", b"GATGCCTAAGCTTGC"),
+                record("b/1", b"ACGTTGCAAGCTTAG"),
+                record("b/2", b"AAAAAAAAAAAAAAA"),
+                record("c/1", b"ACGTTGCAAGCTTAG"),
+            ]
+            .into_iter(),
+        );
+        let counts = Rc::new(MergeCounts::default());
+        let mut merged =
+            merge_interleaved(records, MIN_OVERLAP, Rc::clone(&counts));
+        let first = merged.next().unwrap().unwrap();
+        assert_eq!(first.id(), "a");
+        assert_eq!(first.seq(), b"ACGTTGCAAGCTTAGGCATC");
+        // b is skipped, c has no mate
+        assert!(merged.next().unwrap().is_err());
+        assert_eq!((counts.pairs.get(), counts.merged.get()), (2, 1));
+
+        let records: Records = Box::new(
+            vec![record("a/1", b"ACGT"), record("b/2", b"ACGT")].into_iter(),
+        );
+        assert!(merge_interleaved(records, MIN_OVERLAP, counts)
+            .next()
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn test_merge_mates() {
+        let first: Records = Box::new(
+            vec![record("a", b"ACGTTGCAAGCTTAG"), record("b", b"ACGT")]
+                .into_iter(),
+        );
+        let second: Records =
+            Box::new(vec![record("a", b"GATGCCTAAGCTTGC")].into_iter());
+        let counts = Rc::new(MergeCounts::default());
+        let mut merged = merge_mates(first, second, MIN_OVERLAP, counts);
+        assert_eq!(
+            merged.next().unwrap().unwrap().seq(),
+            b"ACGTTGCAAGCTTAGGCATC"
+        );
+        assert!(merged.next().unwrap().is_err());
+    }
+}
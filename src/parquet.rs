@@ -0,0 +1,308 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::coords::Coords;
+use crate::report::ExtractedRegion;
+use crate::sink::OutputSink;
+
+#[cfg(feature = "parquet")]
+use crate::report::RunReport;
+#[cfg(feature = "parquet")]
+use crate::sink::SinkRegion;
+
+#[cfg(feature = "parquet")]
+use anyhow::Context;
+
+#[cfg(feature = "parquet")]
+use ::parquet::basic::Compression;
+#[cfg(feature = "parquet")]
+use ::parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+#[cfg(feature = "parquet")]
+use ::parquet::file::properties::WriterProperties;
+#[cfg(feature = "parquet")]
+use ::parquet::file::writer::SerializedFileWriter;
+#[cfg(feature = "parquet")]
+use ::parquet::schema::parser::parse_message_type;
+#[cfg(feature = "parquet")]
+use std::fs::File;
+#[cfg(feature = "parquet")]
+use std::sync::Arc;
+
+// Columns of the extracted regions, those of `<prefix>.regions.jsonl`
+#[cfg(feature = "parquet")]
+const SCHEMA: &str = "
+message extraction {
+    REQUIRED BYTE_ARRAY id (UTF8);
+    REQUIRED BYTE_ARRAY region (UTF8);
+    REQUIRED BYTE_ARRAY forward_primer (UTF8);
+    REQUIRED BYTE_ARRAY reverse_primer (UTF8);
+    REQUIRED INT64 start;
+    REQUIRED INT64 end;
+    REQUIRED INT32 forward_dist;
+    REQUIRED INT32 reverse_dist;
+    REQUIRED BYTE_ARRAY strand (UTF8);
+    REQUIRED BYTE_ARRAY confidence (UTF8);
+}
+";
+
+// Regions per row group, bounding the memory of the writer and of readers
+#[cfg(feature = "parquet")]
+const ROW_GROUP_SIZE: usize = 1 << 16;
+
+/// Sink writing the regions of the run as a Snappy compressed Parquet
+/// table, one row per region with the columns of the regions of
+/// `<prefix>.regions.jsonl`. Regions are written by row groups as they are
+/// found, the table being closed by [`OutputSink::finish`].
+#[cfg(feature = "parquet")]
+pub struct ParquetSink {
+    path: String,
+    writer: Option<SerializedFileWriter<File>>,
+    coords: Coords,
+    // Regions of the row group being filled
+    rows: Vec<ExtractedRegion>,
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetSink {
+    pub fn create(path: &str, coords: Coords) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Cannot create {}", path))?;
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+
+        Ok(ParquetSink {
+            path: path.to_string(),
+            writer: Some(SerializedFileWriter::new(file, schema, properties)?),
+            coords,
+            rows: Vec::new(),
+        })
+    }
+
+    fn push(&mut self, extracted: ExtractedRegion) -> anyhow::Result<()> {
+        self.rows.push(extracted);
+        if self.rows.len() >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    // Write the regions gathered since the last row group
+    fn write_row_group(&mut self) -> anyhow::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let chunk = &self.rows;
+        let text = |field: fn(&ExtractedRegion) -> &str| {
+            chunk
+                .iter()
+                .map(|extracted| ByteArray::from(field(extracted)))
+                .collect::<Vec<_>>()
+        };
+        let labels = [
+            chunk
+                .iter()
+                .map(|extracted| extracted.region.strand.to_string())
+                .collect::<Vec<_>>(),
+            chunk
+                .iter()
+                .map(|extracted| extracted.region.confidence.to_string())
+                .collect::<Vec<_>>(),
+        ];
+        let text_columns = [
+            text(|extracted| &extracted.id),
+            text(|extracted| &extracted.region.region),
+            text(|extracted| &extracted.region.forward_primer),
+            text(|extracted| &extracted.region.reverse_primer),
+        ];
+        let positions = [
+            chunk
+                .iter()
+                .map(|extracted| extracted.region.start as i64)
+                .collect::<Vec<_>>(),
+            chunk
+                .iter()
+                .map(|extracted| extracted.region.end as i64)
+                .collect::<Vec<_>>(),
+        ];
+        let distances = [
+            chunk
+                .iter()
+                .map(|extracted| extracted.region.forward_dist as i32)
+                .collect::<Vec<_>>(),
+            chunk
+                .iter()
+                .map(|extracted| extracted.region.reverse_dist as i32)
+                .collect::<Vec<_>>(),
+        ];
+
+        // Columns are written in the order of the schema
+        let mut row_group = writer.next_row_group()?;
+        for values in text_columns.iter() {
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(values, None, None)?;
+            column.close()?;
+        }
+        for values in positions.iter() {
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<Int64Type>()
+                .write_batch(values, None, None)?;
+            column.close()?;
+        }
+        for values in distances.iter() {
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<Int32Type>()
+                .write_batch(values, None, None)?;
+            column.close()?;
+        }
+        for values in labels.iter() {
+            let values = values
+                .iter()
+                .map(|value| ByteArray::from(value.as_str()))
+                .collect::<Vec<_>>();
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(&values, None, None)?;
+            column.close()?;
+        }
+        row_group.close()?;
+        self.rows.clear();
+
+        Ok(())
+    }
+
+    // Write the last row group and the footer of the table
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.write_row_group()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl OutputSink for ParquetSink {
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let mut region = found.region.clone();
+        (region.start, region.end) =
+            self.coords.interval(region.start, region.end);
+        self.push(ExtractedRegion {
+            id: found.record.id().to_string(),
+            region,
+        })
+        .with_context(|| format!("Cannot write {}", self.path))
+    }
+
+    fn finish(&mut self, _report: &RunReport) -> anyhow::Result<()> {
+        self.close().with_context(|| "Cannot write Parquet table")
+    }
+}
+
+/// Sink of the Parquet table, which cannot be created without the
+/// `parquet` feature
+#[cfg(not(feature = "parquet"))]
+pub struct ParquetSink;
+
+#[cfg(not(feature = "parquet"))]
+impl ParquetSink {
+    pub fn create(path: &str, _coords: Coords) -> anyhow::Result<Self> {
+        Err(anyhow::anyhow!(
+            "Cannot write {}: hyperex was built without the `parquet` feature",
+            path
+        ))
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+impl OutputSink for ParquetSink {}
+
+/// Write the extracted regions, located with 0-based starts and exclusive
+/// ends, as a Parquet table with a [`ParquetSink`]
+#[cfg(feature = "parquet")]
+pub fn write_regions(
+    path: &str,
+    regions: &[ExtractedRegion],
+) -> anyhow::Result<()> {
+    let mut sink = ParquetSink::create(path, Coords::ZeroBased)?;
+    for extracted in regions {
+        sink.push(extracted.clone())?;
+    }
+    sink.close()
+}
+
+#[cfg(not(feature = "parquet"))]
+pub fn write_regions(
+    path: &str,
+    _regions: &[ExtractedRegion],
+) -> anyhow::Result<()> {
+    ParquetSink::create(path, Coords::ZeroBased).map(|_| ())
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_write_regions() {
+        use crate::utils::{Confidence, RegionMatch, Strand};
+        use ::parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let region = |id: &str, start, strand| ExtractedRegion {
+            id: id.to_string(),
+            region: RegionMatch {
+                region: "v4".to_string(),
+                forward_primer: "ACGT".to_string(),
+                reverse_primer: "TTGG".to_string(),
+                start,
+                end: start + 250,
+                forward_dist: 0,
+                reverse_dist: 1,
+                strand,
+                confidence: Confidence::Medium,
+            },
+        };
+        let regions =
+            [region("a", 10, Strand::Plus), region("b", 0, Strand::Minus)];
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.parquet");
+        let path = path.to_str().unwrap();
+        write_regions(path, &regions).unwrap();
+
+        let reader =
+            SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows[1],
+            "{id: \"b\", region: \"v4\", forward_primer: \"ACGT\", \
+            reverse_primer: \"TTGG\", start: 0, end: 250, forward_dist: 0, \
+            reverse_dist: 1, strand: \"-\", confidence: \"medium\"}"
+        );
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    #[test]
+    fn test_write_regions_without_feature() {
+        assert!(write_regions("out.parquet", &[]).is_err());
+    }
+}
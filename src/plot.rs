@@ -0,0 +1,311 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::sink::{OutputSink, SinkRegion, SinkSites};
+
+use bio::io::fasta;
+
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+
+// Number of columns used to draw a sequence in the text diagram
+const TEXT_WIDTH: usize = 60;
+// SVG layout, in pixels
+const SVG_LABEL_WIDTH: usize = 240;
+const SVG_TRACK_WIDTH: usize = 560;
+const SVG_ROW_HEIGHT: usize = 16;
+// Longest sequence identifier fitting in the label column
+const SVG_ID_CHARS: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feature {
+    Forward,
+    Reverse,
+    Region,
+}
+
+impl Feature {
+    fn symbol(self) -> char {
+        match self {
+            Feature::Forward => '>',
+            Feature::Reverse => '<',
+            Feature::Region => '=',
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Feature::Forward => "#1f77b4",
+            Feature::Reverse => "#d62728",
+            Feature::Region => "#2ca02c",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlotSequence {
+    id: String,
+    len: usize,
+    // (kind, label, start, exclusive end)
+    features: Vec<(Feature, String, usize, usize)>,
+}
+
+/// Per-sequence diagram of primer hits and extracted regions, written as
+/// SVG when the path ends with `.svg` and as text otherwise
+#[derive(Debug)]
+pub struct RegionPlot {
+    path: String,
+    sequences: Vec<PlotSequence>,
+}
+
+impl RegionPlot {
+    pub fn new(path: &str) -> Self {
+        RegionPlot {
+            path: path.to_string(),
+            sequences: Vec::new(),
+        }
+    }
+
+    /// Start drawing a new sequence, following features are placed on it
+    pub fn add_sequence(&mut self, id: &str, len: usize) {
+        self.sequences.push(PlotSequence {
+            id: id.to_string(),
+            len,
+            features: Vec::new(),
+        });
+    }
+
+    pub fn add_feature(
+        &mut self,
+        kind: Feature,
+        label: &str,
+        start: usize,
+        end: usize,
+    ) {
+        if let Some(sequence) = self.sequences.last_mut() {
+            sequence
+                .features
+                .push((kind, label.to_string(), start, end));
+        }
+    }
+
+    /// Append the sequences drawn by another plot
+    pub fn merge(&mut self, other: RegionPlot) {
+        self.sequences.extend(other.sequences);
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        let content = if self.path.ends_with(".svg") {
+            self.to_svg()
+        } else {
+            self.to_text()
+        };
+        fs::write(&self.path, content)
+    }
+
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+
+        for sequence in self.sequences.iter() {
+            let _ = writeln!(out, "{} ({} bp)", sequence.id, sequence.len);
+            let _ = writeln!(out, "|{}|", "-".repeat(TEXT_WIDTH));
+            if sequence.features.is_empty() {
+                let _ = writeln!(out, " no primer hit");
+            }
+            for (kind, label, start, end) in sequence.features.iter() {
+                let (first, last) =
+                    columns(*start, *end, sequence.len, TEXT_WIDTH);
+                let track = (0..TEXT_WIDTH)
+                    .map(|col| {
+                        if col >= first && col <= last {
+                            kind.symbol()
+                        } else {
+                            ' '
+                        }
+                    })
+                    .collect::<String>();
+                let _ =
+                    writeln!(out, "|{}| {} {}-{}", track, label, start, end);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    fn to_svg(&self) -> String {
+        let rows = self
+            .sequences
+            .iter()
+            .map(|s| s.features.len() + 2)
+            .sum::<usize>();
+        let width = SVG_LABEL_WIDTH + SVG_TRACK_WIDTH + 20;
+        let height = rows * SVG_ROW_HEIGHT + 10;
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"monospace\" font-size=\"11\">",
+            width, height
+        );
+
+        let mut y = SVG_ROW_HEIGHT;
+        for sequence in self.sequences.iter() {
+            // Long identifiers would run over the sequence track
+            let short_id = if sequence.id.chars().count() > SVG_ID_CHARS {
+                let id = sequence.id.chars().take(SVG_ID_CHARS - 1);
+                format!("{}\u{2026}", id.collect::<String>())
+            } else {
+                sequence.id.clone()
+            };
+            let _ = writeln!(
+                out,
+                "<text x=\"4\" y=\"{}\">{} ({} bp)<title>{}</title></text>",
+                y,
+                escape(&short_id),
+                sequence.len,
+                escape(&sequence.id)
+            );
+            let _ = writeln!(
+                out,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#555\" stroke-width=\"2\"/>",
+                SVG_LABEL_WIDTH,
+                y - 4,
+                SVG_LABEL_WIDTH + SVG_TRACK_WIDTH,
+                y - 4
+            );
+            y += SVG_ROW_HEIGHT;
+
+            for (kind, label, start, end) in sequence.features.iter() {
+                let (first, last) =
+                    columns(*start, *end, sequence.len, SVG_TRACK_WIDTH);
+                let text = format!("{} {}-{}", escape(label), start, end);
+                let _ =
+                    writeln!(out, "<text x=\"12\" y=\"{}\">{}</text>", y, text);
+                let _ = writeln!(
+                    out,
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"10\" fill=\"{}\"><title>{}</title></rect>",
+                    SVG_LABEL_WIDTH + first,
+                    y - 10,
+                    last - first + 1,
+                    kind.color(),
+                    text
+                );
+                y += SVG_ROW_HEIGHT;
+            }
+            y += SVG_ROW_HEIGHT;
+        }
+        out.push_str("</svg>\n");
+
+        out
+    }
+}
+
+// First and last drawing columns covered by [start, end) on a sequence of
+// length len drawn on width columns
+fn columns(
+    start: usize,
+    end: usize,
+    len: usize,
+    width: usize,
+) -> (usize, usize) {
+    let column = |pos: usize| (pos * width / len.max(1)).min(width - 1);
+    let first = column(start);
+    let last = column(end.saturating_sub(1)).max(first);
+    (first, last)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl OutputSink for RegionPlot {
+    fn start_record(&mut self, record: &fasta::Record) -> anyhow::Result<()> {
+        self.add_sequence(record.id(), record.seq().len());
+        Ok(())
+    }
+
+    fn write_sites(&mut self, sites: &SinkSites) -> anyhow::Result<()> {
+        for &(start, end, _) in sites.forward.iter() {
+            self.add_feature(
+                Feature::Forward,
+                &sites.primer_pair[0],
+                start,
+                end,
+            );
+        }
+        for &(start, end, _) in sites.reverse.iter() {
+            self.add_feature(
+                Feature::Reverse,
+                &sites.primer_pair[1],
+                start,
+                end,
+            );
+        }
+        Ok(())
+    }
+
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let region = found.region;
+        self.add_feature(
+            Feature::Region,
+            &region.name(),
+            region.start,
+            region.end,
+        );
+        Ok(())
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plot() -> RegionPlot {
+        let mut plot = RegionPlot::new("plot.txt");
+        plot.add_sequence("seq1", 600);
+        plot.add_feature(Feature::Forward, "CCTACGGGNGGCWGCAG", 0, 17);
+        plot.add_feature(Feature::Region, "v3v4", 0, 300);
+        plot.add_sequence("seq2", 100);
+        plot
+    }
+
+    #[test]
+    fn test_columns() {
+        assert_eq!(columns(0, 17, 600, 60), (0, 1));
+        assert_eq!(columns(599, 600, 600, 60), (59, 59));
+        assert_eq!(columns(10, 10, 600, 60), (1, 1));
+    }
+
+    #[test]
+    fn test_to_text() {
+        let text = plot().to_text();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "seq1 (600 bp)");
+        assert!(lines[2].starts_with("|>> "));
+        assert!(lines[2].ends_with("| CCTACGGGNGGCWGCAG 0-17"));
+        assert_eq!(lines[3].matches('=').count(), 30);
+        assert_eq!(lines[7], " no primer hit");
+    }
+
+    #[test]
+    fn test_to_svg() {
+        let mut plot = plot();
+        plot.add_sequence("a<b", 10);
+        let svg = plot.to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<rect").count(), 2);
+        assert!(svg.contains("a&lt;b (10 bp)"));
+        plot.add_sequence(&"x".repeat(30), 10);
+        assert!(plot
+            .to_svg()
+            .contains(&format!("{}\u{2026} (10 bp)", "x".repeat(23))));
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::HashMap;
+
+// Longest seed looked up in the text
+const MAX_SEED_LEN: usize = 12;
+// Shorter seeds would hit almost everywhere on large sequences
+const MIN_SEED_LEN: usize = 6;
+// Maximum number of concrete k-mers a degenerate seed may expand to
+pub(crate) const MAX_SEED_VARIANTS: usize = 256;
+
+/// k-mer index of primer seeds used to avoid running the full matcher on
+/// sequence stretches that cannot contain a primer hit.
+///
+/// A primer with at most `mismatch` edits split into `mismatch + 1`
+/// segments keeps at least one segment intact (pigeonhole principle). Each
+/// segment contributes one seed, so every hit overlaps an exact seed hit
+/// and the filter never loses a match. Patterns whose seeds are too short
+/// or too degenerate are flagged as unfilterable and searched in full.
+///
+/// The seeds of all the patterns share one index, so the text is scanned
+/// once whatever the number of primers, k-mers of plain `ACGT` bases being
+/// looked up by their 2-bit code rolled along the text.
+pub struct SeedIndex {
+    k: usize,
+    mismatch: usize,
+    pattern_lens: Vec<usize>,
+    filterable: Vec<bool>,
+    // 2-bit code of an ACGT k-mer -> (pattern index, seed offset in pattern)
+    packed: HashMap<u32, Vec<(usize, usize)>>,
+    // Other k-mers, e.g. holding ambiguity codes, -> the same
+    seeds: HashMap<Vec<u8>, Vec<(usize, usize)>>,
+}
+
+impl SeedIndex {
+    pub fn new(
+        patterns: &[Vec<u8>],
+        mismatch: u8,
+        ambigs: &[(u8, &[u8])],
+    ) -> Self {
+        let mismatch = mismatch as usize;
+        let segment_len = |pattern: &Vec<u8>| pattern.len() / (mismatch + 1);
+
+        let k = patterns
+            .iter()
+            .map(segment_len)
+            .filter(|&len| len >= MIN_SEED_LEN)
+            .min()
+            .unwrap_or(MIN_SEED_LEN)
+            .min(MAX_SEED_LEN);
+
+        let mut index = SeedIndex {
+            k,
+            mismatch,
+            pattern_lens: patterns.iter().map(|p| p.len()).collect(),
+            filterable: vec![false; patterns.len()],
+            packed: HashMap::new(),
+            seeds: HashMap::new(),
+        };
+
+        for (idx, pattern) in patterns.iter().enumerate() {
+            let seg = segment_len(pattern);
+            if seg < k {
+                continue;
+            }
+
+            // For each segment keep the least degenerate k-window
+            let mut pattern_seeds = Vec::new();
+            for segment in 0..=mismatch {
+                let best = (segment * seg..=segment * seg + seg - k)
+                    .map(|offset| {
+                        let variants =
+                            expand(&pattern[offset..offset + k], ambigs);
+                        (variants.len(), offset, variants)
+                    })
+                    .min_by_key(|(count, _, _)| *count);
+
+                match best {
+                    Some((count, offset, variants))
+                        if count <= MAX_SEED_VARIANTS =>
+                    {
+                        pattern_seeds.push((offset, variants));
+                    }
+                    _ => break,
+                }
+            }
+
+            if pattern_seeds.len() != mismatch + 1 {
+                continue;
+            }
+            index.filterable[idx] = true;
+            for (offset, variants) in pattern_seeds {
+                for kmer in variants {
+                    let hits = match pack(&kmer) {
+                        Some(code) => index.packed.entry(code).or_default(),
+                        None => index.seeds.entry(kmer).or_default(),
+                    };
+                    hits.push((idx, offset));
+                }
+            }
+        }
+
+        index
+    }
+
+    /// Return, for each pattern, the merged text windows that may contain
+    /// a hit, or `None` when the whole text has to be searched.
+    pub fn candidate_windows(
+        &self,
+        text: &[u8],
+    ) -> Vec<Option<Vec<(usize, usize)>>> {
+        let mut windows: Vec<Vec<(usize, usize)>> =
+            vec![Vec::new(); self.pattern_lens.len()];
+
+        let mask = (1u32 << (2 * self.k)) - 1;
+        let mut code = 0u32;
+        // Bases with a 2-bit code ending at the current position
+        let mut run = 0;
+        for (last, &base) in text.iter().enumerate() {
+            match base_code(base) {
+                Some(bits) => {
+                    code = ((code << 2) | bits) & mask;
+                    run += 1;
+                }
+                None => run = 0,
+            }
+            if last + 1 < self.k {
+                continue;
+            }
+            let pos = last + 1 - self.k;
+            let hits = if run >= self.k {
+                self.packed.get(&code)
+            } else if self.seeds.is_empty() {
+                None
+            } else {
+                self.seeds.get(&text[pos..=last])
+            };
+            if let Some(hits) = hits {
+                for &(idx, offset) in hits {
+                    let start = pos.saturating_sub(offset + self.mismatch);
+                    let end = (pos.saturating_sub(offset)
+                        + self.pattern_lens[idx]
+                        + self.mismatch)
+                        .min(text.len());
+                    windows[idx].push((start, end));
+                }
+            }
+        }
+
+        windows
+            .into_iter()
+            .enumerate()
+            .map(|(idx, wins)| {
+                if self.filterable[idx] {
+                    Some(merge_windows(wins))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn base_code(base: u8) -> Option<u32> {
+    match base {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    }
+}
+
+// 2-bit code of a k-mer of ACGT bases
+fn pack(kmer: &[u8]) -> Option<u32> {
+    kmer.iter()
+        .try_fold(0u32, |code, &base| Some((code << 2) | base_code(base)?))
+}
+
+// Expand a degenerate k-mer into all the concrete k-mers it matches
+pub(crate) fn expand(kmer: &[u8], ambigs: &[(u8, &[u8])]) -> Vec<Vec<u8>> {
+    let mut variants = vec![Vec::with_capacity(kmer.len())];
+
+    for &base in kmer {
+        let mut equivalents = vec![base];
+        if let Some((_, eq)) = ambigs.iter().find(|(b, _)| *b == base) {
+            equivalents.extend_from_slice(eq);
+        }
+
+        if variants.len() * equivalents.len() > MAX_SEED_VARIANTS {
+            // Too degenerate, report an oversized set and stop early
+            return vec![Vec::new(); MAX_SEED_VARIANTS + 1];
+        }
+
+        variants = variants
+            .into_iter()
+            .flat_map(|prefix| {
+                equivalents.iter().map(move |&b| {
+                    let mut v = prefix.clone();
+                    v.push(b);
+                    v
+                })
+            })
+            .collect();
+    }
+
+    variants
+}
+
+fn merge_windows(mut windows: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    windows.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+
+    for (start, end) in windows {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let ambigs = [(b'R', &b"AG"[..])];
+        assert_eq!(
+            expand(b"ARC", &ambigs),
+            vec![b"ARC".to_vec(), b"AAC".to_vec(), b"AGC".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_merge_windows() {
+        assert_eq!(
+            merge_windows(vec![(10, 20), (0, 5), (15, 30), (40, 50)]),
+            vec![(0, 5), (10, 30), (40, 50)]
+        );
+    }
+
+    #[test]
+    fn test_candidate_windows() {
+        let patterns = vec![b"ACGTACGTTTGCA".to_vec()];
+        let index = SeedIndex::new(&patterns, 1, &[]);
+        let text = b"GGGGGGGGGGACGTACGATTGCAGGGGGGGGGG";
+
+        let windows = index.candidate_windows(text);
+        assert_eq!(windows.len(), 1);
+        let windows = windows[0].as_ref().unwrap();
+        assert_eq!(windows.len(), 1);
+        assert!(windows[0].0 <= 10 && windows[0].1 >= 23);
+    }
+
+    #[test]
+    fn test_candidate_windows_degenerate_seed() {
+        // Seeds holding an ambiguity code are looked up apart from the
+        // packed ones, the code itself matching in the text
+        let patterns =
+            vec![b"ACGTNCGTTTGCA".to_vec(), b"TTGGCCAATTGG".to_vec()];
+        let ambigs = [(b'N', &b"ACGT"[..])];
+        let index = SeedIndex::new(&patterns, 0, &ambigs);
+        let text = b"ACGTNCGTTTGCAGGGGGACGTGCGTTTGCAGGGGTTGGCCAATTGGacgt";
+
+        let windows = index.candidate_windows(text);
+        assert_eq!(windows[0], Some(vec![(0, 13), (18, 31)]));
+        assert_eq!(windows[1], Some(vec![(35, 47)]));
+    }
+
+    #[test]
+    fn test_candidate_windows_no_seed() {
+        let patterns = vec![b"ACGTACGTTTGCA".to_vec()];
+        let index = SeedIndex::new(&patterns, 0, &[]);
+        let windows = index.candidate_windows(b"GGGGGGGGGGGGGGGGGGGGG");
+        assert_eq!(windows[0], Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_unfilterable_short_pattern() {
+        let patterns = vec![b"ACGTA".to_vec()];
+        let index = SeedIndex::new(&patterns, 1, &[]);
+        assert_eq!(index.candidate_windows(b"ACGTAAAAA")[0], None);
+    }
+}
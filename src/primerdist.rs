@@ -0,0 +1,180 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::matcher::AmbigMap;
+use crate::utils;
+
+use std::fmt::Write as FmtWrite;
+
+/// Primers at most this edit distance apart are near-duplicates by default
+pub const MAX_DIST: usize = 2;
+
+/// Distinct primers of primer pairs, each with the labels it is known by:
+/// the region name and F or R, or the pair number for unnamed pairs
+pub fn primer_labels(primers: &[Vec<String>]) -> Vec<(String, Vec<String>)> {
+    let mut labelled: Vec<(String, Vec<String>)> = Vec::new();
+
+    for (i, pair) in primers.iter().enumerate() {
+        let region = utils::primers_to_region(pair.to_vec());
+        let name = if region.is_empty() {
+            format!("pair{}", i + 1)
+        } else {
+            region
+        };
+        for (primer, side) in pair.iter().zip(["F", "R"]) {
+            let primer = primer.to_ascii_uppercase();
+            let label = format!("{}-{}", name, side);
+            match labelled.iter_mut().find(|(p, _)| *p == primer) {
+                Some((_, labels)) => labels.push(label),
+                None => labelled.push((primer, vec![label])),
+            }
+        }
+    }
+
+    labelled
+}
+
+// Do two primer symbols match a common base?
+fn compatible(a: u8, b: u8, ambigs: &[(u8, &[u8])]) -> bool {
+    let bases = |symbol: u8| {
+        let symbol = symbol.to_ascii_uppercase();
+        let mut bases = vec![symbol];
+        if let Some((_, eq)) = ambigs.iter().find(|(s, _)| *s == symbol) {
+            bases.extend_from_slice(eq);
+        }
+        bases
+    };
+    let a = bases(a);
+    bases(b).iter().any(|base| a.contains(base))
+}
+
+/// Levenshtein distance of two primers, ambiguity codes matching any of
+/// the bases they stand for
+pub fn edit_distance(a: &str, b: &str, ambigs: &AmbigMap) -> usize {
+    let ambigs = ambigs.ambigs();
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        let mut diagonal = row[0];
+        row[0] = i;
+        for j in 1..=b.len() {
+            let substitution = diagonal
+                + usize::from(!compatible(a[i - 1], b[j - 1], &ambigs));
+            diagonal = row[j];
+            row[j] = substitution.min(row[j] + 1).min(row[j - 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Tab-separated matrix of the edit distances of all the primers, followed
+/// by the pairs of primers at most `max_dist` apart, on the same strand or
+/// once one of them is reverse complemented
+pub fn report(
+    primers: &[(String, Vec<String>)],
+    ambigs: &AmbigMap,
+    max_dist: usize,
+) -> String {
+    let mut out = String::new();
+    let names = primers
+        .iter()
+        .map(|(_, labels)| labels.join(","))
+        .collect::<Vec<_>>();
+
+    let _ = writeln!(out, "primer\tsequence\t{}", names.join("\t"));
+    let mut near = Vec::new();
+    for (i, (primer, _)) in primers.iter().enumerate() {
+        let distances = primers
+            .iter()
+            .map(|(other, _)| edit_distance(primer, other, ambigs))
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{}",
+            names[i],
+            primer,
+            distances
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join("\t")
+        );
+
+        for (j, (other, _)) in primers.iter().enumerate().skip(i + 1) {
+            let reverse = utils::to_reverse_complement(other, "dna");
+            let reverse_dist = edit_distance(primer, &reverse, ambigs);
+            if distances[j] <= max_dist {
+                near.push((i, j, distances[j], ""));
+            } else if reverse_dist <= max_dist {
+                near.push((i, j, reverse_dist, " (reverse complement)"));
+            }
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "\nNear-duplicates (edit distance <= {}): {}",
+        max_dist,
+        near.len()
+    );
+    for (i, j, distance, note) in near {
+        let _ = writeln!(
+            out,
+            "  {} {} distance {}{}",
+            names[i], names[j], distance, note
+        );
+    }
+
+    out
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        let ambigs = AmbigMap::default();
+        assert_eq!(edit_distance("ACGT", "ACGT", &ambigs), 0);
+        assert_eq!(edit_distance("ACGT", "AGGT", &ambigs), 1);
+        assert_eq!(edit_distance("ACGT", "ACT", &ambigs), 1);
+        assert_eq!(edit_distance("", "ACG", &ambigs), 3);
+        // M matches A or C, S and W share no base
+        assert_eq!(edit_distance("ATCMTG", "ATCATG", &ambigs), 0);
+        assert_eq!(edit_distance("ACST", "ACWT", &ambigs), 1);
+        assert_eq!(edit_distance("ATCMTG", "ATCATG", &AmbigMap::literal()), 1);
+    }
+
+    #[test]
+    fn test_primer_labels() {
+        let primers = vec![
+            utils::region_to_primer("v1v3").unwrap(),
+            vec!["agagtttgatcmtggctcag".to_string(), "ACGT".to_string()],
+        ];
+        let labels = primer_labels(&primers);
+        assert_eq!(labels.len(), 3);
+        assert_eq!(labels[0].1, vec!["v1v3-F", "pair2-F"]);
+        assert_eq!(labels[2].1, vec!["pair2-R"]);
+    }
+
+    #[test]
+    fn test_report() {
+        let primers = vec![
+            ("ACGTACGTAC".to_string(), vec!["a-F".to_string()]),
+            ("ACGTACGTTC".to_string(), vec!["b-F".to_string()]),
+            ("GTACGTACGT".to_string(), vec!["c-R".to_string()]),
+            ("TTTTTTTTTT".to_string(), vec!["d-R".to_string()]),
+        ];
+        let report = report(&primers, &AmbigMap::default(), 1);
+        assert!(report.starts_with("primer\tsequence\ta-F\tb-F\tc-R\td-R\n"));
+        assert!(report.contains("a-F\tACGTACGTAC\t0\t1\t"));
+        assert!(report.contains("Near-duplicates (edit distance <= 1): 3\n"));
+        assert!(report.contains("  a-F b-F distance 1\n"));
+        assert!(report.contains("  a-F c-R distance 0 (reverse complement)"));
+    }
+}
@@ -0,0 +1,270 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils::{self, Alphabet, Config, Strand};
+
+use anyhow::{anyhow, Context};
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as FmtWrite;
+use std::fs;
+
+// Longest probe the bit-parallel matcher handles
+const MAX_PROBE_LEN: usize = 64;
+
+/// Binding site of a probe on a sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeSite {
+    pub id: String,
+    pub probe: String,
+    // Strand bound by the probe sequence as given, coordinates being on the
+    // plus strand
+    pub strand: Strand,
+    pub start: usize,
+    // Exclusive end
+    pub end: usize,
+    pub dist: u8,
+    // Sequence of the site, read on the plus strand
+    pub site: String,
+}
+
+/// Named probes of a panel
+pub type Panel = Vec<(String, String)>;
+
+/// Read a panel of probes, a two-column TSV file of probe names and
+/// sequences
+pub fn read_panel(filename: &str) -> anyhow::Result<Panel> {
+    let content = fs::read_to_string(filename)
+        .with_context(|| format!("Cannot read panel {}", filename))?;
+    let mut panel = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some((name, probe)) if !probe.trim().is_empty() => {
+                panel.push((
+                    name.trim().to_string(),
+                    probe.trim().to_ascii_uppercase(),
+                ));
+            }
+            _ => {
+                return Err(anyhow!(
+                    "line {}: expected a probe name and sequence separated by a tab",
+                    i + 1
+                ))
+            }
+        }
+    }
+    if panel.is_empty() {
+        return Err(anyhow!("Panel {} contains no probe", filename));
+    }
+
+    Ok(panel)
+}
+
+/// Binding sites of the probes on both strands of the sequences of `file`,
+/// with the ids of the sequences read
+pub fn probe_sites(
+    file: &str,
+    probes: &[String],
+    config: &Config,
+) -> anyhow::Result<(Vec<ProbeSite>, Vec<String>)> {
+    if let Some(probe) = probes.iter().find(|p| p.len() > MAX_PROBE_LEN) {
+        return Err(anyhow!(
+            "Probe {} is longer than {} bases",
+            probe,
+            MAX_PROBE_LEN
+        ));
+    }
+    let mut sites = Vec::new();
+    let mut ids = Vec::new();
+
+    for record in utils::read_records(file)
+        .with_context(|| format!("Cannot read {}", file))?
+    {
+        let record = record.with_context(|| format!("Cannot read {}", file))?;
+        let seq = record.seq();
+        let alphabet = match utils::sequence_type(std::str::from_utf8(seq)?) {
+            Some(Alphabet::Rna) => "rna",
+            _ => "dna",
+        };
+        ids.push(record.id().to_string());
+        for probe in probes {
+            let minus = utils::to_reverse_complement(probe, alphabet);
+            for (pattern, strand) in
+                [(probe.as_str(), Strand::Plus), (&minus, Strand::Minus)]
+            {
+                for (start, end, dist) in
+                    utils::find_hits(pattern.as_bytes(), seq, config)
+                {
+                    sites.push(ProbeSite {
+                        id: record.id().to_string(),
+                        probe: probe.clone(),
+                        strand,
+                        start,
+                        end,
+                        dist,
+                        site: String::from_utf8_lossy(&seq[start..end])
+                            .into_owned(),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok((sites, ids))
+}
+
+/// Tab-separated list of the sites, with 0-based starts and exclusive
+/// ends, followed by the number of sites and sequences bound by each probe
+pub fn report(
+    sites: &[ProbeSite],
+    probes: &[String],
+    sequences: usize,
+) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "id\tprobe\tstrand\tstart\tend\tdistance\tsite");
+    for site in sites {
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            site.id,
+            site.probe,
+            site.strand,
+            site.start,
+            site.end,
+            site.dist,
+            site.site
+        );
+    }
+    for probe in probes {
+        let bound = sites.iter().filter(|site| site.probe == *probe);
+        let count = bound.clone().count();
+        let bound = bound.map(|site| site.id.as_str()).collect::<BTreeSet<_>>();
+        let _ = writeln!(
+            out,
+            "# {}: {} sites on {} of {} sequences",
+            probe,
+            count,
+            bound.len(),
+            sequences
+        );
+    }
+
+    out
+}
+
+/// Tab-separated presence/absence matrix of the panel probes on each
+/// sequence, giving the distance of the best site of a probe or `-` when
+/// it does not bind
+pub fn matrix(sites: &[ProbeSite], panel: &Panel, ids: &[String]) -> String {
+    let mut best: HashMap<(&str, &str), u8> = HashMap::new();
+    for site in sites {
+        let dist = best
+            .entry((site.id.as_str(), site.probe.as_str()))
+            .or_insert(site.dist);
+        *dist = (*dist).min(site.dist);
+    }
+    let mut out = String::from("id");
+
+    for (name, _) in panel {
+        let _ = write!(out, "\t{}", name);
+    }
+    out.push('\n');
+    for id in ids {
+        out.push_str(id);
+        for (_, probe) in panel {
+            match best.get(&(id.as_str(), probe.as_str())) {
+                Some(dist) => {
+                    let _ = write!(out, "\t{}", dist);
+                }
+                None => out.push_str("\t-"),
+            }
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_probe_sites() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        // The probe on the plus strand, then reverse complemented with a
+        // mismatch
+        fs::write(&input, ">a\nTTACGTTGCATTTTTGCTACAACGTTT\n>b\nGGGG\n")
+            .unwrap();
+        let input = input.to_str().unwrap();
+        let probes = vec!["ACGTTGCA".to_string()];
+        let config = Config {
+            mismatch: 1,
+            ..Default::default()
+        };
+
+        let (sites, ids) = probe_sites(input, &probes, &config).unwrap();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(sites.len(), 2);
+        assert_eq!(
+            (sites[0].strand, sites[0].start, sites[0].end, sites[0].dist),
+            (Strand::Plus, 2, 10, 0)
+        );
+        assert_eq!((sites[1].strand, sites[1].dist), (Strand::Minus, 1));
+        let report = report(&sites, &probes, ids.len());
+        assert!(report.contains("a\tACGTTGCA\t+\t2\t10\t0\tACGTTGCA\n"));
+        assert!(report.ends_with("# ACGTTGCA: 2 sites on 1 of 2 sequences\n"));
+
+        let config = Config::default();
+        assert_eq!(probe_sites(input, &probes, &config).unwrap().0.len(), 1);
+        assert!(probe_sites(input, &["A".repeat(65)], &config).is_err());
+    }
+
+    #[test]
+    fn test_panel_matrix() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let panel = dir.path().join("panel.tsv");
+        fs::write(&panel, "# name\tsequence\np1\tacgttgca\n\np2\tCCCC\n")
+            .unwrap();
+        let panel = read_panel(panel.to_str().unwrap()).unwrap();
+        assert_eq!(
+            panel,
+            [
+                ("p1".to_string(), "ACGTTGCA".to_string()),
+                ("p2".to_string(), "CCCC".to_string())
+            ]
+        );
+
+        let input = dir.path().join("in.fa");
+        fs::write(&input, ">a\nTTACGTTGCATTTTTGCTACAACGTTT\n>b\nGGGG\n")
+            .unwrap();
+        let probes = panel.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>();
+        let config = Config {
+            mismatch: 1,
+            ..Default::default()
+        };
+        let (sites, ids) =
+            probe_sites(input.to_str().unwrap(), &probes, &config).unwrap();
+        // CCCC binds b on the minus strand
+        assert_eq!(
+            matrix(&sites, &panel, &ids),
+            "id\tp1\tp2\na\t0\t-\nb\t-\t0\n"
+        );
+
+        let bad = dir.path().join("bad.tsv");
+        fs::write(&bad, "p1 ACGT\n").unwrap();
+        assert!(read_panel(bad.to_str().unwrap()).is_err());
+        fs::write(&bad, "# empty\n").unwrap();
+        assert!(read_panel(bad.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::matcher::Matcher;
+use crate::utils::{self, Alphabet, Config, Strand};
+
+use anyhow::{anyhow, Context};
+use bio::alignment::pairwise::Aligner;
+use bio::alignment::{Alignment, AlignmentOperation};
+
+// Span of a sequence as (start, exclusive end)
+type Site = (usize, usize);
+
+/// Primer sites of a pair transferred to a query, as forward and reverse
+/// hits (start, exclusive end, distance to the primer) on the plus strand
+/// of the query, and the strand of the region. Sites the query does not
+/// cover are missing.
+pub type Transfer = (
+    Option<(usize, usize, u8)>,
+    Option<(usize, usize, u8)>,
+    Strand,
+);
+
+/// Reference sequence with the primer sites of each primer pair located on
+/// it, transferred to the queries by alignment instead of searching the
+/// primers in each of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub id: String,
+    seq: Vec<u8>,
+    alphabet: &'static str,
+    // Forward and reverse primer sites of each pair, in the order of the
+    // pairs
+    sites: Vec<(Site, Site)>,
+}
+
+impl Reference {
+    /// Locate the best amplicon of each primer pair on the first sequence
+    /// of `path`, every pair having to amplify it
+    pub fn locate(
+        path: &str,
+        primers: &[Vec<String>],
+        config: &Config,
+    ) -> anyhow::Result<Self> {
+        let record = utils::read_records(path)?
+            .next()
+            .ok_or_else(|| anyhow!("No sequence in reference {}", path))?
+            .with_context(|| format!("Cannot read reference {}", path))?;
+        let seq = record.seq().to_ascii_uppercase();
+        let text = std::str::from_utf8(&seq)?;
+        let alphabet = match utils::sequence_type(text) {
+            Some(Alphabet::Dna) => "dna",
+            Some(Alphabet::Rna) => "rna",
+            None => {
+                return Err(anyhow!(
+                    "Reference {} is not a DNA or RNA sequence",
+                    record.id()
+                ))
+            }
+        };
+
+        let mut sites = Vec::new();
+        for pair in primers.iter() {
+            let reverse = utils::to_reverse_complement(&pair[1], alphabet);
+            let (forward, reverse) = utils::best_amplicon(
+                &utils::find_hits(pair[0].as_bytes(), &seq, config),
+                &utils::find_hits(reverse.as_bytes(), &seq, config),
+                &config.amplicon_lengths(pair),
+                config.max_pair_distance,
+                None,
+            )
+            .ok_or_else(|| {
+                anyhow!(
+                    "Primers {}, {} do not amplify the reference {}",
+                    pair[0],
+                    pair[1],
+                    record.id()
+                )
+            })?;
+            sites.push(((forward.0, forward.1), (reverse.0, reverse.1)));
+        }
+
+        Ok(Reference {
+            id: record.id().to_string(),
+            seq,
+            alphabet,
+            sites,
+        })
+    }
+
+    /// Forward and reverse primer sites of each pair on the reference
+    pub fn sites(&self) -> &[(Site, Site)] {
+        &self.sites
+    }
+
+    /// Primer sites of each pair transferred to `query` through its local
+    /// alignment to the reference, or to that of its reverse complement
+    /// when it scores higher and `both_strands` is set
+    pub fn transfer(
+        &self,
+        query: &[u8],
+        primers: &[Vec<String>],
+        config: &Config,
+    ) -> Vec<Transfer> {
+        let plus = query.to_ascii_uppercase();
+        let mut aligned = (self.align(&plus), plus, Strand::Plus);
+        if config.both_strands {
+            let minus =
+                utils::reverse_complement_seq(&aligned.1, self.alphabet);
+            let alignment = self.align(&minus);
+            if alignment.score > aligned.0.score {
+                aligned = (alignment, minus, Strand::Minus);
+            }
+        }
+        let (alignment, query, strand) = aligned;
+        let intervals = query_intervals(&alignment);
+
+        let matcher = Matcher::new(config.matcher().ambigs(), false);
+        // Site of the reference on the aligned query, with the distance of
+        // the primer to it
+        let transfer_site = |(start, end): Site, primer: &[u8]| {
+            if start < alignment.ystart || end > alignment.yend || end <= start
+            {
+                return None;
+            }
+            let start = intervals[start - alignment.ystart].0;
+            let end = intervals[end - 1 - alignment.ystart].1;
+            let dist = matcher
+                .best_hit(primer, &query[start..end], primer.len() as u8, None)
+                .map_or(primer.len() as u8, |hit| hit.2);
+            // Sites of the reverse complement are located on the query
+            Some(match strand {
+                Strand::Plus => (start, end, dist),
+                Strand::Minus => (query.len() - end, query.len() - start, dist),
+            })
+        };
+
+        primers
+            .iter()
+            .zip(self.sites.iter())
+            .map(|(pair, &(forward, reverse))| {
+                let reverse_primer =
+                    utils::to_reverse_complement(&pair[1], self.alphabet);
+                (
+                    transfer_site(forward, pair[0].as_bytes()),
+                    transfer_site(reverse, reverse_primer.as_bytes()),
+                    strand,
+                )
+            })
+            .collect()
+    }
+
+    // Local alignment of a query, as x, to the reference, as y
+    fn align(&self, query: &[u8]) -> Alignment {
+        let score = |a: u8, b: u8| if a == b { 1i32 } else { -1i32 };
+        let mut aligner =
+            Aligner::with_capacity(query.len(), self.seq.len(), -5, -1, &score);
+        aligner.local(query, &self.seq)
+    }
+}
+
+// Query span aligned to each reference base of an alignment, from the first
+// aligned one, empty at the following query base for reference bases
+// missing from the query
+fn query_intervals(alignment: &Alignment) -> Vec<Site> {
+    let mut x = alignment.xstart;
+    let mut intervals = Vec::new();
+
+    for op in alignment.operations.iter() {
+        match op {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                intervals.push((x, x + 1));
+                x += 1;
+            }
+            AlignmentOperation::Del => intervals.push((x, x)),
+            AlignmentOperation::Ins => x += 1,
+            _ => (),
+        }
+    }
+
+    intervals
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_transfer() {
+        let primers = vec![utils::region_to_primer("v4").unwrap()];
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("ref.fa");
+        fs::write(&path, format!(">ref\n{}\n", seq)).unwrap();
+        let config = Config::default();
+        let reference =
+            Reference::locate(path.to_str().unwrap(), &primers, &config)
+                .unwrap();
+        let ((start, _), (_, end)) = reference.sites()[0];
+
+        // A query with its forward primer site mutated beyond the allowed
+        // mismatches keeps the region of the reference
+        let mut query = seq.as_bytes()[start - 100..end + 100].to_vec();
+        for base in query[100..104].iter_mut() {
+            *base = if *base == b'A' { b'C' } else { b'A' };
+        }
+        let (forward, reverse, strand) =
+            reference.transfer(&query, &primers, &config)[0];
+        let forward = forward.unwrap();
+        assert_eq!((forward.0, reverse.unwrap().1), (100, end - start + 100));
+        assert_eq!((forward.2, strand), (4, Strand::Plus));
+
+        // Reverse complemented queries align on the minus strand
+        let minus = utils::reverse_complement_seq(&query, "dna");
+        let config = Config {
+            both_strands: true,
+            ..Default::default()
+        };
+        let (forward, reverse, strand) =
+            reference.transfer(&minus, &primers, &config)[0];
+        assert_eq!(strand, Strand::Minus);
+        assert_eq!(forward.unwrap().1, minus.len() - 100);
+        assert_eq!(reverse.unwrap().0, 100);
+
+        // Queries not covering a site miss it
+        let (forward, reverse, _) =
+            reference.transfer(&query[150..], &primers, &config)[0];
+        assert_eq!((forward, reverse.is_some()), (None, true));
+
+        assert!(Reference::locate(
+            path.to_str().unwrap(),
+            &[vec!["ACGTACGTACGTACGTAAAA".to_string(); 2]],
+            &config
+        )
+        .is_err());
+    }
+}
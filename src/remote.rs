@@ -0,0 +1,326 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::io;
+
+#[cfg(feature = "remote")]
+use anyhow::{anyhow, Context};
+#[cfg(feature = "remote")]
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(feature = "remote")]
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(feature = "remote")]
+use std::time::Duration;
+
+// Time allowed to connect to a server and for each read or write of a
+// transfer before giving up
+#[cfg(feature = "remote")]
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Is the input an URL rather than a local path?
+pub fn is_url(path: &str) -> bool {
+    ["http://", "https://", "ftp://"]
+        .iter()
+        .any(|scheme| path.starts_with(scheme))
+}
+
+/// Open a streaming reader on a remote HTTP(S) or FTP file
+#[cfg(feature = "remote")]
+pub fn open(url: &str) -> anyhow::Result<Box<dyn io::Read>> {
+    if url.starts_with("ftp://") {
+        Ok(Box::new(FtpReader::open(url)?))
+    } else {
+        let response = ureq::AgentBuilder::new()
+            .timeout_connect(TIMEOUT)
+            .timeout_read(TIMEOUT)
+            .timeout_write(TIMEOUT)
+            .build()
+            .get(url)
+            .call()
+            .with_context(|| format!("Cannot fetch {}", url))?;
+        Ok(Box::new(response.into_reader()))
+    }
+}
+
+#[cfg(not(feature = "remote"))]
+pub fn open(url: &str) -> anyhow::Result<Box<dyn io::Read>> {
+    Err(anyhow::anyhow!(
+        "Cannot read {}: hyperex was built without the `remote` feature",
+        url
+    ))
+}
+
+// Minimal passive mode FTP download, enough for public sequence servers
+#[cfg(feature = "remote")]
+struct FtpReader {
+    // Kept open until the transfer is done
+    _control: BufReader<TcpStream>,
+    data: TcpStream,
+}
+
+#[cfg(feature = "remote")]
+impl FtpReader {
+    fn open(url: &str) -> anyhow::Result<Self> {
+        let rest = url.trim_start_matches("ftp://");
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => return Err(anyhow!("No file path in {}", url)),
+        };
+        let (credentials, host) = match authority.rfind('@') {
+            Some(i) => (Some(&authority[..i]), &authority[i + 1..]),
+            None => (None, authority),
+        };
+        let (user, pass) = match credentials {
+            Some(c) => match c.find(':') {
+                Some(i) => {
+                    (percent_decode(&c[..i]), percent_decode(&c[i + 1..]))
+                }
+                None => (percent_decode(c), String::new()),
+            },
+            None => ("anonymous".to_string(), "anonymous@".to_string()),
+        };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:21", host)
+        };
+
+        let stream = connect(&address)
+            .with_context(|| format!("Cannot connect to {}", address))?;
+        // Servers behind NAT advertise private addresses for the data
+        // connection, which is opened to the server of the control one
+        let peer = stream.peer_addr()?;
+        let mut control = BufReader::new(stream);
+        ftp_expect(&mut control, "220")?;
+        ftp_command(&mut control, &format!("USER {}", user))?;
+        let reply = ftp_reply(&mut control)?;
+        if reply.starts_with("331") {
+            ftp_command(&mut control, &format!("PASS {}", pass))?;
+            ftp_expect(&mut control, "230")?;
+        } else if !reply.starts_with("230") {
+            return Err(anyhow!("FTP login failed: {}", reply));
+        }
+        ftp_command(&mut control, "TYPE I")?;
+        ftp_expect(&mut control, "200")?;
+        // Extended passive mode also works over IPv6, older servers only
+        // know the passive one
+        ftp_command(&mut control, "EPSV")?;
+        let reply = ftp_reply(&mut control)?;
+        let (port, reply) = if reply.starts_with("229") {
+            (parse_epsv(&reply), reply)
+        } else {
+            ftp_command(&mut control, "PASV")?;
+            let reply = ftp_expect(&mut control, "227")?;
+            (parse_pasv(&reply), reply)
+        };
+        let port =
+            port.ok_or_else(|| anyhow!("Cannot parse FTP reply: {}", reply))?;
+        let data_address = SocketAddr::new(peer.ip(), port);
+        let data = connect(data_address).with_context(|| {
+            format!("Cannot open FTP data connection to {}", data_address)
+        })?;
+        ftp_command(&mut control, &format!("RETR {}", path))?;
+        let reply = ftp_reply(&mut control)?;
+        if !reply.starts_with("150") && !reply.starts_with("125") {
+            return Err(anyhow!("Cannot retrieve {}: {}", path, reply));
+        }
+
+        Ok(FtpReader {
+            _control: control,
+            data,
+        })
+    }
+}
+
+#[cfg(feature = "remote")]
+impl Read for FtpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buf)
+    }
+}
+
+// Connect to the first reachable address, with timeouts on the connection
+// and its reads and writes
+#[cfg(feature = "remote")]
+fn connect(address: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let mut error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+
+    Err(error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no address found")
+    }))
+}
+
+#[cfg(feature = "remote")]
+fn ftp_command(
+    control: &mut BufReader<TcpStream>,
+    command: &str,
+) -> io::Result<()> {
+    control
+        .get_mut()
+        .write_all(format!("{}\r\n", command).as_bytes())
+}
+
+// Read a possibly multi-line FTP reply and return its last line
+#[cfg(feature = "remote")]
+fn ftp_reply<R: BufRead>(control: &mut R) -> anyhow::Result<String> {
+    let mut code = None;
+    loop {
+        let mut line = String::new();
+        if control.read_line(&mut line)? == 0 {
+            return Err(anyhow!("FTP server closed the connection"));
+        }
+        // Last line of a reply is "<code> <text>", the code being the one
+        // of its first line, "<code>-<text>" for multi-line replies
+        let code = code.get_or_insert_with(|| {
+            line.get(..3).unwrap_or_default().to_string()
+        });
+        if line.len() >= 4
+            && line.starts_with(code.as_str())
+            && line.as_bytes()[3] == b' '
+        {
+            return Ok(line.trim_end().to_string());
+        }
+    }
+}
+
+// Value of an URL component with its %XX escapes decoded
+#[cfg(feature = "remote")]
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(feature = "remote")]
+fn ftp_expect(
+    control: &mut BufReader<TcpStream>,
+    code: &str,
+) -> anyhow::Result<String> {
+    let reply = ftp_reply(control)?;
+    if reply.starts_with(code) {
+        Ok(reply)
+    } else {
+        Err(anyhow!("Unexpected FTP reply: {}", reply))
+    }
+}
+
+// Extract the data connection port from a PASV reply like
+// "227 Entering Passive Mode (130,14,250,7,195,80)", its address being
+// ignored
+#[cfg(feature = "remote")]
+fn parse_pasv(reply: &str) -> Option<u16> {
+    let start = reply.find('(')?;
+    let end = reply[start..].find(')')? + start;
+    let fields = reply[start + 1..end]
+        .split(',')
+        .map(|x| x.trim().parse::<u16>().ok())
+        .collect::<Option<Vec<u16>>>()?;
+    if fields.len() != 6 {
+        return None;
+    }
+
+    if fields[4] > 255 || fields[5] > 255 {
+        return None;
+    }
+
+    Some(fields[4] * 256 + fields[5])
+}
+
+// Extract the data connection port from an EPSV reply like
+// "229 Entering Extended Passive Mode (|||6446|)"
+#[cfg(feature = "remote")]
+fn parse_epsv(reply: &str) -> Option<u16> {
+    let start = reply.find('(')?;
+    let end = reply[start..].find(')')? + start;
+    let fields = reply[start + 1..end].split('|').collect::<Vec<_>>();
+    if fields.len() != 5 {
+        return None;
+    }
+
+    fields[3].parse().ok()
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.org/genome.fna.gz"));
+        assert!(is_url("ftp://ftp.ncbi.nlm.nih.gov/genomes/x.fa"));
+        assert!(!is_url("tests/test.fa"));
+        assert!(!is_url("-"));
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_parse_pasv() {
+        assert_eq!(
+            parse_pasv("227 Entering Passive Mode (130,14,250,7,195,80)"),
+            Some(50000)
+        );
+        assert_eq!(parse_pasv("227 Entering Passive Mode"), None);
+        assert_eq!(
+            parse_epsv("229 Entering Extended Passive Mode (|||6446|)"),
+            Some(6446)
+        );
+        assert_eq!(parse_epsv("229 Entering Extended Passive Mode"), None);
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_ftp_reply() {
+        // Text lines of a multi-line reply do not end it
+        let mut control: &[u8] =
+            b"220-Welcome\r\nabc def\r\n 230 text\r\n220 Ready\r\n331 Next\r\n";
+        assert_eq!(ftp_reply(&mut control).unwrap(), "220 Ready");
+        assert_eq!(ftp_reply(&mut control).unwrap(), "331 Next");
+        assert!(ftp_reply(&mut control).is_err());
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("user%40lab"), "user@lab");
+        assert_eq!(percent_decode("p%3Ass%2"), "p:ss%2");
+        assert_eq!(percent_decode("%zz%C3%A9"), "%zzé");
+    }
+
+    #[cfg(not(feature = "remote"))]
+    #[test]
+    fn test_open_without_feature() {
+        assert!(open("https://example.org/genome.fna.gz").is_err());
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::coords::Coords;
+use crate::sink::{self, OutputSink, SinkRegion};
+use crate::utils::{self, RegionMatch, RunSummary};
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, BufRead, Write};
+
+/// Version of the JSON schema, only bumped on incompatible changes. Reports
+/// of version 1 listed the regions, now in `<prefix>.regions.jsonl`.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// A region extracted from the sequence `id`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtractedRegion {
+    pub id: String,
+    #[serde(flatten)]
+    pub region: RegionMatch,
+}
+
+/// Results of a run as written to `<prefix>.run.json`, its regions being
+/// written as they are found by [`RegionsSink`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunReport {
+    pub schema_version: u32,
+    // Version of hyperex which wrote the report
+    pub version: String,
+    // Version of the built-in primer database of the run
+    #[serde(default)]
+    pub primer_db_version: String,
+    // Coordinate convention of the regions
+    #[serde(default)]
+    pub coords: Coords,
+    // Seed of the random draws of the run, repeating them when given again
+    // with --seed, if the run made any
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub summary: RunSummary,
+}
+
+impl RunReport {
+    pub fn new(summary: RunSummary) -> Self {
+        RunReport {
+            schema_version: SCHEMA_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            primer_db_version: utils::PRIMER_DB_VERSION.to_string(),
+            coords: Coords::ZeroBased,
+            seed: None,
+            summary,
+        }
+    }
+
+    /// Report of a run whose regions are located following `coords`
+    pub fn with_coords(mut self, coords: Coords) -> Self {
+        self.coords = coords;
+        self
+    }
+
+    /// Report of a run whose random draws were made from `seed`
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Read a report, refusing those written with a newer schema
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Cannot open report {}", path))?;
+        let report: RunReport =
+            serde_json::from_reader(io::BufReader::new(file))
+                .with_context(|| format!("Cannot parse report {}", path))?;
+        if report.schema_version > SCHEMA_VERSION {
+            return Err(anyhow!(
+                "{} uses schema version {}, this version of hyperex reads up to {}",
+                path,
+                report.schema_version,
+                SCHEMA_VERSION
+            ));
+        }
+
+        Ok(report)
+    }
+
+    /// Concise Markdown report of the run, to be shared in lab notebooks or
+    /// issues: the given run parameters as (name, value), the counts and
+    /// lengths of each region and the warnings of the run
+    pub fn markdown(&self, parameters: &[(String, String)]) -> String {
+        let summary = &self.summary;
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# hyperex {} run report\n", self.version);
+        let _ = writeln!(out, "## Parameters\n");
+        for (name, value) in parameters {
+            let _ = writeln!(out, "- {}: {}", name, value);
+        }
+        let _ = writeln!(out, "\n## Results\n");
+        let _ = writeln!(
+            out,
+            "{} sequences processed ({} full-length, {} partial, {} fragment), {} regions extracted.\n",
+            summary.sequences,
+            summary.full_length,
+            summary.partial,
+            summary.fragment,
+            summary.extracted
+        );
+        if !summary.regions.is_empty() {
+            let _ = writeln!(
+                out,
+                "| Region | Count | Min length | Median length | Max length |"
+            );
+            let _ = writeln!(out, "|---|---:|---:|---:|---:|");
+            for (region, count) in summary.regions.iter() {
+                let (min, median, max) = summary
+                    .lengths
+                    .get(region)
+                    .map(length_stats)
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} |",
+                    region, count, min, median, max
+                );
+            }
+            out.push('\n');
+        }
+        let _ = writeln!(out, "## Warnings\n");
+        let warnings = summary.warnings();
+        if warnings.is_empty() {
+            let _ = writeln!(out, "None");
+        }
+        for warning in warnings {
+            let _ = writeln!(out, "- {}", warning);
+        }
+
+        out
+    }
+
+    pub fn write_markdown(
+        &self,
+        path: &str,
+        parameters: &[(String, String)],
+    ) -> anyhow::Result<()> {
+        fs::write(path, self.markdown(parameters))?;
+        Ok(())
+    }
+}
+
+/// Sink writing the report of the run to `<prefix>.run.json`
+pub struct JsonSink {
+    path: String,
+}
+
+impl JsonSink {
+    pub fn new(path: &str) -> Self {
+        JsonSink {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl OutputSink for JsonSink {
+    fn finish(&mut self, report: &RunReport) -> anyhow::Result<()> {
+        report
+            .write(&self.path)
+            .with_context(|| "Cannot write run report")
+    }
+}
+
+/// Sink writing the regions of the run to `<prefix>.regions.jsonl` as they
+/// are found, one JSON object per line with the fields of
+/// [`ExtractedRegion`], so that runs of any size are written without
+/// holding their regions
+pub struct RegionsSink {
+    path: String,
+    writer: io::BufWriter<File>,
+    coords: Coords,
+}
+
+impl RegionsSink {
+    pub fn create(
+        path: &str,
+        coords: Coords,
+        append: bool,
+    ) -> anyhow::Result<Self> {
+        Ok(RegionsSink {
+            path: path.to_string(),
+            writer: io::BufWriter::new(utils::open_output(path, None, append)?),
+            coords,
+        })
+    }
+}
+
+impl OutputSink for RegionsSink {
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let mut region = found.region.clone();
+        (region.start, region.end) =
+            self.coords.interval(region.start, region.end);
+        let extracted = ExtractedRegion {
+            id: found.record.id().to_string(),
+            region,
+        };
+        serde_json::to_writer(&mut self.writer, &extracted)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(writeln!(self.writer)?))
+            .with_context(|| format!("Cannot write {}", self.path))
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        sink::flush_file(&mut self.writer, fsync)
+    }
+
+    fn finish(&mut self, _report: &RunReport) -> anyhow::Result<()> {
+        self.flush(false)
+    }
+}
+
+/// Read the regions written by [`RegionsSink`], located following the
+/// coordinates of the run
+pub fn read_regions(path: &str) -> anyhow::Result<Vec<ExtractedRegion>> {
+    let file = File::open(path)
+        .with_context(|| format!("Cannot open regions {}", path))?;
+    io::BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.is_empty()))
+        .map(|(i, line)| {
+            let line = line?;
+            serde_json::from_str(&line).with_context(|| {
+                format!("Cannot parse line {} of {}", i + 1, path)
+            })
+        })
+        .collect()
+}
+
+// Shortest, median and longest of the lengths counted in `lengths`
+fn length_stats(lengths: &BTreeMap<usize, usize>) -> (usize, usize, usize) {
+    let total = lengths.values().sum::<usize>();
+    let mut seen = 0;
+    let median = lengths
+        .iter()
+        .find(|(_, &count)| {
+            seen += count;
+            seen * 2 >= total
+        })
+        .map_or(0, |(&length, _)| length);
+    let min = lengths.keys().next().copied().unwrap_or(0);
+    let max = lengths.keys().next_back().copied().unwrap_or(0);
+    (min, median, max)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{Confidence, Strand};
+    use bio::io::fasta;
+    use std::fs;
+
+    #[test]
+    fn test_round_trip() {
+        let region = RegionMatch {
+            region: "v4".to_string(),
+            forward_primer: "GTGYCAGCMGCCGCGGTAA".to_string(),
+            reverse_primer: "GGACTACNVGGGTWTCTAAT".to_string(),
+            start: 10,
+            end: 300,
+            forward_dist: 0,
+            reverse_dist: 1,
+            strand: Strand::Minus,
+            confidence: Confidence::High,
+        };
+        let mut summary = RunSummary::default();
+        summary.add_match(&region);
+        let report = RunReport::new(summary);
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.run.json");
+        let path = path.to_str().unwrap();
+        report.write(path).unwrap();
+        let json = fs::read_to_string(path).unwrap();
+        assert!(json.contains("\"schema_version\": 2"));
+        assert!(!json.contains("\"regions\": ["));
+        assert!(json.contains("\"coords\": \"0based\""));
+        assert!(json.contains("\"primer_db_version\": \"2024.1\""));
+        assert!(json.contains("\"seed\": null"));
+        assert_eq!(RunReport::read(path).unwrap(), report);
+        let seeded = report.clone().with_seed(42);
+        seeded.write(path).unwrap();
+        assert_eq!(RunReport::read(path).unwrap().seed, Some(42));
+
+        let one_based = report.clone().with_coords(Coords::OneBased);
+        assert_eq!(one_based.coords, Coords::OneBased);
+
+        fs::write(
+            path,
+            json.replace("\"schema_version\": 2", "\"schema_version\": 99"),
+        )
+        .unwrap();
+        assert!(RunReport::read(path).is_err());
+
+        // Counts added since a report was written are read as zero
+        fs::write(
+            path,
+            r#"{"schema_version": 1, "version": "0.2.0",
+            "summary": {"sequences": 2, "extracted": 0},
+            "regions": []}"#,
+        )
+        .unwrap();
+        let report = RunReport::read(path).unwrap();
+        assert_eq!(report.summary.sequences, 2);
+        assert_eq!(report.coords, Coords::ZeroBased);
+        assert_eq!(report.seed, None);
+    }
+
+    #[test]
+    fn test_regions_sink() {
+        let region = RegionMatch {
+            region: "v4".to_string(),
+            forward_primer: "GTGYCAGCMGCCGCGGTAA".to_string(),
+            reverse_primer: "GGACTACNVGGGTWTCTAAT".to_string(),
+            start: 10,
+            end: 300,
+            forward_dist: 0,
+            reverse_dist: 1,
+            strand: Strand::Minus,
+            confidence: Confidence::High,
+        };
+        let record = fasta::Record::with_attrs("seq1", None, b"ACGT");
+        let found = SinkRegion {
+            record: &record,
+            region: &region,
+            desc: "",
+            seq: b"ACGT",
+        };
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.regions.jsonl");
+        let path = path.to_str().unwrap();
+        let mut sink = RegionsSink::create(path, Coords::OneBased, false)
+            .expect("Cannot create regions");
+        sink.write_region(&found).unwrap();
+        sink.finish(&RunReport::new(RunSummary::default())).unwrap();
+        let json = fs::read_to_string(path).unwrap();
+        assert_eq!(json.lines().count(), 1);
+        assert!(json.starts_with("{\"id\":\"seq1\",\"region\":\"v4\""));
+        assert!(json.contains("\"strand\":\"-\""));
+
+        // Regions of later runs are appended
+        let mut sink = RegionsSink::create(path, Coords::OneBased, true)
+            .expect("Cannot create regions");
+        sink.write_region(&found).unwrap();
+        sink.flush(false).unwrap();
+        let regions = read_regions(path).unwrap();
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[1].id, "seq1");
+        assert_eq!((regions[1].region.start, regions[1].region.end), (11, 300));
+    }
+
+    #[test]
+    fn test_markdown() {
+        let region = |start, end| RegionMatch {
+            region: "v4".to_string(),
+            forward_primer: "GTGYCAGCMGCCGCGGTAA".to_string(),
+            reverse_primer: "GGACTACNVGGGTWTCTAAT".to_string(),
+            start,
+            end,
+            forward_dist: 0,
+            reverse_dist: 0,
+            strand: Strand::Plus,
+            confidence: Confidence::High,
+        };
+        let mut summary = RunSummary::default();
+        for (start, end) in [(0, 290), (0, 292), (10, 310)] {
+            summary.add_match(&region(start, end));
+        }
+        summary.sequences = 4;
+        summary.fragment = 4;
+        summary.chimera_suspects = 1;
+        let report = RunReport::new(summary);
+        let parameters = vec![("Mismatches".to_string(), "1".to_string())];
+
+        let markdown = report.markdown(&parameters);
+        assert!(markdown.contains("## Parameters\n\n- Mismatches: 1\n"));
+        assert!(markdown.contains("4 sequences processed"));
+        assert!(markdown.contains("| v4 | 3 | 290 | 292 | 300 |\n"));
+        assert!(markdown.ends_with(
+            "## Warnings\n\n- 1 regions flagged chimera_suspect, their length is discordant with the E. coli positions of their primers\n"
+        ));
+
+        let report = RunReport::new(RunSummary::default());
+        let markdown = report.markdown(&[]);
+        assert!(!markdown.contains("| Region"));
+        assert!(markdown.ends_with("## Warnings\n\nNone\n"));
+    }
+}
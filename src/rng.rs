@@ -0,0 +1,88 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Xorshift generator of the stochastic features, e.g. subsampling and
+/// synthetic records, enough for reproducible draws.
+///
+/// Draws on a record only depend on the seed of the run and on the record
+/// itself, through [`Rng::for_key`], never on the order or the thread the
+/// records are processed in, so that a run given the same seed gives the
+/// same results.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // The state must not be zero
+        Rng((seed ^ 0x9E37_79B9_7F4A_7C15).max(1))
+    }
+
+    /// Generator of the draws on `key`, e.g. the id of a record, for the
+    /// run seeded with `seed`
+    pub fn for_key(seed: u64, key: &[u8]) -> Self {
+        // FNV-1a, stable across platforms and releases unlike the hashers
+        // of the standard library
+        let hash = key.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        Rng::new(mix(seed ^ mix(hash)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Integer drawn in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Number drawn in `[0, 1)`
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// SplitMix64 finalizer, spreading close seeds over the whole state
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Seed of a run given none, drawn from the clock, to be recorded so that
+/// the run can be repeated
+pub fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    mix(nanos ^ std::process::id() as u64)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let draws = |mut rng: Rng| (0..4).map(|_| rng.below(100)).collect();
+        let first: Vec<usize> = draws(Rng::new(7));
+        assert_eq!(first, draws(Rng::new(7)));
+        assert_ne!(first, draws(Rng::new(8)));
+
+        let mut rng = Rng::for_key(7, b"seq1");
+        let unit = rng.unit();
+        assert!((0.0..1.0).contains(&unit));
+        assert_eq!(Rng::for_key(7, b"seq1").unit(), unit);
+        assert_ne!(Rng::for_key(7, b"seq2").unit(), unit);
+        assert_ne!(Rng::for_key(8, b"seq1").unit(), unit);
+    }
+}
@@ -0,0 +1,815 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::matcher::{self, Hit, Matcher};
+use crate::prefilter::SeedIndex;
+use crate::reference::Transfer;
+use crate::utils::{
+    self, chunk_windows, ecoli_amplicon_length, primers_to_region, region_gene,
+    terminal_windows, to_reverse_complement, Amplicon, Collapsed, Completeness,
+    Config, Strand, CHIMERA_TOLERANCE, FORWARD_PRIMERS, REVERSE_PRIMERS,
+};
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::time::Instant;
+
+// Bases of the windows searched between checks of --record-timeout, when
+// records are not already searched by chunks
+const TIMEOUT_CHUNK_SIZE: usize = 1 << 20;
+
+// Hits of a primer pair on a sequence, on both strands when searched, and
+// the amplicons they make
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PairHits {
+    pub(crate) forward: Vec<Hit>,
+    pub(crate) reverse: Vec<Hit>,
+    pub(crate) amplicons: Vec<Amplicon>,
+    // Pairings of hits left out as not on one strand
+    pub(crate) inconsistent: usize,
+    // Whether the amplicon was found by re-searching its missing primer
+    pub(crate) rescued: bool,
+}
+
+// Completeness of a sequence with the hits of each primer pair on it
+pub(crate) type RecordHits = (Completeness, Vec<PairHits>);
+
+// Text windows of each pattern, none standing for the whole text
+type Windows = Vec<Option<Vec<(usize, usize)>>>;
+
+// Primer search of the records of an input, with what is shared by the
+// records: the distinct primers of the pairs, the marker gene of each pair
+// and the seed indexes of the prefilter
+pub(crate) struct PrimerSearch<'a> {
+    primers: &'a [Vec<String>],
+    config: &'a Config,
+    // Primer search following the matching settings, with IUPAC
+    // ambiguities in primers
+    matcher: Matcher,
+    // Most mismatches allowed to a primer, bounding the search windows
+    max_mismatch: u8,
+    // Primers shared by several pairs, e.g. the forward primer of v1v2,
+    // v1v3 and v1v9, are searched once per record under their index
+    primer_ids: Vec<[usize; 2]>,
+    // Indices of the terminal primers when searched by a pair, whose hits
+    // then tell full-length sequences apart
+    terminal_ids: [Option<usize>; 2],
+    genes: Vec<Option<&'static str>>,
+    // Seed indexes, one per alphabet as reverse primers are complemented
+    // according to the sequence type
+    seed_indexes: HashMap<&'static str, SeedIndex>,
+}
+
+impl<'a> PrimerSearch<'a> {
+    pub(crate) fn new(primers: &'a [Vec<String>], config: &'a Config) -> Self {
+        let max_mismatch = primers
+            .iter()
+            .flat_map(|pair| {
+                pair.iter()
+                    .map(move |primer| config.primer_mismatch(pair, primer))
+            })
+            .fold(config.mismatch, u8::max);
+        let primer_ids = primer_indices(primers, config);
+        let terminal_ids = [
+            primers
+                .iter()
+                .position(|pair| pair[0] == FORWARD_PRIMERS["27F"])
+                .map(|i| primer_ids[i][0]),
+            primers
+                .iter()
+                .position(|pair| pair[1] == REVERSE_PRIMERS["1492Rmod"])
+                .map(|i| primer_ids[i][1]),
+        ];
+        let genes = primers
+            .iter()
+            .map(|pair| region_gene(&primers_to_region(pair.to_vec())))
+            .collect();
+
+        PrimerSearch {
+            primers,
+            config,
+            matcher: config.matcher(),
+            max_mismatch,
+            primer_ids,
+            terminal_ids,
+            genes,
+            seed_indexes: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    // Is the pair `i` searched in a sequence of `gene`, every pair being
+    // searched in unclassified sequences?
+    pub(crate) fn searched(&self, i: usize, gene: Option<&str>) -> bool {
+        gene.is_none_or(|gene| self.genes[i] == Some(gene))
+    }
+
+    // Completeness of a sequence and hits of each primer pair on it, those
+    // of the pairs of other marker genes than `gene` being left empty.
+    // None when the search is given up past the deadline, its hits being
+    // partial.
+    pub(crate) fn search(
+        &mut self,
+        seq: &[u8],
+        alphabet: &'static str,
+        gene: Option<&str>,
+        deadline: Option<Instant>,
+    ) -> Option<RecordHits> {
+        let terminals = terminal_patterns(alphabet, self.config.both_strands);
+        let windows = self.windows(seq, alphabet, &terminals);
+        let this = &*self;
+        let mut record = RecordSearch::new(this, seq, alphabet, deadline);
+
+        let mut pair_hits = Vec::with_capacity(this.primers.len());
+        for i in 0..this.primers.len() {
+            if record.timed_out() {
+                return None;
+            }
+            pair_hits.push(if this.searched(i, gene) {
+                record.pair_hits(i, &windows)
+            } else {
+                PairHits::default()
+            });
+        }
+        if record.timed_out() {
+            return None;
+        }
+
+        // Sequences only searched for the regions of other marker genes
+        // are 16S fragments
+        let sixteen_s = (0..this.primers.len())
+            .filter(|&i| this.searched(i, gene))
+            .any(|i| this.genes[i].is_none_or(|gene| gene == "16S"));
+        let completeness = if sixteen_s {
+            record.completeness(&terminals, &windows[2 * this.primers.len()..])
+        } else {
+            Completeness::Fragment
+        };
+
+        Some((completeness, pair_hits))
+    }
+
+    // Text windows worth searching for each primer, the record ends for
+    // anchored primers, all the sequence when prefiltering is off, the
+    // terminal primers coming after those of the pairs
+    fn windows(
+        &mut self,
+        seq: &[u8],
+        alphabet: &'static str,
+        terminals: &[String],
+    ) -> Windows {
+        let (primers, config) = (self.primers, self.config);
+        if let Some(k) = config.anchored {
+            primers
+                .iter()
+                .flat_map(|pair| {
+                    pair.iter()
+                        .map(|primer| {
+                            primer.len()
+                                + config.primer_mismatch(pair, primer) as usize
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .chain(
+                    terminals
+                        .iter()
+                        .map(|primer| primer.len() + config.mismatch as usize),
+                )
+                .map(|len| Some(terminal_windows(seq.len(), k + len)))
+                .collect()
+        } else if config.prefilter
+            && !config.ambigs.has_wildcards()
+            && !config.homopolymer_tolerant
+        {
+            let (matcher, max_mismatch) = (&self.matcher, self.max_mismatch);
+            self.seed_indexes
+                .entry(alphabet)
+                .or_insert_with(|| {
+                    let patterns = primers
+                        .iter()
+                        .flat_map(|pair| {
+                            vec![
+                                pair[0].as_bytes().to_vec(),
+                                to_reverse_complement(&pair[1], alphabet)
+                                    .into_bytes(),
+                            ]
+                        })
+                        .chain(
+                            terminals
+                                .iter()
+                                .map(|primer| primer.clone().into_bytes()),
+                        )
+                        .collect::<Vec<_>>();
+                    let ambigs = matcher.ambigs().ambigs();
+                    SeedIndex::new(&patterns, max_mismatch, &ambigs)
+                })
+                .candidate_windows(seq)
+        } else {
+            vec![None; primers.len() * 2 + terminals.len()]
+        }
+    }
+}
+
+// Primer search of a record, the hits of each primer being kept under its
+// index and whether it is complemented
+struct RecordSearch<'s, 'a> {
+    search: &'s PrimerSearch<'a>,
+    seq: &'s [u8],
+    alphabet: &'static str,
+    deadline: Option<Instant>,
+    // Searches are given up past the deadline, between windows of the
+    // record
+    matcher: Cow<'s, Matcher>,
+    // Record with collapsed homopolymer runs, searched with collapsed
+    // primers when tolerating homopolymer errors
+    collapsed: Option<Collapsed>,
+    // Size of the overlapping windows of long records, bounding the memory
+    // of the matcher and the time between checks of the deadline, when no
+    // prefilter window is given
+    chunk_size: Option<usize>,
+    // Primer sites of the reference aligned to the sequence
+    transferred: Option<Vec<Transfer>>,
+    hits: HashMap<(usize, bool), Vec<Hit>>,
+}
+
+impl<'s, 'a> RecordSearch<'s, 'a> {
+    fn new(
+        search: &'s PrimerSearch<'a>,
+        seq: &'s [u8],
+        alphabet: &'static str,
+        deadline: Option<Instant>,
+    ) -> Self {
+        let config = search.config;
+        RecordSearch {
+            search,
+            seq,
+            alphabet,
+            deadline,
+            matcher: match deadline {
+                Some(deadline) => Cow::Owned(search.matcher.until(deadline)),
+                None => Cow::Borrowed(&search.matcher),
+            },
+            collapsed: config.homopolymer_tolerant.then(|| Collapsed::new(seq)),
+            chunk_size: config
+                .chunk_size
+                .or(deadline.map(|_| TIMEOUT_CHUNK_SIZE)),
+            transferred: config.reference.as_ref().map(|reference| {
+                reference.transfer(seq, search.primers, config)
+            }),
+            hits: HashMap::new(),
+        }
+    }
+
+    fn timed_out(&self) -> bool {
+        matcher::expired(self.deadline)
+    }
+
+    fn chunks(
+        &self,
+        pattern: &[u8],
+        len: usize,
+    ) -> Option<Vec<(usize, usize)>> {
+        self.chunk_size.map(|size| {
+            chunk_windows(
+                len,
+                size,
+                pattern.len() + self.search.max_mismatch as usize,
+            )
+        })
+    }
+
+    // Best hit of a pattern in the windows of the record, if any
+    fn best_hit(
+        &self,
+        pattern: &[u8],
+        windows: Option<&[(usize, usize)]>,
+    ) -> Option<Hit> {
+        let mismatch = self.search.config.mismatch;
+        match &self.collapsed {
+            Some(text) => {
+                let pattern = Collapsed::new(pattern).seq;
+                let chunks = self.chunks(&pattern, text.seq.len());
+                self.matcher
+                    .best_hit(&pattern, &text.seq, mismatch, chunks.as_deref())
+                    .map(|hit| text.expand(hit))
+            }
+            None => {
+                let chunks = self.chunks(pattern, self.seq.len());
+                self.matcher.best_hit(
+                    pattern,
+                    self.seq,
+                    mismatch,
+                    windows.or(chunks.as_deref()),
+                )
+            }
+        }
+    }
+
+    // Every hit of a pattern in the windows of the record, hits further
+    // than K bases from both record ends being ignored when primers are
+    // anchored there
+    fn all_hits(
+        &self,
+        pattern: &[u8],
+        mismatch: u8,
+        windows: Option<&[(usize, usize)]>,
+    ) -> Vec<Hit> {
+        let mut hits = match &self.collapsed {
+            Some(text) => {
+                let pattern = Collapsed::new(pattern).seq;
+                let chunks = self.chunks(&pattern, text.seq.len());
+                self.matcher
+                    .all_hits(&pattern, &text.seq, mismatch, chunks.as_deref())
+                    .into_iter()
+                    .map(|hit| text.expand(hit))
+                    .collect::<Vec<_>>()
+            }
+            None => {
+                let chunks = self.chunks(pattern, self.seq.len());
+                self.matcher.all_hits(
+                    pattern,
+                    self.seq,
+                    mismatch,
+                    windows.or(chunks.as_deref()),
+                )
+            }
+        };
+        if let Some(k) = self.search.config.anchored {
+            let len = self.seq.len();
+            hits.retain(|hit| hit.0 <= k || hit.1 + k >= len);
+        }
+
+        hits
+    }
+
+    // Hits of a primer, searched once per record under its index and
+    // whether it is complemented
+    fn primer_hits(
+        &mut self,
+        key: (usize, bool),
+        pattern: &[u8],
+        mismatch: u8,
+        windows: Option<&[(usize, usize)]>,
+    ) -> Vec<Hit> {
+        if let Some(hits) = self.hits.get(&key) {
+            return hits.clone();
+        }
+        let hits = self.all_hits(pattern, mismatch, windows);
+        self.hits.insert(key, hits.clone());
+
+        hits
+    }
+
+    // Forward hits, reverse hits and amplicons of the pair `i`. Every
+    // amplicon when looking for all of them, the best pairing of the
+    // primer hits otherwise
+    fn pair_hits(&mut self, i: usize, windows: &Windows) -> PairHits {
+        let config = self.search.config;
+        let primer_pair = &self.search.primers[i];
+        if let Some(transferred) = self.transferred.as_ref() {
+            let (forward, reverse, strand) = transferred[i];
+            return PairHits {
+                forward: forward.into_iter().collect(),
+                reverse: reverse.into_iter().collect(),
+                amplicons: forward
+                    .zip(reverse)
+                    .map(|(forward, reverse)| (forward, reverse, strand))
+                    .into_iter()
+                    .collect(),
+                inconsistent: 0,
+                rescued: false,
+            };
+        }
+
+        let reverse = to_reverse_complement(&primer_pair[1], self.alphabet);
+        let [forward_id, reverse_id] = self.search.primer_ids[i];
+        let forward_hits = self.primer_hits(
+            (forward_id, false),
+            primer_pair[0].as_bytes(),
+            config.primer_mismatch(primer_pair, &primer_pair[0]),
+            windows[2 * i].as_deref(),
+        );
+        let reverse_hits = self.primer_hits(
+            (reverse_id, true),
+            reverse.as_bytes(),
+            config.primer_mismatch(primer_pair, &primer_pair[1]),
+            windows[2 * i + 1].as_deref(),
+        );
+        if config.both_strands {
+            self.both_strands(i, forward_hits, reverse_hits)
+        } else {
+            self.plus_strand(i, forward_hits, reverse_hits)
+        }
+    }
+
+    // Amplicons of the pair `i` on the plus strand, a primer missing while
+    // its mate is found being rescued when asked
+    fn plus_strand(
+        &self,
+        i: usize,
+        mut forward_hits: Vec<Hit>,
+        mut reverse_hits: Vec<Hit>,
+    ) -> PairHits {
+        let config = self.search.config;
+        let primer_pair = &self.search.primers[i];
+        let lengths = config.amplicon_lengths(primer_pair);
+        let mut amplicons = if config.all_matches {
+            utils::pair_amplicons(
+                &forward_hits,
+                &reverse_hits,
+                &lengths,
+                config.max_pair_distance,
+                self.deadline,
+            )
+        } else {
+            utils::best_amplicon(
+                &forward_hits,
+                &reverse_hits,
+                &lengths,
+                config.max_pair_distance,
+                self.deadline,
+            )
+            .into_iter()
+            .collect()
+        };
+
+        let missing = forward_hits.is_empty() != reverse_hits.is_empty();
+        let rescued = config
+            .rescue
+            .filter(|_| amplicons.is_empty() && missing)
+            .and_then(|extra| {
+                self.rescue(i, (&forward_hits, &reverse_hits), &lengths, extra)
+            });
+        let is_rescued = rescued.is_some();
+        if let Some((amplicon, hits)) = rescued {
+            amplicons.push(amplicon);
+            if reverse_hits.is_empty() {
+                reverse_hits = hits;
+            } else {
+                forward_hits = hits;
+            }
+        }
+
+        PairHits {
+            forward: forward_hits,
+            reverse: reverse_hits,
+            amplicons: amplicons
+                .into_iter()
+                .map(|(forward, reverse)| (forward, reverse, Strand::Plus))
+                .collect(),
+            inconsistent: 0,
+            rescued: is_rescued,
+        }
+    }
+
+    // Amplicon of the pair `i` whose missing primer is searched again, with
+    // `extra` more mismatches, where the typical amplicons of the pair put
+    // it, with the hits of the missing primer
+    fn rescue(
+        &self,
+        i: usize,
+        (forward_hits, reverse_hits): (&[Hit], &[Hit]),
+        lengths: &RangeInclusive<usize>,
+        extra: u8,
+    ) -> Option<((Hit, Hit), Vec<Hit>)> {
+        let config = self.search.config;
+        let primer_pair = &self.search.primers[i];
+        let rescue = rescue_lengths(primer_pair, lengths)?;
+        let downstream = reverse_hits.is_empty();
+        let (anchors, pattern, primer) = if downstream {
+            (
+                forward_hits,
+                to_reverse_complement(&primer_pair[1], self.alphabet),
+                &primer_pair[1],
+            )
+        } else {
+            (reverse_hits, primer_pair[0].clone(), &primer_pair[0])
+        };
+        let mismatch = config.primer_mismatch(primer_pair, primer);
+        let windows =
+            rescue_windows(anchors, downstream, &rescue, self.seq.len());
+        let mut hits = self.all_hits(
+            pattern.as_bytes(),
+            mismatch.saturating_add(extra),
+            Some(&windows),
+        );
+        hits.retain(|hit| {
+            windows
+                .iter()
+                .any(|window| hit.0 >= window.0 && hit.1 <= window.1)
+        });
+        let (forward, reverse) = if downstream {
+            (forward_hits, hits.as_slice())
+        } else {
+            (hits.as_slice(), reverse_hits)
+        };
+        let amplicon = utils::best_amplicon(
+            forward,
+            reverse,
+            &rescue,
+            config.max_pair_distance,
+            self.deadline,
+        )?;
+
+        Some((amplicon, hits))
+    }
+
+    // Amplicons of the pair `i` on both strands, from the hits of its
+    // primers on the plus strand and of their reverse complements
+    fn both_strands(
+        &mut self,
+        i: usize,
+        mut forward_hits: Vec<Hit>,
+        mut reverse_hits: Vec<Hit>,
+    ) -> PairHits {
+        let config = self.search.config;
+        let primer_pair = &self.search.primers[i];
+        let [forward_id, reverse_id] = self.search.primer_ids[i];
+        let minus_forward = self.primer_hits(
+            (forward_id, true),
+            to_reverse_complement(&primer_pair[0], self.alphabet).as_bytes(),
+            config.primer_mismatch(primer_pair, &primer_pair[0]),
+            None,
+        );
+        let minus_reverse = self.primer_hits(
+            (reverse_id, false),
+            primer_pair[1].as_bytes(),
+            config.primer_mismatch(primer_pair, &primer_pair[1]),
+            None,
+        );
+        let (amplicons, inconsistent) = stranded_amplicons(
+            (&forward_hits, &reverse_hits),
+            (&minus_forward, &minus_reverse),
+            &config.amplicon_lengths(primer_pair),
+            config.max_pair_distance,
+            config.all_matches,
+            self.deadline,
+        );
+        forward_hits.extend(minus_forward);
+        forward_hits.sort_unstable();
+        reverse_hits.extend(minus_reverse);
+        reverse_hits.sort_unstable();
+
+        PairHits {
+            forward: forward_hits,
+            reverse: reverse_hits,
+            amplicons,
+            inconsistent,
+            rescued: false,
+        }
+    }
+
+    // Completeness of the record from the terminal primers found on the
+    // plus strand, or on the minus strand when it is searched and has more
+    // of them. The hits of the pairs searching them are reused, the others
+    // being searched in their windows.
+    fn completeness(
+        &self,
+        terminals: &[String],
+        windows: &[Option<Vec<(usize, usize)>>],
+    ) -> Completeness {
+        let found = terminals
+            .iter()
+            .enumerate()
+            .map(|(t, pattern)| {
+                // 27F then 1492Rmod, complemented on the minus strand for
+                // 27F and the plus one for 1492Rmod
+                let key = self.search.terminal_ids[t % 2]
+                    .map(|id| (id, t == 1 || t == 2));
+                match key.and_then(|key| self.hits.get(&key)) {
+                    Some(hits) => !hits.is_empty(),
+                    None => self
+                        .best_hit(pattern.as_bytes(), windows[t].as_deref())
+                        .is_some(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let terminals = found
+            .chunks(2)
+            .map(|strand| strand.iter().filter(|&&found| found).count())
+            .max()
+            .unwrap_or(0);
+
+        match terminals {
+            2 => Completeness::FullLength,
+            0 => Completeness::Fragment,
+            _ => Completeness::Partial,
+        }
+    }
+}
+
+// Terminal primers telling full-length 16S sequences apart, 27F and
+// 1492Rmod as searched on the plus strand then, when searched, on the
+// minus strand
+fn terminal_patterns(alphabet: &str, both_strands: bool) -> Vec<String> {
+    let (first, last) = (FORWARD_PRIMERS["27F"], REVERSE_PRIMERS["1492Rmod"]);
+    let mut patterns =
+        vec![first.to_string(), to_reverse_complement(last, alphabet)];
+    if both_strands {
+        patterns.push(to_reverse_complement(first, alphabet));
+        patterns.push(last.to_string());
+    }
+
+    patterns
+}
+
+// Index of the forward and reverse primers of each pair among the distinct
+// primers of the pairs, a primer allowed other mismatches being distinct
+fn primer_indices(primers: &[Vec<String>], config: &Config) -> Vec<[usize; 2]> {
+    let mut ids = HashMap::new();
+    primers
+        .iter()
+        .map(|pair| {
+            let mut id = |primer: &String| {
+                let mismatch = config.primer_mismatch(pair, primer);
+                let next = ids.len();
+                *ids.entry((primer.clone(), mismatch)).or_insert(next)
+            };
+            [id(&pair[0]), id(&pair[1])]
+        })
+        .collect()
+}
+
+// Lengths of the amplicons a missing primer is searched again for, those
+// within the chimera tolerance of the E. coli amplicon of the pair or, for
+// primers of unknown position, the bounded lengths of the pair
+fn rescue_lengths(
+    primer_pair: &[String],
+    lengths: &RangeInclusive<usize>,
+) -> Option<RangeInclusive<usize>> {
+    match ecoli_amplicon_length(primer_pair) {
+        Some(expected) => {
+            let tolerance = (expected as f64 * CHIMERA_TOLERANCE) as usize;
+            let min = (*lengths.start()).max(expected - tolerance);
+            let max = (*lengths.end()).min(expected + tolerance);
+            (min <= max).then_some(min..=max)
+        }
+        None if *lengths.end() < usize::MAX => Some(lengths.clone()),
+        None => None,
+    }
+}
+
+// Windows of a text of `len` bases where the missing primer of a pair may
+// make an amplicon of `lengths` with the hits of its mate, downstream of
+// forward hits or upstream of reverse hits, overlapping windows merged
+fn rescue_windows(
+    anchors: &[Hit],
+    downstream: bool,
+    lengths: &RangeInclusive<usize>,
+    len: usize,
+) -> Vec<(usize, usize)> {
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    for anchor in anchors {
+        let window = if downstream {
+            (anchor.1, len.min(anchor.0.saturating_add(*lengths.end())))
+        } else {
+            (anchor.1.saturating_sub(*lengths.end()), anchor.0)
+        };
+        if window.0 >= window.1 {
+            continue;
+        }
+        match windows.last_mut() {
+            Some(last) if window.0 <= last.1 => last.1 = last.1.max(window.1),
+            _ => windows.push(window),
+        }
+    }
+
+    windows
+}
+
+// Amplicons of a primer pair on both strands from the hits, on the plus
+// strand, of its primers (forward and minus reverse, extending downstream)
+// and of their reverse complements (reverse and minus forward, extending
+// upstream). As in a PCR, each hit extending downstream is paired with the
+// next hit extending upstream. Pairings of a forward hit with a minus
+// forward hit, or of a reverse hit with a minus reverse hit, are not on one
+// strand, they are left out and counted.
+fn stranded_amplicons(
+    (forward, reverse): (&[Hit], &[Hit]),
+    (minus_forward, minus_reverse): (&[Hit], &[Hit]),
+    lengths: &RangeInclusive<usize>,
+    max_distance: Option<usize>,
+    all_matches: bool,
+    deadline: Option<Instant>,
+) -> (Vec<Amplicon>, usize) {
+    let sorted = |first: &[Hit], second: &[Hit]| {
+        let mut hits = [first, second].concat();
+        hits.sort_unstable();
+        hits
+    };
+    let downstream = sorted(forward, minus_reverse);
+    let upstream = sorted(reverse, minus_forward);
+    let mut amplicons = Vec::new();
+    let mut inconsistent = 0;
+
+    let pairs = utils::pair_amplicons(
+        &downstream,
+        &upstream,
+        lengths,
+        max_distance,
+        deadline,
+    );
+    for (left, right) in pairs {
+        match (forward.contains(&left), reverse.contains(&right)) {
+            (true, true) => amplicons.push((left, right, Strand::Plus)),
+            (false, false) => amplicons.push((right, left, Strand::Minus)),
+            _ => inconsistent += 1,
+        }
+    }
+    if !all_matches {
+        amplicons = amplicons
+            .into_iter()
+            .min_by_key(|(forward, reverse, _)| {
+                let span = forward.1.max(reverse.1) - forward.0.min(reverse.0);
+                (forward.2 + reverse.2, span)
+            })
+            .into_iter()
+            .collect();
+    }
+
+    (amplicons, inconsistent)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{region_to_primer, PairOptions, PairOptionsMap};
+
+    #[test]
+    fn test_primer_indices() {
+        let primers = ["v3v4", "v3v5", "v4"]
+            .iter()
+            .map(|region| region_to_primer(region).unwrap())
+            .collect::<Vec<_>>();
+        // The forward primer of v3v4 and v3v5 is searched once
+        assert_eq!(
+            primer_indices(&primers, &Config::default()),
+            vec![[0, 1], [0, 2], [3, 4]]
+        );
+        // Unless allowed other mismatches
+        let mut pair_options = PairOptionsMap::new();
+        pair_options.insert(
+            primers[1].clone(),
+            PairOptions {
+                mismatch: Some(2),
+                ..Default::default()
+            },
+        );
+        let config = Config {
+            pair_options,
+            ..Default::default()
+        };
+        assert_eq!(
+            primer_indices(&primers, &config),
+            vec![[0, 1], [2, 3], [4, 5]]
+        );
+    }
+
+    #[test]
+    fn test_rescue_windows() {
+        assert_eq!(
+            rescue_windows(&[(10, 20, 0), (50, 60, 0)], true, &(0..=100), 120),
+            vec![(20, 120)]
+        );
+        assert_eq!(
+            rescue_windows(&[(150, 160, 0)], false, &(0..=100), 200),
+            vec![(60, 150)]
+        );
+        assert_eq!(
+            rescue_lengths(&["A".into(), "C".into()], &(0..=usize::MAX)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stranded_amplicons() {
+        let forward = [(0, 5, 0), (300, 305, 1)];
+        let reverse = [(100, 105, 0)];
+        let minus_forward = [(400, 405, 0), (600, 605, 0)];
+        let minus_reverse = [(500, 505, 0)];
+        let (amplicons, inconsistent) = stranded_amplicons(
+            (&forward, &reverse),
+            (&minus_forward, &minus_reverse),
+            &(0..=usize::MAX),
+            None,
+            true,
+            None,
+        );
+        assert_eq!(
+            amplicons,
+            vec![
+                ((0, 5, 0), (100, 105, 0), Strand::Plus),
+                ((600, 605, 0), (500, 505, 0), Strand::Minus)
+            ]
+        );
+        // Both hits of the forward primer, facing each other
+        assert_eq!(inconsistent, 1);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils;
+
+use anyhow::{anyhow, Context};
+
+// Gap symbols of the SILVA and ARB alignments
+const GAPS: &[u8] = b"-.";
+
+/// Columns of the SILVA (ARB) alignment holding the E. coli 16S rRNA gene,
+/// to locate regions given by their E. coli positions in alignment-based
+/// pipelines
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SilvaColumns {
+    // Column, from 1, of each E. coli position
+    columns: Vec<usize>,
+}
+
+impl SilvaColumns {
+    /// Columns of the first sequence of `path`, the E. coli 16S rRNA gene
+    /// (J01859) as aligned by SILVA, e.g. exported from ARB
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let record = utils::read_records(path)?
+            .next()
+            .ok_or_else(|| anyhow!("No sequence in alignment {}", path))?
+            .with_context(|| format!("Cannot read alignment {}", path))?;
+        let columns = SilvaColumns::new(record.seq());
+        if columns.columns.is_empty() {
+            return Err(anyhow!("{} of {} only has gaps", record.id(), path));
+        }
+
+        Ok(columns)
+    }
+
+    /// Columns of an aligned E. coli sequence
+    pub fn new(aligned: &[u8]) -> Self {
+        SilvaColumns {
+            columns: aligned
+                .iter()
+                .enumerate()
+                .filter(|(_, symbol)| !GAPS.contains(symbol))
+                .map(|(i, _)| i + 1)
+                .collect(),
+        }
+    }
+
+    /// Alignment column of an E. coli position, both from 1
+    pub fn column(&self, position: usize) -> Option<usize> {
+        position
+            .checked_sub(1)
+            .and_then(|i| self.columns.get(i))
+            .copied()
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_silva_columns() {
+        let columns = SilvaColumns::new(b"..A-GC--T..");
+        assert_eq!(columns.column(1), Some(3));
+        assert_eq!(columns.column(3), Some(6));
+        assert_eq!(columns.column(4), Some(9));
+        assert_eq!(columns.column(0), None);
+        assert_eq!(columns.column(5), None);
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("ecoli.fa");
+        fs::write(&path, ">J01859\n..A-GC\n--T..\n").unwrap();
+        assert_eq!(
+            SilvaColumns::from_file(path.to_str().unwrap()).unwrap(),
+            columns
+        );
+        fs::write(&path, ">J01859\n.....\n").unwrap();
+        assert!(SilvaColumns::from_file(path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,721 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::coords::Coords;
+use crate::ids::{self, GffIds};
+use crate::matcher::Hit;
+use crate::report::RunReport;
+use crate::utils::{
+    self, Checkpoint, Completeness, Config, GffLabels, RegionMatch,
+};
+
+use anyhow::Context;
+use bio::io::fasta;
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Sinks of a run, sent from the threads processing its inputs
+pub type Sinks = Vec<Box<dyn OutputSink + Send>>;
+
+/// A region extracted from a record, as handed to the sinks
+pub struct SinkRegion<'a> {
+    // Record the region is extracted from
+    pub record: &'a fasta::Record,
+    pub region: &'a RegionMatch,
+    // Description of the region in the FASTA output
+    pub desc: &'a str,
+    // Bases of the region, as written in the FASTA output
+    pub seq: &'a [u8],
+}
+
+/// A record once searched, with the names of the regions extracted from it
+pub struct SinkRecord<'a> {
+    pub id: &'a str,
+    pub length: usize,
+    pub completeness: Completeness,
+    pub regions: &'a [String],
+}
+
+/// Every hit of the primers of a pair on a record, whether or not they
+/// bound a region
+pub struct SinkSites<'a> {
+    pub record_id: &'a str,
+    pub primer_pair: &'a [String],
+    pub forward: &'a [Hit],
+    pub reverse: &'a [Hit],
+}
+
+/// A primer hit bounding a region, with its alignment
+pub struct SinkHit<'a> {
+    pub record_id: &'a str,
+    // Region name, empty for unnamed pairs
+    pub region: &'a str,
+    pub primer: &'a str,
+    // Forward or reverse
+    pub role: &'a str,
+    pub hit: Hit,
+    // CIGAR of the alignment, `*` when not traced back
+    pub cigar: &'a str,
+    // Bases under the hit, in the primer orientation
+    pub matched: &'a str,
+}
+
+/// A record, or a region of a record, left out of the outputs
+pub struct SinkSkipped<'a> {
+    pub id: &'a str,
+    pub region: Option<&'a str>,
+    pub reason: &'a str,
+    pub detail: &'a str,
+}
+
+/// Identity of two copies of a region found on several operons of a record
+pub struct SinkIdentity<'a> {
+    pub id: &'a str,
+    pub region: &'a str,
+    pub operons: (usize, usize),
+    pub identity: f64,
+}
+
+/// Destination of the results of a run, e.g. an output format.
+///
+/// The extraction loop starts every record, hands it the primer hits and
+/// regions found, then the record once searched, to the sinks of the
+/// outputs of the run, followed by the outputs of
+/// [`ExtraOutputs`](crate::utils::ExtraOutputs) and the sinks registered in
+/// its `sinks`, and flushes them. [`OutputSink::finish`] is left to whoever
+/// builds the report of the run, e.g. the command line. Every method does
+/// nothing by default.
+///
+/// ```no_run
+/// use hyperex::sink::{OutputSink, SinkRegion};
+/// use hyperex::utils::ExtraOutputs;
+/// use hyperex::Extractor;
+///
+/// // Regions printed as BED intervals
+/// struct Bed;
+///
+/// impl OutputSink for Bed {
+///     fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+///         let (id, region) = (found.record.id(), found.region);
+///         println!("{}\t{}\t{}", id, region.start, region.end);
+///         Ok(())
+///     }
+/// }
+///
+/// let mut extras = ExtraOutputs::default();
+/// extras.sinks.push(Box::new(Bed));
+/// let extractor = Extractor::builder().regions(["v4"]).build()?;
+/// extractor.run(&["reads.fa".to_string()], "v4", &mut extras)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub trait OutputSink {
+    /// Start a record, before it is searched
+    fn start_record(&mut self, _record: &fasta::Record) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write the hits of the primers of a pair on the record
+    fn write_sites(&mut self, _sites: &SinkSites) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write a primer hit bounding a region, before the region
+    fn write_hit(&mut self, _hit: &SinkHit) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write a region extracted from a record
+    fn write_region(&mut self, _region: &SinkRegion) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write a record, or region, left out with the reason why
+    fn write_skipped(&mut self, _skipped: &SinkSkipped) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write the identity of two copies of a region, after the regions of
+    /// the record
+    fn write_identity(
+        &mut self,
+        _identity: &SinkIdentity,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write a record once searched, after its regions
+    fn write_record(&mut self, _record: &SinkRecord) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Tell what was written apart as the outputs of an interrupted run
+    fn interrupted(&mut self, _checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Flush what was written, syncing it to disk with `fsync`
+    fn flush(&mut self, _fsync: bool) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Write the report of the run, once all its inputs are processed
+    fn finish(&mut self, _report: &RunReport) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sinks of the `<prefix>.fa`, `<prefix>.gff`, `<prefix>.tsv`,
+/// `<prefix>.hits.tsv`, `<prefix>.skipped.tsv` and `<prefix>.operons.tsv`
+/// outputs of an input, disabled outputs being left out
+pub fn output_sinks(prefix: &str, config: &Config) -> anyhow::Result<Sinks> {
+    let mut sinks: Sinks = Vec::new();
+    if !config.no_fasta {
+        let path = format!("{}.fa", prefix);
+        sinks.push(Box::new(FastaSink::create(&path, config.append)?));
+    }
+    if !config.no_gff {
+        let path = format!("{}.gff", prefix);
+        sinks.push(Box::new(GffSink::create(&path, config)?));
+    }
+    let path = format!("{}.tsv", prefix);
+    sinks.push(Box::new(TsvSink::create(&path, config.append)?));
+    let path = format!("{}.hits.tsv", prefix);
+    sinks.push(Box::new(HitsSink::create(&path, config)?));
+    let path = format!("{}.skipped.tsv", prefix);
+    sinks.push(Box::new(SkippedSink::create(&path, config.append)?));
+    // Copies of the regions of each operon are compared when looking for
+    // all amplicons
+    if config.all_matches {
+        let path = format!("{}.operons.tsv", prefix);
+        sinks.push(Box::new(OperonsSink::create(&path, config.append)?));
+    }
+
+    Ok(sinks)
+}
+
+/// FASTA output of the regions
+pub struct FastaSink {
+    path: String,
+    writer: fasta::Writer<File>,
+    // Handle synced to disk, the writer giving no access to its file
+    file: File,
+}
+
+impl FastaSink {
+    pub fn create(path: &str, append: bool) -> anyhow::Result<Self> {
+        let file = utils::open_output(path, None, append)?;
+        Ok(FastaSink {
+            path: path.to_string(),
+            file: file.try_clone()?,
+            writer: fasta::Writer::new(file),
+        })
+    }
+}
+
+impl OutputSink for FastaSink {
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let id = found.record.id();
+        self.writer
+            .write_record(&fasta::Record::with_attrs(
+                id,
+                Some(found.desc),
+                found.seq,
+            ))
+            .with_context(|| {
+                format!(
+                    "Cannot write region {} of {} to {}",
+                    found.region.name(),
+                    id,
+                    self.path
+                )
+            })
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        if fsync {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// GFF3 output of the regions, with the strand of the regions when both
+/// strands are searched
+pub struct GffSink {
+    path: String,
+    writer: io::BufWriter<File>,
+    // Ids of the records, those of the output appended to being used
+    ids: GffIds,
+    labels: GffLabels,
+    both_strands: bool,
+}
+
+impl GffSink {
+    pub fn create(path: &str, config: &Config) -> anyhow::Result<Self> {
+        let writer = io::BufWriter::new(utils::open_output(
+            path,
+            Some(utils::GFF_HEADER),
+            config.append,
+        )?);
+        let ids = if config.append {
+            GffIds::read(path)?
+        } else {
+            GffIds::default()
+        };
+        Ok(GffSink {
+            path: path.to_string(),
+            writer,
+            ids,
+            labels: config.gff.clone(),
+            both_strands: config.both_strands,
+        })
+    }
+}
+
+impl OutputSink for GffSink {
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let region = found.region;
+        let seq_id = found.record.id();
+        let (start, end) = Coords::OneBased.interval(region.start, region.end);
+        let strand = if self.both_strands {
+            region.strand.to_string()
+        } else {
+            ".".to_string()
+        };
+        let id = self.ids.allocate(seq_id, &region.region);
+        // Labels of the primers file may hold the separators of attributes
+        let name = ids::escape(&region.region);
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}\t{}\t.\t{}\t.\tID={};Name={};Note=Hypervariable region {};confidence={}",
+            seq_id,
+            self.labels.source,
+            self.labels.feature_type,
+            start,
+            end,
+            strand,
+            id,
+            name,
+            name,
+            region.confidence
+        )
+        .with_context(|| {
+            format!(
+                "Cannot write region {} of {} to {}",
+                region.name(),
+                seq_id,
+                self.path
+            )
+        })
+    }
+
+    fn interrupted(&mut self, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        writeln!(
+            self.writer,
+            "# partial run: interrupted after {} records of {}",
+            checkpoint.records, checkpoint.file
+        )?;
+        Ok(())
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        flush_file(&mut self.writer, fsync)
+    }
+}
+
+/// TSV output of the records, with their completeness and the names of
+/// their regions
+pub struct TsvSink {
+    path: String,
+    writer: io::BufWriter<File>,
+}
+
+impl TsvSink {
+    pub fn create(path: &str, append: bool) -> anyhow::Result<Self> {
+        Ok(TsvSink {
+            path: path.to_string(),
+            writer: io::BufWriter::new(utils::open_output(
+                path,
+                Some(utils::RECORDS_TSV_HEADER),
+                append,
+            )?),
+        })
+    }
+}
+
+impl OutputSink for TsvSink {
+    fn write_record(&mut self, record: &SinkRecord) -> anyhow::Result<()> {
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}",
+            record.id,
+            record.length,
+            record.completeness,
+            if record.regions.is_empty() {
+                ".".to_string()
+            } else {
+                record.regions.join(",")
+            }
+        )
+        .with_context(|| format!("Cannot write {}", self.path))
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        flush_file(&mut self.writer, fsync)
+    }
+}
+
+/// TSV output of the primer hits bounding the regions
+pub struct HitsSink {
+    path: String,
+    writer: io::BufWriter<File>,
+    coords: Coords,
+}
+
+impl HitsSink {
+    pub fn create(path: &str, config: &Config) -> anyhow::Result<Self> {
+        Ok(HitsSink {
+            path: path.to_string(),
+            writer: io::BufWriter::new(utils::open_output(
+                path,
+                Some(utils::HITS_TSV_HEADER),
+                config.append,
+            )?),
+            coords: config.coords,
+        })
+    }
+}
+
+impl OutputSink for HitsSink {
+    fn write_hit(&mut self, found: &SinkHit) -> anyhow::Result<()> {
+        let (start, end) = self.coords.interval(found.hit.0, found.hit.1);
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            found.record_id,
+            if found.region.is_empty() {
+                "."
+            } else {
+                found.region
+            },
+            found.primer,
+            found.role,
+            start,
+            end,
+            found.hit.2,
+            found.cigar,
+            found.matched
+        )
+        .with_context(|| format!("Cannot write {}", self.path))
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        flush_file(&mut self.writer, fsync)
+    }
+}
+
+/// TSV output of the records, or regions of a record, left out
+pub struct SkippedSink {
+    path: String,
+    writer: io::BufWriter<File>,
+}
+
+impl SkippedSink {
+    pub fn create(path: &str, append: bool) -> anyhow::Result<Self> {
+        Ok(SkippedSink {
+            path: path.to_string(),
+            writer: io::BufWriter::new(utils::open_output(
+                path,
+                Some(utils::SKIPPED_TSV_HEADER),
+                append,
+            )?),
+        })
+    }
+}
+
+impl OutputSink for SkippedSink {
+    fn write_skipped(&mut self, skipped: &SinkSkipped) -> anyhow::Result<()> {
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t{}",
+            skipped.id,
+            skipped.region.filter(|r| !r.is_empty()).unwrap_or("-"),
+            skipped.reason,
+            skipped.detail
+        )
+        .with_context(|| {
+            format!(
+                "Cannot write skipped record {} to {}",
+                skipped.id, self.path
+            )
+        })
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        flush_file(&mut self.writer, fsync)
+    }
+}
+
+/// TSV output of the identities of the copies of the regions found on
+/// several operons
+pub struct OperonsSink {
+    path: String,
+    writer: io::BufWriter<File>,
+}
+
+impl OperonsSink {
+    pub fn create(path: &str, append: bool) -> anyhow::Result<Self> {
+        Ok(OperonsSink {
+            path: path.to_string(),
+            writer: io::BufWriter::new(utils::open_output(
+                path,
+                Some(utils::OPERONS_TSV_HEADER),
+                append,
+            )?),
+        })
+    }
+}
+
+impl OutputSink for OperonsSink {
+    fn write_identity(
+        &mut self,
+        identity: &SinkIdentity,
+    ) -> anyhow::Result<()> {
+        writeln!(
+            self.writer,
+            "{}\t{}\toperon_{}\toperon_{}\t{:.4}",
+            identity.id,
+            identity.region,
+            identity.operons.0,
+            identity.operons.1,
+            identity.identity
+        )
+        .with_context(|| format!("Cannot write {}", self.path))
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        flush_file(&mut self.writer, fsync)
+    }
+}
+
+/// Sinks of the caller shared by the inputs processed concurrently, each
+/// call holding the lock
+pub(crate) struct SharedSinks(pub Arc<Mutex<Sinks>>);
+
+impl OutputSink for SharedSinks {
+    fn start_record(&mut self, record: &fasta::Record) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.start_record(record))
+    }
+
+    fn write_sites(&mut self, sites: &SinkSites) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write_sites(sites))
+    }
+
+    fn write_hit(&mut self, hit: &SinkHit) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks.iter_mut().try_for_each(|sink| sink.write_hit(hit))
+    }
+
+    fn write_region(&mut self, region: &SinkRegion) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write_region(region))
+    }
+
+    fn write_skipped(&mut self, skipped: &SinkSkipped) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write_skipped(skipped))
+    }
+
+    fn write_identity(
+        &mut self,
+        identity: &SinkIdentity,
+    ) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write_identity(identity))
+    }
+
+    fn write_record(&mut self, record: &SinkRecord) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write_record(record))
+    }
+
+    fn interrupted(&mut self, checkpoint: &Checkpoint) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.interrupted(checkpoint))
+    }
+
+    fn flush(&mut self, fsync: bool) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks.iter_mut().try_for_each(|sink| sink.flush(fsync))
+    }
+
+    fn finish(&mut self, report: &RunReport) -> anyhow::Result<()> {
+        let mut sinks = self.0.lock().unwrap();
+        sinks.iter_mut().try_for_each(|sink| sink.finish(report))
+    }
+}
+
+pub(crate) fn flush_file(
+    writer: &mut io::BufWriter<File>,
+    fsync: bool,
+) -> anyhow::Result<()> {
+    writer.flush()?;
+    if fsync {
+        writer.get_ref().sync_data()?;
+    }
+    Ok(())
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::batch;
+    use crate::utils::{region_to_primer, Confidence, ExtraOutputs, Strand};
+    use std::fs;
+
+    // Sink of the caller, collecting the names of the regions
+    struct Seen(Arc<Mutex<Vec<String>>>);
+
+    impl OutputSink for Seen {
+        fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+            let name = format!("{}:{}", found.record.id(), found.region.name());
+            self.0.lock().unwrap().push(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_output_sinks() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            no_gff: true,
+            ..Default::default()
+        };
+        let mut sinks = output_sinks(prefix, &config).unwrap();
+        assert_eq!(sinks.len(), 4);
+        assert!(!dir.path().join("out.gff").exists());
+        assert!(dir.path().join("out.hits.tsv").exists());
+        assert!(!dir.path().join("out.operons.tsv").exists());
+
+        let record = fasta::Record::with_attrs("a", None, b"ACGTACGT");
+        let region = RegionMatch {
+            region: "v4".to_string(),
+            forward_primer: "GT".to_string(),
+            reverse_primer: "AC".to_string(),
+            start: 2,
+            end: 6,
+            forward_dist: 0,
+            reverse_dist: 0,
+            strand: Strand::Plus,
+            confidence: Confidence::High,
+        };
+        let regions = vec![region.name()];
+        for sink in sinks.iter_mut() {
+            sink.write_region(&SinkRegion {
+                record: &record,
+                region: &region,
+                desc: "region=v4",
+                seq: b"GTAC",
+            })
+            .unwrap();
+            sink.write_record(&SinkRecord {
+                id: "a",
+                length: 8,
+                completeness: Completeness::Fragment,
+                regions: &regions,
+            })
+            .unwrap();
+            sink.flush(false).unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(format!("{}.fa", prefix)).unwrap(),
+            ">a region=v4\nGTAC\n"
+        );
+        assert_eq!(
+            fs::read_to_string(format!("{}.tsv", prefix)).unwrap(),
+            format!("{}a\t8\tfragment\tv4\n", utils::RECORDS_TSV_HEADER)
+        );
+
+        // Sinks of the caller see the regions of every input
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut extras = ExtraOutputs::default();
+        extras.sinks.push(Box::new(Seen(Arc::clone(&seen))));
+        let prefix = dir.path().join("batch");
+        batch::run_batch(
+            &["tests/test.fa".to_string(), "tests/test.fa".to_string()],
+            2,
+            &[region_to_primer("v4").unwrap()],
+            prefix.to_str().unwrap(),
+            &Config::default(),
+            &mut extras,
+            None,
+        )
+        .unwrap();
+        assert_eq!(extras.sinks.len(), 1);
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(seen[0].ends_with(":v4"));
+    }
+
+    #[test]
+    fn test_gff_sink_escapes_labels() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.gff");
+        let path = path.to_str().unwrap();
+        let mut sink = GffSink::create(path, &Config::default()).unwrap();
+        let record = fasta::Record::with_attrs("a", None, b"ACGTACGT");
+        let region = RegionMatch {
+            region: "v4;x=1".to_string(),
+            forward_primer: "GT".to_string(),
+            reverse_primer: "AC".to_string(),
+            start: 2,
+            end: 6,
+            forward_dist: 0,
+            reverse_dist: 0,
+            strand: Strand::Plus,
+            confidence: Confidence::High,
+        };
+        sink.write_region(&SinkRegion {
+            record: &record,
+            region: &region,
+            desc: "",
+            seq: b"GTAC",
+        })
+        .unwrap();
+        sink.flush(false).unwrap();
+
+        let gff = fs::read_to_string(path).unwrap();
+        let attributes = gff.lines().last().unwrap().split('\t').nth(8);
+        assert_eq!(
+            attributes,
+            Some(
+                "ID=a.v4%3Bx%3D1;Name=v4%3Bx%3D1;\
+                 Note=Hypervariable region v4%3Bx%3D1;confidence=high"
+            )
+        );
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::diff::Located;
+use crate::utils::{self, Strand};
+
+use anyhow::Context;
+use bio::io::fasta;
+use log::warn;
+
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Intervals cut by [`extract`], and those left out as lying beyond the
+/// end of their sequence or on a sequence missing from the input
+#[derive(Debug, Default, PartialEq)]
+pub struct SliceCounts {
+    pub extracted: usize,
+    pub out_of_bounds: usize,
+    pub missing: usize,
+}
+
+/// Write the `intervals` of the records of `file`, e.g. the regions of a
+/// GFF3 file with edited coordinates, to `writer` as FASTA. Intervals on
+/// the minus strand are reverse complemented.
+pub fn extract<W: Write>(
+    file: &str,
+    intervals: &[Located],
+    writer: &mut fasta::Writer<W>,
+) -> anyhow::Result<SliceCounts> {
+    let mut by_id: HashMap<&str, Vec<&Located>> = HashMap::new();
+    for interval in intervals {
+        by_id
+            .entry(interval.id.as_str())
+            .or_default()
+            .push(interval);
+    }
+
+    let mut counts = SliceCounts::default();
+    for record in utils::read_records(file)? {
+        let record = record.with_context(|| format!("Cannot read {}", file))?;
+        let intervals = match by_id.remove(record.id()) {
+            Some(intervals) => intervals,
+            None => continue,
+        };
+        let seq = record.seq();
+        for interval in intervals {
+            if interval.start >= interval.end || interval.end > seq.len() {
+                warn!(
+                    "Region {} of {} skipped, {}-{} is out of its {} bp",
+                    interval.region,
+                    interval.id,
+                    interval.start + 1,
+                    interval.end,
+                    seq.len()
+                );
+                counts.out_of_bounds += 1;
+                continue;
+            }
+            let mut slice = seq[interval.start..interval.end].to_vec();
+            if interval.strand == Strand::Minus {
+                let slice_str =
+                    String::from_utf8_lossy(&slice).to_ascii_uppercase();
+                slice = utils::to_reverse_complement(&slice_str, "dna")
+                    .into_bytes();
+            }
+            let desc = format!(
+                "region={} start={} end={} strand={}",
+                interval.region,
+                interval.start + 1,
+                interval.end,
+                interval.strand
+            );
+            writer.write(record.id(), Some(&desc), &slice)?;
+            counts.extracted += 1;
+        }
+    }
+
+    for (id, intervals) in by_id {
+        warn!(
+            "{} regions of {} skipped, it is not in {}",
+            intervals.len(),
+            id,
+            file
+        );
+        counts.missing += intervals.len();
+    }
+    writer.flush()?;
+
+    Ok(counts)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn located(id: &str, start: usize, end: usize, strand: Strand) -> Located {
+        Located {
+            id: id.to_string(),
+            region: "v4".to_string(),
+            start,
+            end,
+            strand,
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        std::fs::write(&input, ">a\nAACCGGTTAC\n>b\nACGT\n").unwrap();
+        let intervals = [
+            located("a", 0, 4, Strand::Plus),
+            located("a", 6, 10, Strand::Minus),
+            located("b", 2, 8, Strand::Plus),
+            located("c", 0, 2, Strand::Plus),
+        ];
+        let mut output = Vec::new();
+        let counts = extract(
+            input.to_str().unwrap(),
+            &intervals,
+            &mut fasta::Writer::new(&mut output),
+        )
+        .unwrap();
+        assert_eq!(
+            counts,
+            SliceCounts {
+                extracted: 2,
+                out_of_bounds: 1,
+                missing: 1
+            }
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            ">a region=v4 start=1 end=4 strand=+\nAACC\n\
+            >a region=v4 start=7 end=10 strand=-\nGTAA\n"
+        );
+    }
+}
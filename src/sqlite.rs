@@ -0,0 +1,367 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::coords::Coords;
+use crate::sink::OutputSink;
+
+#[cfg(feature = "sqlite")]
+use crate::report::RunReport;
+#[cfg(feature = "sqlite")]
+use crate::sink::{SinkHit, SinkRecord, SinkRegion};
+
+#[cfg(feature = "sqlite")]
+use anyhow::Context;
+#[cfg(feature = "sqlite")]
+use log::info;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+
+// Tables of the results database, created on first use so that the runs of
+// a lab accumulate in a single file. Sequences and primer hits have the
+// columns of `<prefix>.tsv` and `<prefix>.hits.tsv`, regions those of the
+// regions of `<prefix>.regions.jsonl`.
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    date TEXT NOT NULL DEFAULT (datetime('now')),
+    version TEXT NOT NULL,
+    command TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    coords TEXT NOT NULL,
+    sequences INTEGER NOT NULL,
+    extracted INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sequences (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    id TEXT NOT NULL,
+    length INTEGER NOT NULL,
+    completeness TEXT NOT NULL,
+    regions TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS primer_hits (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    id TEXT NOT NULL,
+    region TEXT NOT NULL,
+    primer TEXT NOT NULL,
+    role TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    \"end\" INTEGER NOT NULL,
+    distance INTEGER NOT NULL,
+    cigar TEXT NOT NULL,
+    matched TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS extractions (
+    run_id INTEGER NOT NULL REFERENCES runs(id),
+    id TEXT NOT NULL,
+    region TEXT NOT NULL,
+    forward_primer TEXT NOT NULL,
+    reverse_primer TEXT NOT NULL,
+    start INTEGER NOT NULL,
+    \"end\" INTEGER NOT NULL,
+    forward_dist INTEGER NOT NULL,
+    reverse_dist INTEGER NOT NULL,
+    strand TEXT NOT NULL
+);
+";
+
+/// Sink adding a run, its sequences, primer hits and extracted regions to
+/// the SQLite database `path`, created if missing. Rows are inserted as the
+/// run writes them, in a transaction committed by [`OutputSink::finish`],
+/// so that only the records of this run are added, e.g. with `--append`.
+#[cfg(feature = "sqlite")]
+pub struct SqliteSink {
+    path: String,
+    db: Connection,
+    run_id: i64,
+    coords: Coords,
+    // Records and regions added by the run
+    sequences: usize,
+    extracted: usize,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSink {
+    pub fn create(
+        path: &str,
+        command: &str,
+        prefix: &str,
+        coords: Coords,
+    ) -> anyhow::Result<Self> {
+        let db = Connection::open(path)
+            .with_context(|| format!("Cannot open database {}", path))?;
+        db.execute_batch(SCHEMA)
+            .with_context(|| format!("Cannot create the tables of {}", path))?;
+        db.execute_batch("BEGIN")?;
+        db.execute(
+            "INSERT INTO runs (version, command, prefix, coords, sequences, \
+             extracted) VALUES (?1, ?2, ?3, ?4, 0, 0)",
+            params![
+                env!("CARGO_PKG_VERSION"),
+                command,
+                prefix,
+                coords.to_string()
+            ],
+        )
+        .with_context(|| format!("Cannot add run to {}", path))?;
+        let run_id = db.last_insert_rowid();
+
+        Ok(SqliteSink {
+            path: path.to_string(),
+            db,
+            run_id,
+            coords,
+            sequences: 0,
+            extracted: 0,
+        })
+    }
+
+    /// Id of the run in the database
+    pub fn run_id(&self) -> i64 {
+        self.run_id
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl OutputSink for SqliteSink {
+    fn write_hit(&mut self, found: &SinkHit) -> anyhow::Result<()> {
+        let (start, end) = self.coords.interval(found.hit.0, found.hit.1);
+        self.db
+            .prepare_cached(
+                "INSERT INTO primer_hits VALUES \
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?
+            .execute(params![
+                self.run_id,
+                found.record_id,
+                if found.region.is_empty() {
+                    "."
+                } else {
+                    found.region
+                },
+                found.primer,
+                found.role,
+                start,
+                end,
+                found.hit.2,
+                found.cigar,
+                found.matched
+            ])
+            .with_context(|| format!("Cannot write {}", self.path))?;
+        Ok(())
+    }
+
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        let region = found.region;
+        let (start, end) = self.coords.interval(region.start, region.end);
+        self.db
+            .prepare_cached(
+                "INSERT INTO extractions VALUES \
+                 (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?
+            .execute(params![
+                self.run_id,
+                found.record.id(),
+                region.region,
+                region.forward_primer,
+                region.reverse_primer,
+                start,
+                end,
+                region.forward_dist,
+                region.reverse_dist,
+                region.strand.to_string()
+            ])
+            .with_context(|| format!("Cannot write {}", self.path))?;
+        self.extracted += 1;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &SinkRecord) -> anyhow::Result<()> {
+        self.db
+            .prepare_cached(
+                "INSERT INTO sequences VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                self.run_id,
+                record.id,
+                record.length,
+                record.completeness.to_string(),
+                if record.regions.is_empty() {
+                    ".".to_string()
+                } else {
+                    record.regions.join(",")
+                }
+            ])
+            .with_context(|| format!("Cannot write {}", self.path))?;
+        self.sequences += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _report: &RunReport) -> anyhow::Result<()> {
+        self.db
+            .execute(
+                "UPDATE runs SET sequences = ?1, extracted = ?2 \
+                 WHERE id = ?3",
+                params![self.sequences, self.extracted, self.run_id],
+            )
+            .and_then(|_| self.db.execute_batch("COMMIT"))
+            .with_context(|| "Cannot write results database")?;
+        info!("Run {} added to {}", self.run_id, self.path);
+        Ok(())
+    }
+}
+
+/// Sink of the SQLite database, which cannot be created without the
+/// `sqlite` feature
+#[cfg(not(feature = "sqlite"))]
+pub struct SqliteSink;
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteSink {
+    pub fn create(
+        path: &str,
+        _command: &str,
+        _prefix: &str,
+        _coords: Coords,
+    ) -> anyhow::Result<Self> {
+        Err(anyhow::anyhow!(
+            "Cannot write {}: hyperex was built without the `sqlite` feature",
+            path
+        ))
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+impl OutputSink for SqliteSink {}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_sink() {
+        use crate::utils::{
+            get_hypervar_regions, region_to_primer, Config, ExtraOutputs,
+        };
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let db = dir.path().join("results.db");
+        let db = db.to_str().unwrap();
+        let config = Config {
+            append: true,
+            coords: Coords::OneBased,
+            ..Default::default()
+        };
+        let run = |regions: &[&str]| {
+            let primers = regions
+                .iter()
+                .map(|region| region_to_primer(region).unwrap())
+                .collect::<Vec<_>>();
+            let mut extras = ExtraOutputs::default();
+            let sink = SqliteSink::create(db, "hyperex", prefix, config.coords)
+                .unwrap();
+            let run_id = sink.run_id();
+            extras.sinks.push(Box::new(sink));
+            get_hypervar_regions(
+                "tests/test.fa",
+                &primers,
+                prefix,
+                &config,
+                &mut extras,
+                None,
+            )
+            .unwrap();
+            let report = RunReport::new(Default::default());
+            for sink in extras.sinks.iter_mut() {
+                sink.finish(&report).unwrap();
+            }
+            run_id
+        };
+        assert_eq!(run(&["v4"]), 1);
+        // Records appended to are not added again
+        assert_eq!(run(&["v4"]), 2);
+        assert_eq!(run(&["v4", "v4v5"]), 3);
+
+        let conn = Connection::open(db).unwrap();
+        let count = |sql: &str| -> i64 {
+            conn.query_row(sql, [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM sequences"), 2);
+        assert_eq!(count("SELECT SUM(sequences) FROM runs"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM sequences WHERE run_id = 2"), 0);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM extractions WHERE region = 'v4'"),
+            1
+        );
+        assert_eq!(
+            count("SELECT extracted FROM runs WHERE id = 3"),
+            count("SELECT COUNT(*) FROM extractions WHERE run_id = 3")
+        );
+        // Hits are in the coordinates of the run, as in its hits TSV
+        let hits =
+            std::fs::read_to_string(format!("{}.hits.tsv", prefix)).unwrap();
+        let fields = hits.lines().nth(1).unwrap().split('\t');
+        let fields = fields.collect::<Vec<_>>();
+        let hit: (i64, String) = conn
+            .query_row(
+                "SELECT start, matched FROM primer_hits \
+                 WHERE run_id = 1 AND role = ?1",
+                [fields[3]],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(hit, (fields[4].parse().unwrap(), fields[8].to_string()));
+        // Regions too, as in its GFF
+        let gff = std::fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        let fields = gff
+            .lines()
+            .map(|line| line.split('\t').collect::<Vec<_>>())
+            .find(|fields| fields.len() > 8 && fields[8].contains("Name=v4;"))
+            .unwrap();
+        let region: (i64, i64) = conn
+            .query_row(
+                "SELECT start, \"end\" FROM extractions \
+                 WHERE region = 'v4'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            region,
+            (fields[3].parse().unwrap(), fields[4].parse().unwrap())
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_sink_rollback() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let db = dir.path().join("results.db");
+        let db = db.to_str().unwrap();
+        // A run not finished leaves the database as it was
+        drop(SqliteSink::create(db, "hyperex", "out", Coords::default()));
+        let conn = Connection::open(db).unwrap();
+        let runs: i64 = conn
+            .query_row("SELECT COUNT(*) FROM runs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(runs, 0);
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    #[test]
+    fn test_sqlite_sink_without_feature() {
+        assert!(SqliteSink::create(
+            "out.db",
+            "hyperex",
+            "out",
+            Coords::default()
+        )
+        .is_err());
+    }
+}
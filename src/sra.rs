@@ -0,0 +1,121 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::remote;
+
+use anyhow::{anyhow, Context};
+
+use std::io::Read;
+
+const FILEREPORT_URL: &str = "https://www.ebi.ac.uk/ena/portal/api/filereport";
+
+/// FASTQ files of a sequencing run, as the URLs of the reads and of their
+/// R2 mates for paired runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunFiles {
+    pub reads: String,
+    pub mates: Option<String>,
+}
+
+/// Is `accession` a SRA, ENA or DDBJ run accession, e.g. SRR1234567?
+pub fn is_valid_run(accession: &str) -> bool {
+    ["SRR", "ERR", "DRR"]
+        .iter()
+        .filter_map(|prefix| accession.strip_prefix(prefix))
+        .any(|number| {
+            !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+        })
+}
+
+fn filereport_url(accession: &str) -> String {
+    format!(
+        "{}?accession={}&result=read_run&fields=fastq_ftp&format=tsv",
+        FILEREPORT_URL, accession
+    )
+}
+
+/// Locate the FASTQ files of a run with the ENA API, to stream its reads
+/// without downloading the whole run first
+pub fn run_files(accession: &str) -> anyhow::Result<RunFiles> {
+    if !is_valid_run(accession) {
+        return Err(anyhow!("{} is not a valid SRA run accession", accession));
+    }
+    let mut report = String::new();
+    remote::open(&filereport_url(accession))?
+        .read_to_string(&mut report)
+        .with_context(|| format!("Cannot read the files of {}", accession))?;
+
+    parse_filereport(&report)
+        .with_context(|| format!("No FASTQ files for run {}", accession))
+}
+
+// FASTQ files listed in a filereport TSV, the single-end file of runs also
+// having paired files being left out
+fn parse_filereport(report: &str) -> anyhow::Result<RunFiles> {
+    let mut lines = report.lines();
+    let column = lines
+        .next()
+        .and_then(|header| header.split('\t').position(|f| f == "fastq_ftp"))
+        .ok_or_else(|| anyhow!("Unexpected ENA reply: {}", report.trim()))?;
+    let urls = lines
+        .filter_map(|line| line.split('\t').nth(column))
+        .flat_map(|files| files.split(';'))
+        .filter(|file| !file.is_empty())
+        .map(|file| format!("https://{}", file))
+        .collect::<Vec<_>>();
+
+    let mate = |n: &str| {
+        urls.iter()
+            .find(|url| url.ends_with(&format!("_{}.fastq.gz", n)))
+            .cloned()
+    };
+    match (mate("1"), mate("2")) {
+        (Some(reads), mates @ Some(_)) => Ok(RunFiles { reads, mates }),
+        _ => match urls.as_slice() {
+            [reads] => Ok(RunFiles {
+                reads: reads.clone(),
+                mates: None,
+            }),
+            _ => Err(anyhow!("Unexpected ENA reply: {}", report.trim())),
+        },
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filereport() {
+        assert!(is_valid_run("SRR1234567"));
+        assert!(is_valid_run("ERR42"));
+        assert!(!is_valid_run("SRR"));
+        assert!(!is_valid_run("SRP123"));
+        assert!(!is_valid_run("SRR12&x=1"));
+        assert!(filereport_url("SRR1").contains("accession=SRR1&"));
+
+        let paired = "run_accession\tfastq_ftp\n\
+            SRR1\tftp.sra.ebi.ac.uk/SRR1.fastq.gz;\
+            ftp.sra.ebi.ac.uk/SRR1_1.fastq.gz;\
+            ftp.sra.ebi.ac.uk/SRR1_2.fastq.gz\n";
+        assert_eq!(
+            parse_filereport(paired).unwrap(),
+            RunFiles {
+                reads: "https://ftp.sra.ebi.ac.uk/SRR1_1.fastq.gz".to_string(),
+                mates: Some(
+                    "https://ftp.sra.ebi.ac.uk/SRR1_2.fastq.gz".to_string()
+                ),
+            }
+        );
+        let single = "run_accession\tfastq_ftp\nSRR2\tftp.x/SRR2.fastq.gz\n";
+        assert_eq!(
+            parse_filereport(single).unwrap().reads,
+            "https://ftp.x/SRR2.fastq.gz"
+        );
+        assert!(parse_filereport("run_accession\tfastq_ftp\nSRR3\t\n").is_err());
+        assert!(parse_filereport("").is_err());
+    }
+}
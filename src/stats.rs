@@ -0,0 +1,222 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use anyhow::{anyhow, Context};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often the live statistics of a run are rewritten, parsed from N
+/// records, e.g. `1000`, or SECS seconds with an `s` suffix, e.g. `30s`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsEvery {
+    // Every N records, over all inputs
+    Records(usize),
+    // Every few seconds, checked after each record
+    Interval(Duration),
+}
+
+impl FromStr for StatsEvery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (number, seconds) = match s.strip_suffix('s') {
+            Some(number) => (number, true),
+            None => (s, false),
+        };
+        match number.parse::<usize>() {
+            Ok(n) if n > 0 && seconds => {
+                Ok(StatsEvery::Interval(Duration::from_secs(n as u64)))
+            }
+            Ok(n) if n > 0 => Ok(StatsEvery::Records(n)),
+            _ => Err(anyhow!(
+                "Invalid statistics interval {}, expected N records or SECS \
+                seconds, e.g. 1000 or 30s",
+                s
+            )),
+        }
+    }
+}
+
+/// Statistics of a running extraction, rewritten to a JSON file every few
+/// records or seconds so that long runs can be monitored. Inputs processed
+/// concurrently share the same statistics.
+#[derive(Debug)]
+pub struct LiveStats {
+    path: String,
+    every: StatsEvery,
+    start: Instant,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    records: usize,
+    extracted: usize,
+    regions: BTreeMap<String, usize>,
+    // Records at the last write, and its time
+    written_records: usize,
+    written_at: Instant,
+}
+
+// Content of the statistics file
+#[derive(Debug, Serialize)]
+struct Snapshot<'a> {
+    // Whether the run is over, the counts being final
+    done: bool,
+    elapsed_secs: f64,
+    records: usize,
+    extracted: usize,
+    records_per_sec: f64,
+    regions_per_sec: f64,
+    // Rate since the previous write
+    recent_records_per_sec: f64,
+    regions: &'a BTreeMap<String, usize>,
+}
+
+impl LiveStats {
+    pub fn new(path: &str, every: StatsEvery) -> Self {
+        let now = Instant::now();
+        LiveStats {
+            path: path.to_string(),
+            every,
+            start: now,
+            state: Mutex::new(State {
+                records: 0,
+                extracted: 0,
+                regions: BTreeMap::new(),
+                written_records: 0,
+                written_at: now,
+            }),
+        }
+    }
+
+    /// Count a processed record with the names of the regions extracted
+    /// from it, rewriting the statistics when due
+    pub fn add_record(&self, regions: &[String]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.records += 1;
+        state.extracted += regions.len();
+        for region in regions {
+            *state.regions.entry(region.clone()).or_default() += 1;
+        }
+        let due = match self.every {
+            StatsEvery::Records(n) => {
+                state.records - state.written_records >= n
+            }
+            StatsEvery::Interval(interval) => {
+                state.written_at.elapsed() >= interval
+            }
+        };
+        if due {
+            self.write(&mut state, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the final statistics, once all inputs are processed
+    pub fn finish(&self) -> anyhow::Result<()> {
+        self.write(&mut self.state.lock().unwrap(), true)
+    }
+
+    fn write(&self, state: &mut State, done: bool) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let recent = now.duration_since(state.written_at).as_secs_f64();
+        let snapshot = Snapshot {
+            done,
+            elapsed_secs: round(elapsed),
+            records: state.records,
+            extracted: state.extracted,
+            records_per_sec: rate(state.records, elapsed),
+            regions_per_sec: rate(state.extracted, elapsed),
+            recent_records_per_sec: rate(
+                state.records - state.written_records,
+                recent,
+            ),
+            regions: &state.regions,
+        };
+        // Written aside then renamed, so that readers never see a partial
+        // file
+        let tmp = format!("{}.tmp", self.path);
+        write_json(&tmp, &snapshot)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .with_context(|| format!("Cannot write {}", self.path))?;
+        state.written_records = state.records;
+        state.written_at = now;
+
+        Ok(())
+    }
+}
+
+fn write_json(path: &str, snapshot: &Snapshot) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, snapshot)?;
+    writeln!(writer)?;
+    writer.flush()
+}
+
+// Events per second, with two decimals
+fn rate(count: usize, secs: f64) -> f64 {
+    if secs > 0.0 {
+        round(count as f64 / secs)
+    } else {
+        0.0
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_live_stats() {
+        assert_eq!(
+            "1000".parse::<StatsEvery>().unwrap(),
+            StatsEvery::Records(1000)
+        );
+        assert_eq!(
+            "30s".parse::<StatsEvery>().unwrap(),
+            StatsEvery::Interval(Duration::from_secs(30))
+        );
+        assert!("0".parse::<StatsEvery>().is_err());
+        assert!("1m".parse::<StatsEvery>().is_err());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.stats.json");
+        let path = path.to_str().unwrap();
+        let stats = LiveStats::new(path, StatsEvery::Records(2));
+        stats.add_record(&["v4".to_string()]).unwrap();
+        assert!(fs::metadata(path).is_err());
+        stats.add_record(&[]).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written["done"], false);
+        assert_eq!(written["records"], 2);
+        assert_eq!(written["regions"]["v4"], 1);
+
+        stats
+            .add_record(&["v4".to_string(), "v3v4".to_string()])
+            .unwrap();
+        stats.finish().unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written["done"], true);
+        assert_eq!(written["extracted"], 3);
+        assert_eq!(written["regions"]["v4"], 2);
+        assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+    }
+}
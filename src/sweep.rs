@@ -0,0 +1,221 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::utils::{self, Alphabet, Config};
+
+use anyhow::Context;
+
+use std::fmt::Write as FmtWrite;
+use std::ops::RangeInclusive;
+
+/// Primer hits of a primer pair with a given number of mismatches allowed
+/// to each primer
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub mismatch: u8,
+    pub forward_hits: usize,
+    pub reverse_hits: usize,
+    // Records holding an amplicon of the pair
+    pub amplified: usize,
+}
+
+/// Primer hits of a primer pair over a range of mismatches
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairSweep {
+    pub primers: Vec<String>,
+    pub records: usize,
+    pub points: Vec<SweepPoint>,
+}
+
+impl PairSweep {
+    /// Fewest mismatches amplifying as many records as the most tolerant
+    /// setting of the sweep, if any record is amplified
+    pub fn recommended(&self) -> Option<u8> {
+        let most = self
+            .points
+            .iter()
+            .map(|point| point.amplified)
+            .max()
+            .filter(|&most| most > 0)?;
+        self.points
+            .iter()
+            .find(|point| point.amplified == most)
+            .map(|point| point.mismatch)
+    }
+}
+
+/// Search the primer pairs in the records of `file` with each number of
+/// mismatches of `mismatches`, the other settings being taken from
+/// `config`. Records are parsed once and kept in memory for all the runs.
+pub fn sweep(
+    file: &str,
+    primers: &[Vec<String>],
+    mismatches: RangeInclusive<u8>,
+    config: &Config,
+) -> anyhow::Result<Vec<PairSweep>> {
+    let mut seqs = Vec::new();
+    let mut records = utils::read_records(file)
+        .with_context(|| format!("Cannot read {}", file))?;
+    while let Some(Ok(record)) = records.next() {
+        let seq = record.seq().to_vec();
+        let alphabet = match utils::sequence_type(std::str::from_utf8(&seq)?) {
+            Some(Alphabet::Rna) => "rna",
+            _ => "dna",
+        };
+        seqs.push((seq, alphabet));
+    }
+
+    let mut config = config.clone();
+    let mut sweeps = Vec::new();
+    for pair in primers {
+        let lengths = config.amplicon_lengths(pair);
+        let reverse = |alphabet: &str| {
+            utils::to_reverse_complement(&pair[1], alphabet).into_bytes()
+        };
+        let (dna_reverse, rna_reverse) = (reverse("dna"), reverse("rna"));
+        let mut points = Vec::new();
+        for mismatch in mismatches.clone() {
+            config.mismatch = mismatch;
+            let mut point = SweepPoint {
+                mismatch,
+                forward_hits: 0,
+                reverse_hits: 0,
+                amplified: 0,
+            };
+            for (seq, alphabet) in seqs.iter() {
+                let reverse = if *alphabet == "rna" {
+                    &rna_reverse
+                } else {
+                    &dna_reverse
+                };
+                let forward_hits =
+                    utils::find_hits(pair[0].as_bytes(), seq, &config);
+                let reverse_hits = utils::find_hits(reverse, seq, &config);
+                point.forward_hits += forward_hits.len();
+                point.reverse_hits += reverse_hits.len();
+                let amplicon = utils::best_amplicon(
+                    &forward_hits,
+                    &reverse_hits,
+                    &lengths,
+                    config.max_pair_distance,
+                    None,
+                );
+                if amplicon.is_some() {
+                    point.amplified += 1;
+                }
+            }
+            points.push(point);
+        }
+        sweeps.push(PairSweep {
+            primers: pair.to_vec(),
+            records: seqs.len(),
+            points,
+        });
+    }
+
+    Ok(sweeps)
+}
+
+/// Human readable report of the hits of each primer pair at each number of
+/// mismatches, with the records gained over the previous one and the
+/// fewest mismatches recovering all the amplified records
+pub fn report(sweeps: &[PairSweep]) -> String {
+    let mut out = String::new();
+
+    for sweep in sweeps {
+        let region = utils::primers_to_region(sweep.primers.to_vec());
+        let _ = writeln!(
+            out,
+            "{} {} {}",
+            if region.is_empty() { "pair" } else { &region },
+            sweep.primers[0],
+            sweep.primers[1]
+        );
+        let _ = writeln!(
+            out,
+            "  mismatch\tforward_hits\treverse_hits\tamplified\tgained"
+        );
+        let mut previous = None;
+        for point in sweep.points.iter() {
+            let _ = writeln!(
+                out,
+                "  {}\t{}\t{}\t{}/{} ({:.1}%)\t{}",
+                point.mismatch,
+                point.forward_hits,
+                point.reverse_hits,
+                point.amplified,
+                sweep.records,
+                point.amplified as f64 * 100.0 / sweep.records.max(1) as f64,
+                match previous {
+                    Some(previous) => {
+                        format!("{:+}", point.amplified as i64 - previous)
+                    }
+                    None => "-".to_string(),
+                }
+            );
+            previous = Some(point.amplified as i64);
+        }
+        let _ = match sweep.recommended() {
+            Some(mismatch) => writeln!(
+                out,
+                "  fewest mismatches recovering all amplified records: -m {}",
+                mismatch
+            ),
+            None => writeln!(out, "  no record amplified"),
+        };
+    }
+
+    out
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_sweep() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("reads.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        // The second record has a substitution in the forward primer of v4
+        let primers = vec![utils::region_to_primer("v4").unwrap()];
+        let site = seq.find("GTGCCAGCAGCCGCGGTAA").unwrap();
+        let mut mutated = seq.clone().into_bytes();
+        mutated[site + 2] = b'A';
+        fs::write(
+            &input,
+            format!(
+                ">s1\n{}\n>s2\n{}\n>s3\nACGTACGTACGT\n",
+                seq,
+                String::from_utf8(mutated).unwrap()
+            ),
+        )
+        .unwrap();
+
+        let sweeps =
+            sweep(input.to_str().unwrap(), &primers, 0..=2, &Config::default())
+                .unwrap();
+        assert_eq!(sweeps.len(), 1);
+        let sweep = &sweeps[0];
+        assert_eq!(sweep.records, 3);
+        let amplified = sweep
+            .points
+            .iter()
+            .map(|point| point.amplified)
+            .collect::<Vec<_>>();
+        assert_eq!(amplified, vec![1, 2, 2]);
+        assert!(sweep.points[0].forward_hits < sweep.points[1].forward_hits);
+        assert_eq!(sweep.recommended(), Some(1));
+
+        let report = report(&sweeps);
+        assert!(report.starts_with("v4 "));
+        assert!(report.contains("  1\t"));
+        assert!(report.contains("2/3 (66.7%)\t+1\n"));
+        assert!(report.contains("recovering all amplified records: -m 1\n"));
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use anyhow::{anyhow, Context};
+use bio::io::fasta;
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// Where the taxonomy of the input sequences comes from
+#[derive(Debug, Clone, Default)]
+pub enum Taxonomy {
+    #[default]
+    None,
+    // Lineage in the description of SILVA or GTDB style FASTA headers
+    Headers,
+    // Lineage of each sequence id
+    Map(HashMap<String, String>),
+}
+
+impl Taxonomy {
+    /// Read a two-column TSV file mapping sequence ids to lineages, as
+    /// distributed with QIIME and GTDB reference databases
+    pub fn from_file(filename: &str) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(filename)
+            .with_context(|| format!("Cannot read taxonomy {}", filename))?;
+        let mut map = HashMap::new();
+
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('\t') {
+                Some((id, lineage)) if !lineage.trim().is_empty() => {
+                    map.insert(id.trim().to_string(), normalize(lineage));
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "line {}: expected a sequence id and a lineage separated by a tab",
+                        i + 1
+                    ))
+                }
+            }
+        }
+
+        Ok(Taxonomy::Map(map))
+    }
+
+    /// Lineage of a record, if known
+    pub fn lineage(&self, record: &fasta::Record) -> Option<String> {
+        match self {
+            Taxonomy::None => None,
+            Taxonomy::Headers => parse_header(record.desc()?),
+            Taxonomy::Map(map) => map.get(record.id()).cloned(),
+        }
+    }
+}
+
+// Lineage without blanks around ranks nor trailing separator
+fn normalize(lineage: &str) -> String {
+    lineage
+        .split(';')
+        .map(str::trim)
+        .filter(|rank| !rank.is_empty())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Lineage of a SILVA (`Bacteria;Proteobacteria;...`) or GTDB
+/// (`d__Bacteria;p__Proteobacteria;... [attributes]`) header description
+pub fn parse_header(desc: &str) -> Option<String> {
+    let lineage = desc.split(" [").next().unwrap_or("");
+    if lineage.contains(';') {
+        Some(normalize(lineage))
+    } else {
+        None
+    }
+}
+
+/// Every taxon of a lineage, from the highest rank down, as the lineage
+/// up to that rank
+pub fn taxa(lineage: &str) -> Vec<String> {
+    let ranks = lineage.split(';').collect::<Vec<_>>();
+    (1..=ranks.len())
+        .map(|depth| ranks[..depth].join(";"))
+        .collect()
+}
+
+// Ranks of the lineages, by GTDB prefix and by depth for unprefixed ones
+const RANKS: [(char, &str); 7] = [
+    ('d', "domain"),
+    ('p', "phylum"),
+    ('c', "class"),
+    ('o', "order"),
+    ('f', "family"),
+    ('g', "genus"),
+    ('s', "species"),
+];
+
+/// Rank of a taxon, given as the lineage up to it, from its GTDB prefix
+/// (`p__`) or else its depth in the lineage: domain, phylum, ..., species,
+/// then `rank_8` and so on
+pub fn rank(taxon: &str) -> String {
+    let name = taxon.rsplit(';').next().unwrap_or(taxon);
+    let prefixed = match name.as_bytes() {
+        [prefix, b'_', b'_', ..] => RANKS
+            .iter()
+            .find(|(letter, _)| *letter as u8 == *prefix)
+            .map(|(_, rank)| rank.to_string()),
+        _ => None,
+    };
+    prefixed.unwrap_or_else(|| {
+        let depth = taxon.split(';').count();
+        match RANKS.get(depth - 1) {
+            Some((_, rank)) => rank.to_string(),
+            None => format!("rank_{}", depth),
+        }
+    })
+}
+
+/// Sequences of a taxon and how many of them each region was extracted
+/// from, with the hits of each primer on them and their summed distances
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TaxonCounts {
+    pub sequences: usize,
+    pub regions: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub primer_hits: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub primer_mismatches: BTreeMap<String, usize>,
+}
+
+impl TaxonCounts {
+    pub fn merge(&mut self, other: &TaxonCounts) {
+        self.sequences += other.sequences;
+        for (region, count) in other.regions.iter() {
+            *self.regions.entry(region.clone()).or_insert(0) += count;
+        }
+        for (primer, count) in other.primer_hits.iter() {
+            *self.primer_hits.entry(primer.clone()).or_insert(0) += count;
+        }
+        for (primer, count) in other.primer_mismatches.iter() {
+            *self.primer_mismatches.entry(primer.clone()).or_insert(0) += count;
+        }
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("Bacteria;Proteobacteria; Gammaproteobacteria;"),
+            Some("Bacteria;Proteobacteria;Gammaproteobacteria".to_string())
+        );
+        assert_eq!(
+            parse_header("d__Bacteria;p__Bacillota [locus_tag=X] [len=1500]"),
+            Some("d__Bacteria;p__Bacillota".to_string())
+        );
+        assert_eq!(parse_header("16S ribosomal RNA"), None);
+        assert_eq!(rank("d__Bacteria;p__Bacillota"), "phylum");
+        assert_eq!(rank("Bacteria;Bacillota;Bacilli"), "class");
+        assert_eq!(rank("g__Bacillus"), "genus");
+        assert_eq!(rank("a;b;c;d;e;f;g;h"), "rank_8");
+        assert_eq!(
+            taxa("Bacteria;Bacillota;Bacilli"),
+            vec![
+                "Bacteria",
+                "Bacteria;Bacillota",
+                "Bacteria;Bacillota;Bacilli"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "seq1\td__Bacteria; p__Bacillota").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "seq2\td__Archaea").unwrap();
+        let taxonomy = Taxonomy::from_file(file.path().to_str().unwrap());
+        let record = fasta::Record::with_attrs("seq1", None, b"ACGT");
+        assert_eq!(
+            taxonomy.unwrap().lineage(&record),
+            Some("d__Bacteria;p__Bacillota".to_string())
+        );
+
+        writeln!(file, "seq3").unwrap();
+        assert!(Taxonomy::from_file(file.path().to_str().unwrap()).is_err());
+    }
+}
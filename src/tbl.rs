@@ -0,0 +1,206 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::coords::Coords;
+use crate::sink::{OutputSink, SinkRegion};
+use crate::utils::{RegionMatch, Strand};
+
+use bio::io::fasta;
+
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+
+// Feature key of extracted regions
+const FEATURE_KEY: &str = "misc_feature";
+
+#[derive(Debug)]
+struct TableSequence {
+    id: String,
+    // (start, exclusive end, strand, note)
+    features: Vec<(usize, usize, Strand, String)>,
+}
+
+/// Feature table of the extracted regions, written in the EMBL format when
+/// the path ends with `.embl` and in the NCBI five-column format (.tbl)
+/// used by GenBank submission tools otherwise
+#[derive(Debug)]
+pub struct FeatureTable {
+    path: String,
+    sequences: Vec<TableSequence>,
+}
+
+impl FeatureTable {
+    pub fn new(path: &str) -> Self {
+        FeatureTable {
+            path: path.to_string(),
+            sequences: Vec::new(),
+        }
+    }
+
+    /// Start a new sequence, following regions are annotated on it
+    pub fn add_sequence(&mut self, id: &str) {
+        self.sequences.push(TableSequence {
+            id: id.to_string(),
+            features: Vec::new(),
+        });
+    }
+
+    pub fn add_region(&mut self, region_match: &RegionMatch) {
+        let note = if region_match.region.is_empty() {
+            format!(
+                "amplified by primers {} and {}",
+                region_match.forward_primer, region_match.reverse_primer
+            )
+        } else {
+            format!(
+                "16S rRNA hypervariable region {}; amplified by primers {} and {}",
+                region_match.region.to_uppercase(),
+                region_match.forward_primer,
+                region_match.reverse_primer
+            )
+        };
+        if let Some(sequence) = self.sequences.last_mut() {
+            sequence.features.push((
+                region_match.start,
+                region_match.end,
+                region_match.strand,
+                note,
+            ));
+        }
+    }
+
+    /// Append the sequences annotated by another table
+    pub fn merge(&mut self, other: FeatureTable) {
+        self.sequences.extend(other.sequences);
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        let content = if self.path.ends_with(".embl") {
+            self.to_embl()
+        } else {
+            self.to_tbl()
+        };
+        fs::write(&self.path, content)
+    }
+
+    // Sequences without any extracted region are left out
+    fn annotated(&self) -> impl Iterator<Item = &TableSequence> {
+        self.sequences.iter().filter(|s| !s.features.is_empty())
+    }
+
+    fn to_tbl(&self) -> String {
+        let mut out = String::new();
+
+        for sequence in self.annotated() {
+            let _ = writeln!(out, ">Feature {}", sequence.id);
+            for (start, end, strand, note) in sequence.features.iter() {
+                // 1-based inclusive coordinates, swapped on the minus strand
+                let (start, end) = Coords::OneBased.interval(*start, *end);
+                let (from, to) = match strand {
+                    Strand::Plus => (start, end),
+                    Strand::Minus => (end, start),
+                };
+                let _ = writeln!(out, "{}\t{}\t{}", from, to, FEATURE_KEY);
+                let _ = writeln!(out, "\t\t\tnote\t{}", note);
+            }
+        }
+
+        out
+    }
+
+    fn to_embl(&self) -> String {
+        let mut out = String::new();
+
+        for sequence in self.annotated() {
+            let _ = writeln!(out, "ID   {}", sequence.id);
+            let _ = writeln!(out, "FH   Key             Location/Qualifiers");
+            for (start, end, strand, note) in sequence.features.iter() {
+                let (start, end) = Coords::OneBased.interval(*start, *end);
+                let location = match strand {
+                    Strand::Plus => format!("{}..{}", start, end),
+                    Strand::Minus => format!("complement({}..{})", start, end),
+                };
+                let _ = writeln!(out, "FT   {:<16}{}", FEATURE_KEY, location);
+                let _ = writeln!(
+                    out,
+                    "FT                   /note=\"{}\"",
+                    note.replace('"', "\"\"")
+                );
+            }
+            out.push_str("//\n");
+        }
+
+        out
+    }
+}
+
+impl OutputSink for FeatureTable {
+    fn start_record(&mut self, record: &fasta::Record) -> anyhow::Result<()> {
+        self.add_sequence(record.id());
+        Ok(())
+    }
+
+    fn write_region(&mut self, found: &SinkRegion) -> anyhow::Result<()> {
+        self.add_region(found.region);
+        Ok(())
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Confidence;
+
+    fn table() -> FeatureTable {
+        let mut table = FeatureTable::new("out.tbl");
+        table.add_sequence("seq1");
+        table.add_region(&RegionMatch {
+            region: "v3v4".to_string(),
+            forward_primer: "CCTACGGGNGGCWGCAG".to_string(),
+            reverse_primer: "GACTACHVGGGTATCTAATCC".to_string(),
+            start: 267,
+            end: 707,
+            forward_dist: 0,
+            reverse_dist: 0,
+            strand: Strand::Plus,
+            confidence: Confidence::High,
+        });
+        table.add_sequence("seq2");
+        table
+    }
+
+    #[test]
+    fn test_to_tbl() {
+        assert_eq!(
+            table().to_tbl(),
+            ">Feature seq1\n268\t707\tmisc_feature\n\t\t\tnote\t16S rRNA \
+            hypervariable region V3V4; amplified by primers \
+            CCTACGGGNGGCWGCAG and GACTACHVGGGTATCTAATCC\n"
+        );
+    }
+
+    #[test]
+    fn test_to_embl() {
+        let embl = table().to_embl();
+        let lines = embl.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "ID   seq1");
+        assert_eq!(lines[2], "FT   misc_feature    268..707");
+        assert!(lines[3].starts_with("FT                   /note=\"16S"));
+        assert_eq!(lines[4], "//");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_minus_strand() {
+        let mut table = table();
+        table.sequences[0].features[0].2 = Strand::Minus;
+        assert!(table.to_tbl().contains("\n707\t268\tmisc_feature\n"));
+        assert!(table
+            .to_embl()
+            .contains("misc_feature    complement(268..707)"));
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::fmt::Write as FmtWrite;
+
+// Consecutive complementary bases making a dimer worth a warning
+const DIMER_WARN: usize = 5;
+// Same, when the run includes a 3' end which polymerase would extend
+const DIMER_3PRIME_WARN: usize = 3;
+// Hairpin stem length worth a warning
+const HAIRPIN_WARN: usize = 4;
+// Shortest hairpin loop
+const MIN_LOOP: usize = 3;
+
+/// Longest complementary run of a primer duplex
+#[derive(Debug, PartialEq)]
+pub struct Dimer {
+    pub length: usize,
+    // Does the run include the 3' end of one of the primers?
+    pub three_prime: bool,
+}
+
+impl Dimer {
+    pub fn is_problematic(&self) -> bool {
+        self.length >= DIMER_WARN
+            || (self.three_prime && self.length >= DIMER_3PRIME_WARN)
+    }
+}
+
+// Watson-Crick pairing, ambiguity codes never pair
+fn pairs(a: u8, b: u8) -> bool {
+    matches!(
+        (a.to_ascii_uppercase(), b.to_ascii_uppercase()),
+        (b'A', b'T')
+            | (b'T', b'A')
+            | (b'A', b'U')
+            | (b'U', b'A')
+            | (b'C', b'G')
+            | (b'G', b'C')
+    )
+}
+
+/// GC content of a primer, S counts as G or C
+pub fn gc_content(primer: &str) -> f64 {
+    if primer.is_empty() {
+        return 0.0;
+    }
+    let gc = primer
+        .bytes()
+        .filter(|b| matches!(b.to_ascii_uppercase(), b'G' | b'C' | b'S'))
+        .count();
+    gc as f64 / primer.len() as f64
+}
+
+/// Basic melting temperature in Celsius, Wallace rule for short primers
+pub fn melting_temperature(primer: &str) -> f64 {
+    let len = primer.len() as f64;
+    let gc = gc_content(primer) * len;
+    if primer.len() < 14 {
+        2.0 * (len - gc) + 4.0 * gc
+    } else {
+        64.9 + 41.0 * (gc - 16.4) / len
+    }
+}
+
+/// Longest run of complementary bases when `first` and `second` anneal
+/// in antiparallel orientation. Use the same primer twice for self-dimers.
+pub fn dimer(first: &str, second: &str) -> Dimer {
+    let a = first.as_bytes();
+    // 3' -> 5' so that a[i] faces b[i - shift]
+    let b = second.bytes().rev().collect::<Vec<_>>();
+    let mut best = Dimer {
+        length: 0,
+        three_prime: false,
+    };
+
+    for shift in -(b.len() as isize)..a.len() as isize {
+        let mut run = 0;
+        for i in 0..a.len() {
+            let j = i as isize - shift;
+            let paired =
+                j >= 0 && (j as usize) < b.len() && pairs(a[i], b[j as usize]);
+            run = if paired { run + 1 } else { 0 };
+            if run == 0 {
+                continue;
+            }
+            // 3' ends of a and of b (first base of the reversed b)
+            let three_prime = i == a.len() - 1 || j as usize + 1 == run;
+            let better = run > best.length
+                || (run == best.length && three_prime && !best.three_prime);
+            if better {
+                best = Dimer {
+                    length: run,
+                    three_prime,
+                };
+            }
+        }
+    }
+
+    best
+}
+
+/// Longest hairpin stem a primer can fold into
+pub fn hairpin(primer: &str) -> usize {
+    let p = primer.as_bytes();
+    let mut best = 0;
+
+    for i in 0..p.len() {
+        for j in (i + 1)..p.len() {
+            // Stem pairs p[i - k] with p[j + k] around the loop p[i+1..j]
+            if j - i - 1 < MIN_LOOP {
+                continue;
+            }
+            let mut stem = 0;
+            while stem <= i
+                && j + stem < p.len()
+                && pairs(p[i - stem], p[j + stem])
+            {
+                stem += 1;
+            }
+            best = best.max(stem);
+        }
+    }
+
+    best
+}
+
+/// Human readable report of the thermodynamic checks of a primer pair
+pub fn report(forward: &str, reverse: &str) -> (String, bool) {
+    let mut out = String::new();
+    let mut problems = false;
+
+    for (name, primer) in [("forward", forward), ("reverse", reverse)] {
+        let _ = writeln!(
+            out,
+            "  {:<20} length {}, GC {:.1}%, Tm {:.1} C",
+            name,
+            primer.len(),
+            gc_content(primer) * 100.0,
+            melting_temperature(primer)
+        );
+    }
+
+    let dimers = [
+        ("forward self-dimer", dimer(forward, forward)),
+        ("reverse self-dimer", dimer(reverse, reverse)),
+        ("cross-dimer", dimer(forward, reverse)),
+    ];
+    for (name, dimer) in dimers.iter() {
+        let status = if dimer.is_problematic() {
+            problems = true;
+            "WARNING"
+        } else {
+            "ok"
+        };
+        let _ = writeln!(
+            out,
+            "  {:<20} {} bp{}  {}",
+            name,
+            dimer.length,
+            if dimer.three_prime { " at 3' end" } else { "" },
+            status
+        );
+    }
+
+    for (name, primer) in
+        [("forward hairpin", forward), ("reverse hairpin", reverse)]
+    {
+        let stem = hairpin(primer);
+        let status = if stem >= HAIRPIN_WARN {
+            problems = true;
+            "WARNING"
+        } else {
+            "ok"
+        };
+        let _ = writeln!(out, "  {:<20} stem {} bp  {}", name, stem, status);
+    }
+
+    (out, problems)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gc_and_tm() {
+        assert_eq!(gc_content("GCAT"), 0.5);
+        assert_eq!(melting_temperature("ACGTACGT"), 24.0);
+        assert!(
+            (melting_temperature("AGAGTTTGATCMTGGCTCAG") - 49.7).abs() < 0.1
+        );
+    }
+
+    #[test]
+    fn test_dimer() {
+        // Fully self-complementary
+        let d = dimer("GAATTC", "GAATTC");
+        assert_eq!(d.length, 6);
+        assert!(d.is_problematic());
+        assert_eq!(dimer("AAAAAA", "AAAAAA").length, 0);
+        // 3' ends ...CCG and ...CGG anneal on 3 bases
+        let d = dimer("GGGGGGCCG", "TTTTTTCGG");
+        assert_eq!(
+            d,
+            Dimer {
+                length: 3,
+                three_prime: true
+            }
+        );
+        assert!(d.is_problematic());
+    }
+
+    #[test]
+    fn test_hairpin() {
+        // GGGG pairs with CCCC around a 4 bases loop
+        assert_eq!(hairpin("GGGGTTTTCCCC"), 4);
+        assert_eq!(hairpin("AAAAAAAA"), 0);
+        // Loop too short
+        assert_eq!(hairpin("GCGC"), 0);
+    }
+
+    #[test]
+    fn test_report() {
+        let (text, problems) =
+            report("CCTACGGGNGGCWGCAG", "GACTACHVGGGTATCTAATCC");
+        assert!(text.contains("cross-dimer"));
+        assert!(!problems);
+        let (_, problems) = report("GAATTCGAATTC", "ACGTTTTT");
+        assert!(problems);
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::sink::OutputSink;
+
+use bio::io::fasta;
+
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+
+/// Default width in bp of the windows of the variability track
+pub const DEFAULT_WINDOW: usize = 50;
+// Windows slide by this fraction of their width
+const STEPS_PER_WINDOW: usize = 5;
+// Name and description of the track of each metric
+const METRICS: [(&str, &str); 2] = [
+    ("GC", "GC content"),
+    ("entropy", "Shannon entropy of the bases, in bits"),
+];
+
+#[derive(Debug)]
+struct TrackSequence {
+    id: String,
+    len: usize,
+    // Metrics of the window centred on each step
+    values: Vec<[f32; 2]>,
+}
+
+/// GC content and Shannon entropy of the bases in sliding windows along
+/// each sequence, written as two bedGraph tracks to check in a genome
+/// browser that extracted regions lie in variable parts of the sequences
+#[derive(Debug)]
+pub struct VariabilityTrack {
+    path: String,
+    window: usize,
+    sequences: Vec<TrackSequence>,
+}
+
+impl VariabilityTrack {
+    pub fn new(path: &str, window: usize) -> Self {
+        VariabilityTrack {
+            path: path.to_string(),
+            window: window.max(1),
+            sequences: Vec::new(),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // Interval of the sequence each value stands for
+    fn step(&self) -> usize {
+        (self.window / STEPS_PER_WINDOW).max(1)
+    }
+
+    pub fn add_sequence(&mut self, id: &str, seq: &[u8]) {
+        // Running counts of A, C, G and T/U, other symbols being left out
+        let mut counts = vec![[0usize; 4]; seq.len() + 1];
+        for (i, base) in seq.iter().enumerate() {
+            counts[i + 1] = counts[i];
+            match base.to_ascii_uppercase() {
+                b'A' => counts[i + 1][0] += 1,
+                b'C' => counts[i + 1][1] += 1,
+                b'G' => counts[i + 1][2] += 1,
+                b'T' | b'U' => counts[i + 1][3] += 1,
+                _ => (),
+            }
+        }
+
+        let step = self.step();
+        let mut values = Vec::new();
+        for start in (0..seq.len()).step_by(step) {
+            // Windows are kept whole at the sequence ends
+            let centre = start + step.min(seq.len() - start) / 2;
+            let from = centre
+                .saturating_sub(self.window / 2)
+                .min(seq.len().saturating_sub(self.window));
+            let to = (from + self.window).min(seq.len());
+            let window = (0..4)
+                .map(|b| counts[to][b] - counts[from][b])
+                .collect::<Vec<_>>();
+            let total = window.iter().sum::<usize>();
+            if total == 0 {
+                values.push([0.0, 0.0]);
+                continue;
+            }
+            let gc = (window[1] + window[2]) as f32 / total as f32;
+            let entropy = window
+                .iter()
+                .filter(|&&count| count > 0)
+                .map(|&count| {
+                    let p = count as f32 / total as f32;
+                    p * (1.0 / p).log2()
+                })
+                .sum();
+            values.push([gc, entropy]);
+        }
+        self.sequences.push(TrackSequence {
+            id: id.to_string(),
+            len: seq.len(),
+            values,
+        });
+    }
+
+    /// Append the sequences of another track
+    pub fn merge(&mut self, other: VariabilityTrack) {
+        self.sequences.extend(other.sequences);
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        fs::write(&self.path, self.to_bedgraph())
+    }
+
+    fn to_bedgraph(&self) -> String {
+        let mut out = String::new();
+        let step = self.step();
+
+        for (metric, (name, description)) in METRICS.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "track type=bedGraph name=\"{}\" description=\"{} in windows \
+                of {} bp\"",
+                name, description, self.window
+            );
+            for sequence in self.sequences.iter() {
+                for (i, values) in sequence.values.iter().enumerate() {
+                    let _ = writeln!(
+                        out,
+                        "{}\t{}\t{}\t{:.3}",
+                        sequence.id,
+                        i * step,
+                        ((i + 1) * step).min(sequence.len),
+                        values[metric]
+                    );
+                }
+            }
+        }
+
+        out
+    }
+}
+
+impl OutputSink for VariabilityTrack {
+    fn start_record(&mut self, record: &fasta::Record) -> anyhow::Result<()> {
+        self.add_sequence(record.id(), record.seq());
+        Ok(())
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track() {
+        let mut track = VariabilityTrack::new("", 10);
+        track.add_sequence("a", b"AAAAAAAAAAGCGCGCGCGCACGTACGTACG");
+        let bedgraph = track.to_bedgraph();
+        let lines = bedgraph.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 34);
+        assert!(lines[0].starts_with("track type=bedGraph name=\"GC\""));
+        assert_eq!(lines[1], "a\t0\t2\t0.000");
+        assert_eq!(lines[8], "a\t14\t16\t1.000");
+        assert_eq!(lines[16], "a\t30\t31\t0.600");
+        assert!(lines[17].starts_with("track type=bedGraph name=\"entropy\""));
+        assert_eq!(lines[18], "a\t0\t2\t0.000");
+        assert_eq!(lines[33], "a\t30\t31\t1.971");
+    }
+}
@@ -3,60 +3,50 @@
 // This file may not be copied, modified, or distributed except according
 // to those terms.
 
+use crate::bam::BamWriter;
+use crate::bgzf::{self, BgzfReader};
+use crate::classify::GeneClassifier;
+use crate::coords::Coords;
+use crate::count::{self, Progress};
+use crate::matcher::{self, AmbigMap, Hit, Matcher};
+use crate::operon;
+use crate::pairs;
+use crate::plot::RegionPlot;
+use crate::reference::Reference;
+use crate::remote;
+use crate::rng::Rng;
+use crate::search::{PairHits, PrimerSearch, RecordHits};
+use crate::silva::SilvaColumns;
+use crate::sink::{
+    self, OutputSink, SinkHit, SinkIdentity, SinkRecord, SinkRegion, SinkSites,
+    SinkSkipped, Sinks,
+};
+use crate::stats::LiveStats;
+use crate::taxonomy::{self, TaxonCounts, Taxonomy};
+use crate::tbl::FeatureTable;
+use crate::track::VariabilityTrack;
+
 use anyhow::{anyhow, Context};
-use bio::io::fasta;
-use bio::pattern_matching::myers::MyersBuilder;
-use fern::colors::ColoredLevelConfig;
+use bio::alignment::distance::levenshtein;
+use bio::alphabets::dna;
+use bio::io::{fasta, fastq};
 use log::{error, info, warn};
 use phf::phf_map;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
-use std::io::{self, Write};
-
-pub fn setup_logging(quiet: bool) -> anyhow::Result<(), fern::InitError> {
-    let colors = ColoredLevelConfig::default();
-    let mut base_config = fern::Dispatch::new();
-
-    base_config = match quiet {
-        // if user required quietness let only output warning messages
-        // or messages more severe than warnings
-        true => base_config.level(log::LevelFilter::Warn),
-        // if quietness is not specified which implies verbosity is allowed
-        // output
-        false => base_config.level(log::LevelFilter::Debug),
-    };
-
-    // Separate file config so we can include year, month and day in file logs
-    let file_config = fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .chain(fern::log_file("hyperex.log")?);
-
-    let stdout_config = fern::Dispatch::new()
-        .format(move |out, message, record| {
-            out.finish(format_args!(
-                "[{}][{}] {}",
-                chrono::Local::now().format("%H:%M:%S"),
-                colors.color(record.level()),
-                message
-            ))
-        })
-        .chain(io::stdout());
-
-    base_config
-        .chain(file_config)
-        .chain(stdout_config)
-        .apply()?;
-
-    Ok(())
-}
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, Write};
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 // Primers data
 static PRIMER_TO_REGION: phf::Map<&'static str, &'static str> = phf_map! {
@@ -77,7 +67,7 @@ static PRIMER_TO_REGION: phf::Map<&'static str, &'static str> = phf_map! {
     "TACGGYTACCTTGTTAYGACTT" => "v9"
 };
 
-static FORWARD_PRIMERS: phf::Map<&'static str, &'static str> = phf_map! {
+pub(crate) static FORWARD_PRIMERS: phf::Map<&'static str, &'static str> = phf_map! {
     "27F" => "AGAGTTTGATCMTGGCTCAG",
     "341F" => "CCTACGGGNGGCWGCAG",
     "515F" => "GTGCCAGCMGCCGCGGTAA",
@@ -87,7 +77,7 @@ static FORWARD_PRIMERS: phf::Map<&'static str, &'static str> = phf_map! {
     "1100F" => "YAACGAGCGCAACCC",
 };
 
-static REVERSE_PRIMERS: phf::Map<&'static str, &'static str> = phf_map! {
+pub(crate) static REVERSE_PRIMERS: phf::Map<&'static str, &'static str> = phf_map! {
     "336R" => "ACTGCTGCSYCCCGTAGGAGTCT",
     "534R" => "ATTACCGCGGCTGCTGG",
     "805R" => "GACTACHVGGGTATCTAATCC",
@@ -98,50 +88,410 @@ static REVERSE_PRIMERS: phf::Map<&'static str, &'static str> = phf_map! {
     "1492Rmod" => "TACGGYTACCTTGTTAYGACTT",
 };
 
+// Literal of the primer database version, for the constants built with
+// concat!
+macro_rules! primer_db_version {
+    () => {
+        "2024.1"
+    };
+}
+
+/// Version of the built-in primer database, FORWARD_PRIMERS,
+/// REVERSE_PRIMERS and the built-in regions, bumped with any change to
+/// them so that results can be traced to the primers used
+pub const PRIMER_DB_VERSION: &str = primer_db_version!();
+
+/// Marker genes with built-in regions
+pub const GENES: [&str; 3] = ["16S", "18S", "ITS"];
+
+// Built-in regions as (gene, region, forward primer, reverse primer). 16S
+// regions are bound by the primers of FORWARD_PRIMERS and REVERSE_PRIMERS,
+// 18S ones by TAReuk454FWD1 and TAReukREV3, 1391F and EukBr, ITS ones by
+// ITS1F and ITS2, fITS7 and ITS4
+static REGIONS: [(&str, &str, &str, &str); 14] = [
+    (
+        "16S",
+        "v1v2",
+        "AGAGTTTGATCMTGGCTCAG",
+        "ACTGCTGCSYCCCGTAGGAGTCT",
+    ),
+    ("16S", "v1v3", "AGAGTTTGATCMTGGCTCAG", "ATTACCGCGGCTGCTGG"),
+    (
+        "16S",
+        "v1v9",
+        "AGAGTTTGATCMTGGCTCAG",
+        "TACGGYTACCTTGTTAYGACTT",
+    ),
+    ("16S", "v3v4", "CCTACGGGNGGCWGCAG", "GACTACHVGGGTATCTAATCC"),
+    ("16S", "v3v5", "CCTACGGGNGGCWGCAG", "CCGTCAATTYMTTTRAGT"),
+    ("16S", "v4", "GTGCCAGCMGCCGCGGTAA", "GGACTACHVGGGTWTCTAAT"),
+    ("16S", "v4v5", "GTGYCAGCMGCCGCGGTAA", "CCCCGYCAATTCMTTTRAGT"),
+    ("16S", "v5v7", "AACMGGATTAGATACCCKG", "ACGTCATCCCCACCTTCC"),
+    (
+        "16S",
+        "v6v9",
+        "TAAAACTYAAAKGAATTGACGGGG",
+        "TACGGYTACCTTGTTAYGACTT",
+    ),
+    ("16S", "v7v9", "YAACGAGCGCAACCC", "TACGGYTACCTTGTTAYGACTT"),
+    (
+        "18S",
+        "18S-v4",
+        "CCAGCASCYGCGGTAATTCC",
+        "ACTTTCGTTCTTGATYRA",
+    ),
+    (
+        "18S",
+        "18S-v9",
+        "GTACACACCGCCCGTC",
+        "TGATCCTTCTGCAGGTTCACCTAC",
+    ),
+    (
+        "ITS",
+        "ITS1",
+        "CTTGGTCATTTAGAGGAAGTAA",
+        "GCTGCGTTCTTCATCGATGC",
+    ),
+    ("ITS", "ITS2", "GTGARTCATCGAATCTTTG", "TCCTCCGCTTATTGATATGC"),
+];
+
+/// Built-in regions of a marker gene
+pub fn gene_regions(gene: &str) -> Vec<&'static str> {
+    REGIONS
+        .iter()
+        .filter(|(region_gene, _, _, _)| *region_gene == gene)
+        .map(|(_, region, _, _)| *region)
+        .collect()
+}
+
+/// Marker gene of a built-in region
+pub fn region_gene(region: &str) -> Option<&'static str> {
+    REGIONS
+        .iter()
+        .find(|(_, name, _, _)| *name == region)
+        .map(|(gene, _, _, _)| *gene)
+}
+
+/// Regions that can be extracted by name: the built-in regions and the
+/// CSV files of primer pairs loaded by the user, known by their path. Both
+/// the command-line parser and the extraction query it, so that a region
+/// accepted by one is never unknown to the other.
+#[derive(Debug, Clone, Default)]
+pub struct RegionRegistry {
+    // Primer pairs of each loaded file
+    user: Vec<(String, Vec<Vec<String>>)>,
+    // Settings of the loaded primer pairs having their own
+    options: PairOptionsMap,
+}
+
+impl RegionRegistry {
+    /// Names of the built-in regions
+    pub fn builtin_names() -> Vec<&'static str> {
+        REGIONS.iter().map(|(_, name, _, _)| *name).collect()
+    }
+
+    /// Name of the built-in region `region` stands for, ignoring case and
+    /// separators so that `V3V4`, `v3-v4` and `V3_V4` all name v3v4
+    pub fn resolve(region: &str) -> Option<&'static str> {
+        let key = normalize_region(region);
+        Self::builtin_names()
+            .into_iter()
+            .find(|name| normalize_region(name) == key)
+    }
+
+    /// Built-in region closest to a misspelt `region`, if any is close
+    /// enough to be meant
+    pub fn suggest(region: &str) -> Option<&'static str> {
+        let key = normalize_region(region);
+        Self::builtin_names()
+            .into_iter()
+            .map(|name| {
+                let distance = levenshtein(
+                    key.as_bytes(),
+                    normalize_region(name).as_bytes(),
+                );
+                (distance, name)
+            })
+            .min()
+            .filter(|&(distance, _)| distance <= 2)
+            .map(|(_, name)| name)
+    }
+
+    /// Error message of an unknown region, suggesting the closest built-in
+    /// region
+    pub fn unknown(region: &str) -> String {
+        let suggestion = match Self::suggest(region) {
+            Some(name) => format!(" Did you mean {}?", name),
+            None => String::new(),
+        };
+        format!(
+            "Unknown region {}.{} Expected a CSV or TOML file of primers or one of {}",
+            region,
+            suggestion,
+            Self::builtin_names().join(", ")
+        )
+    }
+
+    /// Is `region` a built-in or a loaded region?
+    pub fn contains(&self, region: &str) -> bool {
+        Self::resolve(region).is_some()
+            || self.user.iter().any(|(name, _)| name == region)
+    }
+
+    /// Load the primer pairs of a primers file, see [`read_primers_file`],
+    /// as the region named after its path
+    pub fn load(&mut self, filename: &str) -> anyhow::Result<()> {
+        let pairs = read_primers_file(filename)
+            .with_context(|| format!("Cannot load regions {}", filename))?;
+        let mut primers = Vec::new();
+        for (pair, options) in pairs {
+            if options != PairOptions::default() {
+                self.options.insert(pair.clone(), options);
+            }
+            primers.push(pair);
+        }
+        self.user.retain(|(name, _)| name != filename);
+        self.user.push((filename.to_string(), primers));
+
+        Ok(())
+    }
+
+    /// Settings of the loaded primer pairs having their own
+    pub fn pair_options(&self) -> &PairOptionsMap {
+        &self.options
+    }
+
+    /// Primer pairs of a region, an error listing the built-in regions when
+    /// it is unknown
+    pub fn primers(&self, region: &str) -> anyhow::Result<Vec<Vec<String>>> {
+        if let Some(builtin) = Self::resolve(region) {
+            let (_, _, forward, reverse) = REGIONS
+                .iter()
+                .find(|(_, name, _, _)| *name == builtin)
+                .expect("resolved regions are built-in");
+            return Ok(vec![vec![forward.to_string(), reverse.to_string()]]);
+        }
+        match self.user.iter().find(|(name, _)| name == region) {
+            Some((_, pairs)) => Ok(pairs.clone()),
+            None => Err(anyhow!(Self::unknown(region))),
+        }
+    }
+}
+
+// Region name without case and separators
+fn normalize_region(region: &str) -> String {
+    region
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+// E. coli 16S rRNA gene (J01859) positions, 1-based and inclusive, of the
+// binding sites of the built-in primers
+static ECOLI_SITES: phf::Map<&'static str, (usize, usize)> = phf_map! {
+    "AGAGTTTGATCMTGGCTCAG" => (8, 27),
+    "CCTACGGGNGGCWGCAG" => (341, 357),
+    "GTGCCAGCMGCCGCGGTAA" => (515, 533),
+    "GTGYCAGCMGCCGCGGTAA" => (515, 533),
+    "AACMGGATTAGATACCCKG" => (799, 817),
+    "TAAAACTYAAAKGAATTGACGGGG" => (905, 928),
+    "YAACGAGCGCAACCC" => (1100, 1114),
+    "ACTGCTGCSYCCCGTAGGAGTCT" => (338, 360),
+    "ATTACCGCGGCTGCTGG" => (518, 534),
+    "GACTACHVGGGTATCTAATCC" => (785, 805),
+    "CCGTCAATTYMTTTRAGT" => (909, 926),
+    "GGACTACHVGGGTWTCTAAT" => (787, 806),
+    "CCCCGYCAATTCMTTTRAGT" => (909, 928),
+    "ACGTCATCCCCACCTTCC" => (1176, 1193),
+    "TACGGYTACCTTGTTAYGACTT" => (1492, 1513)
+};
+
+// Largest difference, relative to the E. coli amplicon, between the length
+// of an amplicon and that expected from the E. coli positions of its
+// primers, beyond which the amplicon may come from a chimera
+pub(crate) const CHIMERA_TOLERANCE: f64 = 0.2;
+
+/// E. coli positions, 1-based and inclusive, of the region of a primer
+/// pair, primers included unless trimmed, when both primers have a known
+/// E. coli position
+pub fn ecoli_region(
+    primer_pair: &[String],
+    trim_primers: bool,
+) -> Option<(usize, usize)> {
+    let &(forward_start, forward_end) =
+        ECOLI_SITES.get(primer_pair[0].as_str())?;
+    let &(reverse_start, reverse_end) =
+        ECOLI_SITES.get(primer_pair[1].as_str())?;
+    if reverse_end <= forward_start {
+        return None;
+    }
+    Some(if trim_primers {
+        (forward_end + 1, reverse_start - 1)
+    } else {
+        (forward_start, reverse_end)
+    })
+}
+
+/// Length of the E. coli amplicon of a primer pair, primers included, when
+/// both primers have a known E. coli position
+pub fn ecoli_amplicon_length(primer_pair: &[String]) -> Option<usize> {
+    ecoli_region(primer_pair, false).map(|(start, end)| end - start + 1)
+}
+
+/// Is an amplicon of a primer pair, primers included, of `length` bases
+/// discordant with the E. coli positions of the primers, as the amplicons of
+/// chimeras? Primers without a known E. coli position are never flagged.
+pub fn is_chimera_suspect(primer_pair: &[String], length: usize) -> bool {
+    match ecoli_amplicon_length(primer_pair) {
+        Some(expected) => {
+            let expected = expected as f64;
+            (length as f64 - expected).abs() > expected * CHIMERA_TOLERANCE
+        }
+        None => false,
+    }
+}
+
+/// Primer pair of a built-in region
 pub fn region_to_primer(region: &str) -> anyhow::Result<Vec<String>> {
-    match region {
-        "v1v2" => Ok(vec![
-            FORWARD_PRIMERS["27F"].to_string(),
-            REVERSE_PRIMERS["336R"].to_string(),
-        ]),
-        "v1v3" => Ok(vec![
-            FORWARD_PRIMERS["27F"].to_string(),
-            REVERSE_PRIMERS["534R"].to_string(),
-        ]),
-        "v1v9" => Ok(vec![
-            FORWARD_PRIMERS["27F"].to_string(),
-            REVERSE_PRIMERS["1492Rmod"].to_string(),
-        ]),
-        "v3v4" => Ok(vec![
-            FORWARD_PRIMERS["341F"].to_string(),
-            REVERSE_PRIMERS["805R"].to_string(),
-        ]),
-        "v3v5" => Ok(vec![
-            FORWARD_PRIMERS["341F"].to_string(),
-            REVERSE_PRIMERS["926Rb"].to_string(),
-        ]),
-        "v4" => Ok(vec![
-            FORWARD_PRIMERS["515F"].to_string(),
-            REVERSE_PRIMERS["806R"].to_string(),
-        ]),
-        "v4v5" => Ok(vec![
-            FORWARD_PRIMERS["515F-Y"].to_string(),
-            REVERSE_PRIMERS["909-928R"].to_string(),
-        ]),
-        "v5v7" => Ok(vec![
-            FORWARD_PRIMERS["799F"].to_string(),
-            REVERSE_PRIMERS["1193R"].to_string(),
-        ]),
-        "v6v9" => Ok(vec![
-            FORWARD_PRIMERS["928F"].to_string(),
-            REVERSE_PRIMERS["1492Rmod"].to_string(),
-        ]),
-        "v7v9" => Ok(vec![
-            FORWARD_PRIMERS["1100F"].to_string(),
-            REVERSE_PRIMERS["1492Rmod"].to_string(),
-        ]),
-        _ => Ok(vec!["".to_string()]),
+    let mut pairs = RegionRegistry::default().primers(region)?;
+    Ok(pairs.remove(0))
+}
+
+// Primer pair of a TOML primers file, a `[[pair]]` table
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlPair {
+    forward: String,
+    reverse: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mismatch: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlPrimers {
+    #[serde(default)]
+    pair: Vec<TomlPair>,
+}
+
+/// Read a primers file, either a CSV file with a primer pair per line as
+/// `forward,reverse[,mismatch,min_length,max_length,label]`, the optional
+/// columns, which can be left empty, overriding the run settings, or a TOML
+/// file of `[[pair]]` tables with the same keys, told apart by its first
+/// line being a table header or a comment
+pub fn read_primers_file(
+    filename: &str,
+) -> anyhow::Result<Vec<(Vec<String>, PairOptions)>> {
+    let content = fs::read_to_string(filename)?;
+    let first = content.lines().map(str::trim).find(|line| !line.is_empty());
+    if first.is_some_and(|line| line.starts_with('[') || line.starts_with('#'))
+    {
+        return read_primers_toml(&content, filename);
+    }
+    let mut pairs = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let error =
+            |what: &str| anyhow!("line {} of {}: {}", i + 1, filename, what);
+        if fields.len() < 2 || fields.len() > 6 || fields[..2].contains(&"") {
+            return Err(error("expected a forward and a reverse primer, then optionally mismatches, minimum and maximum lengths and a label"));
+        }
+        let field = |n: usize| fields.get(n).filter(|f| !f.is_empty());
+        let number = |n: usize| -> anyhow::Result<Option<usize>> {
+            field(n)
+                .map(|f| f.parse())
+                .transpose()
+                .map_err(|_| error("lengths must be integers"))
+        };
+        let options = PairOptions {
+            mismatch: field(2).map(|f| f.parse()).transpose().map_err(
+                |_| error("mismatches must be an integer up to 255"),
+            )?,
+            min_length: number(3)?,
+            max_length: number(4)?,
+            label: field(5).map(|f| f.to_string()),
+        };
+        if let (Some(min), Some(max)) = (options.min_length, options.max_length)
+        {
+            if min > max {
+                return Err(error("minimum length above the maximum length"));
+            }
+        }
+        pairs.push((
+            vec![fields[0].to_string(), fields[1].to_string()],
+            options,
+        ));
+    }
+
+    Ok(pairs)
+}
+
+// Primer pairs of a TOML primers file
+fn read_primers_toml(
+    content: &str,
+    filename: &str,
+) -> anyhow::Result<Vec<(Vec<String>, PairOptions)>> {
+    let primers: TomlPrimers = toml::from_str(content)
+        .with_context(|| format!("Cannot parse {}", filename))?;
+    let mut pairs = Vec::new();
+
+    for (i, pair) in primers.pair.into_iter().enumerate() {
+        let error =
+            |what: &str| anyhow!("pair {} of {}: {}", i + 1, filename, what);
+        let (forward, reverse) = (pair.forward.trim(), pair.reverse.trim());
+        if forward.is_empty() || reverse.is_empty() {
+            return Err(error("expected a forward and a reverse primer"));
+        }
+        if let (Some(min), Some(max)) = (pair.min_length, pair.max_length) {
+            if min > max {
+                return Err(error("minimum length above the maximum length"));
+            }
+        }
+        pairs.push((
+            vec![forward.to_string(), reverse.to_string()],
+            PairOptions {
+                mismatch: pair.mismatch,
+                min_length: pair.min_length,
+                max_length: pair.max_length,
+                label: pair.label,
+            },
+        ));
     }
+
+    Ok(pairs)
+}
+
+/// Primer pairs and their settings as a TOML primers file, read back by
+/// [`read_primers_file`]
+pub fn primers_to_toml(
+    pairs: &[(Vec<String>, PairOptions)],
+) -> anyhow::Result<String> {
+    let primers = TomlPrimers {
+        pair: pairs
+            .iter()
+            .map(|(pair, options)| TomlPair {
+                forward: pair[0].clone(),
+                reverse: pair[1].clone(),
+                mismatch: options.mismatch,
+                min_length: options.min_length,
+                max_length: options.max_length,
+                label: options.label.clone(),
+            })
+            .collect(),
+    };
+
+    Ok(toml::to_string(&primers)?)
 }
 
 pub fn file_to_vec(filename: &str) -> anyhow::Result<Vec<Vec<String>>> {
@@ -163,38 +513,287 @@ pub fn file_to_vec(filename: &str) -> anyhow::Result<Vec<Vec<String>>> {
     Ok(vec)
 }
 
-pub fn combine_vec(first: Vec<&str>, second: Vec<&str>) -> Vec<Vec<String>> {
-    first
+/// Pair forward and reverse primers in the order they are given, refusing
+/// unequal numbers of them rather than leaving primers out
+pub fn combine_vec(
+    first: Vec<&str>,
+    second: Vec<&str>,
+) -> anyhow::Result<Vec<Vec<String>>> {
+    if first.len() != second.len() {
+        return Err(anyhow!(
+            "{} forward and {} reverse primers given, they are paired in order so their numbers must be equal (or use --cross to test every combination)",
+            first.len(),
+            second.len()
+        ));
+    }
+    Ok(first
         .iter()
         .zip(second)
         .map(|x| vec![x.0.to_string(), x.1.to_string()])
-        .collect::<Vec<Vec<String>>>()
+        .collect::<Vec<Vec<String>>>())
+}
+
+/// Every pair of a forward and a reverse primer, for screening primer
+/// panels, forward primers varying slowest
+pub fn cross_vec(first: Vec<&str>, second: Vec<&str>) -> Vec<Vec<String>> {
+    first
+        .iter()
+        .flat_map(|f| {
+            second
+                .iter()
+                .map(move |r| vec![f.to_string(), r.to_string()])
+        })
+        .collect()
+}
+
+/// Save data read from standard input to `path`, as is so that FASTQ and
+/// compressed inputs are detected when the file is read
+pub fn stdin_to_file(path: &str) -> anyhow::Result<()> {
+    let mut writer = io::BufWriter::new(File::create(path)?);
+    io::copy(&mut io::stdin(), &mut writer)?;
+    writer.flush()?;
+
+    Ok(())
 }
 
-fn read_file(
+pub(crate) fn read_file(
     filename: &str,
 ) -> anyhow::Result<(Box<dyn io::Read>, niffler::compression::Format)> {
-    let raw_in = Box::new(io::BufReader::new(File::open(filename)?));
+    let raw_in: Box<dyn io::Read> = if remote::is_url(filename) {
+        Box::new(io::BufReader::new(remote::open(filename)?))
+    } else {
+        Box::new(io::BufReader::new(File::open(filename)?))
+    };
 
     Ok(niffler::get_reader(raw_in)?)
 }
 
-fn primers_to_region(primers: Vec<String>) -> String {
-    let mut first_part = "";
-    let mut second_part = "";
+pub type Records = Box<dyn Iterator<Item = anyhow::Result<fasta::Record>>>;
+
+// Read FASTA or FASTQ records, guessing the format from the first byte.
+// FASTQ qualities are not used and are dropped.
+pub fn read_records(filename: &str) -> anyhow::Result<Records> {
+    read_records_threads(filename, 1)
+}
+
+// Same as read_records, the blocks of local BGZF files being decompressed
+// on up to `threads` threads
+pub(crate) fn read_records_threads(
+    filename: &str,
+    threads: usize,
+) -> anyhow::Result<Records> {
+    let reader: Box<dyn io::Read> =
+        if threads > 1 && !remote::is_url(filename) && bgzf::is_bgzf(filename)?
+        {
+            Box::new(BgzfReader::new(File::open(filename)?, threads))
+        } else {
+            read_file(filename)?.0
+        };
+    let mut reader = io::BufReader::new(reader);
 
-    if PRIMER_TO_REGION.contains_key(&primers[0]) {
-        first_part = PRIMER_TO_REGION[&primers[0]];
+    if reader.fill_buf()?.first() == Some(&b'@') {
+        Ok(Box::new(fastq::Reader::new(reader).records().map(|r| {
+            r.map(|r| fasta::Record::with_attrs(r.id(), r.desc(), r.seq()))
+                .map_err(anyhow::Error::from)
+        })))
+    } else {
+        Ok(Box::new(
+            fasta::Reader::new(reader)
+                .records()
+                .map(|r| r.map_err(anyhow::Error::from)),
+        ))
     }
+}
 
-    if PRIMER_TO_REGION.contains_key(&primers[1]) {
-        second_part = PRIMER_TO_REGION[&primers[1]];
+// Record of a FASTA index
+struct FaiEntry {
+    name: String,
+    length: u64,
+    // Offset of the first sequence base
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+    // Was a line shorter than the first one, i.e. the last line, seen?
+    short_line: bool,
+}
+
+/// Write the samtools faidx index of a FASTA file to `<path>.fai`
+pub fn index_fasta(path: &str) -> anyhow::Result<()> {
+    let mut reader = io::BufReader::new(File::open(path)?);
+    let mut writer = io::BufWriter::new(File::create(format!("{}.fai", path))?);
+    let write_entry = |writer: &mut io::BufWriter<File>, e: FaiEntry| {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            e.name, e.length, e.offset, e.line_bases, e.line_width
+        )
+    };
+
+    let mut entry: Option<FaiEntry> = None;
+    let mut offset = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)? as u64;
+        if read == 0 {
+            break;
+        }
+        offset += read;
+        let mut content = line.as_slice();
+        while let Some((b'\n' | b'\r', rest)) = content.split_last() {
+            content = rest;
+        }
+
+        if content.first() == Some(&b'>') {
+            if let Some(previous) = entry.take() {
+                write_entry(&mut writer, previous)?;
+            }
+            let header = String::from_utf8_lossy(&content[1..]);
+            entry = Some(FaiEntry {
+                name: header.split_whitespace().next().unwrap_or("").into(),
+                length: 0,
+                offset,
+                line_bases: 0,
+                line_width: 0,
+                short_line: false,
+            });
+        } else if let Some(e) = entry.as_mut() {
+            let bases = content.len() as u64;
+            if bases == 0 {
+                continue;
+            }
+            if e.line_bases == 0 {
+                e.line_bases = bases;
+                e.line_width = read;
+            } else if e.short_line || bases > e.line_bases {
+                return Err(anyhow!(
+                    "Cannot index {}, lines of sequence {} have different lengths",
+                    path,
+                    e.name
+                ));
+            } else if bases < e.line_bases {
+                e.short_line = true;
+            }
+            e.length += bases;
+        }
     }
+    if let Some(last) = entry {
+        write_entry(&mut writer, last)?;
+    }
+    writer.flush()?;
 
-    if first_part == "v4" && second_part == "v4" {
-        first_part.to_string()
-    } else {
-        format!("{}{}", first_part, second_part)
+    Ok(())
+}
+
+/// Check that primer pairs are usable before processing, catching swapped
+/// or mistyped inputs early
+pub fn check_primer_pairs(primers: &[Vec<String>]) -> anyhow::Result<()> {
+    let is_forward = |p: &str| FORWARD_PRIMERS.values().any(|f| *f == p);
+    let is_reverse = |p: &str| REVERSE_PRIMERS.values().any(|r| *r == p);
+    // Variable region number of a known primer, v3 -> 3
+    let region_number = |p: &str| {
+        PRIMER_TO_REGION
+            .get(p)
+            .and_then(|region| region[1..].parse::<u8>().ok())
+    };
+
+    for pair in primers.iter() {
+        let (forward, reverse) = (pair[0].as_str(), pair[1].as_str());
+
+        if forward.eq_ignore_ascii_case(reverse) {
+            return Err(anyhow!(
+                "forward and reverse primers are identical: {}",
+                forward
+            ));
+        }
+        if is_reverse(forward) && is_forward(reverse) {
+            return Err(anyhow!(
+                "primers {} and {} look swapped: {} is a known reverse primer and {} a known forward primer",
+                forward, reverse, forward, reverse
+            ));
+        }
+        if let (Some(first), Some(second)) =
+            (region_number(forward), region_number(reverse))
+        {
+            if first > second {
+                return Err(anyhow!(
+                    "forward primer {} binds v{} after reverse primer {} which binds v{}. Are the primers swapped?",
+                    forward, first, reverse, second
+                ));
+            }
+        }
+
+        let forward_rc = to_reverse_complement(forward, "dna");
+        let reverse_rc = to_reverse_complement(reverse, "dna");
+        if reverse_rc.contains(forward) || forward_rc.contains(reverse) {
+            return Err(anyhow!(
+                "primers {} and {} bind the same site on opposite strands",
+                forward,
+                reverse
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that every primer is allowed fewer mismatches than its length,
+/// with the settings of its pair, as it would otherwise match anywhere
+pub fn check_mismatches(
+    primers: &[Vec<String>],
+    config: &Config,
+) -> anyhow::Result<()> {
+    for pair in primers.iter() {
+        for primer in pair.iter() {
+            let mismatch = config.primer_mismatch(pair, primer) as usize;
+            if mismatch >= primer.len() {
+                return Err(anyhow!(
+                    "{} mismatches allowed to primer {} of {} bases would match it anywhere, allow at most {}",
+                    mismatch,
+                    primer,
+                    primer.len(),
+                    primer.len() - 1
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Region name of a primer pair, from the regions of its primers. A pair
+/// with a single primer of the region map is named after it, the unknown
+/// side being `?`, e.g. `v3-?`.
+pub fn primers_to_region(primers: Vec<String>) -> String {
+    if let Some((_, region, _, _)) = REGIONS
+        .iter()
+        .filter(|r| r.0 != "16S")
+        .find(|r| r.2 == primers[0] && r.3 == primers[1])
+    {
+        return region.to_string();
+    }
+    let first_part = PRIMER_TO_REGION.get(&primers[0]).copied();
+    let second_part = PRIMER_TO_REGION.get(&primers[1]).copied();
+
+    match (first_part, second_part) {
+        (Some("v4"), Some("v4")) => "v4".to_string(),
+        (Some(first), Some(second)) => format!("{}{}", first, second),
+        (Some(first), None) => format!("{}-?", first),
+        (None, Some(second)) => format!("?-{}", second),
+        (None, None) => String::new(),
+    }
+}
+
+/// Primer of a pair missing from the region map while the other one is in
+/// it, leaving a region name such as `v3-?`
+pub fn unmapped_primer(primers: &[String]) -> Option<&str> {
+    match (
+        PRIMER_TO_REGION.contains_key(&primers[0]),
+        PRIMER_TO_REGION.contains_key(&primers[1]),
+    ) {
+        (true, false) => Some(&primers[1]),
+        (false, true) => Some(&primers[0]),
+        _ => None,
     }
 }
 
@@ -247,378 +846,4772 @@ fn to_complement(primer: &str, alphabet: &str) -> String {
     complement
 }
 
-fn to_reverse_complement(primer: &str, alphabet: &str) -> String {
+pub fn to_reverse_complement(primer: &str, alphabet: &str) -> String {
     let complement = to_complement(primer, alphabet);
     let reverse_complement = complement.chars().rev().collect();
 
     reverse_complement
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Alphabet {
-    Dna,
-    Rna,
+/// Strand of a sequence a region is extracted from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Strand {
+    #[default]
+    #[serde(rename = "+")]
+    Plus,
+    #[serde(rename = "-")]
+    Minus,
 }
 
-pub fn sequence_type(sequence: &str) -> Option<Alphabet> {
-    let valid_dna_iupac = "ACGTRYSWKMBDHVN";
-    let valid_rna_iupac = "ACGURYSWKMBDHVN";
-
-    if sequence.chars().all(|x| valid_dna_iupac.contains(x)) {
-        Some(Alphabet::Dna)
-    } else if sequence.chars().all(|x| valid_rna_iupac.contains(x)) {
-        Some(Alphabet::Rna)
-    } else {
-        None
+impl fmt::Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strand::Plus => write!(f, "+"),
+            Strand::Minus => write!(f, "-"),
+        }
     }
 }
 
-pub fn get_hypervar_regions(
-    file: &str,
-    primers: Vec<Vec<String>>,
-    prefix: &str,
-    mismatch: u8,
-) -> anyhow::Result<()> {
-    let (reader, mut _compression) =
-        read_file(file).with_context(|| "Cannot read file")?;
-
-    let mut records = fasta::Reader::new(reader).records();
-
-    let mut fasta_writer = fasta::Writer::to_file(format!("{}.fa", prefix))?;
-    let gff_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(format!("{}.gff", prefix))?;
-    let mut gff_writer = io::BufWriter::new(gff_file);
-    gff_writer.write_all(b"##gff-version 3\n")?;
-
-    // Build Myers with IUPAC ambiguities in patterns
-    let ambigs = [
-        (b'M', &b"AC"[..]),
-        (b'R', &b"AG"[..]),
-        (b'W', &b"AT"[..]),
-        (b'S', &b"CG"[..]),
-        (b'Y', &b"CT"[..]),
-        (b'K', &b"GT"[..]),
-        (b'V', &b"ACGMRS"[..]),
-        (b'H', &b"ACTMWY"[..]),
-        (b'D', &b"AGTRWK"[..]),
-        (b'B', &b"CGTSYK"[..]),
-        (b'N', &b"ACGTMRWSYKVHDB"[..]),
-    ];
-
-    let mut builder = MyersBuilder::new();
-
-    for &(base, equivalents) in &ambigs {
-        builder.ambig(base, equivalents);
-    }
-
-    while let Some(Ok(record)) = records.next() {
-        let seq = record.seq();
-        let mut alphabet = "";
-        match sequence_type(std::str::from_utf8(seq)?) {
-            Some(alp) => {
-                if alp == Alphabet::Dna {
-                    info!("Sequence type is DNA");
-                    alphabet = "dna";
-                } else if alp == Alphabet::Rna {
-                    info!("Sequence type is RNA");
-                    alphabet = "rna";
-                }
-            }
-            None => error!("Sequence type is not recognized as DNA or RNA"),
-        }
-        if seq.len() <= 1500 {
-            warn!("Sequence length is less than 1500 bp. We may not be able to find some regions");
+/// Confidence of an extracted region, from the distances of its primer
+/// hits, the plausibility of its length and rival sites of its primers
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Confidence {
+    #[default]
+    High,
+    Medium,
+    Low,
+}
+
+impl Confidence {
+    /// Classify a region from the total `distance` of its primer hits and
+    /// the total mismatches `allowed`, whether another site of one of its
+    /// primers binds as well as the one used (`tied`) and whether its length
+    /// is `plausible`. Exact hits without rival sites give high confidence,
+    /// mismatches or a rival site medium confidence, and more than half the
+    /// allowed mismatches, both mismatches and a rival site, or an
+    /// implausible length low confidence.
+    pub fn classify(
+        distance: usize,
+        allowed: usize,
+        tied: bool,
+        plausible: bool,
+    ) -> Self {
+        if !plausible || 2 * distance > allowed || tied && distance > 0 {
+            Confidence::Low
+        } else if distance > 0 || tied {
+            Confidence::Medium
+        } else {
+            Confidence::High
         }
+    }
+}
 
-        for primer_pair in primers.iter() {
-            let region = primers_to_region(primer_pair.to_vec());
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Confidence::High => write!(f, "high"),
+            Confidence::Medium => write!(f, "medium"),
+            Confidence::Low => write!(f, "low"),
+        }
+    }
+}
 
-            let mut forward_myers = builder.build_64(primer_pair[0].as_bytes());
-            let mut reverse_myers = builder.build_64(
-                to_reverse_complement(&primer_pair[1], alphabet).as_bytes(),
-            );
+/// A hypervariable region located on a sequence by a primer pair
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegionMatch {
+    pub region: String,
+    pub forward_primer: String,
+    pub reverse_primer: String,
+    // 0-based start of the forward primer hit, or of the region after it
+    // when primers are trimmed
+    pub start: usize,
+    // 0-based exclusive end of the reverse primer hit, or of the region
+    // before it when primers are trimmed
+    pub end: usize,
+    pub forward_dist: u8,
+    pub reverse_dist: u8,
+    // Strand the region is read on, coordinates being on the plus strand
+    #[serde(default)]
+    pub strand: Strand,
+    #[serde(default)]
+    pub confidence: Confidence,
+}
 
-            let mut forward_matches =
-                forward_myers.find_all_lazy(seq, mismatch);
-            let mut reverse_matches =
-                reverse_myers.find_all_lazy(seq, mismatch);
-
-            // Get the best hit
-            let forward_best_hit =
-                forward_matches.by_ref().min_by_key(|&(_, dist)| dist);
-            let reverse_best_hit =
-                reverse_matches.by_ref().min_by_key(|&(_, dist)| dist);
-
-            match forward_best_hit {
-                Some((forward_best_hit_end, _)) => {
-                    match reverse_best_hit {
-                        Some((reverse_best_hit_end, _)) => {
-                            // Get match start position of forward primer
-                            let (forward_start, _) = forward_matches
-                                .hit_at(forward_best_hit_end)
-                                .unwrap();
-                            // Get match start position of reverse primer
-                            let (reverse_start, _) = reverse_matches
-                                .hit_at(reverse_best_hit_end)
-                                .unwrap();
-
-                            if !region.is_empty() {
-                                fasta_writer.write_record(
-                                    &fasta::Record::with_attrs(
-                                        record.id(),
-                                        Some(
-                                            format!(
-                                            "region={} forward={} reverse={}",
-                                            region,
-                                            primer_pair[0],
-                                            primer_pair[1]
-                                        )
-                                            .as_str(),
-                                        ),
-                                        &seq[forward_start
-                                            ..reverse_start
-                                                + primer_pair[1].len()],
-                                    ),
-                                )?;
-                            } else {
-                                fasta_writer.write_record(
-                                    &fasta::Record::with_attrs(
-                                        record.id(),
-                                        Some(
-                                            format!(
-                                                "forward={} reverse={}",
-                                                primer_pair[0], primer_pair[1]
-                                            )
-                                            .as_str(),
-                                        ),
-                                        &seq[forward_start
-                                            ..reverse_start
-                                                + primer_pair[1].len()],
-                                    ),
-                                )?;
-                            }
-                            // Write region to GFF3 file
-                            gff_writer.write_all(format!("{}\thyperex\tregion\t{}\t{}\t.\t.\t.\tNote Hypervariable region {}\n", record.id(), forward_start, reverse_start + primer_pair[1].len(), region).as_bytes())?;
-                        }
-                        None => {
-                            warn!("Region {} not found because primer {} was not found in the sequence", region, primer_pair[1])
-                        }
-                    }
-                }
-                None => match reverse_best_hit {
-                    Some((_, _)) => {
-                        warn!("Region {} not found because primer {} was not found in the sequence", region, primer_pair[0]);
-                    }
-                    None => {
-                        warn!("Region {} not found because primers {}, {} was not found in the sequence", region, primer_pair[0], primer_pair[1])
-                    }
-                },
-            }
+impl RegionMatch {
+    /// Region name, or the primer pair for unnamed regions
+    pub fn name(&self) -> String {
+        if self.region.is_empty() {
+            format!("{}/{}", self.forward_primer, self.reverse_primer)
+        } else {
+            self.region.clone()
         }
     }
-
-    Ok(())
 }
 
-// Tests --------------------------------------------------------------------
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+/// Extraction settings shared by all the processed files
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub mismatch: u8,
+    pub prefilter: bool,
+    pub ambigs: AmbigMap,
+    // Only allow substitutions in primer hits
+    pub no_indels: bool,
+    // Sequences are bisulfite-converted, their unmethylated C read as T
+    pub bisulfite: bool,
+    // Extract every amplicon instead of the best one per primer pair
+    pub all_matches: bool,
+    // Maximum number of amplicons extracted from a sequence
+    pub max_amplicons: Option<usize>,
+    // Smallest identity, 1 - distance / primer length, of the primer hits,
+    // replacing the mismatch count of the run
+    pub min_primer_identity: Option<f64>,
+    // Longest amplicon, primers included, a primer pair may amplify
+    pub max_amplicon_length: Option<usize>,
+    // Most bases between a forward hit and the reverse hit it is paired
+    // with
+    pub max_pair_distance: Option<usize>,
+    // Search records in overlapping windows of this many bases
+    pub chunk_size: Option<usize>,
+    // Skip records longer than this many bases
+    pub max_record_len: Option<usize>,
+    // Give up the primer search of a record after this time
+    pub record_timeout: Option<Duration>,
+    // Log the progress of the run periodically
+    pub progress: bool,
+    // Extract the primer-dimer-like amplicons instead of leaving them out
+    pub keep_primer_dimers: bool,
+    // Mismatches added to a primer missing while its mate is found, to
+    // search it again where the typical amplicons of the pair put it
+    pub rescue: Option<u8>,
+    // Handling of overlapping regions of a sequence
+    pub overlaps: Overlaps,
+    // Primers bounding a region, a single one for truncated sequences
+    pub anchor: Anchor,
+    // Handling of the padding characters of the records
+    pub sanitize: Sanitize,
+    // Bases from the start of a record telling its alphabet, all of them
+    // when 0
+    pub alphabet_sample: usize,
+    // Handling of the records of an unknown alphabet
+    pub mixed_alphabet: MixedAlphabet,
+    // Id of the records written in the outputs, parsed from their header
+    pub id_parser: IdParser,
+    // Only process the records whose header passes this filter
+    pub header_filter: Option<HeaderFilter>,
+    // Only process each record with this probability, drawn from the seed
+    pub subsample: Option<f64>,
+    // Seed of the random draws of the run
+    pub seed: Option<u64>,
+    // Only keep primer hits within this many bases of the record ends
+    pub anchored: Option<usize>,
+    // Reuse the primer hits of identical sequences
+    pub cache_hits: bool,
+    // Search primers on both strands of the sequences
+    pub both_strands: bool,
+    // Write regions of the minus strand as read on the sequence rather than
+    // in the orientation of their gene
+    pub keep_strand: bool,
+    // Tag regions with their marker gene, when screening several genes
+    pub tag_genes: bool,
+    // Search each sequence with the primers of its classified marker gene
+    pub auto_gene: bool,
+    pub gff: GffLabels,
+    // Coordinate convention of the TSV outputs, the GFF3 output being
+    // always 1-based
+    pub coords: Coords,
+    // Match primers on collapsed homopolymer runs
+    pub homopolymer_tolerant: bool,
+    // Score primer hits by the share of the degenerate primer mix matching
+    // them
+    pub degenerate_score: bool,
+    // Leave the primer-binding sites out of the extracted regions
+    pub trim_primers: bool,
+    // Tell where the primer sites lie in the extracted regions
+    pub primer_offsets: bool,
+    // Merge the read pairs of interleaved inputs before searching them
+    pub interleaved: bool,
+    // Reverse reads of the input, merged with its reads
+    pub mates: Option<String>,
+    // Shortest overlap of merged mates
+    pub min_overlap: Option<usize>,
+    // Skip the FASTA and GFF3 outputs
+    pub no_fasta: bool,
+    pub no_gff: bool,
+    // Flush the outputs every this many records
+    pub flush_every: Option<usize>,
+    // Sync the outputs to disk at each flush
+    pub fsync: bool,
+    // Append to existing outputs instead of overwriting them
+    pub append: bool,
+    // Lineages of the input sequences, for per-taxon statistics
+    pub taxonomy: Taxonomy,
+    // Settings of the primer pairs overriding those of the run
+    pub pair_options: PairOptionsMap,
+    // Set, e.g. by a signal handler, to stop the run before the next record,
+    // the outputs being finalized as those of a partial run
+    pub interrupt: Option<Arc<AtomicBool>>,
+    // Hashed ids of the records in the outputs appended to, alone and with
+    // each region extracted from them, read from the outputs when not given
+    pub seen_ids: Option<Arc<HashSet<u64>>>,
+    // Statistics rewritten during the run, shared by its inputs
+    pub live_stats: Option<Arc<LiveStats>>,
+    // Reference whose primer sites are transferred to the sequences by
+    // alignment instead of searching the primers
+    pub reference: Option<Arc<Reference>>,
+    // Columns of the E. coli positions in the SILVA alignment, to add the
+    // E. coli and SILVA boundaries of the regions to the FASTA headers
+    pub silva: Option<Arc<SilvaColumns>>,
+    // Threads decompressing the blocks of a BGZF input, e.g. a .fa.bgz
+    // reference, one or none reading it as any gzip file
+    pub decompress_threads: usize,
+}
 
-    #[test]
-    fn test_primers_to_region_ok() {
-        assert_eq!(
-            primers_to_region(vec![
-                "CCTACGGGNGGCWGCAG".to_string(),
-                "GTGCCAGCMGCCGCGGTAA".to_string()
-            ]),
-            "v3v4".to_string()
-        );
+impl Config {
+    /// Primer search following the matching settings. Sequences being
+    /// searched as read, for the regions of both strands, the C of the
+    /// primers and of their reverse complements also match T on
+    /// bisulfite-converted sequences.
+    pub fn matcher(&self) -> Matcher {
+        if self.bisulfite {
+            Matcher::new(&self.ambigs.bisulfite(), self.no_indels)
+        } else {
+            Matcher::new(&self.ambigs, self.no_indels)
+        }
     }
 
-    #[test]
-    fn test_primers_to_region_ok2() {
-        assert_eq!(
-            primers_to_region(vec![
-                "GTGCCAGCMGCCGCGGTAA".to_string(),
-                "GTGCCAGCMGCCGCGGTAA".to_string()
-            ]),
-            "v4".to_string()
-        );
+    /// Mismatches allowed to the primers of a pair
+    pub fn pair_mismatch(&self, primer_pair: &[String]) -> u8 {
+        self.pair_options
+            .get(primer_pair)
+            .and_then(|options| options.mismatch)
+            .unwrap_or(self.mismatch)
     }
 
-    #[test]
-    fn test_primers_to_region_empty() {
-        assert_eq!(
-            primers_to_region(vec!["ZZZZZ".to_string(), "AAAAAA".to_string()]),
-            "".to_string()
-        );
+    /// Mismatches allowed to `primer` of a pair, those of the pair or, with
+    /// a minimum primer identity, the most keeping the hits of the primer
+    /// at least that identical to it
+    pub fn primer_mismatch(&self, primer_pair: &[String], primer: &str) -> u8 {
+        let own = self
+            .pair_options
+            .get(primer_pair)
+            .and_then(|options| options.mismatch);
+        match (own, self.min_primer_identity) {
+            (None, Some(identity)) => {
+                // Rounding errors must not cost a mismatch
+                let mismatch =
+                    ((1.0 - identity) * primer.len() as f64 + 1e-9).floor();
+                mismatch.min(u8::MAX as f64) as u8
+            }
+            _ => self.pair_mismatch(primer_pair),
+        }
     }
 
-    #[test]
-    fn test_complement_dna() {
-        assert_eq!(
-            to_complement("ATCGATCGATCGATCGRYKBVDHX", "dna"),
-            String::from("TAGCTAGCTAGCTAGCYRMVBHDX")
-        );
+    /// Lengths, primers included, of the amplicons of a pair
+    pub fn amplicon_lengths(
+        &self,
+        primer_pair: &[String],
+    ) -> RangeInclusive<usize> {
+        let options = self.pair_options.get(primer_pair);
+        let min = options.and_then(|options| options.min_length);
+        let max = options
+            .and_then(|options| options.max_length)
+            .or(self.max_amplicon_length);
+        min.unwrap_or(0)..=max.unwrap_or(usize::MAX)
     }
 
-    #[test]
-    fn test_complement_rna() {
-        assert_eq!(
-            to_complement("AUCGAUCGAUCGAUCGRYKBVDHMXN", "rna"),
-            String::from("UAGCUAGCUAGCUAGCYRMVBHDKXN")
-        );
+    /// Region name of a pair, its label or the name of its primers
+    pub fn region_name(&self, primer_pair: &[String]) -> String {
+        self.pair_options
+            .get(primer_pair)
+            .and_then(|options| options.label.clone())
+            .unwrap_or_else(|| primers_to_region(primer_pair.to_vec()))
     }
+}
 
-    #[test]
+/// Settings of a primer pair overriding those of the run, from the
+/// optional columns of a primers file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PairOptions {
+    pub mismatch: Option<u8>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    // Region name of the pair
+    pub label: Option<String>,
+}
+
+/// Settings of the primer pairs having their own
+pub type PairOptionsMap = HashMap<Vec<String>, PairOptions>;
+
+/// Callback invoked with each extracted region and its source record
+pub type OnMatch<'a> = dyn FnMut(&RegionMatch, &fasta::Record) + Send + 'a;
+
+/// Optional outputs kept in memory until all files are processed
+#[derive(Default)]
+pub struct ExtraOutputs {
+    pub bam: Option<BamWriter>,
+    pub plot: Option<RegionPlot>,
+    pub tbl: Option<FeatureTable>,
+    pub track: Option<VariabilityTrack>,
+    // Sinks of the caller, sent the regions and records of every input
+    pub sinks: Sinks,
+}
+
+impl ExtraOutputs {
+    /// Empty outputs of the same kinds, collecting the results of one file
+    pub fn empty_like(&self) -> Self {
+        ExtraOutputs {
+            bam: self.bam.as_ref().map(|_| BamWriter::new("")),
+            plot: self.plot.as_ref().map(|_| RegionPlot::new("")),
+            tbl: self.tbl.as_ref().map(|_| FeatureTable::new("")),
+            track: self
+                .track
+                .as_ref()
+                .map(|track| VariabilityTrack::new("", track.window())),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Append the results collected by other outputs
+    pub fn merge(&mut self, other: ExtraOutputs) -> anyhow::Result<()> {
+        if let (Some(bam), Some(other)) = (self.bam.as_mut(), other.bam) {
+            bam.merge(other).with_context(|| "Cannot write BAM file")?;
+        }
+        if let (Some(plot), Some(other)) = (self.plot.as_mut(), other.plot) {
+            plot.merge(other);
+        }
+        if let (Some(tbl), Some(other)) = (self.tbl.as_mut(), other.tbl) {
+            tbl.merge(other);
+        }
+        if let (Some(track), Some(other)) = (self.track.as_mut(), other.track) {
+            track.merge(other);
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        if let Some(bam) = self.bam {
+            bam.finish().with_context(|| "Cannot write BAM file")?;
+        }
+        if let Some(plot) = self.plot {
+            plot.finish().with_context(|| "Cannot write region plot")?;
+        }
+        if let Some(tbl) = self.tbl {
+            tbl.finish().with_context(|| "Cannot write feature table")?;
+        }
+        if let Some(track) = self.track {
+            track
+                .finish()
+                .with_context(|| "Cannot write variability track")?;
+        }
+        Ok(())
+    }
+}
+
+/// Handling of the overlapping regions extracted from a sequence
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Overlaps {
+    #[default]
+    KeepAll,
+    // Only the longest of overlapping regions is kept
+    KeepLongest,
+}
+
+impl FromStr for Overlaps {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "keep-all" => Ok(Overlaps::KeepAll),
+            "keep-longest" => Ok(Overlaps::KeepLongest),
+            _ => Err(anyhow!(
+                "Unknown overlap handling {}, expected keep-all or keep-longest",
+                s
+            )),
+        }
+    }
+}
+
+/// Primers a region has to be bound by. A region anchored on a single
+/// primer, whose mate is missing from a truncated sequence, extends to the
+/// sequence end
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Anchor {
+    #[default]
+    Both,
+    Forward,
+    Reverse,
+}
+
+impl FromStr for Anchor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "both" => Ok(Anchor::Both),
+            "forward" => Ok(Anchor::Forward),
+            "reverse" => Ok(Anchor::Reverse),
+            _ => Err(anyhow!(
+                "Unknown anchor {}, expected forward, reverse or both",
+                s
+            )),
+        }
+    }
+}
+
+/// Handling of the `-` and `*` padding characters of input records, such
+/// as alignment gaps and translation stops, that no alphabet accepts
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Sanitize {
+    // Records with padding are skipped as of an unknown alphabet
+    #[default]
+    Error,
+    // Padding is removed, shifting the positions of the following bases
+    Strip,
+    // Padding is replaced by N, keeping positions
+    ToN,
+}
+
+impl Sanitize {
+    /// Sequence with its padding handled, `None` when it is left unchanged
+    pub fn apply(self, seq: &[u8]) -> Option<Vec<u8>> {
+        if !seq.iter().any(is_padding) {
+            return None;
+        }
+        match self {
+            Sanitize::Error => None,
+            Sanitize::Strip => {
+                Some(seq.iter().filter(|b| !is_padding(b)).copied().collect())
+            }
+            Sanitize::ToN => Some(
+                seq.iter()
+                    .map(|b| if is_padding(b) { b'N' } else { *b })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl FromStr for Sanitize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "error" => Ok(Sanitize::Error),
+            "strip" => Ok(Sanitize::Strip),
+            "to-N" => Ok(Sanitize::ToN),
+            _ => Err(anyhow!(
+                "Unknown sanitization {}, expected strip, to-N or error",
+                s
+            )),
+        }
+    }
+}
+
+fn is_padding(base: &u8) -> bool {
+    matches!(base, b'-' | b'*')
+}
+
+// Databases of NCBI FASTA header ids, e.g. gi|42|ref|NR_000001.1|, whose
+// accession follows their tag
+const NCBI_DATABASES: &[&str] = &[
+    "ref", "gb", "emb", "dbj", "tpg", "tpe", "tpd", "sp", "tr", "lcl",
+];
+
+/// Parsing of the record ids written in the outputs from the headers of
+/// the input records
+#[derive(Debug, Clone, Default)]
+pub enum IdParser {
+    // First word of the header, as is
+    #[default]
+    FirstWord,
+    // Accession of NCBI headers, e.g. NR_000001.1 of gi|42|ref|NR_000001.1|
+    Ncbi,
+    // First capture group, or whole match, of a regex on the header
+    Regex(Regex),
+}
+
+impl IdParser {
+    /// Id parsed from the header of a record, `None` when it is the first
+    /// word or when the header does not match
+    pub fn parse(&self, id: &str, desc: Option<&str>) -> Option<String> {
+        match self {
+            IdParser::FirstWord => None,
+            IdParser::Ncbi => {
+                let fields = id.split('|').collect::<Vec<_>>();
+                fields
+                    .windows(2)
+                    .find(|pair| {
+                        NCBI_DATABASES.contains(&pair[0]) && !pair[1].is_empty()
+                    })
+                    .map(|pair| pair[1].to_string())
+            }
+            IdParser::Regex(re) => {
+                let header = header(id, desc);
+                let captures = re.captures(&header)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|id| id.as_str().to_string())
+                    .filter(|id| !id.is_empty())
+            }
+        }
+    }
+}
+
+impl FromStr for IdParser {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "first-word" => Ok(IdParser::FirstWord),
+            "ncbi" => Ok(IdParser::Ncbi),
+            _ => match s.strip_prefix("regex:") {
+                Some(re) => Regex::new(re)
+                    .map(IdParser::Regex)
+                    .with_context(|| format!("Invalid id regex {}", re)),
+                None => Err(anyhow!(
+                    "Unknown id parser {}, expected ncbi, first-word or \
+                    regex:<re>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Filter of the records by their header, e.g. on a taxon name, to process
+/// part of a large reference file
+#[derive(Debug, Clone)]
+pub enum HeaderFilter {
+    // Headers containing this text
+    Substring(String),
+    // Headers matching a regex
+    Regex(Regex),
+}
+
+impl HeaderFilter {
+    /// Does the header of a record pass the filter?
+    pub fn matches(&self, id: &str, desc: Option<&str>) -> bool {
+        let header = header(id, desc);
+        match self {
+            HeaderFilter::Substring(text) => header.contains(text.as_str()),
+            HeaderFilter::Regex(re) => re.is_match(&header),
+        }
+    }
+}
+
+impl FromStr for HeaderFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.strip_prefix("regex:") {
+            Some(re) => Regex::new(re)
+                .map(HeaderFilter::Regex)
+                .with_context(|| format!("Invalid header regex {}", re)),
+            None => Ok(HeaderFilter::Substring(s.to_string())),
+        }
+    }
+}
+
+// Whole header of a record, its id and description
+fn header(id: &str, desc: Option<&str>) -> String {
+    match desc {
+        Some(desc) => format!("{} {}", id, desc),
+        None => id.to_string(),
+    }
+}
+
+/// Source and feature type columns of the GFF3 records
+#[derive(Debug, Clone, PartialEq)]
+pub struct GffLabels {
+    pub source: String,
+    pub feature_type: String,
+}
+
+impl Default for GffLabels {
+    fn default() -> Self {
+        GffLabels {
+            source: "hyperex".to_string(),
+            feature_type: "region".to_string(),
+        }
+    }
+}
+
+impl GffLabels {
+    /// Build the labels, rejecting values which would break GFF3 columns
+    pub fn new(source: &str, feature_type: &str) -> anyhow::Result<Self> {
+        for (option, value) in
+            [("--gff-source", source), ("--gff-type", feature_type)]
+        {
+            if value.is_empty() || value.contains(['\t', '\n', '\r']) {
+                return Err(anyhow!(
+                    "{} must be a non-empty value without tabs or newlines",
+                    option
+                ));
+            }
+        }
+
+        Ok(GffLabels {
+            source: source.to_string(),
+            feature_type: feature_type.to_string(),
+        })
+    }
+}
+
+/// Completeness of a 16S sequence judged from its terminal primers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Completeness {
+    // Both 27F and 1492R are found
+    FullLength,
+    // Only one of them is found
+    Partial,
+    // None of them is found
+    Fragment,
+}
+
+impl fmt::Display for Completeness {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Completeness::FullLength => write!(f, "full-length"),
+            Completeness::Partial => write!(f, "partial"),
+            Completeness::Fragment => write!(f, "fragment"),
+        }
+    }
+}
+
+/// Extraction counts of a run, counts missing from reports of older
+/// versions being read as zero
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunSummary {
+    pub sequences: usize,
+    pub extracted: usize,
+    // Amplicons left out by the per-sequence cap
+    pub suppressed: usize,
+    // Sequences reusing the primer hits of an identical one
+    pub cached: usize,
+    // Pairings of primer hits left out as not on one strand
+    pub inconsistent: usize,
+    // Extracted regions flagged as possible chimeras
+    pub chimera_suspects: usize,
+    // Read pairs of paired inputs and how many of them were merged
+    pub read_pairs: usize,
+    pub merged_pairs: usize,
+    // Records skipped by the size guard and the per-record time budget
+    pub too_long: usize,
+    pub timed_out: usize,
+    // Records whose padding characters were stripped or masked
+    pub sanitized: usize,
+    // Records, or regions, skipped as already in the outputs appended to
+    pub duplicates: usize,
+    // Records whose header passed --header-filter and those left out
+    pub filtered_in: usize,
+    pub filtered_out: usize,
+    // Records left out by --subsample
+    pub sampled_out: usize,
+    // Amplicons left out as overlapping a longer one
+    pub overlapping: usize,
+    // Primer-dimer-like amplicons, left out unless kept
+    pub primer_dimers: usize,
+    // Extracted regions whose missing primer was found by --rescue
+    pub rescued: usize,
+    // Regions named after a single primer, the other one missing from the
+    // region map
+    pub half_named: usize,
+    // Sequences by classified marker gene
+    pub genes: BTreeMap<String, usize>,
+    // Sequences by completeness
+    pub full_length: usize,
+    pub partial: usize,
+    pub fragment: usize,
+    // Extracted regions by region name
+    pub regions: BTreeMap<String, usize>,
+    // Number of extracted regions by region name and length
+    pub lengths: BTreeMap<String, BTreeMap<usize, usize>>,
+    // Counts of every taxon of the sequences with a known lineage
+    pub taxa: BTreeMap<String, TaxonCounts>,
+    // Mismatch profile of each primer, by primer sequence
+    pub primers: BTreeMap<String, PrimerProfile>,
+    // Inputs left unfinished by an interrupted run
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+/// Records of an input handled by an interrupted run, to resume it from the
+/// next record
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub file: String,
+    pub records: usize,
+    // Identifier of the last record handled, if any
+    pub last_id: Option<String>,
+}
+
+/// Primer hits, as aligned for extraction, and how many of them mismatch at
+/// each primer position from the 5' end
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrimerProfile {
+    pub hits: usize,
+    pub mismatches: Vec<usize>,
+}
+
+impl PrimerProfile {
+    pub fn merge(&mut self, other: &PrimerProfile) {
+        self.hits += other.hits;
+        if self.mismatches.len() < other.mismatches.len() {
+            self.mismatches.resize(other.mismatches.len(), 0);
+        }
+        for (count, other) in self.mismatches.iter_mut().zip(&other.mismatches)
+        {
+            *count += other;
+        }
+    }
+}
+
+// Width in bp of the region length histogram bins
+const LENGTH_BIN: usize = 10;
+// Sparkline levels, from the least to the most filled bin
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// Primer bases making its 3' end, where mismatches hinder extension most
+const THREE_PRIME_END: usize = 5;
+
+impl RunSummary {
+    pub fn add_match(&mut self, region_match: &RegionMatch) {
+        self.extracted += 1;
+        *self.regions.entry(region_match.name()).or_insert(0) += 1;
+        let length = region_match.end - region_match.start;
+        *self
+            .lengths
+            .entry(region_match.name())
+            .or_default()
+            .entry(length)
+            .or_insert(0) += 1;
+    }
+
+    pub fn add_sequence(&mut self, completeness: Completeness) {
+        self.sequences += 1;
+        match completeness {
+            Completeness::FullLength => self.full_length += 1,
+            Completeness::Partial => self.partial += 1,
+            Completeness::Fragment => self.fragment += 1,
+        }
+    }
+
+    /// Count a sequence of known lineage with the names of the regions
+    /// extracted from it, and the primer hits of these regions as (primer,
+    /// distance), in all the taxa of the lineage
+    pub fn add_taxon(
+        &mut self,
+        lineage: &str,
+        regions: &[String],
+        hits: &[(String, u8)],
+    ) {
+        let mut regions = regions.to_vec();
+        regions.sort();
+        regions.dedup();
+        for taxon in taxonomy::taxa(lineage) {
+            let counts = self.taxa.entry(taxon).or_default();
+            counts.sequences += 1;
+            for region in regions.iter() {
+                *counts.regions.entry(region.clone()).or_insert(0) += 1;
+            }
+            for (primer, distance) in hits.iter() {
+                *counts.primer_hits.entry(primer.clone()).or_insert(0) += 1;
+                *counts.primer_mismatches.entry(primer.clone()).or_insert(0) +=
+                    *distance as usize;
+            }
+        }
+    }
+
+    /// Count the mismatched primer positions of the alignment operations
+    /// (=, X, I, D) of a hit. Reverse primers are aligned reverse
+    /// complemented and sequence bases missing from the primer count
+    /// against the following primer position.
+    pub fn add_primer_hit(&mut self, primer: &str, ops: &[u8], reverse: bool) {
+        let len = primer.len();
+        let profile = self.primers.entry(primer.to_string()).or_default();
+        profile.mismatches.resize(len, 0);
+        profile.hits += 1;
+        let mut mismatched = vec![false; len];
+        let mut position = 0;
+        for &op in ops {
+            if op != b'=' && len > 0 {
+                mismatched[position.min(len - 1)] = true;
+            }
+            if op != b'D' {
+                position += 1;
+            }
+        }
+        for (i, _) in mismatched.iter().enumerate().filter(|(_, &m)| m) {
+            profile.mismatches[if reverse { len - 1 - i } else { i }] += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &RunSummary) {
+        self.sequences += other.sequences;
+        self.extracted += other.extracted;
+        self.suppressed += other.suppressed;
+        self.cached += other.cached;
+        self.inconsistent += other.inconsistent;
+        self.chimera_suspects += other.chimera_suspects;
+        self.read_pairs += other.read_pairs;
+        self.merged_pairs += other.merged_pairs;
+        self.too_long += other.too_long;
+        self.timed_out += other.timed_out;
+        self.sanitized += other.sanitized;
+        self.duplicates += other.duplicates;
+        self.filtered_in += other.filtered_in;
+        self.filtered_out += other.filtered_out;
+        self.sampled_out += other.sampled_out;
+        self.overlapping += other.overlapping;
+        self.primer_dimers += other.primer_dimers;
+        self.rescued += other.rescued;
+        self.half_named += other.half_named;
+        self.full_length += other.full_length;
+        self.partial += other.partial;
+        self.fragment += other.fragment;
+        for (gene, count) in other.genes.iter() {
+            *self.genes.entry(gene.clone()).or_insert(0) += count;
+        }
+        for (region, count) in other.regions.iter() {
+            *self.regions.entry(region.clone()).or_insert(0) += count;
+        }
+        for (region, lengths) in other.lengths.iter() {
+            let merged = self.lengths.entry(region.clone()).or_default();
+            for (length, count) in lengths.iter() {
+                *merged.entry(*length).or_insert(0) += count;
+            }
+        }
+        for (taxon, counts) in other.taxa.iter() {
+            self.taxa.entry(taxon.clone()).or_default().merge(counts);
+        }
+        for (primer, profile) in other.primers.iter() {
+            self.primers
+                .entry(primer.clone())
+                .or_default()
+                .merge(profile);
+        }
+        self.checkpoints.extend(other.checkpoints.iter().cloned());
+    }
+
+    /// Length histogram of each region as the first length and count of
+    /// consecutive bins, empty bins included, from the shortest to the
+    /// longest region
+    pub fn length_histograms(&self) -> Vec<(&str, Vec<(usize, usize)>)> {
+        self.lengths
+            .iter()
+            .filter_map(|(region, lengths)| {
+                let first = lengths.keys().next()? / LENGTH_BIN;
+                let last = lengths.keys().next_back()? / LENGTH_BIN;
+                let mut bins = (first..=last)
+                    .map(|bin| (bin * LENGTH_BIN, 0))
+                    .collect::<Vec<_>>();
+                for (length, count) in lengths.iter() {
+                    bins[length / LENGTH_BIN - first].1 += count;
+                }
+                Some((region.as_str(), bins))
+            })
+            .collect()
+    }
+
+    /// Write the region length histograms as TSV
+    pub fn write_lengths(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        writeln!(writer, "region\tmin_length\tmax_length\tcount")?;
+        for (region, bins) in self.length_histograms() {
+            for (start, count) in bins {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}",
+                    region,
+                    start,
+                    start + LENGTH_BIN - 1,
+                    count
+                )?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Write the extraction success rate of each region in each taxon as
+    /// TSV, the depth being the rank of the taxon in its lineage
+    pub fn write_taxa(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "taxon\tdepth\tregion\tsequences\textracted\tsuccess_rate"
+        )?;
+        let regions = self
+            .taxa
+            .values()
+            .flat_map(|counts| counts.regions.keys())
+            .collect::<BTreeSet<_>>();
+        for (taxon, counts) in self.taxa.iter() {
+            for region in regions.iter() {
+                let extracted =
+                    counts.regions.get(region.as_str()).copied().unwrap_or(0);
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{:.4}",
+                    taxon,
+                    taxon.split(';').count(),
+                    region,
+                    counts.sequences,
+                    extracted,
+                    extracted as f64 / counts.sequences as f64
+                )?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Write the statistics of each taxon in long format, one measure per
+    /// row: the extraction rate of each region and the mean distance of the
+    /// hits of each primer, over the extracted regions, for primer bias
+    /// analyses by rank
+    pub fn write_ranks(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        writeln!(writer, "rank\ttaxon\tsequences\tmeasure\tname\tvalue")?;
+        let regions = self
+            .taxa
+            .values()
+            .flat_map(|counts| counts.regions.keys())
+            .collect::<BTreeSet<_>>();
+        for (taxon, counts) in self.taxa.iter() {
+            let rank = taxonomy::rank(taxon);
+            let name = taxon.rsplit(';').next().unwrap_or(taxon);
+            for region in regions.iter() {
+                let extracted =
+                    counts.regions.get(region.as_str()).copied().unwrap_or(0);
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\textraction_rate\t{}\t{:.4}",
+                    rank,
+                    name,
+                    counts.sequences,
+                    region,
+                    extracted as f64 / counts.sequences as f64
+                )?;
+            }
+            for (primer, hits) in counts.primer_hits.iter() {
+                let mismatches =
+                    counts.primer_mismatches.get(primer).copied().unwrap_or(0);
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\tmean_mismatches\t{}\t{:.4}",
+                    rank,
+                    name,
+                    counts.sequences,
+                    primer,
+                    mismatches as f64 / *hits as f64
+                )?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Write the mismatch frequency of each primer position as TSV,
+    /// positions being numbered from the 5' end and flagged when part of
+    /// the 3' end
+    pub fn write_mismatches(&self, path: &str) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "primer\tposition\tbase\thits\tmismatches\tfrequency\tthree_prime"
+        )?;
+        for (primer, profile) in self.primers.iter() {
+            for (i, (base, count)) in
+                primer.chars().zip(profile.mismatches.iter()).enumerate()
+            {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{:.4}\t{}",
+                    primer,
+                    i + 1,
+                    base,
+                    profile.hits,
+                    count,
+                    *count as f64 / profile.hits.max(1) as f64,
+                    if i + THREE_PRIME_END >= primer.len() {
+                        "yes"
+                    } else {
+                        "no"
+                    }
+                )?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Warn about primers mismatching at their 3' end
+    pub fn log_three_prime_mismatches(&self, label: &str) {
+        for (primer, profile) in self.primers.iter() {
+            let three_prime = profile
+                .mismatches
+                .iter()
+                .enumerate()
+                .skip(primer.len().saturating_sub(THREE_PRIME_END))
+                .max_by_key(|&(_, count)| *count);
+            if let Some((i, &count)) = three_prime.filter(|(_, c)| **c > 0) {
+                warn!(
+                    "{}: primer {} mismatches at position {} of its 3' end in {:.1}% of hits",
+                    label,
+                    primer,
+                    i + 1,
+                    count as f64 * 100.0 / profile.hits as f64
+                );
+            }
+        }
+    }
+
+    /// Log the region length histograms as sparklines
+    pub fn log_sparklines(&self, label: &str) {
+        for (region, bins) in self.length_histograms() {
+            let max = bins.iter().map(|&(_, count)| count).max().unwrap_or(0);
+            let sparkline = bins
+                .iter()
+                .map(|&(_, count)| match count {
+                    0 => ' ',
+                    _ => SPARKS[(count * SPARKS.len() - 1) / max.max(1)],
+                })
+                .collect::<String>();
+            let first = bins.first().map_or(0, |&(start, _)| start);
+            let last = bins.last().map_or(0, |&(start, _)| start);
+            info!(
+                "{}: {} lengths {}-{} bp {}",
+                label,
+                region,
+                first,
+                last + LENGTH_BIN - 1,
+                sparkline
+            );
+        }
+    }
+
+    /// Single line outcome of the run for scripts, e.g. `OK sequences=2
+    /// extracted=2 regions=v3v4:1,v4:1 warnings=0`, the warnings counting
+    /// the amplicons, hits and records behind the issues of [`warnings`]
+    ///
+    /// [`warnings`]: RunSummary::warnings
+    pub fn porcelain(&self) -> String {
+        let regions = self
+            .regions
+            .iter()
+            .map(|(region, count)| format!("{}:{}", region, count))
+            .collect::<Vec<_>>();
+        format!(
+            "{} sequences={} extracted={} regions={} warnings={}",
+            if self.checkpoints.is_empty() {
+                "OK"
+            } else {
+                "INTERRUPTED"
+            },
+            self.sequences,
+            self.extracted,
+            regions.join(","),
+            self.suppressed
+                + self.inconsistent
+                + self.too_long
+                + self.timed_out
+                + self.half_named
+                + self.duplicates
+                + self.chimera_suspects
+        )
+    }
+
+    /// Issues met by the run, worth a look before using its results
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.suppressed > 0 {
+            warnings.push(format!(
+                "{} amplicons suppressed by --max-amplicons-per-seq",
+                self.suppressed
+            ));
+        }
+        if self.inconsistent > 0 {
+            warnings.push(format!(
+                "{} primer hit pairings rejected as not on the same strand or not facing each other",
+                self.inconsistent
+            ));
+        }
+        if self.too_long + self.timed_out > 0 {
+            warnings.push(format!(
+                "{} records skipped by --max-record-len and {} by --record-timeout",
+                self.too_long, self.timed_out
+            ));
+        }
+        if self.half_named > 0 {
+            warnings.push(format!(
+                "{} regions named after a single primer, e.g. v3-?, the other one is not a built-in primer",
+                self.half_named
+            ));
+        }
+        if self.duplicates > 0 {
+            warnings.push(format!(
+                "{} records or regions skipped as already in the outputs appended to",
+                self.duplicates
+            ));
+        }
+        if self.chimera_suspects > 0 {
+            warnings.push(format!(
+                "{} regions flagged chimera_suspect, their length is discordant with the E. coli positions of their primers",
+                self.chimera_suspects
+            ));
+        }
+        for checkpoint in self.checkpoints.iter() {
+            warnings.push(format!(
+                "run interrupted, outputs only cover the first {} records of {}{}",
+                checkpoint.records,
+                checkpoint.file,
+                match &checkpoint.last_id {
+                    Some(id) => format!(", up to {}", id),
+                    None => String::new(),
+                }
+            ));
+        }
+        warnings
+    }
+
+    /// Log the summary, `label` names the summarized input
+    pub fn log(&self, label: &str) {
+        info!(
+            "{}: {} sequences processed, {} regions extracted",
+            label, self.sequences, self.extracted
+        );
+        info!(
+            "{}: {} full-length, {} partial and {} fragment sequences",
+            label, self.full_length, self.partial, self.fragment
+        );
+        if self.filtered_in + self.filtered_out > 0 {
+            info!(
+                "{}: {} records passed --header-filter and {} were left out",
+                label, self.filtered_in, self.filtered_out
+            );
+        }
+        if self.sampled_out > 0 {
+            info!(
+                "{}: {} records left out by --subsample",
+                label, self.sampled_out
+            );
+        }
+        if self.sanitized > 0 {
+            info!(
+                "{}: {} records sanitized by --sanitize",
+                label, self.sanitized
+            );
+        }
+        if self.overlapping > 0 {
+            info!(
+                "{}: {} amplicons left out by --dedup-overlaps as overlapping a longer region",
+                label, self.overlapping
+            );
+        }
+        if self.primer_dimers > 0 {
+            info!(
+                "{}: {} primer-dimer-like amplicons, shorter than their primers",
+                label, self.primer_dimers
+            );
+        }
+        if self.rescued > 0 {
+            info!(
+                "{}: {} regions rescued by searching their missing primer with more mismatches",
+                label, self.rescued
+            );
+        }
+        for warning in self.warnings() {
+            warn!("{}: {}", label, warning);
+        }
+        if self.read_pairs > 0 {
+            info!(
+                "{}: {} of {} read pairs merged ({:.1}%)",
+                label,
+                self.merged_pairs,
+                self.read_pairs,
+                self.merged_pairs as f64 * 100.0 / self.read_pairs as f64
+            );
+        }
+        for (gene, count) in self.genes.iter() {
+            info!("{}: {} {} sequences", label, count, gene);
+        }
+        if self.cached > 0 {
+            info!(
+                "{}: {} sequences ({:.1}%) reused the primer hits of an identical sequence",
+                label,
+                self.cached,
+                self.cached as f64 * 100.0 / self.sequences as f64
+            );
+        }
+        for (region, count) in self.regions.iter() {
+            info!("{}: {} {}", label, region, count);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Alphabet {
+    Dna,
+    Rna,
+}
+
+pub fn sequence_type(sequence: &str) -> Option<Alphabet> {
+    let valid_dna_iupac = "ACGTRYSWKMBDHVN";
+    let valid_rna_iupac = "ACGURYSWKMBDHVN";
+
+    if sequence.chars().all(|x| valid_dna_iupac.contains(x)) {
+        Some(Alphabet::Dna)
+    } else if sequence.chars().all(|x| valid_rna_iupac.contains(x)) {
+        Some(Alphabet::Rna)
+    } else {
+        None
+    }
+}
+
+/// Alphabet of a sequence from its first `sample` bases, or from all of
+/// them when `sample` is 0, so that long genomes are not scanned in full
+pub fn sampled_sequence_type(seq: &[u8], sample: usize) -> Option<Alphabet> {
+    let end = match sample {
+        0 => seq.len(),
+        sample => sample.min(seq.len()),
+    };
+    std::str::from_utf8(&seq[..end])
+        .ok()
+        .and_then(sequence_type)
+}
+
+/// Handling of the records whose bases are neither all DNA nor all RNA,
+/// e.g. mixing T and U or holding other symbols
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MixedAlphabet {
+    // Records are skipped as of an unknown alphabet
+    #[default]
+    Skip,
+    // Records are searched as DNA
+    Dna,
+    // The run stops at the first such record
+    Error,
+}
+
+impl FromStr for MixedAlphabet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "skip" => Ok(MixedAlphabet::Skip),
+            "dna" => Ok(MixedAlphabet::Dna),
+            "error" => Ok(MixedAlphabet::Error),
+            _ => Err(anyhow!(
+                "Unknown mixed alphabet handling {}, expected skip, dna or \
+                error",
+                s
+            )),
+        }
+    }
+}
+
+/// Header of the GFF3 output, with the version of the built-in primer
+/// database as a `#!` directive
+pub const GFF_HEADER: &str = concat!(
+    "##gff-version 3\n#!primer-db-version ",
+    primer_db_version!(),
+    "\n"
+);
+/// Header of the per-record TSV output
+pub const RECORDS_TSV_HEADER: &str = "id\tlength\tcompleteness\tregions\n";
+/// Header of the per-hit TSV output
+pub const HITS_TSV_HEADER: &str =
+    "id\tregion\tprimer\trole\tstart\tend\tdistance\tcigar\tmatched\n";
+/// Header of the TSV output of the records, or regions of a record, that
+/// were skipped or not found, with the reason
+pub const SKIPPED_TSV_HEADER: &str = "id\tregion\treason\tdetail\n";
+/// Header of the TSV output comparing the copies of a region on a genome
+pub const OPERONS_TSV_HEADER: &str =
+    "id\tregion\toperon\tother_operon\tidentity\n";
+
+// Amplicon as (forward hit, reverse hit, strand)
+pub(crate) type Amplicon = (Hit, Hit, Strand);
+
+/// Split a text of `len` bases in windows of `size` bases, consecutive
+/// windows sharing `overlap` bases so that hits up to `overlap` bases long
+/// are entirely found in one of them
+pub fn chunk_windows(
+    len: usize,
+    size: usize,
+    overlap: usize,
+) -> Vec<(usize, usize)> {
+    let size = size.max(overlap + 1);
+    let step = size - overlap;
+    let mut windows = Vec::new();
+    let mut start = 0;
+
+    while start + size < len {
+        windows.push((start, start + size));
+        start += step;
+    }
+    windows.push((start, len));
+
+    windows
+}
+
+/// Windows of the first and last `span` bases of a text of `len` bases,
+/// the whole text when they overlap
+pub fn terminal_windows(len: usize, span: usize) -> Vec<(usize, usize)> {
+    if 2 * span >= len {
+        vec![(0, len)]
+    } else {
+        vec![(0, span), (len - span, len)]
+    }
+}
+
+/// Every non-overlapping hit of `pattern` in `text` as (start, exclusive
+/// end, distance), following the matching settings of `config`
+pub fn find_hits(
+    pattern: &[u8],
+    text: &[u8],
+    config: &Config,
+) -> Vec<(usize, usize, u8)> {
+    config
+        .matcher()
+        .all_hits(pattern, text, config.mismatch, None)
+}
+
+/// Sequence with each homopolymer run collapsed to a single base, keeping
+/// track of the original coordinates
+#[derive(Debug, PartialEq)]
+pub struct Collapsed {
+    pub seq: Vec<u8>,
+    // Original (start, exclusive end) of the run of each collapsed base
+    runs: Vec<(usize, usize)>,
+}
+
+impl Collapsed {
+    pub fn new(seq: &[u8]) -> Self {
+        let mut collapsed = Collapsed {
+            seq: Vec::new(),
+            runs: Vec::new(),
+        };
+        for (i, &base) in seq.iter().enumerate() {
+            let base = base.to_ascii_uppercase();
+            match collapsed.runs.last_mut() {
+                Some(run) if collapsed.seq.last() == Some(&base) => {
+                    run.1 = i + 1
+                }
+                _ => {
+                    collapsed.seq.push(base);
+                    collapsed.runs.push((i, i + 1));
+                }
+            }
+        }
+
+        collapsed
+    }
+
+    /// Original coordinates of a hit on the collapsed sequence
+    pub(crate) fn expand(&self, hit: Hit) -> Hit {
+        let original_len = self.runs.last().map_or(0, |run| run.1);
+        let start = self.runs.get(hit.0).map_or(original_len, |run| run.0);
+        let end = if hit.1 > hit.0 {
+            self.runs[hit.1 - 1].1
+        } else {
+            start
+        };
+        (start, end, hit.2)
+    }
+}
+
+// Fraction of the oligos of an equimolar degenerate primer mix matching a
+// hit at the degenerate positions of the primer: each matched position
+// resolved to one of its k variants weighs 1/k, so hits of the common
+// variants of the mix score higher than those of rarer combinations
+fn degenerate_score(
+    pattern: &[u8],
+    site: &[u8],
+    ops: &[u8],
+    ambigs: &AmbigMap,
+) -> f64 {
+    let (mut p, mut t) = (0, 0);
+    let mut score = 1.0;
+    for &op in ops {
+        match op {
+            b'=' | b'X' => {
+                let matched = ambigs.matched_by(pattern[p]);
+                let variants =
+                    b"ACGTU".iter().filter(|&&b| matched[b as usize]).count();
+                // Ambiguous sequence bases match every variant
+                if op == b'=' && variants > 1 && b"ACGTU".contains(&site[t]) {
+                    score /= variants as f64;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'I' => p += 1,
+            b'D' => t += 1,
+            _ => {}
+        }
+    }
+
+    score
+}
+
+// Extended CIGAR string of alignment operations
+fn to_cigar(ops: &[u8]) -> String {
+    let mut cigar = String::new();
+    let mut ops = ops.iter().peekable();
+    while let Some(&op) = ops.next() {
+        let mut len = 1;
+        while ops.next_if_eq(&&op).is_some() {
+            len += 1;
+        }
+        cigar.push_str(&format!("{}{}", len, op as char));
+    }
+
+    cigar
+}
+
+// Is the amplicon of a forward and a reverse hit plausible: the reverse hit
+// ending downstream of the forward one and the amplicon neither too short
+// nor too long?
+fn is_amplicon(
+    forward: &Hit,
+    reverse: &Hit,
+    lengths: &RangeInclusive<usize>,
+) -> bool {
+    reverse.0 > forward.0
+        && reverse.1 > forward.1
+        && lengths.contains(&(reverse.1 - forward.0))
+}
+
+// Pick the forward and reverse hits jointly making the best amplicon, with
+// the lowest total distance and then the shortest. Only hits without any
+// other hit between them are paired, so that the best hits of different
+// operons of a genome are not mixed up.
+pub(crate) fn best_amplicon(
+    forward_hits: &[Hit],
+    reverse_hits: &[Hit],
+    lengths: &RangeInclusive<usize>,
+    max_distance: Option<usize>,
+    deadline: Option<Instant>,
+) -> Option<(Hit, Hit)> {
+    pair_amplicons(forward_hits, reverse_hits, lengths, max_distance, deadline)
+        .into_iter()
+        .min_by_key(|(forward, reverse)| {
+            (forward.2 + reverse.2, reverse.1 - forward.0)
+        })
+}
+
+// Reverse complement of a sequence, soft-masked bases staying lowercase
+pub(crate) fn reverse_complement_seq(seq: &[u8], alphabet: &str) -> Vec<u8> {
+    let to_dna = |base: &u8| match base {
+        b'U' => b'T',
+        b'u' => b't',
+        _ => *base,
+    };
+    let complement = dna::revcomp(seq.iter().map(to_dna));
+    if alphabet == "rna" {
+        complement
+            .into_iter()
+            .map(|base| match base {
+                b'T' => b'U',
+                b't' => b'u',
+                _ => base,
+            })
+            .collect()
+    } else {
+        complement
+    }
+}
+
+/// Fewest mismatches, allowed to each primer, with which a primer pair
+/// amplifies a sequence of the given alphabet, up to `config.mismatch`
+pub fn amplicon_mismatches(
+    primer_pair: &[String],
+    seq: &[u8],
+    alphabet: &str,
+    config: &Config,
+) -> Option<u8> {
+    let hits = |pattern: &[u8]| find_hits(pattern, seq, config);
+    let reverse = to_reverse_complement(&primer_pair[1], alphabet);
+
+    pair_amplicons(
+        &hits(primer_pair[0].as_bytes()),
+        &hits(reverse.as_bytes()),
+        &config.amplicon_lengths(primer_pair),
+        config.max_pair_distance,
+        None,
+    )
+    .iter()
+    .map(|(forward, reverse)| forward.2.max(reverse.2))
+    .min()
+}
+
+// Pair each forward hit with the first reverse hit downstream of it, within
+// `max_distance` bases of its end when given, or failing that with one
+// overlapping its end, as primer dimers do. When several forward hits
+// precede the same reverse hit only the closest one makes an amplicon, and
+// amplicons of lengths out of `lengths` are left out. Hits are sorted by
+// start position, the reverse hit downstream being found by bisection.
+// Hits are paired up to the deadline.
+pub(crate) fn pair_amplicons(
+    forward_hits: &[Hit],
+    reverse_hits: &[Hit],
+    lengths: &RangeInclusive<usize>,
+    max_distance: Option<usize>,
+    deadline: Option<Instant>,
+) -> Vec<(Hit, Hit)> {
+    let mut amplicons = Vec::new();
+
+    for (i, forward) in forward_hits.iter().enumerate() {
+        if matcher::expired(deadline) {
+            break;
+        }
+        let next = reverse_hits.partition_point(|r| r.0 < forward.1);
+        let reverse = reverse_hits
+            .get(next)
+            .filter(|r| max_distance.is_none_or(|max| r.0 - forward.1 <= max))
+            .or_else(|| {
+                reverse_hits[..next]
+                    .iter()
+                    .find(|r| r.0 > forward.0 && r.1 > forward.1)
+            });
+        let reverse = match reverse {
+            Some(reverse) => reverse,
+            None => continue,
+        };
+        let nested = forward_hits
+            .get(i + 1)
+            .is_some_and(|next| next.1 <= reverse.0);
+        if !nested && is_amplicon(forward, reverse, lengths) {
+            amplicons.push((*forward, *reverse));
+        }
+    }
+
+    amplicons
+}
+
+// Amplicon of a region anchored on one primer, from its best hit, the
+// most upstream forward or downstream reverse one among the closest, to
+// the end of a sequence of `len` bases. The missing hit is an empty hit at
+// the sequence end.
+fn anchored_amplicon(
+    forward_hits: &[Hit],
+    reverse_hits: &[Hit],
+    len: usize,
+    anchor: Anchor,
+) -> Option<Amplicon> {
+    match anchor {
+        Anchor::Both => None,
+        Anchor::Forward => forward_hits
+            .iter()
+            .min_by_key(|hit| hit.2)
+            .map(|&hit| (hit, (len, len, 0), Strand::Plus)),
+        Anchor::Reverse => reverse_hits
+            .iter()
+            .rev()
+            .min_by_key(|hit| hit.2)
+            .map(|&hit| ((0, 0, 0), hit, Strand::Plus)),
+    }
+}
+
+// Is a hit the sequence end standing for the missing primer of an anchored
+// region?
+fn is_sequence_end(hit: &Hit) -> bool {
+    hit.0 == hit.1
+}
+
+// Record, or region of a record, skipped or not found, sent to the sinks
+fn write_skipped<'a>(
+    sinks: impl Iterator<Item = &'a mut (dyn OutputSink + 'a)>,
+    id: &str,
+    region: Option<&str>,
+    reason: &str,
+    detail: &str,
+) -> anyhow::Result<()> {
+    let skipped = SinkSkipped {
+        id,
+        region,
+        reason,
+        detail,
+    };
+    for sink in sinks {
+        sink.write_skipped(&skipped)?;
+    }
+
+    Ok(())
+}
+
+// Which of the (start, exclusive end) spans to keep so that none overlaps
+// a longer one. Spans are kept from the longest, ties going to the first.
+fn longest_disjoint(spans: &[(usize, usize)]) -> Vec<bool> {
+    let mut order = (0..spans.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(spans[i].1 - spans[i].0));
+    let mut kept = vec![false; spans.len()];
+
+    for i in order {
+        let (start, end) = spans[i];
+        let overlapping = (0..spans.len())
+            .any(|j| kept[j] && spans[j].0 < end && start < spans[j].1);
+        kept[i] = !overlapping;
+    }
+
+    kept
+}
+
+/// Create the output `path`, starting with its `header` lines if any. With
+/// `append`, an existing output is appended to instead, once checked to
+/// start with the same header so that the columns of both runs line up.
+pub fn open_output(
+    path: &str,
+    header: Option<&str>,
+    append: bool,
+) -> anyhow::Result<File> {
+    if append && fs::metadata(path).is_ok_and(|meta| meta.len() > 0) {
+        if let Some(header) = header {
+            let first = io::BufReader::new(File::open(path)?)
+                .lines()
+                .take(header.lines().count())
+                .collect::<io::Result<Vec<_>>>()?
+                .join("\n");
+            if first != header.trim_end() {
+                return Err(anyhow!(
+                    "Cannot append to {}, its header {:?} is not {:?}",
+                    path,
+                    first.trim_end(),
+                    header.trim_end()
+                ));
+            }
+        }
+        return Ok(OpenOptions::new().append(true).open(path)?);
+    }
+
+    let mut file = File::create(path)?;
+    if let Some(header) = header {
+        file.write_all(header.as_bytes())?;
+    }
+    Ok(file)
+}
+
+// Hash of a record id, alone or with the name of a region extracted from
+// the record, so that the ids of large outputs fit in memory
+fn seen_key(id: &str, region: Option<&str>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (id, region).hash(&mut hasher);
+    hasher.finish()
+}
+
+// Was the region of a primer pair, named after its primers when it has no
+// name, already extracted from a record?
+fn seen_region(
+    ids: &HashSet<u64>,
+    id: &str,
+    region: &str,
+    primer_pair: &[String],
+) -> bool {
+    let name = if region.is_empty() {
+        primer_pair.join("/")
+    } else {
+        region.to_string()
+    };
+    ids.contains(&seen_key(id, Some(&name)))
+}
+
+/// Hashed ids of the records listed in `<prefix>.tsv`, those handled by the
+/// runs appended to, alone and with the name of each region extracted from
+/// them. A missing output lists none.
+pub fn read_seen_ids(prefix: &str) -> anyhow::Result<HashSet<u64>> {
+    let path = format!("{}.tsv", prefix);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(HashSet::new())
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Cannot read {}", path))
+        }
+    };
+    let mut ids = HashSet::new();
+    for line in io::BufReader::new(file).lines().skip(1) {
+        let line = line.with_context(|| format!("Cannot read {}", path))?;
+        let mut fields = line.split('\t');
+        let id = fields.next().unwrap_or_default();
+        if id.is_empty() {
+            continue;
+        }
+        ids.insert(seen_key(id, None));
+        for region in fields.nth(2).unwrap_or(".").split(',') {
+            if region != "." {
+                ids.insert(seen_key(id, Some(region)));
+            }
+        }
+    }
+
+    Ok(ids)
+}
+
+// Sinks of the outputs of an input, followed by the other outputs and the
+// sinks of the caller
+fn run_sinks<'a>(
+    sinks: &'a mut Sinks,
+    extras: &'a mut ExtraOutputs,
+) -> impl Iterator<Item = &'a mut (dyn OutputSink + 'a)> {
+    let ExtraOutputs {
+        bam,
+        plot,
+        tbl,
+        track,
+        sinks: caller_sinks,
+    } = extras;
+    let boxed = |sink: &'a mut Box<dyn OutputSink + Send>| {
+        &mut **sink as &'a mut (dyn OutputSink + 'a)
+    };
+    sinks
+        .iter_mut()
+        .map(boxed)
+        .chain(bam.iter_mut().map(|sink| sink as &mut dyn OutputSink))
+        .chain(plot.iter_mut().map(|sink| sink as &mut dyn OutputSink))
+        .chain(tbl.iter_mut().map(|sink| sink as &mut dyn OutputSink))
+        .chain(track.iter_mut().map(|sink| sink as &mut dyn OutputSink))
+        .chain(caller_sinks.iter_mut().map(boxed))
+}
+
+// Flush the sinks, syncing them to disk with `fsync`
+fn flush_outputs<'a>(
+    sinks: impl Iterator<Item = &'a mut (dyn OutputSink + 'a)>,
+    fsync: bool,
+) -> anyhow::Result<()> {
+    for sink in sinks {
+        sink.flush(fsync)?;
+    }
+
+    Ok(())
+}
+
+// Amplicons of a primer pair on a record, with the pair, its region, the
+// reverse complemented reverse primer, whether the best amplicon is tied
+// with another and whether it was rescued
+struct FoundPair<'p> {
+    primer_pair: &'p [String],
+    region: String,
+    reverse: String,
+    amplicons: Vec<Amplicon>,
+    tied: bool,
+    rescued: bool,
+}
+
+// Leave out the amplicons overlapping a longer one, of any region, and
+// return how many were left out
+fn drop_overlapping(found: &mut [FoundPair], id: &str) -> usize {
+    let spans = found
+        .iter()
+        .flat_map(|pair| {
+            pair.amplicons.iter().map(|(forward_hit, reverse_hit, _)| {
+                (
+                    forward_hit.0.min(reverse_hit.0),
+                    forward_hit.1.max(reverse_hit.1),
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut kept = longest_disjoint(&spans).into_iter();
+    let mut overlapping = 0;
+    for pair in found.iter_mut() {
+        let before = pair.amplicons.len();
+        pair.amplicons.retain(|_| kept.next().unwrap_or(true));
+        let dropped = before - pair.amplicons.len();
+        if dropped > 0 {
+            info!("{} amplicons of region {} on {} left out as overlapping a longer region", dropped, pair.region, id);
+            overlapping += dropped;
+        }
+    }
+
+    overlapping
+}
+
+// Operon of each amplicon, in the order of found, grouping the amplicons of
+// all regions along the sequence
+fn amplicon_operons(found: &[FoundPair]) -> Vec<usize> {
+    let spans = found
+        .iter()
+        .enumerate()
+        .flat_map(|(i, pair)| {
+            pair.amplicons
+                .iter()
+                .map(move |(forward_hit, reverse_hit, _)| {
+                    (
+                        forward_hit.0.min(reverse_hit.0),
+                        forward_hit.1.max(reverse_hit.1),
+                        i,
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+
+    operon::label_operons(&spans)
+}
+
+// Outputs of the regions found on a record, sent to the sinks of the run
+// along with the primer hits bounding them
+struct RecordOutput<'r> {
+    record: &'r fasta::Record,
+    alphabet: &'static str,
+    config: &'r Config,
+    matcher: &'r Matcher,
+    sinks: &'r mut Sinks,
+    extras: &'r mut ExtraOutputs,
+    summary: &'r mut RunSummary,
+    lineage: Option<String>,
+    // Names of the regions extracted from the record and the primer hits
+    // bounding them, as (primer, distance)
+    regions: Vec<String>,
+    hits: Vec<(String, u8)>,
+    // Amplicons extracted from the record and left out by the cap
+    extracted: usize,
+    suppressed: usize,
+    // Extracted copies of each region as (operon, sequence)
+    copies: BTreeMap<String, Vec<(usize, Vec<u8>)>>,
+}
+
+impl<'r> RecordOutput<'r> {
+    fn new(
+        record: &'r fasta::Record,
+        alphabet: &'static str,
+        config: &'r Config,
+        matcher: &'r Matcher,
+        sinks: &'r mut Sinks,
+        extras: &'r mut ExtraOutputs,
+        summary: &'r mut RunSummary,
+    ) -> Self {
+        RecordOutput {
+            record,
+            alphabet,
+            config,
+            matcher,
+            sinks,
+            extras,
+            summary,
+            lineage: config.taxonomy.lineage(record),
+            regions: Vec::new(),
+            hits: Vec::new(),
+            extracted: 0,
+            suppressed: 0,
+            copies: BTreeMap::new(),
+        }
+    }
+
+    fn skip(
+        &mut self,
+        region: Option<&str>,
+        reason: &str,
+        detail: &str,
+    ) -> anyhow::Result<()> {
+        write_skipped(
+            run_sinks(self.sinks, self.extras),
+            self.record.id(),
+            region,
+            reason,
+            detail,
+        )
+    }
+
+    // Amplicons of a primer pair from its hits, the region read from the
+    // anchoring primer of truncated sequences being kept and the reason
+    // the region is not found being written otherwise
+    fn select<'p>(
+        &mut self,
+        primer_pair: &'p [String],
+        region: String,
+        hits: PairHits,
+    ) -> anyhow::Result<FoundPair<'p>> {
+        let (config, record, seq) =
+            (self.config, self.record, self.record.seq());
+        let PairHits {
+            forward: forward_hits,
+            reverse: reverse_hits,
+            mut amplicons,
+            inconsistent,
+            rescued,
+        } = hits;
+        let reverse = to_reverse_complement(&primer_pair[1], self.alphabet);
+        if inconsistent > 0 {
+            warn!("{} pairings of the primer hits of region {} on {} rejected as not on the same strand or not facing each other", inconsistent, region, record.id());
+            self.summary.inconsistent += inconsistent;
+        }
+
+        let sites = SinkSites {
+            record_id: record.id(),
+            primer_pair,
+            forward: &forward_hits,
+            reverse: &reverse_hits,
+        };
+        for sink in run_sinks(self.sinks, self.extras) {
+            sink.write_sites(&sites)?;
+        }
+
+        // Truncated sequences keep the region read from the anchoring
+        // primer when its mate is missing, on the plus strand only
+        let anchored = if amplicons.is_empty() && !config.both_strands {
+            anchored_amplicon(
+                &forward_hits,
+                &reverse_hits,
+                seq.len(),
+                config.anchor,
+            )
+        } else {
+            None
+        };
+        if let Some(amplicon) = anchored {
+            let primer = if is_sequence_end(&amplicon.1) {
+                "forward"
+            } else {
+                "reverse"
+            };
+            info!("Region {} of {} extracted up to the sequence end, anchored on its {} primer", region, record.id(), primer);
+            amplicons.push(amplicon);
+        }
+
+        let not_found = match (forward_hits.is_empty(), reverse_hits.is_empty())
+        {
+            _ if anchored.is_some() => None,
+            (false, true) => {
+                warn!("Region {} not found because primer {} was not found in the sequence", region, primer_pair[1]);
+                Some((
+                    "no_reverse_hit",
+                    format!("primer {} not found", primer_pair[1]),
+                ))
+            }
+            (true, false) => {
+                warn!("Region {} not found because primer {} was not found in the sequence", region, primer_pair[0]);
+                Some((
+                    "no_forward_hit",
+                    format!("primer {} not found", primer_pair[0]),
+                ))
+            }
+            (true, true) => {
+                warn!("Region {} not found because primers {}, {} was not found in the sequence", region, primer_pair[0], primer_pair[1]);
+                Some((
+                    "no_hits",
+                    format!(
+                        "primers {}, {} not found",
+                        primer_pair[0], primer_pair[1]
+                    ),
+                ))
+            }
+            (false, false) if amplicons.is_empty() => {
+                warn!("Region {} not found because primer {} was not found downstream of primer {}", region, primer_pair[1], primer_pair[0]);
+                Some((
+                    "no_amplicon",
+                    format!(
+                        "primer {} not found downstream of primer {}",
+                        primer_pair[1], primer_pair[0]
+                    ),
+                ))
+            }
+            _ => None,
+        };
+        if let Some((reason, detail)) = not_found {
+            // Records too short to hold both primers could not match
+            let primers_len = primer_pair[0].len() + primer_pair[1].len();
+            let (reason, detail) = if seq.len() < primers_len {
+                (
+                    "too_short",
+                    format!("{} bp, shorter than the primers", seq.len()),
+                )
+            } else {
+                (reason, detail)
+            };
+            self.skip(Some(&region), reason, &detail)?;
+        }
+
+        // Another site of a primer binding as well as the one used makes
+        // the best amplicon ambiguous, unlike the copies of all matches
+        let rival = |hits: &[Hit], used: &Hit| {
+            hits.iter().any(|hit| hit != used && hit.2 <= used.2)
+        };
+        let tied = !config.all_matches
+            && amplicons.first().is_some_and(|(forward, reverse, _)| {
+                rival(&forward_hits, forward) || rival(&reverse_hits, reverse)
+            });
+
+        if rescued {
+            info!("Region {} of {} rescued by searching its missing primer with more mismatches", region, record.id());
+        }
+
+        Ok(FoundPair {
+            primer_pair,
+            region,
+            reverse,
+            amplicons,
+            tied,
+            rescued,
+        })
+    }
+
+    // Write the region of an amplicon of a pair and the primer hits bounding
+    // it, unless it is left out as a primer dimer, as ill-formed or by the
+    // cap of amplicons per record
+    fn write_amplicon(
+        &mut self,
+        pair: &FoundPair,
+        amplicon: Amplicon,
+        operon: Option<usize>,
+    ) -> anyhow::Result<Option<RegionMatch>> {
+        let (config, record, seq) =
+            (self.config, self.record, self.record.seq());
+        let (primer_pair, region) = (pair.primer_pair, &pair.region);
+        let (forward_hit, reverse_hit, strand) = amplicon;
+        // Hits upstream and downstream of the region on the plus strand
+        let (left, right) = match strand {
+            Strand::Plus => (forward_hit, reverse_hit),
+            Strand::Minus => (reverse_hit, forward_hit),
+        };
+        let (start, end) = if config.trim_primers {
+            (left.1, right.0)
+        } else {
+            (left.0, right.1)
+        };
+        // Overlapping hits, or amplicons shorter than the primers, are
+        // artifacts rather than regions. Regions cut short by the sequence
+        // end are not.
+        let primer_dimer = left.0 <= right.1
+            && !is_sequence_end(&forward_hit)
+            && !is_sequence_end(&reverse_hit)
+            && (left.1 > right.0
+                || right.1 - left.0
+                    < primer_pair[0].len() + primer_pair[1].len());
+        if primer_dimer {
+            self.summary.primer_dimers += 1;
+            if !config.keep_primer_dimers {
+                info!(
+                    "Primer-dimer-like amplicon of region {} on {} left out",
+                    region,
+                    record.id()
+                );
+                self.skip(
+                    Some(region),
+                    "primer_dimer",
+                    &format!(
+                        "amplicon of {} bases at {}-{}",
+                        right.1 - left.0,
+                        left.0,
+                        right.1
+                    ),
+                )?;
+                return Ok(None);
+            }
+        }
+        if start > end {
+            warn!("Region {} not extracted from {} because its primers overlap or are in the wrong order", region, record.id());
+            self.skip(
+                Some(region),
+                "coordinate_error",
+                "primers overlap or are in the wrong order",
+            )?;
+            return Ok(None);
+        }
+        if config
+            .max_amplicons
+            .is_some_and(|max| self.extracted >= max)
+        {
+            self.suppressed += 1;
+            return Ok(None);
+        }
+        self.extracted += 1;
+
+        let scores = self.write_hits(pair, &amplicon)?;
+
+        // Anchored regions are cut short by the sequence end
+        let anchored = if is_sequence_end(&reverse_hit) {
+            Some("forward")
+        } else if is_sequence_end(&forward_hit) {
+            Some("reverse")
+        } else {
+            None
+        };
+        let chimera_suspect = anchored.is_none()
+            && is_chimera_suspect(primer_pair, right.1 - left.0);
+        let allowed = config.primer_mismatch(primer_pair, &primer_pair[0])
+            as usize
+            + config.primer_mismatch(primer_pair, &primer_pair[1]) as usize;
+        let region_match = RegionMatch {
+            region: region.clone(),
+            forward_primer: primer_pair[0].clone(),
+            reverse_primer: primer_pair[1].clone(),
+            start,
+            end,
+            forward_dist: forward_hit.2,
+            reverse_dist: reverse_hit.2,
+            strand,
+            confidence: Confidence::classify(
+                forward_hit.2 as usize + reverse_hit.2 as usize,
+                allowed,
+                pair.tied,
+                anchored.is_none()
+                    && !chimera_suspect
+                    && !primer_dimer
+                    && !pair.rescued,
+            ),
+        };
+
+        let mut desc = self.region_desc(pair, &region_match, scores);
+        // Regions of the minus strand are read in the orientation of their
+        // primers
+        let region_seq = &seq[region_match.start..region_match.end];
+        let region_seq = match strand {
+            Strand::Plus => region_seq.to_vec(),
+            Strand::Minus => reverse_complement_seq(region_seq, self.alphabet),
+        };
+        if let Some(primer) = anchored {
+            desc.push_str(&format!(" anchored={}", primer));
+        } else if chimera_suspect {
+            desc.push_str(" chimera_suspect=true");
+            self.summary.chimera_suspects += 1;
+        }
+        if primer_dimer {
+            desc.push_str(" primer_dimer=true");
+        }
+        if pair.rescued {
+            desc.push_str(" rescued=true");
+            self.summary.rescued += 1;
+        }
+        if config.primer_offsets {
+            desc.push_str(&primer_offsets(config, &region_match, &amplicon));
+        }
+        if let Some(operon) = operon {
+            desc.push_str(&format!(" operon=operon_{}", operon));
+            self.copies
+                .entry(region_match.name())
+                .or_default()
+                .push((operon, region_seq.clone()));
+        }
+        // Last as lineages may contain spaces
+        if let Some(lineage) = self.lineage.as_deref() {
+            desc.push_str(&format!(" taxonomy={}", lineage));
+        }
+        let region_seq = if config.keep_strand {
+            seq[region_match.start..region_match.end].to_vec()
+        } else {
+            region_seq
+        };
+        let found = SinkRegion {
+            record,
+            region: &region_match,
+            desc: &desc,
+            seq: &region_seq,
+        };
+        for sink in run_sinks(self.sinks, self.extras) {
+            sink.write_region(&found)?;
+        }
+
+        self.summary.add_match(&region_match);
+        self.regions.push(region_match.name());
+
+        Ok(Some(region_match))
+    }
+
+    // Write the primer hits bounding an amplicon, with their alignment, and
+    // return the degenerate scores of the primers when asked
+    fn write_hits(
+        &mut self,
+        pair: &FoundPair,
+        &(forward_hit, reverse_hit, strand): &Amplicon,
+    ) -> anyhow::Result<Vec<(&'static str, f64)>> {
+        let (config, record, seq) =
+            (self.config, self.record, self.record.seq());
+        let primer_pair = pair.primer_pair;
+        // Alignments are on the plus strand, of the reverse complement of
+        // the primers binding it
+        let minus_forward =
+            to_reverse_complement(&primer_pair[0], self.alphabet);
+        let (forward, reverse) = match strand {
+            Strand::Plus => (primer_pair[0].as_str(), pair.reverse.as_str()),
+            Strand::Minus => (minus_forward.as_str(), primer_pair[1].as_str()),
+        };
+        let minus = strand == Strand::Minus;
+        let mut scores = Vec::new();
+        let hits = [
+            ("forward", &primer_pair[0], forward, forward_hit, minus),
+            ("reverse", &primer_pair[1], reverse, reverse_hit, !minus),
+        ];
+        for (role, primer, pattern, hit, complemented) in hits {
+            if is_sequence_end(&hit) {
+                continue;
+            }
+            if self.lineage.is_some() {
+                self.hits.push((primer.clone(), hit.2));
+            }
+            // Alignments on collapsed runs are not reported
+            let ops = if config.homopolymer_tolerant {
+                None
+            } else {
+                self.matcher.alignment(pattern.as_bytes(), seq, hit)
+            };
+            let cigar = match ops.as_deref() {
+                Some(ops) => {
+                    if config.degenerate_score {
+                        let score = degenerate_score(
+                            pattern.as_bytes(),
+                            &seq[hit.0..hit.1],
+                            ops,
+                            &config.ambigs,
+                        );
+                        scores.push((role, score));
+                    }
+                    self.summary.add_primer_hit(primer, ops, complemented);
+                    to_cigar(ops)
+                }
+                None => "*".to_string(),
+            };
+            // Bases under the hit, read in the primer orientation to be
+            // checked against its degenerate bases
+            let matched = String::from_utf8_lossy(&seq[hit.0..hit.1]);
+            let matched = if complemented {
+                to_reverse_complement(&matched, self.alphabet)
+            } else {
+                matched.into_owned()
+            };
+            let found = SinkHit {
+                record_id: record.id(),
+                region: &pair.region,
+                primer,
+                role,
+                hit,
+                cigar: &cigar,
+                matched: &matched,
+            };
+            for sink in run_sinks(self.sinks, self.extras) {
+                sink.write_hit(&found)?;
+            }
+        }
+
+        Ok(scores)
+    }
+
+    // Description of a region in the FASTA output, from its primers, their
+    // scores and the settings of the run
+    fn region_desc(
+        &self,
+        pair: &FoundPair,
+        region_match: &RegionMatch,
+        scores: Vec<(&str, f64)>,
+    ) -> String {
+        let config = self.config;
+        let (primer_pair, region) = (pair.primer_pair, &pair.region);
+        let mut desc = if !region.is_empty() {
+            format!(
+                "region={} forward={} reverse={}",
+                region, primer_pair[0], primer_pair[1]
+            )
+        } else {
+            format!("forward={} reverse={}", primer_pair[0], primer_pair[1])
+        };
+        desc.push_str(&format!(" confidence={}", region_match.confidence));
+        // Several amplicons of a sequence are told apart by location
+        if config.all_matches {
+            desc.push_str(&format!(
+                " location={}-{}",
+                region_match.start, region_match.end
+            ));
+        }
+        if config.both_strands {
+            desc.push_str(&format!(" strand={}", region_match.strand));
+        }
+        for (role, score) in scores {
+            desc.push_str(&format!(" {}_score={:.3}", role, score));
+        }
+        if let Some(gene) = region_gene(region).filter(|_| config.tag_genes) {
+            desc.push_str(&format!(" gene={}", gene));
+        }
+        // Boundaries of the region in E. coli positions and SILVA alignment
+        // columns, the same for every sequence
+        if let Some(silva) = config.silva.as_ref() {
+            if let Some((first, last)) =
+                ecoli_region(primer_pair, config.trim_primers)
+            {
+                desc.push_str(&format!(" ecoli={}-{}", first, last));
+                if let (Some(first), Some(last)) =
+                    (silva.column(first), silva.column(last))
+                {
+                    desc.push_str(&format!(" silva={}-{}", first, last));
+                }
+            }
+        }
+
+        desc
+    }
+
+    // Write the identities between the copies of each region found on
+    // several operons, telling the intragenomic heterogeneity of the regions
+    fn write_identities(&mut self) -> anyhow::Result<()> {
+        let id = self.record.id();
+        for (name, copies) in self.copies.iter() {
+            let sequences = copies
+                .iter()
+                .map(|(operon, copy)| (*operon, copy.as_slice()))
+                .collect::<Vec<_>>();
+            let identities = operon::pairwise_identities(&sequences);
+            for &(first, second, identity) in identities.iter() {
+                let found = SinkIdentity {
+                    id,
+                    region: name,
+                    operons: (first, second),
+                    identity,
+                };
+                for sink in run_sinks(self.sinks, self.extras) {
+                    sink.write_identity(&found)?;
+                }
+            }
+            let lowest = identities
+                .iter()
+                .map(|(_, _, identity)| *identity)
+                .fold(f64::INFINITY, f64::min);
+            if lowest < 1.0 {
+                info!(
+                    "{} copies of region {} on {} are heterogeneous, down to {:.1}% identity",
+                    copies.len(),
+                    name,
+                    id,
+                    lowest * 100.0
+                );
+            } else if !identities.is_empty() {
+                info!(
+                    "{} copies of region {} on {} are identical",
+                    copies.len(),
+                    name,
+                    id
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    // Write the record with the names of its regions, unless `listed`
+    // already and without new regions, and return the names
+    fn finish(
+        self,
+        completeness: Completeness,
+        listed: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let record = self.record;
+        if self.suppressed > 0 {
+            warn!(
+                "{} amplicons suppressed on {}, only the first {} are extracted",
+                self.suppressed,
+                record.id(),
+                self.extracted
+            );
+            self.summary.suppressed += self.suppressed;
+        }
+
+        if let Some(lineage) = self.lineage.as_deref() {
+            self.summary.add_taxon(lineage, &self.regions, &self.hits);
+        }
+
+        let searched = SinkRecord {
+            id: record.id(),
+            length: record.seq().len(),
+            completeness,
+            regions: &self.regions,
+        };
+        // Records already listed are only listed again with new regions
+        if !listed || !self.regions.is_empty() {
+            for sink in run_sinks(self.sinks, self.extras) {
+                sink.write_record(&searched)?;
+            }
+        }
+
+        Ok(self.regions)
+    }
+}
+
+// Start of the primer sites of an amplicon in the written region, negative
+// before it when primers are trimmed
+fn primer_offsets(
+    config: &Config,
+    region_match: &RegionMatch,
+    &(forward_hit, reverse_hit, strand): &Amplicon,
+) -> String {
+    let reversed = strand == Strand::Minus && !config.keep_strand;
+    let offset = |hit: &Hit| {
+        if reversed {
+            region_match.end as i64 - hit.1 as i64
+        } else {
+            hit.0 as i64 - region_match.start as i64
+        }
+    };
+    let mut offsets = String::new();
+    for (role, hit) in [("forward", &forward_hit), ("reverse", &reverse_hit)] {
+        if !is_sequence_end(hit) {
+            offsets.push_str(&format!(" {}_offset={}", role, offset(hit)));
+        }
+    }
+
+    offsets
+}
+
+pub fn get_hypervar_regions(
+    file: &str,
+    primers: &[Vec<String>],
+    prefix: &str,
+    config: &Config,
+    extras: &mut ExtraOutputs,
+    mut on_match: Option<&mut OnMatch>,
+) -> anyhow::Result<RunSummary> {
+    let mut summary = RunSummary::default();
+    if config.both_strands
+        && (config.rescue.is_some() || config.anchor != Anchor::Both)
+    {
+        warn!("Rescue and anchoring are ignored when searching both strands");
+    }
+    // Pairs named after a single primer, their regions being counted
+    let half_named = primers
+        .iter()
+        .filter(|pair| {
+            // Labelled pairs are named after their label
+            let labelled = config
+                .pair_options
+                .get(*pair)
+                .is_some_and(|options| options.label.is_some());
+            match unmapped_primer(pair).filter(|_| !labelled) {
+                Some(primer) => {
+                    warn!(
+                        "Region {} is named after a single primer, primer {} is not in the region map",
+                        config.region_name(pair),
+                        primer
+                    );
+                    true
+                }
+                None => false,
+            }
+        })
+        .map(Vec::as_slice)
+        .collect::<Vec<_>>();
+    // Records of the outputs appended to are not extracted again
+    let seen_ids = match &config.seen_ids {
+        Some(ids) => Some(Arc::clone(ids)),
+        None if config.append => Some(Arc::new(read_seen_ids(prefix)?)),
+        None => None,
+    };
+
+    let mut records = read_records_threads(file, config.decompress_threads)
+        .with_context(|| "Cannot read file")?;
+    // Read pairs are merged into the fragments they were read from
+    let min_overlap = config.min_overlap.unwrap_or(pairs::MIN_OVERLAP);
+    let merge_counts = Rc::new(pairs::MergeCounts::default());
+    if let Some(mates) = config.mates.as_deref() {
+        let mates = read_records(mates)
+            .with_context(|| format!("Cannot read mates {}", mates))?;
+        records = pairs::merge_mates(
+            records,
+            mates,
+            min_overlap,
+            Rc::clone(&merge_counts),
+        );
+    } else if config.interleaved {
+        records = pairs::merge_interleaved(
+            records,
+            min_overlap,
+            Rc::clone(&merge_counts),
+        );
+    }
+
+    // FASTA, GFF3 and TSV outputs of the regions, records and primer hits,
+    // the other outputs and the sinks of the caller being sent the same
+    let mut sinks = sink::output_sinks(prefix, config)?;
+
+    // Primer search of the records, sharing the primers of the pairs and
+    // the prefilter between them
+    let mut search = PrimerSearch::new(primers, config);
+    // Primer hits of the sequences already searched, shared by identical
+    // sequences
+    let mut hit_cache: Option<HashMap<Vec<u8>, RecordHits>> =
+        if config.cache_hits {
+            Some(HashMap::new())
+        } else {
+            None
+        };
+    // Marker gene classifier, when the primers of the classified gene only
+    // are searched
+    let classifier = if config.auto_gene {
+        Some(GeneClassifier::new())
+    } else {
+        None
+    };
+    let mut processed = 0;
+    // Progress of the run, as a share of the input records when they can
+    // be counted beforehand, i.e. for local inputs
+    let mut progress = if config.progress {
+        let total = if remote::is_url(file) {
+            None
+        } else {
+            let counts = count::count(file)
+                .with_context(|| format!("Cannot count records of {}", file))?;
+            // Interleaved mates make a single record once merged
+            Some(if config.interleaved {
+                counts.records / 2
+            } else {
+                counts.records
+            })
+        };
+        Some(Progress::new(file, total))
+    } else {
+        None
+    };
+
+    // Last record read, the checkpoint of an interrupted run with the
+    // number of records read
+    let mut last_id = String::new();
+    for (read, record) in records.enumerate() {
+        if config
+            .interrupt
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+        {
+            warn!("Interrupted after {} records of {}", read, file);
+            summary.checkpoints.push(Checkpoint {
+                file: file.to_string(),
+                records: read,
+                last_id: (read > 0).then(|| last_id.clone()),
+            });
+            break;
+        }
+        let record = record.with_context(|| format!("Cannot read {}", file))?;
+        let parsed_id = config.id_parser.parse(record.id(), record.desc());
+        let record = match parsed_id {
+            Some(id) => {
+                fasta::Record::with_attrs(&id, record.desc(), record.seq())
+            }
+            None => record,
+        };
+        last_id.clear();
+        last_id.push_str(record.id());
+        if let Some(filter) = config.header_filter.as_ref() {
+            if !filter.matches(record.id(), record.desc()) {
+                summary.filtered_out += 1;
+                continue;
+            }
+            summary.filtered_in += 1;
+        }
+        if let Some(fraction) = config.subsample {
+            let seed = config.seed.unwrap_or_default();
+            if Rng::for_key(seed, record.id().as_bytes()).unit() >= fraction {
+                summary.sampled_out += 1;
+                continue;
+            }
+        }
+        // Records already listed are searched again for the regions not
+        // extracted from them yet, and skipped when there are none
+        let seen = seen_ids
+            .as_deref()
+            .filter(|ids| ids.contains(&seen_key(record.id(), None)));
+        if seen.is_some_and(|ids| {
+            primers.iter().all(|pair| {
+                let region = config.region_name(pair);
+                seen_region(ids, record.id(), &region, pair)
+            })
+        }) {
+            write_skipped(
+                run_sinks(&mut sinks, extras),
+                record.id(),
+                None,
+                "duplicate",
+                "already in the outputs appended to",
+            )?;
+            summary.duplicates += 1;
+            continue;
+        }
+        let record = match config.sanitize.apply(record.seq()) {
+            Some(seq) => {
+                summary.sanitized += 1;
+                fasta::Record::with_attrs(record.id(), record.desc(), &seq)
+            }
+            None => record,
+        };
+        let seq = record.seq();
+        // Oversized records are skipped before any search
+        if config.max_record_len.is_some_and(|max| seq.len() > max) {
+            warn!(
+                "{} skipped, its {} bp exceed --max-record-len",
+                record.id(),
+                seq.len()
+            );
+            write_skipped(
+                run_sinks(&mut sinks, extras),
+                record.id(),
+                None,
+                "too_long",
+                &format!("{} bp exceed --max-record-len", seq.len()),
+            )?;
+            summary.too_long += 1;
+            continue;
+        }
+        // Primer searches of the record are given up past this instant
+        let deadline = config
+            .record_timeout
+            .map(|timeout| Instant::now() + timeout);
+        // Padding past the sampled bases is still left to --sanitize
+        let padded = seq.iter().any(is_padding);
+        let sampled = if padded {
+            None
+        } else {
+            sampled_sequence_type(seq, config.alphabet_sample)
+        };
+        let alphabet = match sampled {
+            Some(Alphabet::Dna) => {
+                info!("Sequence type is DNA");
+                "dna"
+            }
+            Some(Alphabet::Rna) => {
+                info!("Sequence type is RNA");
+                "rna"
+            }
+            // Primers cannot be complemented for an unknown alphabet
+            None => {
+                let detail = if padded {
+                    "padding characters - or *, see --sanitize"
+                } else {
+                    "sequence type is not recognized as DNA or RNA"
+                };
+                match config.mixed_alphabet {
+                    MixedAlphabet::Dna => {
+                        warn!(
+                            "{} searched as DNA, its sequence type is not DNA or RNA",
+                            record.id()
+                        );
+                        "dna"
+                    }
+                    MixedAlphabet::Error => {
+                        return Err(anyhow!(
+                            "Record {} has an unknown alphabet: {}",
+                            record.id(),
+                            detail
+                        ));
+                    }
+                    MixedAlphabet::Skip => {
+                        error!(
+                            "{} skipped, sequence type is not DNA or RNA",
+                            record.id()
+                        );
+                        write_skipped(
+                            run_sinks(&mut sinks, extras),
+                            record.id(),
+                            None,
+                            "unknown_alphabet",
+                            detail,
+                        )?;
+                        continue;
+                    }
+                }
+            }
+        };
+        if seq.len() <= 1500 {
+            warn!("Sequence length is less than 1500 bp. We may not be able to find some regions");
+        }
+
+        for sink in run_sinks(&mut sinks, extras) {
+            sink.start_record(&record)?;
+        }
+
+        // Primer pairs searched in the sequence, those of its marker gene
+        // when classified, all of them otherwise
+        let gene = classifier.as_ref().and_then(|c| c.classify(seq));
+        if classifier.is_some() {
+            let label = gene.unwrap_or("unclassified");
+            match gene {
+                Some(gene) => info!("{} classified as {}", record.id(), gene),
+                None => info!(
+                    "{} matches no marker gene, all regions searched",
+                    record.id()
+                ),
+            }
+            *summary.genes.entry(label.to_string()).or_insert(0) += 1;
+        }
+        // Primer hits of an identical sequence already searched, if any
+        let cached_hits =
+            hit_cache.as_ref().and_then(|cache| cache.get(seq)).cloned();
+        let (completeness, pair_hits) = match cached_hits {
+            Some(hits) => {
+                summary.cached += 1;
+                hits
+            }
+            None => match search.search(seq, alphabet, gene, deadline) {
+                Some(hits) => {
+                    if let Some(cache) = hit_cache.as_mut() {
+                        cache.insert(seq.to_vec(), hits.clone());
+                    }
+                    hits
+                }
+                // Hits of a search given up are partial
+                None => {
+                    warn!(
+                        "{} skipped, its primer search exceeded --record-timeout",
+                        record.id()
+                    );
+                    write_skipped(
+                        run_sinks(&mut sinks, extras),
+                        record.id(),
+                        None,
+                        "timed_out",
+                        "primer search exceeded --record-timeout",
+                    )?;
+                    summary.timed_out += 1;
+                    continue;
+                }
+            },
+        };
+        summary.add_sequence(completeness);
+
+        let mut output = RecordOutput::new(
+            &record,
+            alphabet,
+            config,
+            search.matcher(),
+            &mut sinks,
+            extras,
+            &mut summary,
+        );
+
+        // Amplicons of each primer pair searched in the sequence
+        let mut found = Vec::new();
+        for (i, (primer_pair, hits)) in
+            primers.iter().zip(pair_hits).enumerate()
+        {
+            if !search.searched(i, gene) {
+                continue;
+            }
+            let region = config.region_name(primer_pair);
+            if seen.is_some_and(|ids| {
+                seen_region(ids, record.id(), &region, primer_pair)
+            }) {
+                output.skip(
+                    Some(&region),
+                    "duplicate",
+                    "already in the outputs appended to",
+                )?;
+                output.summary.duplicates += 1;
+                continue;
+            }
+            found.push(output.select(primer_pair, region, hits)?);
+        }
+
+        if config.overlaps == Overlaps::KeepLongest {
+            output.summary.overlapping +=
+                drop_overlapping(&mut found, record.id());
+        }
+
+        let mut operons = if config.all_matches {
+            amplicon_operons(&found)
+        } else {
+            Vec::new()
+        }
+        .into_iter();
+        for pair in found.iter() {
+            for &amplicon in pair.amplicons.iter() {
+                let operon = operons.next();
+                if let Some(region_match) =
+                    output.write_amplicon(pair, amplicon, operon)?
+                {
+                    if half_named.contains(&pair.primer_pair) {
+                        output.summary.half_named += 1;
+                    }
+                    if let Some(on_match) = on_match.as_deref_mut() {
+                        on_match(&region_match, &record);
+                    }
+                }
+            }
+        }
+
+        // Intragenomic heterogeneity of the regions found on several
+        // operons
+        if config.all_matches {
+            output.write_identities()?;
+        }
+        let record_regions = output.finish(completeness, seen.is_some())?;
+
+        // Outputs of the records processed so far survive an interrupted run
+        processed += 1;
+        if let Some(progress) = progress.as_mut() {
+            progress.update(processed);
+        }
+        if let Some(stats) = config.live_stats.as_ref() {
+            stats.add_record(&record_regions)?;
+        }
+        if config
+            .flush_every
+            .is_some_and(|every| every > 0 && processed % every == 0)
+        {
+            flush_outputs(run_sinks(&mut sinks, extras), config.fsync)
+                .with_context(|| {
+                    format!("Cannot flush outputs after record {}", record.id())
+                })?;
+        }
+    }
+
+    // Outputs of an interrupted run are told apart from complete ones
+    if let Some(checkpoint) = summary.checkpoints.last() {
+        for sink in run_sinks(&mut sinks, extras) {
+            sink.interrupted(checkpoint)?;
+        }
+    }
+    flush_outputs(run_sinks(&mut sinks, extras), config.fsync)
+        .with_context(|| format!("Cannot flush outputs {}.*", prefix))?;
+
+    summary.read_pairs = merge_counts.pairs.get();
+    summary.merged_pairs = merge_counts.merged.get();
+
+    Ok(summary)
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_primers_to_region_ok() {
+        assert_eq!(
+            primers_to_region(vec![
+                "CCTACGGGNGGCWGCAG".to_string(),
+                "GTGCCAGCMGCCGCGGTAA".to_string()
+            ]),
+            "v3v4".to_string()
+        );
+    }
+
+    #[test]
+    fn test_primers_to_region_ok2() {
+        assert_eq!(
+            primers_to_region(vec![
+                "GTGCCAGCMGCCGCGGTAA".to_string(),
+                "GTGCCAGCMGCCGCGGTAA".to_string()
+            ]),
+            "v4".to_string()
+        );
+    }
+
+    #[test]
+    fn test_primers_to_region_empty() {
+        assert_eq!(
+            primers_to_region(vec!["ZZZZZ".to_string(), "AAAAAA".to_string()]),
+            "".to_string()
+        );
+    }
+
+    #[test]
+    fn test_primers_to_region_half() {
+        let forward = vec![
+            "CCTACGGGNGGCWGCAG".to_string(),
+            "GGACTACNNGGGTATCTAAT".to_string(),
+        ];
+        assert_eq!(primers_to_region(forward.clone()), "v3-?");
+        assert_eq!(unmapped_primer(&forward), Some("GGACTACNNGGGTATCTAAT"));
+        // 515F-Y without its last base
+        let reverse = vec![
+            "GTGYCAGCMGCCGCGGTA".to_string(),
+            "GGACTACHVGGGTWTCTAAT".to_string(),
+        ];
+        assert_eq!(primers_to_region(reverse.clone()), "?-v4");
+        assert_eq!(unmapped_primer(&reverse), Some("GTGYCAGCMGCCGCGGTA"));
+        assert_eq!(unmapped_primer(&region_to_primer("v3v4").unwrap()), None);
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let summary = get_hypervar_regions(
+            "tests/test.fa",
+            &[reverse],
+            prefix.to_str().unwrap(),
+            &Config::default(),
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.regions["?-v4"], 1);
+        assert_eq!(summary.half_named, 1);
+        assert!(summary.warnings().iter().any(|w| w.contains("v3-?")));
+    }
+
+    #[test]
+    fn test_gene_regions() {
+        assert_eq!(gene_regions("16S").len(), 10);
+        assert_eq!(gene_regions("ITS"), vec!["ITS1", "ITS2"]);
+        assert_eq!(region_gene("v3v4"), Some("16S"));
+        assert_eq!(region_gene("18S-v9"), Some("18S"));
+        assert_eq!(region_gene("v2v3"), None);
+        let primers = region_to_primer("ITS2").unwrap();
+        assert_eq!(primers[0], "GTGARTCATCGAATCTTTG");
+        assert_eq!(primers_to_region(primers), "ITS2");
+    }
+
+    #[test]
+    fn test_confidence() {
+        assert_eq!(Confidence::classify(0, 4, false, true), Confidence::High);
+        assert_eq!(Confidence::classify(1, 4, false, true), Confidence::Medium);
+        assert_eq!(Confidence::classify(0, 4, true, true), Confidence::Medium);
+        assert_eq!(Confidence::classify(3, 4, false, true), Confidence::Low);
+        assert_eq!(Confidence::classify(1, 4, true, true), Confidence::Low);
+        assert_eq!(Confidence::classify(0, 4, false, false), Confidence::Low);
+        assert_eq!(Confidence::Medium.to_string(), "medium");
+    }
+
+    #[test]
+    fn test_is_chimera_suspect() {
+        let v4 = region_to_primer("v4").unwrap();
+        assert!(!is_chimera_suspect(&v4, 253));
+        assert!(is_chimera_suspect(&v4, 600));
+        assert!(is_chimera_suspect(&v4, 150));
+        // Primers without E. coli positions
+        let custom = vec!["ACGT".to_string(), "TTGG".to_string()];
+        assert!(!is_chimera_suspect(&custom, 10000));
+        assert_eq!(ecoli_region(&v4, false), Some((515, 806)));
+        assert_eq!(ecoli_region(&v4, true), Some((534, 786)));
+        assert_eq!(ecoli_region(&custom, false), None);
+    }
+
+    #[test]
+    fn test_complement_dna() {
+        assert_eq!(
+            to_complement("ATCGATCGATCGATCGRYKBVDHX", "dna"),
+            String::from("TAGCTAGCTAGCTAGCYRMVBHDX")
+        );
+    }
+
+    #[test]
+    fn test_complement_rna() {
+        assert_eq!(
+            to_complement("AUCGAUCGAUCGAUCGRYKBVDHMXN", "rna"),
+            String::from("UAGCUAGCUAGCUAGCYRMVBHDKXN")
+        );
+    }
+
+    #[test]
     fn test_reverse_complement() {
         assert_eq!(
-            to_reverse_complement("GTGCCAGCMGCCGCGGTAAN", "dna"),
-            "NTTACCGCGGCKGCTGGCAC"
+            to_reverse_complement("GTGCCAGCMGCCGCGGTAAN", "dna"),
+            "NTTACCGCGGCKGCTGGCAC"
+        );
+    }
+
+    #[test]
+    fn test_sequence_type_dna_ok() {
+        assert_eq!(sequence_type("ATCGATCGATCG"), Some(Alphabet::Dna));
+    }
+
+    #[test]
+    fn test_sequence_type_dna_iupac_ok() {
+        assert_eq!(sequence_type("ATCGMTGCAATCG"), Some(Alphabet::Dna));
+    }
+
+    #[test]
+    fn test_sequence_type_rna_ok() {
+        assert_eq!(sequence_type("AGCUUUGCA"), Some(Alphabet::Rna));
+    }
+
+    #[test]
+    fn test_sequence_type_rna_iupac_ok() {
+        assert_eq!(sequence_type("GUUUUAACCCAAM"), Some(Alphabet::Rna));
+    }
+
+    #[test]
+    fn test_sequence_type_err() {
+        assert_eq!(sequence_type("ATCXXXRMGU"), None);
+    }
+
+    #[test]
+    fn test_region_to_primer_ok() {
+        assert_eq!(
+            region_to_primer("v1v2").unwrap(),
+            vec!["AGAGTTTGATCMTGGCTCAG", "ACTGCTGCSYCCCGTAGGAGTCT"]
+        );
+        assert_eq!(
+            region_to_primer("v1v3").unwrap(),
+            vec!["AGAGTTTGATCMTGGCTCAG", "ATTACCGCGGCTGCTGG"]
+        );
+        assert_eq!(
+            region_to_primer("v1v9").unwrap(),
+            vec!["AGAGTTTGATCMTGGCTCAG", "TACGGYTACCTTGTTAYGACTT"]
+        );
+        assert_eq!(
+            region_to_primer("v3v4").unwrap(),
+            vec!["CCTACGGGNGGCWGCAG", "GACTACHVGGGTATCTAATCC"]
+        );
+        assert_eq!(
+            region_to_primer("v3v5").unwrap(),
+            vec!["CCTACGGGNGGCWGCAG", "CCGTCAATTYMTTTRAGT"]
+        );
+        assert_eq!(
+            region_to_primer("v4").unwrap(),
+            vec!["GTGCCAGCMGCCGCGGTAA", "GGACTACHVGGGTWTCTAAT"]
+        );
+        assert_eq!(
+            region_to_primer("v4v5").unwrap(),
+            vec!["GTGYCAGCMGCCGCGGTAA", "CCCCGYCAATTCMTTTRAGT"]
+        );
+        assert_eq!(
+            region_to_primer("v5v7").unwrap(),
+            vec!["AACMGGATTAGATACCCKG", "ACGTCATCCCCACCTTCC"]
+        );
+        assert_eq!(
+            region_to_primer("v6v9").unwrap(),
+            vec!["TAAAACTYAAAKGAATTGACGGGG", "TACGGYTACCTTGTTAYGACTT"]
+        );
+        assert_eq!(
+            region_to_primer("v7v9").unwrap(),
+            vec!["YAACGAGCGCAACCC", "TACGGYTACCTTGTTAYGACTT"]
+        );
+        assert!(region_to_primer("").is_err());
+        assert!(region_to_primer("v2v3").is_err());
+    }
+
+    #[test]
+    fn test_region_registry() {
+        let mut registry = RegionRegistry::default();
+        assert_eq!(RegionRegistry::builtin_names().len(), 14);
+        assert!(registry.contains("ITS1"));
+        assert!(!registry.contains("v10"));
+        let err = registry.primers("v10").unwrap_err().to_string();
+        assert!(err.contains("v1v2, v1v3"));
+        for alias in ["V3V4", "v3-v4", "V3_V4"] {
+            assert_eq!(RegionRegistry::resolve(alias), Some("v3v4"));
+            assert_eq!(
+                registry.primers(alias).unwrap(),
+                registry.primers("v3v4").unwrap()
+            );
+        }
+        assert_eq!(RegionRegistry::resolve("its-2"), Some("ITS2"));
+        assert_eq!(RegionRegistry::suggest("v3v44"), Some("v3v4"));
+        assert_eq!(RegionRegistry::suggest("18S-v5"), Some("18S-v4"));
+        assert_eq!(RegionRegistry::suggest("mitochondria"), None);
+        let err = registry.primers("v3v44").unwrap_err().to_string();
+        assert!(err.contains("Did you mean v3v4?"));
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "ACGT,TTGA\nCCGG,AATT").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let path = path.as_str();
+        registry.load(path).unwrap();
+        assert!(registry.contains(path));
+        assert_eq!(
+            registry.primers(path).unwrap(),
+            vec![vec!["ACGT", "TTGA"], vec!["CCGG", "AATT"]]
+        );
+
+        writeln!(file, "ACGT,").unwrap();
+        assert!(registry.load(path).is_err());
+    }
+
+    #[test]
+    fn test_read_primers_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "ACGT,TTGA\nCCGG,AATT,2,,500,amp\n\nGGCC,AATT,,100")
+            .unwrap();
+        let pairs = read_primers_file(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(
+            pairs[0],
+            (
+                vec!["ACGT".to_string(), "TTGA".to_string()],
+                PairOptions::default()
+            )
+        );
+        assert_eq!(
+            pairs[1].1,
+            PairOptions {
+                mismatch: Some(2),
+                min_length: None,
+                max_length: Some(500),
+                label: Some("amp".to_string()),
+            }
+        );
+        assert_eq!(pairs[2].1.min_length, Some(100));
+
+        for line in ["ACGT", "ACGT,TTGA,x", "ACGT,TTGA,,600,500", "A,T,300"] {
+            let mut file = NamedTempFile::new().unwrap();
+            writeln!(file, "{}", line).unwrap();
+            assert!(read_primers_file(file.path().to_str().unwrap()).is_err());
+        }
+
+        // TOML primers files, written back as read
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "# Pairs of the lab\n\
+            [[pair]]\nforward = \"ACGT\"\nreverse = \"TTGA\"\n\n\
+            [[pair]]\nforward = \"CCGG\"\nreverse = \"AATT\"\n\
+            mismatch = 2\nmax_length = 500\nlabel = \"amp, long\""
+        )
+        .unwrap();
+        let toml_pairs =
+            read_primers_file(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(toml_pairs[0], pairs[0]);
+        assert_eq!(
+            toml_pairs[1].1,
+            PairOptions {
+                label: Some("amp, long".to_string()),
+                ..pairs[1].1.clone()
+            }
+        );
+        let mut file = NamedTempFile::new().unwrap();
+        write!(file, "{}", primers_to_toml(&toml_pairs).unwrap()).unwrap();
+        assert_eq!(
+            read_primers_file(file.path().to_str().unwrap()).unwrap(),
+            toml_pairs
+        );
+        for toml in [
+            "[[pair]]\nforward = \"ACGT\"",
+            "[[pair]]\nforward = \"ACGT\"\nreverse = \"TTGA\"\nmismatches = 1",
+            "[[pair]]\nforward = \"ACGT\"\nreverse = \"TTGA\"\n\
+            min_length = 600\nmax_length = 500",
+        ] {
+            let mut file = NamedTempFile::new().unwrap();
+            writeln!(file, "{}", toml).unwrap();
+            assert!(read_primers_file(file.path().to_str().unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_pair_options() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v3v4").unwrap()];
+        let mut options = PairOptions {
+            min_length: Some(500),
+            label: Some("V3-V4".to_string()),
+            ..Default::default()
+        };
+        let mut config = Config {
+            pair_options: vec![(primers[0].clone(), options.clone())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(config.region_name(&primers[0]), "V3-V4");
+        assert_eq!(config.pair_mismatch(&primers[0]), 0);
+        // 17 and 21 bases primers with 10% of mismatches
+        let identity = Config {
+            min_primer_identity: Some(0.9),
+            ..config.clone()
+        };
+        assert_eq!(identity.primer_mismatch(&primers[0], &primers[0][0]), 1);
+        assert_eq!(identity.primer_mismatch(&primers[0], &primers[0][1]), 2);
+        let run = |config: &Config| {
+            get_hypervar_regions(
+                "tests/test.fa",
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap()
+        };
+
+        // The 441 bases amplicon is too short for the pair
+        assert_eq!(run(&config).extracted, 0);
+
+        options.min_length = None;
+        options.max_length = Some(450);
+        config.pair_options.insert(primers[0].clone(), options);
+        assert_eq!(run(&config).extracted, 1);
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        assert!(fasta.contains("V3-V4"));
+    }
+
+    #[test]
+    fn test_write_fa_ok2() {
+        let mut tmpfile =
+            NamedTempFile::new().expect("Cannot create temp file");
+        writeln!(tmpfile, ">id_str desc\nATCGCCG")
+            .expect("Cannot write to tmp file");
+
+        let mut fa_records = fasta::Reader::from_file(tmpfile)
+            .expect("Cannot read file.")
+            .records();
+
+        while let Some(Ok(rec)) = fa_records.next() {
+            assert_eq!(rec.id(), "id_str");
+            assert_eq!(rec.desc(), Some("desc"));
+            assert_eq!(rec.seq(), b"ATCGCCG");
+        }
+    }
+
+    #[test]
+    fn test_combine_vec() {
+        let first = vec!["ab", "cd", "ef"];
+        let second = vec!["cd", "ef", "gh"];
+        assert_eq!(
+            combine_vec(first, second).unwrap(),
+            vec![
+                vec!["ab".to_string(), "cd".to_string()],
+                vec!["cd".to_string(), "ef".to_string()],
+                vec!["ef".to_string(), "gh".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_primer_db_version() {
+        let directive = format!("#!primer-db-version {}", PRIMER_DB_VERSION);
+        assert!(GFF_HEADER.lines().any(|line| line == directive));
+    }
+
+    #[test]
+    fn test_cross_vec() {
+        let pairs = cross_vec(vec!["ab", "cd"], vec!["ef", "gh", "ij"]);
+        assert_eq!(pairs.len(), 6);
+        assert_eq!(pairs[0], vec!["ab".to_string(), "ef".to_string()]);
+        assert_eq!(pairs[5], vec!["cd".to_string(), "ij".to_string()]);
+    }
+
+    #[test]
+    fn test_combine_vec_not_ok() {
+        let first = vec!["ab", "cd", "ef"];
+        let second = vec!["ab"];
+        assert!(combine_vec(first, second).is_err());
+    }
+
+    #[test]
+    fn test_get_hypervar_regions() {
+        assert!(get_hypervar_regions(
+            "tests/test.fa.gz",
+            &[vec![
+                "AGAGTTTGATCMTGGCTCAG".to_string(),
+                "TACGGYTACCTTGTTAYGACTT".to_string()
+            ]],
+            "hyperex",
+            &Config::default(),
+            &mut ExtraOutputs::default(),
+            None
+        )
+        .is_ok());
+        fs::remove_file("hyperex.fa").expect("cannot delete file");
+        fs::remove_file("hyperex.gff").expect("cannot delete file");
+        fs::remove_file("hyperex.tsv").expect("cannot delete file");
+        fs::remove_file("hyperex.hits.tsv").expect("cannot delete file");
+        fs::remove_file("hyperex.skipped.tsv").expect("cannot delete file");
+    }
+
+    #[test]
+    fn test_check_primer_pairs() {
+        let pair = |f: &str, r: &str| vec![vec![f.to_string(), r.to_string()]];
+        let all = ["v1v2", "v1v3", "v1v9", "v3v4", "v4", "v4v5", "v7v9"]
+            .iter()
+            .map(|region| region_to_primer(region).unwrap())
+            .collect::<Vec<_>>();
+        assert!(check_primer_pairs(&all).is_ok());
+        assert!(check_primer_pairs(&pair("ACGTTG", "ACGTTG")).is_err());
+        // 805R given as forward and 341F as reverse
+        assert!(check_primer_pairs(&pair(
+            "GACTACHVGGGTATCTAATCC",
+            "CCTACGGGNGGCWGCAG"
+        ))
+        .is_err());
+        // 799F (v5) before 534R (v3)
+        assert!(check_primer_pairs(&pair(
+            "AACMGGATTAGATACCCKG",
+            "ATTACCGCGGCTGCTGG"
+        ))
+        .is_err());
+        assert!(check_primer_pairs(&pair("AACCGGTTA", "GGTT")).is_err());
+        assert!(check_primer_pairs(&pair("AACCGGTTA", "TTTGGG")).is_ok());
+    }
+
+    #[test]
+    fn test_check_mismatches() {
+        let primers =
+            vec![vec!["ACGTACGTAC".to_string(), "TTGGCCA".to_string()]];
+        let config = |mismatch| Config {
+            mismatch,
+            ..Default::default()
+        };
+        assert!(check_mismatches(&primers, &config(6)).is_ok());
+        let err = check_mismatches(&primers, &config(7)).unwrap_err();
+        assert!(err.to_string().contains("primer TTGGCCA of 7 bases"));
+
+        // Mismatches of the pair override the command-line ones
+        let mut overridden = config(0);
+        overridden.pair_options.insert(
+            primers[0].clone(),
+            PairOptions {
+                mismatch: Some(8),
+                ..Default::default()
+            },
+        );
+        assert!(check_mismatches(&primers, &overridden).is_err());
+        let identity = Config {
+            min_primer_identity: Some(0.0),
+            ..Default::default()
+        };
+        assert!(check_mismatches(&primers, &identity).is_err());
+    }
+
+    #[test]
+    fn test_anchored() {
+        assert_eq!(terminal_windows(100, 30), vec![(0, 30), (70, 100)]);
+        assert_eq!(terminal_windows(50, 30), vec![(0, 50)]);
+
+        // v3v4 amplicon read with a few extra bases at both ends, and the
+        // whole test sequence where the primers are internal
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(
+            &input,
+            format!(">read\nACG{}TT\n>full\n{}\n", &seq[267..707], seq),
+        )
+        .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            anchored: Some(5),
+            ..Default::default()
+        };
+        let mut ids = Vec::new();
+        let mut on_match = |_: &RegionMatch, record: &fasta::Record| {
+            ids.push(record.id().to_string());
+        };
+        get_hypervar_regions(
+            input.to_str().unwrap(),
+            &[region_to_primer("v3v4").unwrap()],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            Some(&mut on_match),
+        )
+        .unwrap();
+        assert_eq!(ids, ["read"]);
+    }
+
+    #[test]
+    fn test_chunk_windows() {
+        assert_eq!(chunk_windows(10, 100, 5), vec![(0, 10)]);
+        assert_eq!(
+            chunk_windows(25, 10, 4),
+            vec![(0, 10), (6, 16), (12, 22), (18, 25)]
+        );
+        // Windows never shorter than the overlap
+        assert_eq!(chunk_windows(6, 2, 4), vec![(0, 5), (1, 6)]);
+
+        // Same hits as searching the whole record, a hit spanning two
+        // windows included
+        let config = Config::default();
+        let matcher = Matcher::new(&config.ambigs, config.no_indels);
+        let text = b"TTTTTTTACGTACGTTTTTTTTTACGTACGTTT";
+        let windows = chunk_windows(text.len(), 12, 8);
+        assert_eq!(
+            matcher.all_hits(b"ACGTACGT", text, 0, Some(&windows)),
+            matcher.all_hits(b"ACGTACGT", text, 0, None)
+        );
+        assert_eq!(
+            matcher.best_hit(b"ACGTACGT", text, 0, Some(&windows)),
+            Some((7, 15, 0))
         );
     }
 
     #[test]
-    fn test_sequence_type_dna_ok() {
-        assert_eq!(sequence_type("ATCGATCGATCG"), Some(Alphabet::Dna));
+    fn test_pair_amplicons() {
+        let forward = [(0, 10, 0), (20, 30, 0), (100, 110, 0), (300, 310, 0)];
+        let reverse = [(50, 60, 0), (150, 160, 1)];
+        assert_eq!(
+            pair_amplicons(&forward, &reverse, &(0..=usize::MAX), None, None),
+            vec![((20, 30, 0), (50, 60, 0)), ((100, 110, 0), (150, 160, 1))]
+        );
+        assert_eq!(
+            pair_amplicons(&forward, &reverse, &(0..=50), None, None),
+            vec![((20, 30, 0), (50, 60, 0))]
+        );
+        // Reverse hits searched within 30 bases of the forward hits
+        assert_eq!(
+            pair_amplicons(
+                &forward,
+                &reverse,
+                &(0..=usize::MAX),
+                Some(30),
+                None
+            ),
+            vec![((20, 30, 0), (50, 60, 0))]
+        );
+        assert_eq!(
+            pair_amplicons(
+                &forward,
+                &reverse,
+                &(0..=usize::MAX),
+                Some(40),
+                None
+            ),
+            vec![((20, 30, 0), (50, 60, 0)), ((100, 110, 0), (150, 160, 1))]
+        );
     }
 
     #[test]
-    fn test_sequence_type_dna_iupac_ok() {
-        assert_eq!(sequence_type("ATCGMTGCAATCG"), Some(Alphabet::Dna));
+    fn test_best_amplicon() {
+        // Two operons, the best forward hit in the first one and the best
+        // reverse hit in the second one
+        let forward = [(0, 10, 0), (1000, 1010, 1)];
+        let reverse = [(400, 410, 1), (1400, 1410, 0)];
+        assert_eq!(
+            best_amplicon(&forward, &reverse, &(0..=usize::MAX), None, None),
+            Some(((0, 10, 0), (400, 410, 1)))
+        );
+        assert_eq!(
+            best_amplicon(&forward, &reverse, &(0..=300), None, None),
+            None
+        );
+        assert_eq!(
+            best_amplicon(&forward, &reverse, &(500..=1000), None, None),
+            None
+        );
+        // Reverse hit upstream of the forward one
+        let lengths = 0..=usize::MAX;
+        assert_eq!(
+            best_amplicon(
+                &[(500, 510, 0)],
+                &reverse[..1],
+                &lengths,
+                None,
+                None
+            ),
+            None
+        );
     }
 
     #[test]
-    fn test_sequence_type_rna_ok() {
-        assert_eq!(sequence_type("AGCUUUGCA"), Some(Alphabet::Rna));
+    fn test_get_hypervar_regions_all_matches() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let mut record = fasta::Reader::from_file("tests/test.fa")
+            .unwrap()
+            .records()
+            .next()
+            .unwrap()
+            .unwrap()
+            .seq()
+            .to_vec();
+        record.extend_from_within(..);
+        let input = dir.path().join("repeat.fa");
+        let mut writer = fasta::Writer::to_file(&input).unwrap();
+        writer.write("repeat", None, &record).unwrap();
+        writer.flush().unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v3v4").unwrap()];
+
+        let mut config = Config::default();
+        let mut extras = ExtraOutputs::default();
+        let summary = get_hypervar_regions(
+            input,
+            &primers,
+            prefix,
+            &config,
+            &mut extras,
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.extracted, 1);
+
+        config.all_matches = true;
+        let summary = get_hypervar_regions(
+            input,
+            &primers,
+            prefix,
+            &config,
+            &mut extras,
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.extracted, 2);
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        assert!(fasta.contains("location=267-707"));
+        assert!(fasta.contains("location=1620-2060 operon=operon_2"));
+        let operons =
+            fs::read_to_string(format!("{}.operons.tsv", prefix)).unwrap();
+        assert_eq!(
+            operons.lines().nth(1),
+            Some("repeat\tv3v4\toperon_1\toperon_2\t1.0000")
+        );
+
+        config.max_amplicons = Some(1);
+        let summary = get_hypervar_regions(
+            input,
+            &primers,
+            prefix,
+            &config,
+            &mut extras,
+            None,
+        )
+        .unwrap();
+        assert_eq!((summary.extracted, summary.suppressed), (1, 1));
     }
 
     #[test]
-    fn test_sequence_type_rna_iupac_ok() {
-        assert_eq!(sequence_type("GUUUUAACCCAAM"), Some(Alphabet::Rna));
+    fn test_records_tsv() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![
+            region_to_primer("v3v4").unwrap(),
+            region_to_primer("v4").unwrap(),
+        ];
+
+        let summary = get_hypervar_regions(
+            "tests/test.fa",
+            &primers,
+            prefix,
+            &Config::default(),
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!((summary.full_length, summary.fragment), (0, 1));
+        assert_eq!(
+            fs::read_to_string(format!("{}.tsv", prefix)).unwrap(),
+            "id\tlength\tcompleteness\tregions\n\
+            Allorhizobium_borbori__DN316__EF125187\t1353\tfragment\tv3v4,v4\n"
+        );
+        let hits = fs::read_to_string(format!("{}.hits.tsv", prefix)).unwrap();
+        let lines = hits.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[1],
+            "Allorhizobium_borbori__DN316__EF125187\tv3v4\tCCTACGGGNGGCWGCAG\tforward\t267\t284\t0\t17=\tCCTACGGGAGGCAGCAG"
+        );
+        // Reverse primer sites are read in the primer orientation
+        assert!(lines[2].ends_with(
+            "\tGACTACHVGGGTATCTAATCC\treverse\t686\t707\t0\t21=\tGACTACCAGGGTATCTAATCC"
+        ));
     }
 
     #[test]
-    fn test_sequence_type_err() {
-        assert_eq!(sequence_type("ATCXXXRMGU"), None);
+    fn test_completeness() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        fs::write(
+            &input,
+            format!(
+                ">full\nAGAGTTTGATCATGGCTCAG{}AAGTCGTAACAAGGTAGCCGTA\n",
+                "ACCTGA".repeat(50)
+            ),
+        )
+        .unwrap();
+        let input = input.to_str().unwrap();
+
+        // Terminal primers found from the hits of v1v9, or searched alone
+        for region in ["v1v9", "v3v4"] {
+            let summary = get_hypervar_regions(
+                input,
+                &[region_to_primer(region).unwrap()],
+                prefix,
+                &Config::default(),
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(summary.full_length, 1);
+        }
+
+        // Sequences searched for 18S regions only are not 16S sequences
+        let summary = get_hypervar_regions(
+            input,
+            &[region_to_primer("18S-v4").unwrap()],
+            prefix,
+            &Config::default(),
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!((summary.full_length, summary.fragment), (0, 1));
     }
 
     #[test]
-    fn test_region_to_primer_ok() {
+    fn test_gff_labels() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            gff: GffLabels::new("SILVA", "rRNA_hypervariable_region").unwrap(),
+            ..Default::default()
+        };
+        get_hypervar_regions(
+            "tests/test.fa",
+            &[region_to_primer("v4").unwrap()],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff
+            .lines()
+            .nth(2)
+            .unwrap()
+            .contains("\tSILVA\trRNA_hypervariable_region\t"));
+        assert!(GffLabels::new("", "region").is_err());
+        assert!(GffLabels::new("hyperex", "a\tb").is_err());
+    }
+
+    #[test]
+    fn test_flush_every() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n{}\n>b\n{}\n", seq, seq)).unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        // Whether the region of the first record is on disk when the
+        // second one is matched
+        let run = |config: &Config| {
+            let mut written = false;
+            let mut on_match = |_: &RegionMatch, record: &fasta::Record| {
+                if record.id() == "b" {
+                    let fasta = fs::read_to_string(format!("{}.fa", prefix));
+                    written = fasta.unwrap().starts_with(">a ");
+                }
+            };
+            get_hypervar_regions(
+                input,
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                Some(&mut on_match),
+            )
+            .unwrap();
+            written
+        };
+        assert!(!run(&Config::default()));
+        let config = Config {
+            flush_every: Some(1),
+            fsync: true,
+            ..Default::default()
+        };
+        assert!(run(&config));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n{}\n>b\n{}\n", seq, seq)).unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let flag = Arc::new(AtomicBool::new(false));
+        let config = Config {
+            interrupt: Some(Arc::clone(&flag)),
+            ..Default::default()
+        };
+        // Interrupted while the first record is processed
+        let mut on_match = |_: &RegionMatch, _: &fasta::Record| {
+            flag.store(true, Ordering::Relaxed);
+        };
+        let summary = get_hypervar_regions(
+            input,
+            &[region_to_primer("v4").unwrap()],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            Some(&mut on_match),
+        )
+        .unwrap();
+        assert_eq!(summary.sequences, 1);
         assert_eq!(
-            region_to_primer("v1v2").unwrap(),
-            vec!["AGAGTTTGATCMTGGCTCAG", "ACTGCTGCSYCCCGTAGGAGTCT"]
+            summary.checkpoints,
+            vec![Checkpoint {
+                file: input.to_string(),
+                records: 1,
+                last_id: Some("a".to_string()),
+            }]
         );
+        assert!(summary.porcelain().starts_with("INTERRUPTED sequences=1"));
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.ends_with(&format!(
+            "# partial run: interrupted after 1 records of {}\n",
+            input
+        )));
+    }
+
+    #[test]
+    fn test_record_guards() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n{}\n>b\n{}{}\n", seq, seq, seq))
+            .unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let run = |config: &Config| {
+            get_hypervar_regions(
+                input,
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap()
+        };
+
+        let config = Config {
+            max_record_len: Some(seq.len()),
+            ..Default::default()
+        };
+        let summary = run(&config);
+        assert_eq!((summary.sequences, summary.too_long), (1, 1));
+        assert_eq!(summary.extracted, 1);
+        let config = Config {
+            record_timeout: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let summary = run(&config);
+        assert_eq!((summary.sequences, summary.timed_out), (0, 2));
+        assert_eq!(run(&Config::default()).sequences, 2);
+    }
+
+    #[test]
+    fn test_record_timeout_within_search() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        fs::write(&input, format!(">a\n{}\n", "ACGT".repeat(500_000))).unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let run = |config: &Config| {
+            let start = Instant::now();
+            let summary = get_hypervar_regions(
+                input,
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            (summary, start.elapsed())
+        };
+
+        let config = Config {
+            chunk_size: Some(100_000),
+            ..Default::default()
+        };
+        let (summary, searched) = run(&config);
+        assert_eq!((summary.sequences, summary.timed_out), (1, 0));
+        // A single pair is given up within its scans of the record, not
+        // once they are over
+        let (summary, given_up) = run(&Config {
+            record_timeout: Some(Duration::from_millis(1)),
+            ..config
+        });
+        assert_eq!((summary.sequences, summary.timed_out), (0, 1));
+        assert!(given_up < searched / 4);
+    }
+
+    #[test]
+    fn test_skipped_tsv() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(
+            &input,
+            format!(
+                ">a\n{}\n>b\nACGTQQ\n>c\n{}\n>d\n{}{}\n>e\nACGTAC\n",
+                seq,
+                "ACGT".repeat(100),
+                seq,
+                seq
+            ),
+        )
+        .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let config = Config {
+            max_record_len: Some(seq.len()),
+            ..Default::default()
+        };
+
+        get_hypervar_regions(
+            input.to_str().unwrap(),
+            &primers,
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        let skipped =
+            fs::read_to_string(format!("{}.skipped.tsv", prefix)).unwrap();
+        let lines = skipped.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], SKIPPED_TSV_HEADER.trim_end());
         assert_eq!(
-            region_to_primer("v1v3").unwrap(),
-            vec!["AGAGTTTGATCMTGGCTCAG", "ATTACCGCGGCTGCTGG"]
+            lines[1],
+            "b\t-\tunknown_alphabet\tsequence type is not recognized as DNA or RNA"
         );
+        assert!(lines[2].starts_with("c\tv4\tno_hits\tprimers "));
+        assert!(lines[3].starts_with("d\t-\ttoo_long\t"));
         assert_eq!(
-            region_to_primer("v1v9").unwrap(),
-            vec!["AGAGTTTGATCMTGGCTCAG", "TACGGYTACCTTGTTAYGACTT"]
+            lines[4],
+            "e\tv4\ttoo_short\t6 bp, shorter than the primers"
         );
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_anchored_amplicon() {
+        let forward = [(10, 30, 1), (100, 120, 0), (200, 220, 0)];
+        let reverse = [(400, 420, 0), (500, 520, 0)];
         assert_eq!(
-            region_to_primer("v3v4").unwrap(),
-            vec!["CCTACGGGNGGCWGCAG", "GACTACHVGGGTATCTAATCC"]
+            anchored_amplicon(&forward, &reverse, 1000, Anchor::Forward),
+            Some(((100, 120, 0), (1000, 1000, 0), Strand::Plus))
         );
         assert_eq!(
-            region_to_primer("v3v5").unwrap(),
-            vec!["CCTACGGGNGGCWGCAG", "CCGTCAATTYMTTTRAGT"]
+            anchored_amplicon(&forward, &reverse, 1000, Anchor::Reverse),
+            Some(((0, 0, 0), (500, 520, 0), Strand::Plus))
         );
         assert_eq!(
-            region_to_primer("v4").unwrap(),
-            vec!["GTGCCAGCMGCCGCGGTAA", "GGACTACHVGGGTWTCTAAT"]
+            anchored_amplicon(&forward, &reverse, 1000, Anchor::Both),
+            None
         );
         assert_eq!(
-            region_to_primer("v4v5").unwrap(),
-            vec!["GTGYCAGCMGCCGCGGTAA", "CCCCGYCAATTCMTTTRAGT"]
+            anchored_amplicon(&[], &reverse, 1000, Anchor::Forward),
+            None
         );
+        assert_eq!("reverse".parse::<Anchor>().unwrap(), Anchor::Reverse);
+
+        // v3v4 of the test sequence truncated after v4
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n{}\n", &seq[..650])).unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            anchor: Anchor::Forward,
+            ..Default::default()
+        };
+        let summary = get_hypervar_regions(
+            input.to_str().unwrap(),
+            &[region_to_primer("v3v4").unwrap()],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(summary.extracted, 1);
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        assert!(fasta.lines().next().unwrap().ends_with(" anchored=forward"));
+        assert_eq!(fasta.lines().nth(1).unwrap(), &seq[267..650]);
+        let hits = fs::read_to_string(format!("{}.hits.tsv", prefix)).unwrap();
+        assert_eq!(hits.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_shared_primers() {
+        let primers = ["v3v4", "v3v5", "v4"]
+            .iter()
+            .map(|region| region_to_primer(region).unwrap())
+            .collect::<Vec<_>>();
+        // Regions are the same whether the pairs sharing primers are
+        // searched together or apart
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        for both_strands in [false, true] {
+            let config = Config {
+                both_strands,
+                ..Default::default()
+            };
+            let run = |primers: &[Vec<String>], name: &str| {
+                let prefix = dir.path().join(name);
+                let prefix = prefix.to_str().unwrap();
+                get_hypervar_regions(
+                    "tests/test.fa",
+                    primers,
+                    prefix,
+                    &config,
+                    &mut ExtraOutputs::default(),
+                    None,
+                )
+                .unwrap();
+                fs::read_to_string(format!("{}.fa", prefix)).unwrap()
+            };
+            let together = run(&primers, "together");
+            let apart = primers
+                .iter()
+                .enumerate()
+                .map(|(i, pair)| {
+                    run(std::slice::from_ref(pair), &i.to_string())
+                })
+                .collect::<String>();
+            assert_eq!(together.matches('>').count(), 3);
+            assert_eq!(together, apart);
+        }
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let run = |config: &Config| {
+            get_hypervar_regions(
+                "tests/test.fa",
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+        };
+        let config = Config {
+            append: true,
+            ..Default::default()
+        };
+        run(&config).unwrap();
+        // Records already in the outputs are skipped
+        let summary = run(&config).unwrap();
+        assert_eq!((summary.sequences, summary.duplicates), (0, 1));
+        let skipped =
+            fs::read_to_string(format!("{}.skipped.tsv", prefix)).unwrap();
+        assert!(skipped.lines().any(|line| line.contains("\tduplicate\t")));
+        let tsv = fs::read_to_string(format!("{}.tsv", prefix)).unwrap();
+        assert_eq!(tsv.matches(RECORDS_TSV_HEADER).count(), 1);
+        assert_eq!(tsv.lines().count(), 2);
+        // Unless the ids of the outputs are given
+        let summary = run(&Config {
+            seen_ids: Some(Arc::new(HashSet::new())),
+            ..config.clone()
+        })
+        .unwrap();
+        assert_eq!((summary.sequences, summary.duplicates), (1, 0));
+        let tsv = fs::read_to_string(format!("{}.tsv", prefix)).unwrap();
+        assert_eq!(tsv.lines().count(), 3);
+        let gff_path = format!("{}.gff", prefix);
+        let gff = fs::read_to_string(&gff_path).unwrap();
+        assert_eq!(gff.matches(GFF_HEADER).count(), 1);
+        assert_eq!(gff.lines().count(), 4);
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        assert_eq!(fasta.matches('>').count(), 2);
+        // Regions not extracted from the records yet are still appended
+        let summary = get_hypervar_regions(
+            "tests/test.fa",
+            &[
+                region_to_primer("v4").unwrap(),
+                region_to_primer("v4v5").unwrap(),
+            ],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!((summary.sequences, summary.duplicates), (1, 1));
+        let tsv = fs::read_to_string(format!("{}.tsv", prefix)).unwrap();
+        assert!(tsv.lines().last().unwrap().ends_with("\tv4v5"));
+        let skipped =
+            fs::read_to_string(format!("{}.skipped.tsv", prefix)).unwrap();
+        assert!(skipped.lines().any(|line| line.contains("\tv4\tduplicate")));
+
+        // Outputs with other columns are not appended to
+        fs::write(format!("{}.hits.tsv", prefix), "id\tregion\n").unwrap();
+        assert!(run(&config).is_err());
+        // Outputs are overwritten otherwise
+        run(&Config::default()).unwrap();
+        assert_eq!(fs::read_to_string(&gff_path).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(Sanitize::Strip.apply(b"--AC*GT*"), Some(b"ACGT".to_vec()));
         assert_eq!(
-            region_to_primer("v5v7").unwrap(),
-            vec!["AACMGGATTAGATACCCKG", "ACGTCATCCCCACCTTCC"]
+            Sanitize::ToN.apply(b"--AC*GT*"),
+            Some(b"NNACNGTN".to_vec())
+        );
+        assert_eq!(Sanitize::Error.apply(b"--ACGT"), None);
+        assert_eq!(Sanitize::Strip.apply(b"ACGT"), None);
+        assert_eq!("to-N".parse::<Sanitize>().unwrap(), Sanitize::ToN);
+        assert!("to-n".parse::<Sanitize>().is_err());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n--{}**\n", seq)).unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        for (sanitize, extracted) in [
+            (Sanitize::Error, 0),
+            (Sanitize::Strip, 1),
+            (Sanitize::ToN, 1),
+        ] {
+            let config = Config {
+                sanitize,
+                ..Default::default()
+            };
+            let summary = get_hypervar_regions(
+                input.to_str().unwrap(),
+                &primers,
+                prefix,
+                &config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(summary.extracted, extracted);
+            assert_eq!(summary.sanitized, extracted);
+        }
+    }
+
+    #[test]
+    fn test_rescue() {
+        // One substitution in the reverse primer site of v4
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        let site = seq.find("GATACCCTGGTAGTCC").unwrap();
+        let mut seq = seq.into_bytes();
+        seq[site + 3] = b'G';
+        fs::write(&input, [b">a\n", &seq[..], b"\n"].concat()).unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        for (rescue, extracted) in [(None, 0), (Some(1), 1)] {
+            let prefix = dir.path().join(format!("{:?}", rescue));
+            let config = Config {
+                rescue,
+                ..Default::default()
+            };
+            let summary = get_hypervar_regions(
+                input.to_str().unwrap(),
+                &primers,
+                prefix.to_str().unwrap(),
+                &config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                (summary.extracted, summary.rescued),
+                (extracted, extracted)
+            );
+        }
+        let fasta = fs::read_to_string(dir.path().join("Some(1).fa")).unwrap();
+        assert!(fasta.contains(" confidence=low "));
+        assert!(fasta.contains(" rescued=true"));
+    }
+
+    #[test]
+    fn test_header_filter() {
+        let filter = "Lactobacillus".parse::<HeaderFilter>().unwrap();
+        assert!(filter.matches("a", Some("Bacilli;Lactobacillus")));
+        assert!(!filter.matches("Lactobacillales", None));
+        let filter = "regex:^b\\d".parse::<HeaderFilter>().unwrap();
+        assert!(filter.matches("b1", None));
+        assert!(!filter.matches("a", Some("b1")));
+        assert!("regex:(".parse::<HeaderFilter>().is_err());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(
+            &input,
+            format!(
+                ">a Lactobacillus casei\n{}\n>b Bacillus subtilis\n{}\n\
+                >c Lactobacillus sakei\nACGT\n",
+                seq, seq
+            ),
+        )
+        .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            header_filter: Some("Lactobacillus".parse().unwrap()),
+            ..Default::default()
+        };
+        let summary = get_hypervar_regions(
+            input.to_str().unwrap(),
+            &[region_to_primer("v4").unwrap()],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!((summary.filtered_in, summary.filtered_out), (2, 1));
+        assert_eq!(summary.extracted, 1);
+        let records = fs::read_to_string(format!("{}.tsv", prefix)).unwrap();
+        assert!(!records.contains("\nb\t"));
+    }
+
+    #[test]
+    fn test_subsample() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let records = (0..40)
+            .map(|i| format!(">r{}\nACGTACGTACGT\n", i))
+            .collect::<String>();
+        fs::write(&input, records).unwrap();
+        let kept = |seed: u64, name: &str| {
+            let prefix = dir.path().join(name);
+            let prefix = prefix.to_str().unwrap();
+            let config = Config {
+                subsample: Some(0.5),
+                seed: Some(seed),
+                ..Default::default()
+            };
+            let summary = get_hypervar_regions(
+                input.to_str().unwrap(),
+                &[region_to_primer("v4").unwrap()],
+                prefix,
+                &config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(summary.sequences + summary.sampled_out, 40);
+            fs::read_to_string(format!("{}.tsv", prefix)).unwrap()
+        };
+
+        let first = kept(3, "a");
+        assert_eq!(kept(3, "b"), first);
+        assert_ne!(kept(4, "c"), first);
+        let lines = first.lines().count() - 1;
+        assert!(lines > 0 && lines < 40);
+    }
+
+    #[test]
+    fn test_mixed_alphabet() {
+        assert_eq!(sampled_sequence_type(b"ACGTACGU", 4), Some(Alphabet::Dna));
+        assert_eq!(sampled_sequence_type(b"ACGTACGU", 0), None);
+        assert_eq!(sampled_sequence_type(b"ACGU", 100), Some(Alphabet::Rna));
+        assert_eq!("dna".parse::<MixedAlphabet>().unwrap(), MixedAlphabet::Dna);
+        assert!("treat-as-dna".parse::<MixedAlphabet>().is_err());
+
+        // Both T and U, the U past the first 100 bases
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n{}UUU\n", seq)).unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let run = |alphabet_sample, mixed_alphabet| {
+            let config = Config {
+                alphabet_sample,
+                mixed_alphabet,
+                ..Default::default()
+            };
+            get_hypervar_regions(
+                input.to_str().unwrap(),
+                &primers,
+                prefix,
+                &config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+        };
+        assert_eq!(run(0, MixedAlphabet::Skip).unwrap().extracted, 0);
+        let skipped =
+            fs::read_to_string(format!("{}.skipped.tsv", prefix)).unwrap();
+        assert!(skipped.contains("a\t-\tunknown_alphabet\t"));
+        assert_eq!(run(0, MixedAlphabet::Dna).unwrap().extracted, 1);
+        assert!(run(0, MixedAlphabet::Error).is_err());
+        assert_eq!(run(100, MixedAlphabet::Error).unwrap().extracted, 1);
+    }
+
+    #[test]
+    fn test_id_parser() {
+        let ncbi = "ncbi".parse::<IdParser>().unwrap();
+        let gi = "gi|42|ref|NR_000001.1|";
+        assert_eq!(ncbi.parse(gi, None).unwrap(), "NR_000001.1");
+        assert_eq!(ncbi.parse("lcl|seq1", None).unwrap(), "seq1");
+        assert_eq!(ncbi.parse("gi|42|", None), None);
+        assert_eq!(ncbi.parse("NR_000001.1", None), None);
+        assert_eq!(IdParser::FirstWord.parse(gi, None), None);
+
+        let re = r"regex:strain=(\S+)".parse::<IdParser>().unwrap();
+        assert_eq!(re.parse("a", Some("E. coli strain=K12")).unwrap(), "K12");
+        assert_eq!(re.parse("a", Some("E. coli")), None);
+        let re = r"regex:^\w+".parse::<IdParser>().unwrap();
+        assert_eq!(re.parse("a_1|x", None).unwrap(), "a_1");
+        assert!("regex:(".parse::<IdParser>().is_err());
+        assert!("first".parse::<IdParser>().is_err());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">{} E. coli\n{}\n", gi, seq)).unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            id_parser: ncbi,
+            ..Default::default()
+        };
+        get_hypervar_regions(
+            input.to_str().unwrap(),
+            &[region_to_primer("v4").unwrap()],
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        assert!(fasta.starts_with(">NR_000001.1 "));
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.contains("\nNR_000001.1\t"));
+        assert!(gff.contains("ID=NR_000001.1.v4;"));
+    }
+
+    #[test]
+    fn test_longest_disjoint() {
+        // v3v4, the nested v4 and a disjoint region
+        let spans = [(100, 560), (230, 560), (600, 900), (550, 620)];
+        assert_eq!(longest_disjoint(&spans), vec![true, false, true, false]);
+        assert_eq!(longest_disjoint(&[(0, 10), (10, 20)]), vec![true, true]);
+        assert!(longest_disjoint(&[]).is_empty());
+        assert_eq!(
+            "keep-longest".parse::<Overlaps>().unwrap(),
+            Overlaps::KeepLongest
         );
+        assert!("longest".parse::<Overlaps>().is_err());
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![
+            region_to_primer("v3v4").unwrap(),
+            region_to_primer("v4").unwrap(),
+        ];
+        let config = Config {
+            overlaps: Overlaps::KeepLongest,
+            ..Default::default()
+        };
+        let summary = get_hypervar_regions(
+            "tests/test.fa",
+            &primers,
+            prefix,
+            &config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!((summary.extracted, summary.overlapping), (1, 1));
+        assert_eq!(summary.regions.keys().collect::<Vec<_>>(), ["v3v4"]);
+    }
+
+    #[test]
+    fn test_primer_dimers() {
+        // v4 primers sharing their last base
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let flank = "T".repeat(100);
+        fs::write(
+            &input,
+            format!(
+                ">a\n{}GTGCCAGCAGCCGCGGTAATTAGATACCCTGGTAGTCC{}\n",
+                flank, flank
+            ),
+        )
+        .unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        for (keep_primer_dimers, extracted) in [(false, 0), (true, 1)] {
+            let prefix = dir.path().join(format!("{}", keep_primer_dimers));
+            let prefix = prefix.to_str().unwrap();
+            let config = Config {
+                keep_primer_dimers,
+                ..Default::default()
+            };
+            let summary = get_hypervar_regions(
+                input.to_str().unwrap(),
+                &primers,
+                prefix,
+                &config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(
+                (summary.extracted, summary.primer_dimers),
+                (extracted, 1)
+            );
+            let skipped =
+                fs::read_to_string(format!("{}.skipped.tsv", prefix)).unwrap();
+            assert_eq!(
+                skipped.contains("\tprimer_dimer\t"),
+                !keep_primer_dimers
+            );
+        }
+        let fasta = fs::read_to_string(dir.path().join("true.fa")).unwrap();
+        assert!(fasta.contains(" confidence=low "));
+        assert!(fasta.contains(" primer_dimer=true"));
+    }
+
+    #[test]
+    fn test_both_strands() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        let minus = reverse_complement_seq(seq.as_bytes(), "dna");
+        fs::write(
+            &input,
+            format!(
+                ">plus\n{}\n>minus\n{}\n",
+                seq,
+                String::from_utf8(minus).unwrap()
+            ),
+        )
+        .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let config = Config {
+            both_strands: true,
+            ..Default::default()
+        };
+        let run = |config: &Config| {
+            get_hypervar_regions(
+                input.to_str().unwrap(),
+                &[region_to_primer("v3v4").unwrap()],
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap()
+        };
+        let summary = run(&config);
+        assert_eq!((summary.extracted, summary.inconsistent), (2, 0));
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        let lines = fasta.lines().collect::<Vec<_>>();
+        assert!(lines[0].ends_with(" strand=+"));
+        assert!(lines[2].starts_with(">minus") && lines[2].ends_with("-"));
+        assert_eq!(lines[1], lines[3]);
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.contains("plus\thyperex\tregion\t268\t707\t.\t+\t"));
+        assert!(gff.contains(&format!(
+            "minus\thyperex\tregion\t{}\t{}\t.\t-\t",
+            seq.len() - 707 + 1,
+            seq.len() - 267
+        )));
+
+        // Minus strand regions as read on the sequence
+        run(&Config {
+            keep_strand: true,
+            ..config
+        });
+        let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+        let lines = fasta.lines().collect::<Vec<_>>();
         assert_eq!(
-            region_to_primer("v6v9").unwrap(),
-            vec!["TAAAACTYAAAKGAATTGACGGGG", "TACGGYTACCTTGTTAYGACTT"]
+            lines[3].as_bytes(),
+            reverse_complement_seq(lines[1].as_bytes(), "dna")
         );
+    }
+
+    #[test]
+    fn test_bisulfite() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        let minus = reverse_complement_seq(seq.as_bytes(), "dna");
+        // Both strands converted, every C read as T
+        fs::write(
+            &input,
+            format!(
+                ">plus\n{}\n>minus\n{}\n",
+                seq.replace('C', "T"),
+                String::from_utf8(minus).unwrap().replace('C', "T")
+            ),
+        )
+        .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let run = |bisulfite: bool| {
+            let config = Config {
+                both_strands: true,
+                bisulfite,
+                ..Default::default()
+            };
+            get_hypervar_regions(
+                input.to_str().unwrap(),
+                &[region_to_primer("v3v4").unwrap()],
+                prefix,
+                &config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap()
+        };
+        assert_eq!(run(false).extracted, 0);
+        let summary = run(true);
+        assert_eq!((summary.extracted, summary.inconsistent), (2, 0));
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.contains("plus\thyperex\tregion\t268\t707\t.\t+\t"));
+        assert!(gff.contains(&format!(
+            "minus\thyperex\tregion\t{}\t{}\t.\t-\t",
+            seq.len() - 707 + 1,
+            seq.len() - 267
+        )));
+    }
+
+    #[test]
+    fn test_primer_offsets() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        let minus = reverse_complement_seq(seq.as_bytes(), "dna");
+        fs::write(
+            &input,
+            format!(
+                ">plus\n{}\n>minus\n{}\n",
+                seq,
+                String::from_utf8(minus).unwrap()
+            ),
+        )
+        .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let offsets = |config: &Config| {
+            get_hypervar_regions(
+                input.to_str().unwrap(),
+                &[region_to_primer("v3v4").unwrap()],
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            let fasta = fs::read_to_string(format!("{}.fa", prefix)).unwrap();
+            fasta
+                .lines()
+                .filter(|line| line.starts_with('>'))
+                .map(|line| {
+                    let offset = |key: &str| {
+                        let value = line
+                            .split(' ')
+                            .find_map(|field| field.strip_prefix(key));
+                        value.unwrap().parse::<i64>().unwrap()
+                    };
+                    (offset("forward_offset="), offset("reverse_offset="))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The v3v4 region spans 440 bases, its primers 17 and 21 bases
+        let config = Config {
+            both_strands: true,
+            primer_offsets: true,
+            ..Default::default()
+        };
+        assert_eq!(offsets(&config), vec![(0, 419), (0, 419)]);
+        let trimmed = Config {
+            trim_primers: true,
+            ..config
+        };
+        assert_eq!(offsets(&trimmed), vec![(-17, 402), (-17, 402)]);
+    }
+
+    #[test]
+    fn test_cache_hits() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = fs::read_to_string("tests/test.fa").unwrap();
+        let seq = seq.lines().skip(1).collect::<String>();
+        fs::write(&input, format!(">a\n{}\n>b\n{}\n>c\nACGT\n", seq, seq))
+            .unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let run = |config: &Config| {
+            let summary = get_hypervar_regions(
+                input,
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap();
+            let hits = fs::read_to_string(format!("{}.hits.tsv", prefix));
+            (summary, hits.unwrap())
+        };
+        let (uncached, uncached_hits) = run(&Config::default());
+        let config = Config {
+            cache_hits: true,
+            ..Default::default()
+        };
+        let (cached, cached_hits) = run(&config);
+        assert_eq!((uncached.cached, cached.cached), (0, 1));
+        assert_eq!(cached.extracted, 2);
+        assert_eq!(cached.regions, uncached.regions);
+        assert_eq!(cached_hits, uncached_hits);
+    }
+
+    #[test]
+    fn test_index_fasta() {
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.fa");
+        fs::write(&path, ">a region=v4\nACGTAC\nGTA\n>b\nAC\n").unwrap();
+        let path = path.to_str().unwrap();
+        index_fasta(path).unwrap();
         assert_eq!(
-            region_to_primer("v7v9").unwrap(),
-            vec!["YAACGAGCGCAACCC", "TACGGYTACCTTGTTAYGACTT"]
+            fs::read_to_string(format!("{}.fai", path)).unwrap(),
+            "a\t9\t13\t6\t7\nb\t2\t27\t2\t3\n"
         );
-        assert_eq!(region_to_primer("").unwrap(), vec![""]);
+
+        fs::write(path, ">a\nAC\nGTA\n").unwrap();
+        assert!(index_fasta(path).is_err());
     }
 
     #[test]
-    fn test_write_fa_ok2() {
-        let mut tmpfile =
-            NamedTempFile::new().expect("Cannot create temp file");
-        writeln!(tmpfile, ">id_str desc\nATCGCCG")
-            .expect("Cannot write to tmp file");
+    fn test_collapsed() {
+        let collapsed = Collapsed::new(b"AAcGGGT");
+        assert_eq!(collapsed.seq, b"ACGT");
+        assert_eq!(collapsed.expand((1, 3, 0)), (2, 6, 0));
+        assert_eq!(collapsed.expand((4, 4, 0)), (7, 7, 0));
 
-        let mut fa_records = fasta::Reader::from_file(tmpfile)
-            .expect("Cannot read file.")
-            .records();
+        // A homopolymer insertion costs nothing once collapsed
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let input = dir.path().join("in.fa");
+        let seq = format!(
+            ">s\nTTGTGCCAAAGCAGCCGCGGTAA{}ATTAGATACCCGAGTAGTCC\n",
+            "T".repeat(15)
+        );
+        fs::write(&input, seq).unwrap();
+        let input = input.to_str().unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![region_to_primer("v4").unwrap()];
+        let mut config = Config::default();
+        let run = |config: &Config| {
+            get_hypervar_regions(
+                input,
+                &primers,
+                prefix,
+                config,
+                &mut ExtraOutputs::default(),
+                None,
+            )
+            .unwrap()
+        };
+        assert_eq!(run(&config).extracted, 0);
+        config.homopolymer_tolerant = true;
+        assert_eq!(run(&config).extracted, 1);
+        let gff = fs::read_to_string(format!("{}.gff", prefix)).unwrap();
+        assert!(gff.contains("\t3\t58\t"));
+    }
 
-        while let Some(Ok(rec)) = fa_records.next() {
-            assert_eq!(rec.id(), "id_str");
-            assert_eq!(rec.desc(), Some("desc"));
-            assert_eq!(rec.seq(), b"ATCGCCG");
-        }
+    #[test]
+    fn test_hit_cigar() {
+        let hit_cigar = |no_indels, pattern, text, hit| {
+            let matcher = Matcher::new(&AmbigMap::default(), no_indels);
+            to_cigar(&matcher.alignment(pattern, text, hit).unwrap())
+        };
+        // R matches A, then a substitution
+        let text = b"TTTTACGTAAGTTTT";
+        assert_eq!(hit_cigar(false, b"ACGTRCGT", text, (4, 12, 1)), "5=1X2=");
+        // Sequence base missing from the primer
+        let text = b"TTACGTTACGTTT";
+        assert_eq!(hit_cigar(false, b"ACGTACGT", text, (2, 11, 1)), "4=1D4=");
+        let text = b"TTTTACGAAGGTTTT";
+        assert_eq!(hit_cigar(true, b"ACGARCGT", text, (4, 12, 1)), "5=1X2=");
     }
 
     #[test]
-    fn test_combine_vec() {
-        let first = vec!["ab", "cd", "ef"];
-        let second = vec!["cd", "ef", "gh"];
+    fn test_degenerate_score() {
+        let ambigs = AmbigMap::default();
+        // R resolved to A and N to C, a mismatch at a degenerate position
+        // being ignored
         assert_eq!(
-            combine_vec(first, second),
-            vec![
-                vec!["ab".to_string(), "cd".to_string()],
-                vec!["cd".to_string(), "ef".to_string()],
-                vec!["ef".to_string(), "gh".to_string()]
-            ]
+            degenerate_score(b"ARCNT", b"AACCT", b"=====", &ambigs),
+            0.125
+        );
+        assert_eq!(
+            degenerate_score(b"ARCNT", b"ATCCT", b"=X===", &ambigs),
+            0.25
         );
+        // Ambiguous sequence bases and indels
+        assert_eq!(
+            degenerate_score(b"ARCNT", b"ANGCCT", b"==D===", &ambigs),
+            0.25
+        );
+        assert_eq!(degenerate_score(b"ACGT", b"ACGT", b"====", &ambigs), 1.0);
     }
 
     #[test]
-    fn test_combine_vec_not_ok() {
-        let first = vec!["ab", "cd", "ef"];
-        let second = vec!["ab"];
-        assert_ne!(
-            combine_vec(first, second),
+    fn test_length_histograms() {
+        let mut summary = RunSummary::default();
+        for (start, end) in [(0, 253), (0, 251), (0, 275), (10, 20)] {
+            let region_match = RegionMatch {
+                region: if end == 20 { "v3v4" } else { "v4" }.to_string(),
+                forward_primer: "AC".to_string(),
+                reverse_primer: "GT".to_string(),
+                start,
+                end,
+                forward_dist: 0,
+                reverse_dist: 0,
+                strand: Strand::Plus,
+                confidence: Confidence::High,
+            };
+            summary.add_match(&region_match);
+        }
+        assert_eq!(
+            summary.length_histograms(),
             vec![
-                vec!["ab".to_string(), "cd".to_string()],
-                vec!["cd".to_string(), "ef".to_string()],
-                vec!["ef".to_string(), "gh".to_string()]
+                ("v3v4", vec![(10, 1)]),
+                ("v4", vec![(250, 2), (260, 0), (270, 1)])
             ]
         );
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.lengths.tsv");
+        let path = path.to_str().unwrap();
+        summary.write_lengths(path).unwrap();
+        let tsv = fs::read_to_string(path).unwrap();
+        assert_eq!(tsv.lines().count(), 5);
+        assert!(tsv.contains("v4\t250\t259\t2\n"));
     }
 
     #[test]
-    fn test_get_hypervar_regions() {
-        assert!(get_hypervar_regions(
-            "tests/test.fa.gz",
-            vec![vec![
-                "AGAGTTTGATCMTGGCTCAG".to_string(),
-                "TACGGYTACCTTGTTAYGACTT".to_string()
-            ]],
-            "hyperex",
-            0
-        )
-        .is_ok());
-        fs::remove_file("hyperex.fa").expect("cannot delete file");
-        fs::remove_file("hyperex.gff").expect("cannot delete file");
+    fn test_primer_mismatches() {
+        let mut summary = RunSummary::default();
+        summary.add_primer_hit("ACGTACGT", b"=====X==", false);
+        summary.add_primer_hit("ACGTACGT", b"====D====", false);
+        summary.add_primer_hit("AAGGCC", b"X=====", true);
+        let mut other = RunSummary::default();
+        other.add_primer_hit("ACGTACGT", b"========", false);
+        summary.merge(&other);
+        let profile = &summary.primers["ACGTACGT"];
+        assert_eq!(profile.hits, 3);
+        assert_eq!(profile.mismatches, vec![0, 0, 0, 0, 1, 1, 0, 0]);
+        // Reverse primer mismatching at its 3' end
+        assert_eq!(
+            summary.primers["AAGGCC"].mismatches,
+            vec![0, 0, 0, 0, 0, 1]
+        );
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.mismatches.tsv");
+        let path = path.to_str().unwrap();
+        summary.write_mismatches(path).unwrap();
+        let tsv = fs::read_to_string(path).unwrap();
+        assert_eq!(tsv.lines().count(), 15);
+        assert!(tsv.contains("AAGGCC\t1\tA\t1\t0\t0.0000\tno\n"));
+        assert!(tsv.contains("ACGTACGT\t6\tC\t3\t1\t0.3333\tyes\n"));
+        assert!(tsv.contains("ACGTACGT\t3\tG\t3\t0\t0.0000\tno\n"));
+    }
+
+    #[test]
+    fn test_taxa() {
+        let mut summary = RunSummary::default();
+        let regions = ["v4".to_string(), "v4".to_string()];
+        let hits = [("ACGT".to_string(), 0), ("TTGG".to_string(), 2)];
+        summary.add_taxon("Bacteria;Bacillota", &regions, &hits);
+        summary.add_taxon("Bacteria;Pseudomonadota", &[], &[]);
+        let mut other = RunSummary::default();
+        other.add_taxon("Bacteria;Bacillota", &regions[..1], &hits[1..]);
+        summary.merge(&other);
+        assert_eq!(summary.taxa["Bacteria"].sequences, 3);
+        assert_eq!(summary.taxa["Bacteria"].regions["v4"], 2);
+
+        let dir = tempfile::tempdir().expect("Cannot create temp dir");
+        let path = dir.path().join("out.taxa.tsv");
+        let path = path.to_str().unwrap();
+        summary.write_taxa(path).unwrap();
+        let tsv = fs::read_to_string(path).unwrap();
+        assert_eq!(
+            tsv.lines().collect::<Vec<_>>(),
+            vec![
+                "taxon\tdepth\tregion\tsequences\textracted\tsuccess_rate",
+                "Bacteria\t1\tv4\t3\t2\t0.6667",
+                "Bacteria;Bacillota\t2\tv4\t2\t2\t1.0000",
+                "Bacteria;Pseudomonadota\t2\tv4\t1\t0\t0.0000",
+            ]
+        );
+
+        let path = dir.path().join("out.ranks.tsv");
+        let path = path.to_str().unwrap();
+        summary.write_ranks(path).unwrap();
+        let tsv = fs::read_to_string(path).unwrap();
+        assert_eq!(
+            tsv.lines().collect::<Vec<_>>()[..5],
+            [
+                "rank\ttaxon\tsequences\tmeasure\tname\tvalue",
+                "domain\tBacteria\t3\textraction_rate\tv4\t0.6667",
+                "domain\tBacteria\t3\tmean_mismatches\tACGT\t0.0000",
+                "domain\tBacteria\t3\tmean_mismatches\tTTGG\t2.0000",
+                "phylum\tBacillota\t2\textraction_rate\tv4\t1.0000",
+            ]
+        );
     }
 
     #[test]
-    fn test_setup_logging() {
-        assert!(setup_logging(false).is_ok());
+    fn test_run_summary_merge() {
+        let region_match = RegionMatch {
+            region: "v4".to_string(),
+            forward_primer: "AC".to_string(),
+            reverse_primer: "GT".to_string(),
+            start: 0,
+            end: 4,
+            forward_dist: 0,
+            reverse_dist: 0,
+            strand: Strand::Plus,
+            confidence: Confidence::High,
+        };
+        let mut first = RunSummary {
+            sequences: 2,
+            ..Default::default()
+        };
+        first.add_match(&region_match);
+        let mut second = RunSummary {
+            sequences: 3,
+            ..Default::default()
+        };
+        second.add_match(&region_match);
+        second.add_match(&RegionMatch {
+            region: String::new(),
+            ..region_match.clone()
+        });
+
+        first.merge(&second);
+        assert_eq!(first.sequences, 5);
+        assert_eq!(first.extracted, 3);
+        assert_eq!(first.regions["v4"], 2);
+        assert_eq!(first.regions["AC/GT"], 1);
+        assert_eq!(first.lengths["v4"][&4], 2);
+
+        first.too_long = 1;
+        first.half_named = 1;
+        first.duplicates = 1;
+        assert_eq!(
+            first.porcelain(),
+            "OK sequences=5 extracted=3 regions=AC/GT:1,v4:2 warnings=3"
+        );
     }
 
     #[test]
@@ -627,6 +5620,22 @@ mod tests {
         assert!(read_file(myfile).is_ok());
     }
 
+    #[test]
+    fn test_read_records_fastq() {
+        let mut tmpfile =
+            NamedTempFile::new().expect("Cannot create temp file");
+        writeln!(tmpfile, "@read1 desc\nACGT\n+\nIIII")
+            .expect("Cannot write to tmp file");
+
+        let records = read_records(tmpfile.path().to_str().unwrap())
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id(), "read1");
+        assert_eq!(records[0].seq(), b"ACGT");
+    }
+
     #[test]
     fn test_file_to_vec() {
         assert_eq!(
@@ -0,0 +1,295 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::matcher;
+use crate::utils::{self, Alphabet, Config};
+
+use bio::alignment::AlignmentOperation;
+
+use std::io::Write;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[1;31m";
+const RESET: &str = "\x1b[0m";
+
+/// Alignment of a primer on a sequence
+#[derive(Debug, PartialEq)]
+pub struct PrimerSite {
+    pub start: usize,
+    // Exclusive end
+    pub end: usize,
+    pub dist: u8,
+    pub ops: Vec<AlignmentOperation>,
+}
+
+/// Align `pattern` at its best site in `text`, allowing up to `max_dist`
+/// edits, or only substitutions when indels are disabled. Ties are resolved
+/// to the first site as during extraction.
+pub fn align_primer(
+    pattern: &[u8],
+    text: &[u8],
+    max_dist: u8,
+    config: &Config,
+) -> Option<PrimerSite> {
+    let matcher = config.matcher();
+    let ambigs = matcher.ambigs();
+    if config.no_indels {
+        let (start, end, dist) = matcher::best_substitution_hit(
+            pattern, text, max_dist, ambigs, None, None,
+        )?;
+        let ops = pattern
+            .iter()
+            .zip(text[start..end].iter())
+            .map(|(&symbol, &base)| {
+                if ambigs.matched_by(symbol)[base as usize] {
+                    AlignmentOperation::Match
+                } else {
+                    AlignmentOperation::Subst
+                }
+            })
+            .collect();
+        return Some(PrimerSite {
+            start,
+            end,
+            dist,
+            ops,
+        });
+    }
+
+    let mut myers = ambigs.builder().build_64(pattern);
+    let mut matches = myers.find_all_lazy(text, max_dist);
+
+    let (end, _) = matches.by_ref().min_by_key(|&(_, dist)| dist)?;
+    let mut ops = Vec::new();
+    let (start, dist) = matches.path_at(end, &mut ops)?;
+
+    Some(PrimerSite {
+        start,
+        end: end + 1,
+        dist,
+        ops,
+    })
+}
+
+/// Render the site as the sequence with `context` flanking bases, a marker
+/// line and the aligned primer. Markers are `|` for identical bases, `:`
+/// for bases matched by an ambiguity code and `x` for mismatches and gaps.
+pub fn render_site(
+    site: &PrimerSite,
+    pattern: &[u8],
+    text: &[u8],
+    context: usize,
+    color: bool,
+) -> String {
+    let paint = |base: u8, code: &str| {
+        if color {
+            format!("{}{}{}", code, base as char, RESET)
+        } else {
+            (base as char).to_string()
+        }
+    };
+
+    let left = &text[site.start.saturating_sub(context)..site.start];
+    let right = &text[site.end..(site.end + context).min(text.len())];
+    let mut sequence = String::from_utf8_lossy(left).to_string();
+    let mut markers = " ".repeat(left.len());
+    let mut primer = " ".repeat(left.len());
+
+    let (mut i, mut j) = (0, site.start);
+    for op in site.ops.iter() {
+        match op {
+            AlignmentOperation::Match => {
+                let same = text[j] == pattern[i];
+                sequence.push_str(&paint(text[j], GREEN));
+                markers.push(if same { '|' } else { ':' });
+                primer.push(pattern[i] as char);
+                i += 1;
+                j += 1;
+            }
+            AlignmentOperation::Subst => {
+                sequence.push_str(&paint(text[j], RED));
+                markers.push('x');
+                primer.push(pattern[i] as char);
+                i += 1;
+                j += 1;
+            }
+            // Primer base missing from the sequence
+            AlignmentOperation::Ins => {
+                sequence.push_str(&paint(b'-', RED));
+                markers.push('x');
+                primer.push(pattern[i] as char);
+                i += 1;
+            }
+            // Sequence base missing from the primer
+            AlignmentOperation::Del => {
+                sequence.push_str(&paint(text[j], RED));
+                markers.push('x');
+                primer.push('-');
+                j += 1;
+            }
+            _ => {}
+        }
+    }
+    sequence.push_str(&String::from_utf8_lossy(right));
+
+    format!(
+        "  sequence  {}\n            {}\n  primer    {}\n",
+        sequence,
+        markers.trim_end(),
+        primer
+    )
+}
+
+/// Print the binding sites of each primer on the sequences of `file`, or
+/// only on the sequences named in `ids` when it is not empty.
+pub fn view(
+    file: &str,
+    ids: &[&str],
+    primers: &[Vec<String>],
+    config: &Config,
+    context: usize,
+    color: bool,
+    out: &mut dyn Write,
+) -> anyhow::Result<()> {
+    let mismatch = config.mismatch;
+    let mut records = utils::read_records(file)?;
+
+    while let Some(record) = records.next().transpose()? {
+        if !ids.is_empty() && !ids.contains(&record.id()) {
+            continue;
+        }
+        let seq = record.seq();
+        let alphabet = match utils::sequence_type(std::str::from_utf8(seq)?) {
+            Some(Alphabet::Rna) => "rna",
+            _ => "dna",
+        };
+        writeln!(out, ">{} ({} bp)", record.id(), seq.len())?;
+
+        for pair in primers.iter() {
+            let region = utils::primers_to_region(pair.to_vec());
+            let reverse = utils::to_reverse_complement(&pair[1], alphabet);
+            for (strand, primer) in
+                [("forward", pair[0].as_str()), ("reverse", reverse.as_str())]
+            {
+                let pattern = primer.as_bytes();
+                let label = if region.is_empty() {
+                    format!("{} {}", strand, primer)
+                } else {
+                    format!("{} {} {}", region, strand, primer)
+                };
+
+                if let Some(site) = align_primer(pattern, seq, mismatch, config)
+                {
+                    writeln!(
+                        out,
+                        "{}: {}-{}, {} mismatch(es)",
+                        label, site.start, site.end, site.dist
+                    )?;
+                    out.write_all(
+                        render_site(&site, pattern, seq, context, color)
+                            .as_bytes(),
+                    )?;
+                    continue;
+                }
+
+                // Show the closest site to explain why the primer missed
+                let max_dist = (pattern.len() / 3).max(mismatch as usize + 1);
+                match align_primer(pattern, seq, max_dist as u8, config) {
+                    Some(site) => {
+                        writeln!(
+                            out,
+                            "{}: not found with up to {} mismatch(es), closest site {}-{} has {}",
+                            label, mismatch, site.start, site.end, site.dist
+                        )?;
+                        out.write_all(
+                            render_site(&site, pattern, seq, context, color)
+                                .as_bytes(),
+                        )?;
+                    }
+                    None => writeln!(
+                        out,
+                        "{}: not found, no site within {} mismatches",
+                        label, max_dist
+                    )?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_align_primer() {
+        let text = b"TTTTACGTAAGTTTT";
+        let site =
+            align_primer(b"ACGTRCGT", text, 1, &Config::default()).unwrap();
+        assert_eq!((site.start, site.end, site.dist), (4, 12, 1));
+        assert!(align_primer(b"GGGGGG", text, 1, &Config::default()).is_none());
+    }
+
+    #[test]
+    fn test_align_primer_no_indels() {
+        let config = Config {
+            no_indels: true,
+            ..Default::default()
+        };
+        let text = b"TTTTACGAAGGTTTT";
+        assert!(align_primer(b"ACGTRCGT", text, 1, &config).is_none());
+        let site = align_primer(b"ACGARCGT", text, 1, &config).unwrap();
+        assert_eq!((site.start, site.end, site.dist), (4, 12, 1));
+        assert_eq!(site.ops[5], AlignmentOperation::Subst);
+    }
+
+    #[test]
+    fn test_render_site() {
+        let text = b"TTTTACGTAAGTTTT";
+        let site =
+            align_primer(b"ACGTRCGT", text, 1, &Config::default()).unwrap();
+        assert_eq!(
+            render_site(&site, b"ACGTRCGT", text, 2, false),
+            "  sequence  TTACGTAAGTTT\n              ||||:x||\n  primer      ACGTRCGT\n"
+        );
+        let colored = render_site(&site, b"ACGTRCGT", text, 2, true);
+        assert!(colored.contains("\x1b[1;31mA\x1b[0m"));
+    }
+
+    #[test]
+    fn test_view() {
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+        let mut out = Vec::new();
+        view(
+            "tests/test.fa",
+            &[],
+            &primers,
+            &Config::default(),
+            5,
+            false,
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("v3v4 forward CCTACGGGNGGCWGCAG: 267-284"));
+        assert!(out.contains("v3v4 reverse GGATTAGATACCCBDGTAGTC: 686-707"));
+
+        let mut out = Vec::new();
+        view(
+            "tests/test.fa",
+            &["other"],
+            &primers,
+            &Config::default(),
+            5,
+            false,
+            &mut out,
+        )
+        .unwrap();
+        assert!(out.is_empty());
+    }
+}
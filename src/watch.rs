@@ -0,0 +1,571 @@
+// Copyright 2021-2024 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::batch;
+use crate::utils::{self, Config, ExtraOutputs, RunSummary};
+
+use anyhow::Context;
+use log::{debug, error, info};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SEQUENCE_EXTENSIONS: [&str; 5] = ["fa", "fasta", "fna", "fq", "fastq"];
+const COMPRESSION_EXTENSIONS: [&str; 3] = ["gz", "xz", "bz2"];
+
+/// Watch a directory for new FASTA/FASTQ files, as written by a basecaller
+/// during a sequencing run.
+///
+/// A file is processed once its size did not change between two scans.
+/// Regions are appended to `<prefix>_<sample>.fa` and `<prefix>_<sample>.gff`
+/// where the sample is the subdirectory of the watched directory holding
+/// the file, or the file name up to its first dot for top-level files.
+/// Per-sample counts are kept in `<prefix>_summary.tsv`, region lengths
+/// in `<prefix>_<sample>.lengths.tsv` and taxon statistics, when lineages
+/// are known, in `<prefix>_<sample>.taxa.tsv` and
+/// `<prefix>_<sample>.ranks.tsv`. Outputs written to the watched directory
+/// are not processed. A file that cannot be processed is logged and
+/// counted, and the watch goes on until interrupted.
+pub struct Watcher<'a> {
+    dir: PathBuf,
+    primers: &'a [Vec<String>],
+    prefix: String,
+    // Directory and file name of the prefix, the outputs it names being
+    // left out of the scans when written to the watched directory
+    outputs: (PathBuf, String),
+    config: Config,
+    // Size of the files seen on the previous scan and not processed yet
+    pending: HashMap<PathBuf, u64>,
+    processed: HashSet<PathBuf>,
+    // Files that could not be processed
+    failed: usize,
+    samples: BTreeMap<String, RunSummary>,
+    // Prometheus text file of the counters, rewritten after each scan
+    metrics: Option<PathBuf>,
+    // Set to stop watching once the file being processed is done
+    interrupt: Option<Arc<AtomicBool>>,
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new(
+        dir: &str,
+        primers: &'a [Vec<String>],
+        prefix: &str,
+        config: &Config,
+    ) -> Self {
+        let path = Path::new(prefix);
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let name = path
+            .file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().to_string());
+        Watcher {
+            dir: PathBuf::from(dir),
+            primers,
+            prefix: prefix.to_string(),
+            outputs: (canonical(parent), name),
+            // Files are extracted to new parts appended to the sample outputs
+            config: Config {
+                append: false,
+                ..config.clone()
+            },
+            pending: HashMap::new(),
+            processed: HashSet::new(),
+            failed: 0,
+            samples: BTreeMap::new(),
+            metrics: None,
+            interrupt: None,
+        }
+    }
+
+    /// Dump the counters of the watch to `path` after each scan, in the
+    /// Prometheus text format read by the node exporter textfile collector
+    pub fn with_metrics(mut self, path: &str) -> Self {
+        self.metrics = Some(PathBuf::from(path));
+        self
+    }
+
+    /// Stop watching when `interrupt` is set, e.g. by a signal handler
+    pub fn with_interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Scan the directory every `interval` until interrupted
+    pub fn run(&mut self, interval: Duration) -> anyhow::Result<()> {
+        info!("Watching {} for new files", self.dir.display());
+        while !self.interrupted() {
+            self.scan_once()?;
+            // Interrupts are checked while waiting for the next scan
+            let next = Instant::now() + interval;
+            while !self.interrupted() && Instant::now() < next {
+                let left = next.saturating_duration_since(Instant::now());
+                thread::sleep(left.min(Duration::from_millis(100)));
+            }
+        }
+        info!("Stopped watching {}", self.dir.display());
+
+        Ok(())
+    }
+
+    fn interrupted(&self) -> bool {
+        self.interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.load(Ordering::Relaxed))
+    }
+
+    /// Scan the directory once and process the files that are complete.
+    /// Return the number of processed files.
+    pub fn scan_once(&mut self) -> anyhow::Result<usize> {
+        let mut files = Vec::new();
+        list_sequence_files(&self.dir, &mut files).with_context(|| {
+            format!("Cannot read directory {}", self.dir.display())
+        })?;
+        files.sort();
+        // Files renamed or deleted since the previous scan
+        self.pending
+            .retain(|path, _| files.binary_search(path).is_ok());
+
+        let mut ready = Vec::new();
+        for path in files {
+            if self.processed.contains(&path) || self.is_output(&path) {
+                continue;
+            }
+            let size = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                // Renamed or deleted since the directory was read
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    debug!("{} is gone, skipping it", path.display());
+                    self.pending.remove(&path);
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Cannot read {}", path.display())
+                    })
+                }
+            };
+            // A file still being written will grow between two scans
+            if self.pending.insert(path.clone(), size) == Some(size) {
+                ready.push(path);
+            }
+        }
+
+        for path in ready.iter() {
+            self.pending.remove(path);
+            if let Err(e) = self.process(path) {
+                error!("{:#}", e);
+                self.failed += 1;
+            }
+            self.processed.insert(path.clone());
+        }
+        if !ready.is_empty() {
+            self.write_summary()?;
+        }
+        if let Some(path) = self.metrics.as_ref() {
+            write_atomically(path, &self.metrics()).with_context(|| {
+                format!("Cannot write metrics {}", path.display())
+            })?;
+        }
+
+        Ok(ready.len())
+    }
+
+    /// Counters of the watch in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        let mut records = Vec::new();
+        let mut skipped = Vec::new();
+        let mut regions = Vec::new();
+        for (sample, summary) in self.samples.iter() {
+            let sample = format!("sample=\"{}\"", escape_label(sample));
+            records.push((sample.clone(), summary.sequences as u64));
+            skipped.push((
+                format!("{},reason=\"too_long\"", sample),
+                summary.too_long as u64,
+            ));
+            skipped.push((
+                format!("{},reason=\"timed_out\"", sample),
+                summary.timed_out as u64,
+            ));
+            for (region, count) in summary.regions.iter() {
+                regions.push((
+                    format!("{},region=\"{}\"", sample, escape_label(region)),
+                    *count as u64,
+                ));
+            }
+        }
+        let processed = (self.processed.len() - self.failed) as u64;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let metrics = [
+            (
+                "files_processed_total",
+                "counter",
+                "Files processed.",
+                vec![(String::new(), processed)],
+            ),
+            (
+                "files_failed_total",
+                "counter",
+                "Files that could not be processed.",
+                vec![(String::new(), self.failed as u64)],
+            ),
+            (
+                "records_processed_total",
+                "counter",
+                "Records processed, by sample.",
+                records,
+            ),
+            (
+                "records_skipped_total",
+                "counter",
+                "Records skipped by the size guard or the time budget.",
+                skipped,
+            ),
+            (
+                "regions_extracted_total",
+                "counter",
+                "Regions extracted, by sample and region.",
+                regions,
+            ),
+            (
+                "last_scan_timestamp_seconds",
+                "gauge",
+                "Time of the last scan of the watched directory.",
+                vec![(String::new(), now)],
+            ),
+        ];
+        let mut out = String::new();
+
+        // Each metric is one group, its HELP and TYPE lines first
+        for (name, kind, help, samples) in metrics.iter() {
+            let _ = writeln!(out, "# HELP hyperex_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE hyperex_{} {}", name, kind);
+            for (labels, value) in samples {
+                if labels.is_empty() {
+                    let _ = writeln!(out, "hyperex_{} {}", name, value);
+                } else {
+                    let _ = writeln!(
+                        out,
+                        "hyperex_{}{{{}}} {}",
+                        name, labels, value
+                    );
+                }
+            }
+        }
+
+        out
+    }
+
+    // Is the file one of the outputs, named <prefix>_*?
+    fn is_output(&self, path: &Path) -> bool {
+        let (dir, name) = &self.outputs;
+        let parent = path.parent().unwrap_or_else(|| Path::new("."));
+        path.file_name().is_some_and(|file| {
+            file.to_string_lossy().starts_with(&format!("{}_", name))
+        }) && canonical(parent) == *dir
+    }
+
+    fn process(&mut self, path: &Path) -> anyhow::Result<()> {
+        let sample = sample_name(&self.dir, path);
+        let file = path.to_string_lossy().to_string();
+        let sample_prefix = format!("{}_{}", self.prefix, sample);
+        let part = vec![format!("{}.watch", sample_prefix)];
+        info!("Processing {} for sample {}", file, sample);
+
+        let result = utils::get_hypervar_regions(
+            &file,
+            self.primers,
+            &part[0],
+            &self.config,
+            &mut ExtraOutputs::default(),
+            None,
+        )
+        .with_context(|| format!("Cannot process {}", file))
+        .and_then(|summary| {
+            batch::append_outputs(&part, &sample_prefix, true)?;
+            Ok(summary)
+        });
+        batch::remove_parts(&part);
+
+        let summary = self.samples.entry(sample.clone()).or_default();
+        summary.merge(&result?);
+        summary.log(&sample);
+
+        Ok(())
+    }
+
+    fn write_summary(&self) -> io::Result<()> {
+        let path = format!("{}_summary.tsv", self.prefix);
+        let mut writer = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(writer, "sample\tsequences\textracted\tregions")?;
+        for (sample, summary) in self.samples.iter() {
+            let regions = summary
+                .regions
+                .iter()
+                .map(|(region, count)| format!("{}:{}", region, count))
+                .collect::<Vec<_>>()
+                .join(",");
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                sample, summary.sequences, summary.extracted, regions
+            )?;
+            summary.write_lengths(&format!(
+                "{}_{}.lengths.tsv",
+                self.prefix, sample
+            ))?;
+            if !summary.taxa.is_empty() {
+                summary.write_taxa(&format!(
+                    "{}_{}.taxa.tsv",
+                    self.prefix, sample
+                ))?;
+                summary.write_ranks(&format!(
+                    "{}_{}.ranks.tsv",
+                    self.prefix, sample
+                ))?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+// Label value with backslashes, quotes and newlines escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Replace the file at `path`, so that readers never see it half written
+fn write_atomically(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
+
+// Is the file a, possibly compressed, FASTA or FASTQ file?
+fn is_sequence_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) if !name.starts_with('.') => name,
+        _ => return false,
+    };
+    let mut extensions = name.rsplit('.');
+    let mut extension = extensions.next().unwrap_or("");
+    if COMPRESSION_EXTENSIONS.contains(&extension) {
+        extension = extensions.next().unwrap_or("");
+    }
+
+    name.contains('.') && SEQUENCE_EXTENSIONS.contains(&extension)
+}
+
+fn list_sequence_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_sequence_files(&path, files)?;
+        } else if is_sequence_file(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// Absolute path of a directory, as given when it cannot be resolved
+fn canonical(dir: &Path) -> PathBuf {
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+fn sample_name(dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    let mut components = relative.components();
+    let first = components
+        .next()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    if components.next().is_some() {
+        first
+    } else {
+        first.split('.').next().unwrap_or_default().to_string()
+    }
+}
+
+// Tests --------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_sequence_file() {
+        assert!(is_sequence_file(Path::new("run/reads_0.fastq.gz")));
+        assert!(is_sequence_file(Path::new("contigs.fa")));
+        assert!(!is_sequence_file(Path::new("run/.reads_0.fastq.tmp")));
+        assert!(!is_sequence_file(Path::new("run/sequencing_summary.txt")));
+        assert!(!is_sequence_file(Path::new("fastq")));
+    }
+
+    #[test]
+    fn test_sample_name() {
+        let dir = Path::new("run");
+        assert_eq!(
+            sample_name(dir, Path::new("run/barcode01/reads_0.fq")),
+            "barcode01"
+        );
+        assert_eq!(sample_name(dir, Path::new("run/s1.part2.fa")), "s1");
+    }
+
+    #[test]
+    fn test_scan_once() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let watched = dir.path().join("run");
+        fs::create_dir_all(watched.join("barcode01")).unwrap();
+        fs::copy("tests/test.fa", watched.join("barcode01/reads_0.fa"))
+            .unwrap();
+        let prefix = dir.path().join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+        let mut watcher = Watcher::new(
+            watched.to_str().unwrap(),
+            &primers,
+            prefix,
+            &Config::default(),
+        );
+
+        // New files wait one scan to make sure they are complete
+        assert_eq!(watcher.scan_once().unwrap(), 0);
+        assert_eq!(watcher.scan_once().unwrap(), 1);
+        // Files gone before they are complete are forgotten
+        let gone = watched.join("barcode01/reads_gone.fa");
+        fs::copy("tests/test.fa", &gone).unwrap();
+        assert_eq!(watcher.scan_once().unwrap(), 0);
+        fs::remove_file(&gone).unwrap();
+        assert_eq!(watcher.scan_once().unwrap(), 0);
+        assert!(watcher.pending.is_empty());
+        fs::copy("tests/test.fa", watched.join("barcode01/reads_1.fa"))
+            .unwrap();
+        assert_eq!(watcher.scan_once().unwrap(), 0);
+        assert_eq!(watcher.scan_once().unwrap(), 1);
+        assert_eq!(watcher.scan_once().unwrap(), 0);
+
+        let gff =
+            fs::read_to_string(format!("{}_barcode01.gff", prefix)).unwrap();
+        assert_eq!(gff.matches("##gff-version").count(), 1);
+        assert_eq!(gff.lines().count(), 4);
+        let summary =
+            fs::read_to_string(format!("{}_summary.tsv", prefix)).unwrap();
+        assert_eq!(
+            summary,
+            "sample\tsequences\textracted\tregions\nbarcode01\t2\t2\tv3v4:2\n"
+        );
+    }
+
+    #[test]
+    fn test_prefix_in_watched_dir() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let watched = dir.path().join("w");
+        fs::create_dir_all(&watched).unwrap();
+        fs::copy("tests/test.fa", watched.join("s1.fa")).unwrap();
+        let prefix = watched.join("out");
+        let prefix = prefix.to_str().unwrap();
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+        let mut watcher = Watcher::new(
+            watched.to_str().unwrap(),
+            &primers,
+            prefix,
+            &Config::default(),
+        );
+
+        // The outputs written to the watched directory are not samples
+        assert_eq!(watcher.scan_once().unwrap(), 0);
+        assert_eq!(watcher.scan_once().unwrap(), 1);
+        assert!(Path::new(&format!("{}_s1.fa", prefix)).exists());
+        for _ in 0..3 {
+            assert_eq!(watcher.scan_once().unwrap(), 0);
+        }
+        assert!(!Path::new(&format!("{}_out_s1.fa", prefix)).exists());
+        let summary =
+            fs::read_to_string(format!("{}_summary.tsv", prefix)).unwrap();
+        assert_eq!(summary.lines().count(), 2);
+
+        // Files of the same name outside the prefix directory are samples
+        fs::create_dir_all(watched.join("b1")).unwrap();
+        fs::copy("tests/test.fa", watched.join("b1/out_1.fa")).unwrap();
+        watcher.scan_once().unwrap();
+        assert_eq!(watcher.scan_once().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_run_interrupted() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let prefix = dir.path().join("out");
+        let primers = vec![utils::region_to_primer("v4").unwrap()];
+        let interrupt = Arc::new(AtomicBool::new(false));
+        let mut watcher = Watcher::new(
+            dir.path().to_str().unwrap(),
+            &primers,
+            prefix.to_str().unwrap(),
+            &Config::default(),
+        )
+        .with_interrupt(Arc::clone(&interrupt));
+
+        // Interrupts are seen while waiting for the next scan
+        let start = Instant::now();
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            interrupt.store(true, Ordering::Relaxed);
+        });
+        watcher.run(Duration::from_secs(3600)).unwrap();
+        setter.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let dir = tempdir().expect("Cannot create temp dir");
+        let watched = dir.path().join("run");
+        fs::create_dir_all(&watched).unwrap();
+        fs::copy("tests/test.fa", watched.join("s1.fa")).unwrap();
+        fs::write(watched.join("s2.fa"), "not a sequence file\n").unwrap();
+        let prefix = dir.path().join("out");
+        let metrics = dir.path().join("hyperex.prom");
+        let primers = vec![utils::region_to_primer("v3v4").unwrap()];
+        let mut watcher = Watcher::new(
+            watched.to_str().unwrap(),
+            &primers,
+            prefix.to_str().unwrap(),
+            &Config::default(),
+        )
+        .with_metrics(metrics.to_str().unwrap());
+
+        watcher.scan_once().unwrap();
+        // The unreadable file is counted and the watch goes on
+        assert_eq!(watcher.scan_once().unwrap(), 2);
+        let text = fs::read_to_string(&metrics).unwrap();
+        assert!(text.contains("hyperex_files_processed_total 1\n"));
+        assert!(text.contains("hyperex_files_failed_total 1\n"));
+        assert!(text.contains(
+            "# TYPE hyperex_records_processed_total counter\nhyperex_records_processed_total{sample=\"s1\"} 1\n"
+        ));
+        assert!(text.contains(
+            "hyperex_regions_extracted_total{sample=\"s1\",region=\"v3v4\"} 1\n"
+        ));
+        assert_eq!(escape_label("a\"b\\"), "a\\\"b\\\\");
+    }
+}